@@ -0,0 +1,57 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::generate::{self, GenOptions};
+use pngme::png::Png;
+use pngme::stego::{LsbBackend, StegoBackend};
+
+/// A 512x512 RGB noise image: about 768 KiB of image data that doesn't compress
+fn large_png() -> Png {
+    generate::generate(&GenOptions { width: 512, height: 512, ..GenOptions::default() }).unwrap()
+}
+
+fn chunks(c: &mut Criterion) {
+    let data = vec![0x5a; 1 << 20];
+    let bytes = Chunk::new(ChunkType::from_str("IDAT").unwrap(), data.clone()).as_bytes();
+
+    let mut group = c.benchmark_group("chunk");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("crc", |b| {
+        b.iter(|| Chunk::new(ChunkType::from_str("IDAT").unwrap(), black_box(data.clone())))
+    });
+    group.bench_function("parse", |b| b.iter(|| Chunk::try_from(black_box(&bytes[..])).unwrap()));
+    group.finish();
+}
+
+fn files(c: &mut Criterion) {
+    let png = large_png();
+    let bytes = png.as_bytes();
+
+    let mut group = c.benchmark_group("file");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("parse", |b| b.iter(|| Png::try_from(black_box(&bytes[..])).unwrap()));
+    group.bench_function("serialize", |b| b.iter(|| black_box(&png).as_bytes()));
+    group.finish();
+}
+
+fn lsb(c: &mut Criterion) {
+    let bytes = large_png().as_bytes();
+    let payload = vec![0xa5; 16 << 10];
+    let mut embedded = Png::try_from(&bytes[..]).unwrap();
+    LsbBackend::default().embed(&mut embedded, &payload).unwrap();
+
+    let mut group = c.benchmark_group("lsb");
+    group.throughput(Throughput::Bytes(payload.len() as u64));
+    group.sample_size(20);
+    group.bench_function("embed", |b| {
+        b.iter_batched(|| Png::try_from(&bytes[..]).unwrap(), |mut png| LsbBackend::default().embed(&mut png, &payload).unwrap(), BatchSize::LargeInput)
+    });
+    group.bench_function("extract", |b| b.iter(|| LsbBackend::default().extract(black_box(&embedded)).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, chunks, files, lsb);
+criterion_main!(benches);
@@ -0,0 +1,187 @@
+// Typed wrapper around the WebAssembly build of pngme (`npm run build:wasm`).
+// Everything that needs the WebAssembly module is async, the module is loaded on first use.
+
+import initWasm, { Png as RawPng } from "../pkg/pngme.js";
+
+/** How a payload is hidden, see `pngme encode --mode` */
+export type Mode = "chunk" | "lsb" | "alpha" | "eof" | "interleave";
+
+export interface StegoOptions {
+  /** Defaults to `chunk` */
+  mode?: Mode;
+  /** The chunk type used by the `chunk` and `interleave` modes, defaults to `ruSt` */
+  chunkType?: string;
+}
+
+/** A chunk of a PNG file */
+export interface Chunk {
+  /** The four letter chunk type, e.g. `IHDR` */
+  readonly type: string;
+  readonly data: Uint8Array;
+  readonly length: number;
+  readonly crc: number;
+  /** Decoders must understand critical chunks (uppercase first letter) */
+  readonly critical: boolean;
+  /** Public chunks are defined by the PNG spec (uppercase second letter) */
+  readonly public: boolean;
+  /** Editors may copy the chunk to a modified image (lowercase fourth letter) */
+  readonly safeToCopy: boolean;
+}
+
+/** Anything a PNG file can be read from */
+export type PngSource = Uint8Array | ArrayBuffer | Blob | Response;
+
+let ready: Promise<unknown> | undefined;
+
+/**
+ * Loads the WebAssembly module. Called by every function that needs it, so calling it
+ * explicitly is only useful to load the module ahead of time or from a custom location.
+ */
+export function init(wasm?: URL | string | BufferSource): Promise<unknown> {
+  ready ??= (async () => {
+    if (wasm === undefined && isNode()) {
+      // fetch() can't read file: URLs in Node.js
+      const { readFile } = await import("node:fs/promises");
+      wasm = await readFile(new URL("../pkg/pngme_bg.wasm", import.meta.url));
+    }
+    return initWasm(wasm === undefined ? undefined : { module_or_path: wasm });
+  })();
+  return ready;
+}
+
+/** A PNG file held in memory */
+export class Png {
+  private constructor(private readonly raw: RawPng) {}
+
+  /** Parses a PNG file */
+  static async from(source: PngSource): Promise<Png> {
+    const bytes = await toBytes(source);
+    await init();
+    return new Png(RawPng.fromBytes(bytes));
+  }
+
+  /** Every chunk, in file order */
+  get chunks(): Chunk[] {
+    const chunks: Chunk[] = [];
+    for (let index = 0; index < this.raw.chunkCount(); index++) {
+      const type = this.raw.chunkType(index)!;
+      const data = this.raw.chunkData(index)!;
+      chunks.push({
+        type,
+        data,
+        length: data.length,
+        crc: this.raw.chunkCrc(index)!,
+        critical: isUpperCase(type, 0),
+        public: isUpperCase(type, 1),
+        safeToCopy: !isUpperCase(type, 3),
+      });
+    }
+    return chunks;
+  }
+
+  /** The first chunk of the given type */
+  chunk(type: string): Chunk | undefined {
+    return this.chunks.find((chunk) => chunk.type === type);
+  }
+
+  /** The data after `IEND`, usually empty */
+  get trailer(): Uint8Array {
+    return this.raw.trailer();
+  }
+
+  /** Adds a chunk before `IEND`. Throws if `type` isn't a valid chunk type. */
+  insertChunk(type: string, data: Uint8Array | string): void {
+    this.raw.insertChunk(type, toData(data));
+  }
+
+  /** Removes the first chunk of the given type and returns its data. Throws if there is none. */
+  removeChunk(type: string): Uint8Array {
+    return this.raw.removeChunk(type);
+  }
+
+  /** Hides a payload like `pngme encode` */
+  encode(payload: Uint8Array | string, options: StegoOptions = {}): void {
+    this.raw.encode(options.mode ?? "chunk", options.chunkType ?? "ruSt", toData(payload));
+  }
+
+  /**
+   * Reads a payload hidden like `pngme decode`. Throws if there is none, and for encrypted or
+   * split payloads, which only the CLI can decode.
+   */
+  decode(options: StegoOptions = {}): Uint8Array {
+    return this.raw.decode(options.mode ?? "chunk", options.chunkType ?? "ruSt");
+  }
+
+  /** Reads a text message hidden like `pngme encode` */
+  decodeText(options: StegoOptions = {}): string {
+    return new TextDecoder().decode(this.decode(options));
+  }
+
+  /** The whole file */
+  toBytes(): Uint8Array {
+    return this.raw.toBytes();
+  }
+
+  toBlob(): Blob {
+    return new Blob([this.toBytes()], { type: "image/png" });
+  }
+
+  /** Frees the WebAssembly memory of this file, which can't be used afterwards */
+  free(): void {
+    this.raw.free();
+  }
+}
+
+/** Downloads a PNG file and parses it */
+export async function fetchPng(url: URL | string, init?: RequestInit): Promise<Png> {
+  const response = await fetch(url, init);
+  if (!response.ok) {
+    throw new Error(`couldn't fetch ${url}: ${response.status} ${response.statusText}`);
+  }
+  return Png.from(response);
+}
+
+/** Reads and parses a PNG file (Node.js only) */
+export async function readPngFile(path: string): Promise<Png> {
+  const { readFile } = await import("node:fs/promises");
+  return Png.from(await readFile(path));
+}
+
+/** Saves a PNG file (Node.js only) */
+export async function writePngFile(path: string, png: Png): Promise<void> {
+  const { writeFile } = await import("node:fs/promises");
+  await writeFile(path, png.toBytes());
+}
+
+/** Offers a PNG file as a download (browsers only) */
+export function downloadPng(png: Png, fileName: string): void {
+  const url = URL.createObjectURL(png.toBlob());
+  const link = document.createElement("a");
+  link.href = url;
+  link.download = fileName;
+  link.click();
+  setTimeout(() => URL.revokeObjectURL(url), 0);
+}
+
+async function toBytes(source: PngSource): Promise<Uint8Array> {
+  if (source instanceof Uint8Array) {
+    return source;
+  }
+  if (source instanceof ArrayBuffer) {
+    return new Uint8Array(source);
+  }
+  return new Uint8Array(await source.arrayBuffer());
+}
+
+function toData(data: Uint8Array | string): Uint8Array {
+  return typeof data === "string" ? new TextEncoder().encode(data) : data;
+}
+
+function isUpperCase(type: string, index: number): boolean {
+  const code = type.charCodeAt(index);
+  return code >= 65 && code <= 90;
+}
+
+function isNode(): boolean {
+  return typeof process !== "undefined" && process.versions?.node !== undefined;
+}
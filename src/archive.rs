@@ -0,0 +1,272 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::EntryType;
+
+use crate::Result;
+
+/// The magic bytes of gzip streams
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Archives the content of `dir` as a tar file, gzip compressed with `compress`.
+/// Symbolic links are stored as links, not followed.
+pub fn pack<P: AsRef<Path>>(dir: P, compress: bool) -> Result<Vec<u8>> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()).into());
+    }
+
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.follow_symlinks(false);
+    builder.append_dir_all(".", dir)?;
+    let tar = builder.into_inner()?;
+
+    if !compress {
+        return Ok(tar);
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&tar)?;
+    Ok(encoder.finish()?)
+}
+
+/// Extracts an archive written by `pack` (compressed or not) into `dir`, creating it if
+/// needed. Entries that would land outside of `dir` are skipped. Returns the number of
+/// entries extracted.
+pub fn unpack<P: AsRef<Path>>(archive: &[u8], dir: P) -> Result<usize> {
+    let reader: Box<dyn Read + '_> = if archive.starts_with(&GZIP_MAGIC) {
+        Box::new(GzDecoder::new(archive))
+    } else {
+        Box::new(archive)
+    };
+
+    fs::create_dir_all(&dir)?;
+    let mut count = 0;
+    for entry in tar::Archive::new(reader).entries()? {
+        if entry?.unpack_in(&dir)? {
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return Err("the payload isn't a directory archive".into());
+    }
+    Ok(count)
+}
+
+/// An archive written by `pack`, held in memory so it can be browsed without extracting it
+/// (see `pngme mount`). Nodes are numbered from the root, `Tree::ROOT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tree {
+    nodes: Vec<Node>,
+}
+
+/// A file, directory or symbolic link of a `Tree`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub name: String,
+    /// The directory containing the node (the root is its own parent)
+    pub parent: usize,
+    pub kind: NodeKind,
+    /// The permission bits
+    pub mode: u32,
+    /// The modification time, in seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A directory and the indices of its entries
+    Dir(Vec<usize>),
+    File(Vec<u8>),
+    /// A symbolic link and its target
+    Symlink(String),
+}
+
+impl Tree {
+    pub const ROOT: usize = 0;
+
+    /// Reads every entry of an archive written by `pack` (compressed or not). Entries whose
+    /// path leaves the archive are skipped, like `unpack` does.
+    pub fn from_archive(archive: &[u8]) -> Result<Tree> {
+        let reader: Box<dyn Read + '_> = if archive.starts_with(&GZIP_MAGIC) {
+            Box::new(GzDecoder::new(archive))
+        } else {
+            Box::new(archive)
+        };
+
+        let mut tree = Tree::empty();
+        let mut count = 0;
+        for entry in tar::Archive::new(reader).entries()? {
+            let mut entry = entry?;
+            let names: Option<Vec<String>> = entry.path()?.components()
+                .filter(|component| *component != Component::CurDir)
+                .map(|component| match component {
+                    Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                    _ => None,
+                })
+                .collect();
+            let Some(names) = names else { continue };
+
+            let kind = match entry.header().entry_type() {
+                EntryType::Directory => NodeKind::Dir(Vec::new()),
+                EntryType::Regular => {
+                    let mut data = Vec::new();
+                    entry.read_to_end(&mut data)?;
+                    NodeKind::File(data)
+                }
+                EntryType::Symlink => match entry.link_name()? {
+                    Some(target) => NodeKind::Symlink(target.to_string_lossy().into_owned()),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            let mode = entry.header().mode()? & 0o7777;
+            let mtime = entry.header().mtime()?;
+            tree.insert(&names, kind, mode, mtime);
+            count += 1;
+        }
+
+        if count == 0 {
+            return Err("the payload isn't a directory archive".into());
+        }
+        Ok(tree)
+    }
+
+    /// A tree holding a single file
+    pub fn single_file(name: &str, data: Vec<u8>) -> Tree {
+        let mut tree = Tree::empty();
+        tree.insert(&[name.to_owned()], NodeKind::File(data), 0o444, 0);
+        tree
+    }
+
+    fn empty() -> Tree {
+        let root = Node { name: String::new(), parent: Self::ROOT, kind: NodeKind::Dir(Vec::new()), mode: 0o755, mtime: 0 };
+        Tree { nodes: vec![root] }
+    }
+
+    /// Adds a node at the path made of `names`, creating the missing directories on the way.
+    /// An existing node at that path is replaced, except for the entries of a directory.
+    fn insert(&mut self, names: &[String], kind: NodeKind, mode: u32, mtime: u64) {
+        let Some((name, parents)) = names.split_last() else {
+            self.nodes[Self::ROOT].mode = mode;
+            self.nodes[Self::ROOT].mtime = mtime;
+            return;
+        };
+
+        let mut dir = Self::ROOT;
+        for parent in parents {
+            dir = match self.lookup(dir, parent) {
+                Some(index) if matches!(self.nodes[index].kind, NodeKind::Dir(_)) => index,
+                _ => self.add(dir, parent, NodeKind::Dir(Vec::new()), 0o755, mtime),
+            };
+        }
+
+        match self.lookup(dir, name) {
+            Some(index) => {
+                let node = &mut self.nodes[index];
+                if !(matches!(node.kind, NodeKind::Dir(_)) && matches!(kind, NodeKind::Dir(_))) {
+                    node.kind = kind;
+                }
+                node.mode = mode;
+                node.mtime = mtime;
+            }
+            None => {
+                self.add(dir, name, kind, mode, mtime);
+            }
+        }
+    }
+
+    fn add(&mut self, dir: usize, name: &str, kind: NodeKind, mode: u32, mtime: u64) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(Node { name: name.to_owned(), parent: dir, kind, mode, mtime });
+        if let NodeKind::Dir(entries) = &mut self.nodes[dir].kind {
+            entries.push(index);
+        }
+        index
+    }
+
+    /// The node at `index`
+    pub fn node(&self, index: usize) -> Option<&Node> {
+        self.nodes.get(index)
+    }
+
+    /// The number of nodes, the root included
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Always false, a tree has at least its root
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The entry called `name` of the directory at `dir`
+    pub fn lookup(&self, dir: usize, name: &str) -> Option<usize> {
+        self.entries(dir).iter().copied().find(|&index| self.nodes[index].name == name)
+    }
+
+    /// The entries of the directory at `dir`, or nothing if it isn't a directory
+    pub fn entries(&self, dir: usize) -> &[usize] {
+        match self.nodes.get(dir).map(|node| &node.kind) {
+            Some(NodeKind::Dir(entries)) => entries,
+            _ => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir(source.path().join("nested")).unwrap();
+        fs::write(source.path().join("a.txt"), "first").unwrap();
+        fs::write(source.path().join("nested/b.bin"), [0u8, 1, 2]).unwrap();
+
+        for compress in [false, true] {
+            let archive = pack(source.path(), compress).unwrap();
+            assert_eq!(archive.starts_with(&GZIP_MAGIC), compress);
+
+            let target = tempfile::tempdir().unwrap();
+            unpack(&archive, target.path()).unwrap();
+            assert_eq!(fs::read_to_string(target.path().join("a.txt")).unwrap(), "first");
+            assert_eq!(fs::read(target.path().join("nested/b.bin")).unwrap(), [0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn test_tree() {
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir_all(source.path().join("docs/old")).unwrap();
+        fs::write(source.path().join("docs/a.txt"), "first").unwrap();
+        fs::write(source.path().join("readme"), "top").unwrap();
+
+        let tree = Tree::from_archive(&pack(source.path(), true).unwrap()).unwrap();
+        let docs = tree.lookup(Tree::ROOT, "docs").unwrap();
+        let file = tree.lookup(docs, "a.txt").unwrap();
+        assert_eq!(tree.node(file).unwrap().kind, NodeKind::File(b"first".to_vec()));
+        assert_eq!(tree.node(file).unwrap().parent, docs);
+        assert!(matches!(tree.node(tree.lookup(docs, "old").unwrap()).unwrap().kind, NodeKind::Dir(_)));
+        assert_eq!(tree.entries(Tree::ROOT).len(), 2);
+        assert!(tree.entries(file).is_empty());
+        assert!(tree.lookup(Tree::ROOT, "missing").is_none());
+
+        let single = Tree::single_file("payload.txt", b"hi".to_vec());
+        assert_eq!(single.len(), 2);
+        assert!(Tree::from_archive(b"not an archive").is_err());
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert!(pack(file.path(), false).is_err());
+
+        let target = tempfile::tempdir().unwrap();
+        assert!(unpack(b"not an archive", target.path()).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde_json::{json, Value};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::keys;
+use crate::png::Png;
+use crate::report::sha256_hex;
+use crate::Result;
+
+/// The chunk type attestations are stored in: ancillary and private, but unsafe to copy,
+/// since the attestation no longer holds once the image is edited
+pub const ATTESTATION_CHUNK_TYPE: &str = "dsSE";
+
+/// The DSSE payload type of in-toto statements
+pub const PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+/// The type of in-toto statements
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+
+/// The predicate type of SLSA build provenance, the default predicate type
+pub const SLSA_PROVENANCE: &str = "https://slsa.dev/provenance/v1";
+
+/// A verified attestation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attestation {
+    /// The fingerprint of the key that signed the attestation
+    pub keyid: String,
+    pub predicate_type: String,
+    pub predicate: Value,
+}
+
+/// The digest attestations are made for: the SHA-256 of the file without its attestation
+pub fn subject_digest(png: &Png) -> String {
+    let chunks = png.chunks().iter()
+        .filter(|chunk| chunk.chunk_type() != ATTESTATION_CHUNK_TYPE)
+        .cloned()
+        .collect();
+    let mut unattested = Png::from_chunks(chunks);
+    unattested.set_trailer(png.trailer().to_vec());
+    sha256_hex(&unattested.as_bytes())
+}
+
+/// Signs an in-toto statement about `png` (named `name`) with `key` and stores it in a DSSE
+/// envelope in `png`, replacing the existing attestation.
+/// https://github.com/secure-systems-lab/dsse/blob/master/envelope.md
+pub fn attest(png: &mut Png, name: &str, predicate_type: &str, predicate: Value, key: &SigningKey) -> Result<()> {
+    let statement = json!({
+        "_type": STATEMENT_TYPE,
+        "subject": [{ "name": name, "digest": { "sha256": subject_digest(png) } }],
+        "predicateType": predicate_type,
+        "predicate": predicate,
+    });
+    let payload = serde_json::to_vec(&statement)?;
+    let signature = key.sign(&pae(PAYLOAD_TYPE, &payload));
+
+    let envelope = json!({
+        "payloadType": PAYLOAD_TYPE,
+        "payload": BASE64.encode(&payload),
+        "signatures": [{
+            "keyid": keys::fingerprint(&key.verifying_key()),
+            "sig": BASE64.encode(signature.to_bytes()),
+        }],
+    });
+
+    let chunk = Chunk::new(ChunkType::from_str(ATTESTATION_CHUNK_TYPE)?, serde_json::to_vec(&envelope)?);
+    png.chunks_mut().retain(|chunk| chunk.chunk_type() != ATTESTATION_CHUNK_TYPE);
+    png.insert_before_end(chunk);
+    Ok(())
+}
+
+/// Checks that the attestation of `png` is signed by `key` and that it is about `png`
+/// as it is now
+pub fn verify(png: &Png, key: &VerifyingKey) -> Result<Attestation> {
+    let chunk = png.chunk_by_type(ATTESTATION_CHUNK_TYPE)
+        .ok_or(format!("no attestation ({} chunk) found", ATTESTATION_CHUNK_TYPE))?;
+    let envelope: Value = serde_json::from_slice(chunk.data())
+        .map_err(|e| format!("invalid attestation envelope: {}", e))?;
+
+    if envelope["payloadType"] != PAYLOAD_TYPE {
+        return Err(format!("unsupported payload type {}", envelope["payloadType"]).into());
+    }
+    let payload = BASE64.decode(envelope["payload"].as_str().ok_or("attestation without payload")?)?;
+
+    let keyid = keys::fingerprint(key);
+    let signatures = envelope["signatures"].as_array().ok_or("attestation without signatures")?;
+    let signature = signatures.iter()
+        .find(|signature| signature["keyid"] == keyid.as_str())
+        .ok_or("the attestation isn't signed by this key")?;
+    let signature = BASE64.decode(signature["sig"].as_str().ok_or("invalid signature")?)?;
+    let signature = Signature::from_slice(&signature)?;
+    key.verify_strict(&pae(PAYLOAD_TYPE, &payload), &signature)
+        .map_err(|_| "invalid attestation signature")?;
+
+    let statement: Value = serde_json::from_slice(&payload)?;
+    if statement["_type"] != STATEMENT_TYPE {
+        return Err(format!("unsupported statement type {}", statement["_type"]).into());
+    }
+    let digest = subject_digest(png);
+    let subjects = statement["subject"].as_array().ok_or("attestation without subject")?;
+    if !subjects.iter().any(|subject| subject["digest"]["sha256"] == digest.as_str()) {
+        return Err("the image was modified after it was attested".into());
+    }
+
+    Ok(Attestation {
+        keyid,
+        predicate_type: statement["predicateType"].as_str().unwrap_or_default().to_owned(),
+        predicate: statement["predicate"].clone(),
+    })
+}
+
+/// DSSE pre-authentication encoding: the signed bytes
+fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = format!("DSSEv1 {} {} {} ", payload_type.len(), payload_type, payload.len()).into_bytes();
+    bytes.extend(payload);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    fn attested(key: &SigningKey) -> Png {
+        let mut png = Png::try_from(DICE).unwrap();
+        let predicate = json!({ "buildDefinition": { "buildType": "https://example.com/render" } });
+        attest(&mut png, "dice.png", SLSA_PROVENANCE, predicate, key).unwrap();
+        Png::try_from(&png.as_bytes()[..]).unwrap()
+    }
+
+    #[test]
+    fn test_pae() {
+        assert_eq!(pae("http://example.com/HelloWorld", b"hello world"), b"DSSEv1 29 http://example.com/HelloWorld 11 hello world");
+    }
+
+    #[test]
+    fn test_attest_and_verify() {
+        let key = keys::generate_signing_key();
+        let png = attested(&key);
+
+        let attestation = verify(&png, &key.verifying_key()).unwrap();
+        assert_eq!(attestation.keyid, keys::fingerprint(&key.verifying_key()));
+        assert_eq!(attestation.predicate_type, SLSA_PROVENANCE);
+        assert_eq!(subject_digest(&png), sha256_hex(DICE));
+    }
+
+    #[test]
+    fn test_verify_with_other_key() {
+        let png = attested(&keys::generate_signing_key());
+        assert!(verify(&png, &keys::generate_signing_key().verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_verify_modified_image() {
+        let key = keys::generate_signing_key();
+        let mut png = attested(&key);
+        png.set_trailer(b"appended later".to_vec());
+
+        let error = verify(&png, &key.verifying_key()).unwrap_err();
+        assert_eq!(error.to_string(), "the image was modified after it was attested");
+    }
+}
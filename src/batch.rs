@@ -0,0 +1,463 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::Result;
+
+/// Controls which files the recursive walker visits
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// Follows symbolic links to files and directories. Without it, symbolic links are skipped.
+    pub follow_symlinks: bool,
+    /// Visits files and directories whose name starts with a dot
+    pub include_hidden: bool,
+    /// Glob patterns, matched against paths relative to the root, of files and directories to skip
+    pub exclude: Vec<String>,
+}
+
+/// Controls how a batch run processes the files it finds
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// The number of files processed in parallel
+    pub jobs: usize,
+    /// The memory, in bytes, the files being processed may take. A file is assumed to take
+    /// [`RunOptions::MEMORY_PER_BYTE`] times its size while loaded. Fewer files are processed
+    /// in parallel when the budget is used up, and a file larger than the budget is processed alone.
+    pub max_memory: Option<u64>,
+    /// Doesn't print the path of each file and the summary, so the output of the operation
+    /// stays machine readable (failures are still reported on stderr)
+    pub quiet: bool,
+}
+
+impl RunOptions {
+    /// How many bytes of memory a byte of a file takes while it is processed: the file
+    /// contents, the parsed chunks and the output written back
+    pub const MEMORY_PER_BYTE: u64 = 3;
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions { jobs: 1, max_memory: None, quiet: false }
+    }
+}
+
+/// Finds the PNG files (by their `.png` extension) under `root`, sorted by path.
+/// Entries that can't be read, such as symbolic link loops, are reported on stderr and skipped.
+pub fn find_pngs<P: AsRef<Path>>(root: P, options: &WalkOptions) -> Result<Vec<PathBuf>> {
+    let root = root.as_ref();
+    let exclude = exclude_set(&options.exclude)?;
+
+    let visible = |entry: &DirEntry| {
+        if entry.depth() == 0 {
+            return true;
+        }
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        let symlink = entry.path_is_symlink();
+        let excluded = exclude.is_match(entry.path().strip_prefix(root).unwrap_or(entry.path()));
+        (options.include_hidden || !hidden) && (options.follow_symlinks || !symlink) && !excluded
+    };
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(root).follow_links(options.follow_symlinks).into_iter().filter_entry(visible) {
+        match entry {
+            Ok(entry) if entry.file_type().is_file() && is_png_path(entry.path()) => {
+                files.push(entry.into_path());
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("warning: {}", e),
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Returns true if `path` is a glob pattern, e.g. `assets/**/*.png`, rather than the path of
+/// an existing file or directory
+pub fn is_pattern<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    !path.exists() && path.to_string_lossy().contains(['*', '?', '[', '{'])
+}
+
+/// Expands a directory (searched like `find_pngs` does) or a glob pattern (see `is_pattern`)
+/// into the PNG files it names, sorted by path. Returns `None` for any other path, which
+/// names a single file. In patterns, `*` doesn't match `/` but `**` matches any number of
+/// directories.
+pub fn expand<P: AsRef<Path>>(path: P) -> Result<Option<Vec<PathBuf>>> {
+    let path = path.as_ref();
+    if path.is_dir() {
+        return find_pngs(path, &WalkOptions::default()).map(Some);
+    }
+    if !is_pattern(path) {
+        return Ok(None);
+    }
+
+    // Only the directories below the part of the pattern without wildcards are searched
+    let mut root = PathBuf::new();
+    let mut components = path.components().peekable();
+    while let Some(component) = components.next_if(|component| !is_pattern(component.as_os_str())) {
+        root.push(component);
+    }
+    let rest: PathBuf = components.collect();
+    let matcher = GlobBuilder::new(&rest.to_string_lossy()).literal_separator(true).build()?.compile_matcher();
+
+    let root = if root.as_os_str().is_empty() { Path::new(".") } else { &root };
+    let files = find_pngs(root, &WalkOptions::default())?.into_iter()
+        .filter(|file| file.strip_prefix(root).is_ok_and(|relative| matcher.is_match(relative)))
+        .collect();
+    Ok(Some(files))
+}
+
+/// Records the files a batch run has processed, so an interrupted run can be resumed.
+/// The checkpoint file starts with a line describing the run, followed by one processed
+/// path per line. Every line is written as soon as a file is done.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    file: File,
+    done: HashSet<PathBuf>,
+}
+
+impl Checkpoint {
+    /// Starts a new checkpoint for the run described by `run`, overwriting an existing one
+    pub fn create<P: AsRef<Path>>(path: P, run: &str) -> Result<Checkpoint> {
+        let mut file = File::create(&path)?;
+        writeln!(file, "{}", run)?;
+        Ok(Checkpoint { path: path.as_ref().to_path_buf(), file, done: HashSet::new() })
+    }
+
+    /// Continues the checkpoint of an interrupted run. Starts a new one if there is none;
+    /// fails if the existing checkpoint belongs to a different run.
+    pub fn resume<P: AsRef<Path>>(path: P, run: &str) -> Result<Checkpoint> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Checkpoint::create(path, run),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut lines = contents.lines();
+        if lines.next() != Some(run) {
+            return Err(format!("{} belongs to a different batch run", path.as_ref().display()).into());
+        }
+        let done = lines.map(PathBuf::from).collect();
+        let file = OpenOptions::new().append(true).open(&path)?;
+
+        Ok(Checkpoint { path: path.as_ref().to_path_buf(), file, done })
+    }
+
+    /// Returns true if `file` was processed before
+    pub fn is_done(&self, file: &Path) -> bool {
+        self.done.contains(file)
+    }
+
+    /// Records that `file` was processed
+    pub fn mark_done(&mut self, file: &Path) -> Result<()> {
+        writeln!(self.file, "{}", file.display())?;
+        self.file.flush()?;
+        self.done.insert(file.to_path_buf());
+        Ok(())
+    }
+
+    /// Deletes the checkpoint file once the run is complete
+    pub fn finish(self) -> Result<()> {
+        Ok(fs::remove_file(self.path)?)
+    }
+}
+
+/// Runs `operation` on every PNG file under `root`, see `run_files`
+pub fn run<P, F>(
+    root: P,
+    options: &WalkOptions,
+    run_options: &RunOptions,
+    checkpoint: Option<Checkpoint>,
+    operation: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: Fn(&Path) -> Result<()> + Sync,
+{
+    run_files(&find_pngs(root, options)?, run_options, checkpoint, operation)
+}
+
+/// Runs `operation` on every file of `files`, printing each file's path first (unless
+/// `run_options.quiet` is set).
+/// A failure doesn't stop the run; an error counting the failed files is returned at the end.
+/// With a checkpoint, files it records are skipped and every successfully processed file
+/// is added to it. The checkpoint is deleted when no file failed.
+///
+/// With more than one job, files are processed in parallel and their output may interleave.
+pub fn run_files<F>(
+    files: &[PathBuf],
+    run_options: &RunOptions,
+    checkpoint: Option<Checkpoint>,
+    operation: F,
+) -> Result<()>
+where
+    F: Fn(&Path) -> Result<()> + Sync,
+{
+    let pending: Vec<&PathBuf> = files.iter()
+        .filter(|file| !checkpoint.as_ref().is_some_and(|checkpoint| checkpoint.is_done(file)))
+        .collect();
+    let skipped = files.len() - pending.len();
+
+    let checkpoint = Mutex::new(checkpoint);
+    let budget = run_options.max_memory.map(MemoryBudget::new);
+    let (next, failed) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+    let process = |file: &Path| {
+        let reserved = match &budget {
+            Some(budget) => budget.acquire(file)?,
+            None => 0,
+        };
+
+        if !run_options.quiet {
+            println!("== {} ==", file.display());
+        }
+        let result = operation(file);
+
+        if let Some(budget) = &budget {
+            budget.release(reserved);
+        }
+        result
+    };
+
+    thread::scope(|scope| {
+        for _ in 0..run_options.jobs.max(1) {
+            scope.spawn(|| {
+                while let Some(file) = pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if let Err(e) = process(file) {
+                        eprintln!("{}: {}", file.display(), e);
+                        failed.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    if let Some(checkpoint) = checkpoint.lock().unwrap().as_mut() {
+                        if let Err(e) = checkpoint.mark_done(file) {
+                            eprintln!("warning: can't update the checkpoint: {}", e);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner();
+    if !run_options.quiet {
+        println!("Processed {} file(s), {} failed, {} skipped", pending.len(), failed, skipped);
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} file(s) failed", failed, files.len()).into());
+    }
+    if let Some(checkpoint) = checkpoint.into_inner().unwrap() {
+        checkpoint.finish()?;
+    }
+    Ok(())
+}
+
+/// Tracks the memory taken by the files being processed in parallel
+struct MemoryBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    fn new(limit: u64) -> MemoryBudget {
+        MemoryBudget { limit, used: Mutex::new(0), released: Condvar::new() }
+    }
+
+    /// Waits until the memory `file` needs is available and reserves it, returning the amount
+    /// reserved. A file needing more than the whole budget waits until nothing else is running.
+    fn acquire(&self, file: &Path) -> Result<u64> {
+        let needed = fs::metadata(file)?.len().saturating_mul(RunOptions::MEMORY_PER_BYTE);
+        if needed > self.limit {
+            eprintln!("warning: {} needs more memory than the budget, processing it alone", file.display());
+        }
+
+        let amount = needed.min(self.limit);
+        let mut used = self.used.lock().unwrap();
+        while *used + amount > self.limit {
+            used = self.released.wait(used).unwrap();
+        }
+        *used += amount;
+        Ok(amount)
+    }
+
+    fn release(&self, amount: u64) {
+        *self.used.lock().unwrap() -= amount;
+        self.released.notify_all();
+    }
+}
+
+fn is_png_path(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+fn exclude_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for path in ["a.png", "b.PNG", "notes.txt", ".hidden.png", ".cache/c.png", "vendor/d.png", "sub/e.png"] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, DICE).unwrap();
+        }
+        dir
+    }
+
+    fn names(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files.iter()
+            .map(|file| file.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_pngs() {
+        let dir = tree();
+        let files = find_pngs(dir.path(), &WalkOptions::default()).unwrap();
+        assert_eq!(names(dir.path(), files), ["a.png", "b.PNG", "sub/e.png", "vendor/d.png"]);
+    }
+
+    #[test]
+    fn test_include_hidden_and_exclude() {
+        let dir = tree();
+        let options = WalkOptions {
+            include_hidden: true,
+            exclude: vec!["vendor".to_owned(), "**/e.png".to_owned()],
+            ..WalkOptions::default()
+        };
+        let files = find_pngs(dir.path(), &options).unwrap();
+        assert_eq!(names(dir.path(), files), [".cache/c.png", ".hidden.png", "a.png", "b.PNG"]);
+    }
+
+    #[test]
+    fn test_invalid_exclude_pattern() {
+        let options = WalkOptions { exclude: vec!["[".to_owned()], ..WalkOptions::default() };
+        assert!(find_pngs(".", &options).is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = tree();
+        let root = dir.path();
+
+        let files = expand(root).unwrap().unwrap();
+        assert_eq!(names(root, files), ["a.png", "b.PNG", "sub/e.png", "vendor/d.png"]);
+        let files = expand(root.join("*.png")).unwrap().unwrap();
+        assert_eq!(names(root, files), ["a.png"]);
+        let files = expand(root.join("**/*.png")).unwrap().unwrap();
+        assert_eq!(names(root, files), ["a.png", "sub/e.png", "vendor/d.png"]);
+        let files = expand(root.join("{sub,vendor}/*.png")).unwrap().unwrap();
+        assert_eq!(names(root, files), ["sub/e.png", "vendor/d.png"]);
+
+        assert!(expand(root.join("a.png")).unwrap().is_none());
+        assert!(expand(root.join("missing.png")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let dir = tree();
+        let checkpoint_path = dir.path().join("checkpoint");
+        let files = find_pngs(dir.path(), &WalkOptions::default()).unwrap();
+
+        let mut checkpoint = Checkpoint::create(&checkpoint_path, "verify").unwrap();
+        checkpoint.mark_done(&files[0]).unwrap();
+        checkpoint.mark_done(&files[1]).unwrap();
+        drop(checkpoint);
+
+        assert!(Checkpoint::resume(&checkpoint_path, "print").is_err());
+
+        let checkpoint = Checkpoint::resume(&checkpoint_path, "verify").unwrap();
+        let visited = Mutex::new(Vec::new());
+        run(dir.path(), &WalkOptions::default(), &RunOptions::default(), Some(checkpoint), |file| {
+            visited.lock().unwrap().push(file.to_path_buf());
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(visited.into_inner().unwrap(), files[2..]);
+        assert!(!checkpoint_path.exists());
+    }
+
+    #[test]
+    fn test_checkpoint_kept_on_failure() {
+        let dir = tree();
+        let checkpoint_path = dir.path().join("checkpoint");
+        let checkpoint = Checkpoint::create(&checkpoint_path, "verify").unwrap();
+
+        let result = run(dir.path(), &WalkOptions::default(), &RunOptions::default(), Some(checkpoint), |file| {
+            if file.ends_with("sub/e.png") {
+                return Err("failed".into());
+            }
+            Ok(())
+        });
+
+        assert!(result.is_err());
+        let contents = fs::read_to_string(&checkpoint_path).unwrap();
+        assert_eq!(contents.lines().count(), 4);
+        assert!(!contents.contains("e.png"));
+    }
+
+    #[test]
+    fn test_parallel_run_within_memory_budget() {
+        let dir = tree();
+        // Room for a single file at a time
+        let run_options = RunOptions { jobs: 4, max_memory: Some(DICE.len() as u64 * 4), ..RunOptions::default() };
+        let (running, max_running) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let visited = Mutex::new(Vec::new());
+
+        run(dir.path(), &WalkOptions::default(), &run_options, None, |file| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(std::time::Duration::from_millis(10));
+            visited.lock().unwrap().push(file.to_path_buf());
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }).unwrap();
+
+        let mut visited = visited.into_inner().unwrap();
+        visited.sort();
+        assert_eq!(visited, find_pngs(dir.path(), &WalkOptions::default()).unwrap());
+        assert_eq!(max_running.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_file_larger_than_memory_budget() {
+        let dir = tree();
+        let run_options = RunOptions { jobs: 2, max_memory: Some(1), ..RunOptions::default() };
+        run(dir.path(), &WalkOptions::default(), &run_options, None, |_| Ok(())).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        let dir = tree();
+        std::os::unix::fs::symlink(dir.path().join("sub"), dir.path().join("link")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("sub/loop")).unwrap();
+
+        let files = find_pngs(dir.path(), &WalkOptions::default()).unwrap();
+        assert_eq!(files.len(), 4);
+
+        let options = WalkOptions { follow_symlinks: true, ..WalkOptions::default() };
+        let files = find_pngs(dir.path(), &options).unwrap();
+        assert_eq!(names(dir.path(), files), ["a.png", "b.PNG", "link/e.png", "sub/e.png", "vendor/d.png"]);
+    }
+}
@@ -1,10 +1,14 @@
 use std::convert::TryFrom;
 use std::fmt;
-use std::io::{BufReader, Read};
+use std::io::{self, BufReader, Read, Write};
 
 use crate::chunk_type::ChunkType;
-use crate::{Error, Result};
+use crate::error::PngMeError;
+use crate::keys;
+use crate::Result;
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use crc::{self, Crc};
 
 const CRC_HDLC: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
@@ -20,20 +24,39 @@ pub struct Chunk {
 }
 
 impl Chunk {
+    /// The maximum length of the data portion of a chunk allowed by the PNG spec
+    pub const MAX_LENGTH: u32 = i32::MAX as u32;
+
     /// Creates a new chunk of type `ChunkType` containing  `data`
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
-        let chained_data: Vec<_> = chunk_type.bytes()
-            .iter()
-            .copied()
-            .chain(data.iter().copied())
-            .collect();
+        let crc = Self::compute_crc(&chunk_type, &data);
+        Chunk::from_parts_unchecked(chunk_type, data, crc)
+    }
 
-        let crc = CRC_HDLC.checksum(&chained_data);
+    /// Creates a chunk from already known parts without verifying `crc`.
+    /// Only meant for trusted input, e.g. chunks whose CRC has just been checked.
+    pub fn from_parts_unchecked(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Chunk {
         let length = data.len() as u32;
-
         Chunk { length, chunk_type, data, crc }
     }
 
+    /// Replaces the stored CRC with the one computed from the chunk type and data, e.g. for a
+    /// chunk read with a damaged CRC. Returns true if the stored CRC was wrong.
+    pub fn recompute_crc(&mut self) -> bool {
+        let crc = Self::compute_crc(&self.chunk_type, &self.data);
+        let changed = crc != self.crc;
+        self.crc = crc;
+        changed
+    }
+
+    /// Computes the CRC of the chunk type and data without copying them into one buffer
+    pub(crate) fn compute_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+        let mut digest = CRC_HDLC.digest();
+        digest.update(&chunk_type.bytes());
+        digest.update(data);
+        digest.finalize()
+    }
+
     /// The length of the data portion of this chunk
     pub fn length(&self) -> u32 {
         self.length
@@ -54,63 +77,130 @@ impl Chunk {
         self.crc
     }
 
+    /// The length, type and CRC of this chunk, without its data
+    pub fn summary(&self) -> ChunkSummary {
+        ChunkSummary { length: self.length, chunk_type: self.chunk_type, crc: self.crc }
+    }
+
     /// Returns the data stored in this chunk as a `String`. This function will return an error
     /// if the stored data is not valid UTF-8.
     pub fn data_as_string(&self) -> Result<String> {
         Ok(String::from_utf8(self.data.to_owned())?)
     }
 
-    /// Returns this chunk as a byte sequences described by the PNG spec.
-    /// The following data is included in this byte sequence in order:
-    /// 1. Length of the data *(4 bytes)*
-    /// 2. Chunk type *(4 bytes)*
-    /// 3. The data itself *(`length` bytes)*
-    /// 4. The CRC of the chunk type and data *(4 bytes)*
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let length = u32::to_be_bytes(self.length);
-        let crc = u32::to_be_bytes(self.crc);
-
-        length.into_iter()
-            .chain(self.chunk_type.bytes().into_iter())
-            .chain(self.data.iter().copied())
-            .chain(crc.into_iter())
-            .collect()
+    /// Returns the data stored in this chunk as lowercase hex, for data that isn't text
+    pub fn data_as_hex(&self) -> String {
+        keys::to_hex(&self.data)
     }
 
-}
-
-impl TryFrom<&[u8]> for Chunk {
-    type Error = Error;
+    /// Returns the data stored in this chunk in base64, for data that isn't text
+    pub fn data_as_base64(&self) -> String {
+        BASE64.encode(&self.data)
+    }
 
-    fn try_from(value: &[u8]) -> Result<Self> {
-        let mut reader = BufReader::new(value);
+    /// Reads a single chunk from `reader`, returning the chunk and the number of bytes consumed.
+    /// The data is read incrementally, so a bogus length can't cause a huge allocation up front.
+    pub fn read_from<R: Read>(reader: &mut R) -> std::result::Result<(Chunk, usize), PngMeError> {
         let mut buffer: [u8; 4] = [0; 4];
 
         reader.read_exact(&mut buffer)?;
         let length = u32::from_be_bytes(buffer);
+        if length > Self::MAX_LENGTH {
+            return Err(PngMeError::ChunkTooLong);
+        }
 
         reader.read_exact(&mut buffer)?;
         let chunk_type = ChunkType::try_from(buffer)?;
 
-        let mut data = vec![0u8; length as usize];
-        reader.read_exact(&mut data)?;
+        let chunk = Chunk::read_body(reader, chunk_type, length)?;
+        Ok((chunk, 12 + length as usize))
+    }
+
+    /// Reads the data and CRC of a chunk whose length and type have already been read,
+    /// checking the CRC
+    pub(crate) fn read_body<R: Read>(reader: &mut R, chunk_type: ChunkType, length: u32) -> std::result::Result<Chunk, PngMeError> {
+        let mut data = Vec::new();
+        reader.take(length as u64).read_to_end(&mut data)?;
+        if data.len() != length as usize {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
 
+        let mut buffer: [u8; 4] = [0; 4];
         reader.read_exact(&mut buffer)?;
         let crc = u32::from_be_bytes(buffer);
 
-        let chunk = Chunk::new(chunk_type, data);
-
-        if chunk.crc != crc {
-            Err("invalid chunk CRC".into())
-        } else {
-            Ok(chunk)
+        let expected = Self::compute_crc(&chunk_type, &data);
+        if expected != crc {
+            return Err(PngMeError::CrcMismatch { expected, actual: crc });
         }
+
+        Ok(Chunk::from_parts_unchecked(chunk_type, data, crc))
+    }
+
+    /// Returns this chunk as a byte sequences described by the PNG spec.
+    /// The following data is included in this byte sequence in order:
+    /// 1. Length of the data *(4 bytes)*
+    /// 2. Chunk type *(4 bytes)*
+    /// 3. The data itself *(`length` bytes)*
+    /// 4. The CRC of the chunk type and data *(4 bytes)*
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        self.extend_bytes(&mut bytes);
+        bytes
+    }
+
+    /// The number of bytes `as_bytes` returns: the data plus 12 bytes of length, type and CRC
+    pub fn encoded_len(&self) -> usize {
+        12 + self.data.len()
+    }
+
+    /// Appends the bytes of `as_bytes` to `bytes`, so one buffer can be reused for many chunks
+    pub fn extend_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.length.to_be_bytes());
+        bytes.extend_from_slice(&self.chunk_type.bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(&self.crc.to_be_bytes());
+    }
+
+    /// Writes the bytes of `as_bytes` to `writer` without building them in memory first
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.length.to_be_bytes())?;
+        writer.write_all(&self.chunk_type.bytes())?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&self.crc.to_be_bytes())
+    }
+
+}
+
+impl TryFrom<&[u8]> for Chunk {
+    type Error = PngMeError;
+
+    fn try_from(value: &[u8]) -> std::result::Result<Self, PngMeError> {
+        let (chunk, _) = Chunk::read_from(&mut BufReader::new(value))?;
+        Ok(chunk)
     }
 }
 
 impl fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Chunk (Type: {}; CRC: {}; Length: {})", self.chunk_type, self.crc, self.length)
+        self.summary().fmt(f)
+    }
+}
+
+/// The length, type and CRC of a chunk, e.g. of a file listed one chunk at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkSummary {
+    pub length: u32,
+    pub chunk_type: ChunkType,
+    pub crc: u32,
+}
+
+impl fmt::Display for ChunkSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.chunk_type.description() {
+            Some(description) => write!(f, "Chunk (Type: {} — {}; CRC: {}; Length: {})", self.chunk_type, description, self.crc, self.length),
+            None => write!(f, "Chunk (Type: {}; CRC: {}; Length: {})", self.chunk_type, self.crc, self.length),
+        }
     }
 }
 
@@ -167,6 +257,30 @@ mod tests {
         assert_eq!(chunk_string, expected_chunk_string);
     }
 
+    #[test]
+    fn test_recompute_crc() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let mut chunk = Chunk::from_parts_unchecked(chunk_type, b"data".to_vec(), 0);
+        assert!(chunk.recompute_crc());
+        assert_eq!(chunk.crc(), Chunk::new(chunk_type, b"data".to_vec()).crc());
+        assert!(!chunk.recompute_crc());
+    }
+
+    #[test]
+    fn test_chunk_display() {
+        assert_eq!(testing_chunk().to_string(), "Chunk (Type: RuSt; CRC: 2882656334; Length: 42)");
+        let chunk = Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]);
+        assert_eq!(chunk.to_string(), "Chunk (Type: IEND — image trailer; CRC: 2923585666; Length: 0)");
+    }
+
+    #[test]
+    fn test_chunk_hex_and_base64() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![0, 0xff, b'h', b'i']);
+        assert!(chunk.data_as_string().is_err());
+        assert_eq!(chunk.data_as_hex(), "00ff6869");
+        assert_eq!(chunk.data_as_base64(), "AP9oaQ==");
+    }
+
     #[test]
     fn test_chunk_crc() {
         let chunk = testing_chunk();
@@ -218,7 +332,47 @@ mod tests {
 
         let chunk = Chunk::try_from(chunk_data.as_ref());
 
-        assert!(chunk.is_err());
+        let err = chunk.unwrap_err();
+        assert!(matches!(err, PngMeError::CrcMismatch { expected: 2882656334, actual } if actual == crc));
+    }
+
+    #[test]
+    fn test_from_parts_unchecked() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let data = "This is where your secret message will be!".as_bytes().to_vec();
+
+        let chunk = Chunk::from_parts_unchecked(chunk_type, data.clone(), 2882656334);
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.crc(), Chunk::new(chunk_type, data.clone()).crc());
+
+        // The CRC is taken as is
+        let chunk = Chunk::from_parts_unchecked(chunk_type, data, 1);
+        assert_eq!(chunk.crc(), 1);
+    }
+
+    #[test]
+    fn test_read_from() {
+        let first = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"first".to_vec());
+        let second = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"second".to_vec());
+        let bytes: Vec<u8> = first.as_bytes().into_iter().chain(second.as_bytes()).collect();
+        let mut reader = &bytes[..];
+
+        let (chunk, read) = Chunk::read_from(&mut reader).unwrap();
+        assert_eq!(chunk.data(), b"first");
+        assert_eq!(read, 12 + 5);
+
+        let (chunk, read) = Chunk::read_from(&mut reader).unwrap();
+        assert_eq!(chunk.data(), b"second");
+        assert_eq!(read, 12 + 6);
+
+        assert!(Chunk::read_from(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_read_from_truncated() {
+        // Claims 2^31 - 1 bytes of data but only has a few
+        let bytes = [127, 255, 255, 255, 82, 117, 83, 116, 1, 2, 3];
+        assert!(Chunk::read_from(&mut &bytes[..]).is_err());
     }
 
     #[test]
@@ -241,4 +395,17 @@ mod tests {
         
         let _chunk_string = format!("{}", chunk);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_serialization_into_buffers() {
+        let chunk = testing_chunk();
+        let mut bytes = b"prefix".to_vec();
+        chunk.extend_bytes(&mut bytes);
+        assert_eq!(&bytes[6..], &chunk.as_bytes()[..]);
+
+        let mut written = Vec::new();
+        chunk.write_to(&mut written).unwrap();
+        assert_eq!(written, chunk.as_bytes());
+        assert_eq!(chunk.encoded_len(), written.len());
+    }
+}
@@ -2,27 +2,120 @@ use std::fmt;
 use std::convert::TryFrom;
 use std::str::{self, FromStr};
 
-use crate::{Error, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+
+use crate::error::PngMeError;
+use crate::Result;
+
+/// The chunk types defined by the PNG spec and the registered extensions to it, with what
+/// they hold
+/// https://www.w3.org/TR/png-3/#11Chunks
+pub const REGISTERED_CHUNK_TYPES: [(&str, &str); 33] = [
+    ("IHDR", "image header"),
+    ("PLTE", "palette"),
+    ("IDAT", "image data"),
+    ("IEND", "image trailer"),
+    ("acTL", "animation control"),
+    ("fcTL", "frame control"),
+    ("fdAT", "frame data"),
+    ("cHRM", "primary chromaticities and white point"),
+    ("cICP", "coding-independent code points"),
+    ("gAMA", "image gamma"),
+    ("iCCP", "embedded ICC profile"),
+    ("mDCV", "mastering display color volume"),
+    ("cLLI", "content light level information"),
+    ("sBIT", "significant bits"),
+    ("sRGB", "standard RGB color space"),
+    ("tEXt", "textual data"),
+    ("zTXt", "compressed textual data"),
+    ("iTXt", "international textual data"),
+    ("bKGD", "background color"),
+    ("hIST", "image histogram"),
+    ("tRNS", "transparency"),
+    ("eXIf", "Exif metadata"),
+    ("pHYs", "physical pixel dimensions"),
+    ("sPLT", "suggested palette"),
+    ("tIME", "image last-modification time"),
+    ("oFFs", "image offset"),
+    ("pCAL", "pixel calibration"),
+    ("sCAL", "physical scale of image subject"),
+    ("gIFg", "GIF graphic control extension"),
+    ("gIFt", "GIF plain text extension"),
+    ("gIFx", "GIF application extension"),
+    ("sTER", "stereo image indicator"),
+    ("dSIG", "digital signature"),
+];
 
 /// A validated PNG chunk type. See the PNG spec for more details.
 /// http://www.libpng.org/pub/png/spec/1.2/PNG-Structure.html
-#[derive(Debug,Clone,PartialEq,Eq)]
-pub struct ChunkType(String);
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,PartialOrd,Ord)]
+pub struct ChunkType([u8; 4]);
 
 impl ChunkType {
+    /// Common registered chunk types, to compare with e.g. `chunk.chunk_type() == &ChunkType::IEND`
+    pub const IHDR: ChunkType = ChunkType(*b"IHDR");
+    pub const PLTE: ChunkType = ChunkType(*b"PLTE");
+    pub const IDAT: ChunkType = ChunkType(*b"IDAT");
+    pub const IEND: ChunkType = ChunkType(*b"IEND");
+    pub const TEXT: ChunkType = ChunkType(*b"tEXt");
+    pub const ZTXT: ChunkType = ChunkType(*b"zTXt");
+    pub const ITXT: ChunkType = ChunkType(*b"iTXt");
+
     /// Creates a new ChunkType. Checks the basic properties that a ChunkType must have according to the PNG spec:
     /// the ChunkType consists of 4 characters and all four bytes are represented by the characters A-Z or a-z.
-    fn build(chunk_type: String) -> Result<ChunkType> {
-        if chunk_type.len() == 4 && chunk_type.chars().all(|c| c.is_ascii_alphabetic()) {
-            Ok(ChunkType(chunk_type))
+    fn build(chunk_type: &[u8]) -> std::result::Result<ChunkType, PngMeError> {
+        match <[u8; 4]>::try_from(chunk_type) {
+            Ok(bytes) if bytes.iter().all(u8::is_ascii_alphabetic) => Ok(ChunkType(bytes)),
+            _ => Err(PngMeError::InvalidChunkType),
+        }
+    }
+
+    /// Creates a ChunkType from raw bytes, validating them like `TryFrom<[u8; 4]>` does.
+    pub fn new(bytes: [u8; 4]) -> Result<ChunkType> {
+        Ok(ChunkType::try_from(bytes)?)
+    }
+
+    /// Returns a copy of this ChunkType with the ancillary bit (case of the first byte) set as requested
+    pub fn with_ancillary(self, ancillary: bool) -> ChunkType {
+        self.with_property_bit(0, ancillary)
+    }
+
+    /// Returns a copy of this ChunkType with the private bit (case of the second byte) set as requested
+    pub fn with_private(self, private: bool) -> ChunkType {
+        self.with_property_bit(1, private)
+    }
+
+    /// Returns a copy of this ChunkType with the safe-to-copy bit (case of the fourth byte) set as requested
+    pub fn with_safe_to_copy(self, safe_to_copy: bool) -> ChunkType {
+        self.with_property_bit(3, safe_to_copy)
+    }
+
+    /// Sets or clears the property bit (bit 5, lowercase) of the byte at `index`
+    fn with_property_bit(self, index: usize, set: bool) -> ChunkType {
+        let mut bytes = self.bytes();
+        if set {
+            bytes[index] |= 0x20;
         } else {
-            Err("invalid chunk type".into())
+            bytes[index] &= !0x20;
         }
+        ChunkType(bytes)
+    }
+
+    /// Returns true if `other` names this chunk type when case is ignored, e.g. `rust` for `ruSt`
+    pub fn eq_ignore_case(&self, other: &str) -> bool {
+        self.0.eq_ignore_ascii_case(other.as_bytes())
     }
 
     /// Returns the raw bytes contained in this chunk
     pub fn bytes(&self) -> [u8; 4] {
-        self.0.as_bytes().try_into().unwrap()
+        self.0
+    }
+
+    /// Returns the four letters of this chunk type as a string slice
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters get past `build`
+        str::from_utf8(&self.0).unwrap_or_default()
     }
 
     /// Returns true if the reserved byte is valid. Other properties is is validated during construction.
@@ -32,22 +125,35 @@ impl ChunkType {
 
     /// Returns the property state of the first byte as described in the PNG spec
     pub fn is_critical(&self) -> bool {
-        Self::fifth_bit_is_zero(self.0.as_bytes()[0])
+        Self::fifth_bit_is_zero(self.0[0])
     }
 
     /// Returns the property state of the second byte as described in the PNG spec
     pub fn is_public(&self) -> bool {
-        Self::fifth_bit_is_zero(self.0.as_bytes()[1])
+        Self::fifth_bit_is_zero(self.0[1])
+    }
+
+    /// Returns true if this chunk type is defined by the PNG spec or a registered extension
+    pub fn is_registered(&self) -> bool {
+        self.description().is_some()
+    }
+
+    /// What a chunk of this type holds according to the PNG spec, e.g. "image header" for
+    /// `IHDR`, or `None` if the type isn't registered
+    pub fn description(&self) -> Option<&'static str> {
+        REGISTERED_CHUNK_TYPES.iter()
+            .find(|(chunk_type, _)| chunk_type.as_bytes() == self.0)
+            .map(|&(_, description)| description)
     }
 
     /// Returns the property state of the third byte as described in the PNG spec
     pub fn is_reserved_bit_valid(&self) -> bool {
-        Self::fifth_bit_is_zero(self.0.as_bytes()[2])
+        Self::fifth_bit_is_zero(self.0[2])
     }
 
     /// Returns the property state of the fourth byte as described in the PNG spec
     pub fn is_safe_to_copy(&self) -> bool {
-        !Self::fifth_bit_is_zero(self.0.as_bytes()[3])
+        !Self::fifth_bit_is_zero(self.0[3])
     }
 
     /// Check the property state of byte
@@ -58,24 +164,82 @@ impl ChunkType {
 
 impl fmt::Display for ChunkType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for ChunkType {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<ChunkType> for str {
+    fn eq(&self, other: &ChunkType) -> bool {
+        self.as_bytes() == other.0
+    }
+}
+
+impl PartialEq<ChunkType> for String {
+    fn eq(&self, other: &ChunkType) -> bool {
+        self.as_bytes() == other.0
+    }
+}
+
+impl AsRef<[u8]> for ChunkType {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
     }
 }
 
 impl TryFrom<[u8; 4]> for ChunkType {
-    type Error = Error;
+    type Error = PngMeError;
 
-    fn try_from(value: [u8; 4]) -> Result<Self> {
-        let chunk_type = str::from_utf8(&value[..])?;
-        ChunkType::from_str(chunk_type)
+    fn try_from(value: [u8; 4]) -> std::result::Result<Self, PngMeError> {
+        ChunkType::build(&value)
     }
 }
 
 impl FromStr for ChunkType {
-    type Err = Error;
+    type Err = PngMeError;
 
-    fn from_str(s: &str) -> Result<Self> {
-        ChunkType::build(s.to_owned())
+    fn from_str(s: &str) -> std::result::Result<Self, PngMeError> {
+        ChunkType::build(s.as_bytes())
+    }
+}
+
+/// Matches a family of chunk types, e.g. every `ru??` chunk
+#[derive(Debug, Clone)]
+pub enum ChunkTypePattern {
+    /// A glob: `?` matches one letter, `*` any letters, `[a-z]` a set of letters
+    Glob(GlobMatcher),
+    /// A regular expression, matched against the four letters
+    Regex(Regex),
+}
+
+impl ChunkTypePattern {
+    /// Returns true if `pattern` uses glob syntax rather than naming a single chunk type
+    pub fn is_glob(pattern: &str) -> bool {
+        pattern.contains(['?', '*', '['])
+    }
+
+    /// Compiles a glob pattern
+    pub fn glob(pattern: &str, ignore_case: bool) -> Result<ChunkTypePattern> {
+        let glob = GlobBuilder::new(pattern).case_insensitive(ignore_case).build()?;
+        Ok(ChunkTypePattern::Glob(glob.compile_matcher()))
+    }
+
+    /// Compiles a regular expression
+    pub fn regex(pattern: &str, ignore_case: bool) -> Result<ChunkTypePattern> {
+        Ok(ChunkTypePattern::Regex(RegexBuilder::new(pattern).case_insensitive(ignore_case).build()?))
+    }
+
+    /// Returns true if `chunk_type` matches the pattern
+    pub fn matches(&self, chunk_type: &ChunkType) -> bool {
+        match self {
+            ChunkTypePattern::Glob(glob) => glob.is_match(chunk_type.as_str()),
+            ChunkTypePattern::Regex(regex) => regex.is_match(chunk_type.as_str()),
+        }
     }
 }
 
@@ -124,6 +288,20 @@ mod tests {
         assert!(!chunk.is_public());
     }
 
+    #[test]
+    pub fn test_chunk_type_is_registered() {
+        assert!(ChunkType::from_str("eXIf").unwrap().is_registered());
+        assert!(!ChunkType::from_str("iDOT").unwrap().is_registered());
+        assert!(!ChunkType::from_str("exif").unwrap().is_registered());
+    }
+
+    #[test]
+    pub fn test_chunk_type_description() {
+        assert_eq!(ChunkType::IHDR.description(), Some("image header"));
+        assert_eq!(ChunkType::TEXT.description(), Some("textual data"));
+        assert_eq!(ChunkType::from_str("ruSt").unwrap().description(), None);
+    }
+
     #[test]
     pub fn test_chunk_type_is_reserved_bit_valid() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -160,7 +338,30 @@ mod tests {
         assert!(!chunk.is_valid());
 
         let chunk = ChunkType::from_str("Ru1t");
-        assert!(chunk.is_err());
+        assert!(matches!(chunk.unwrap_err(), PngMeError::InvalidChunkType));
+    }
+
+    #[test]
+    pub fn test_chunk_type_new() {
+        assert_eq!(ChunkType::new(*b"RuSt").unwrap(), ChunkType::from_str("RuSt").unwrap());
+        assert!(ChunkType::new(*b"Ru1t").is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_type_property_setters() {
+        let chunk = ChunkType::from_str("RUST").unwrap()
+            .with_ancillary(true)
+            .with_private(true)
+            .with_safe_to_copy(true);
+
+        assert_eq!(&chunk.to_string(), "ruSt");
+        assert!(!chunk.is_critical());
+        assert!(!chunk.is_public());
+        assert!(chunk.is_safe_to_copy());
+        assert!(chunk.is_valid());
+
+        let chunk = chunk.with_ancillary(false).with_private(false).with_safe_to_copy(false);
+        assert_eq!(&chunk.to_string(), "RUST");
     }
 
     #[test]
@@ -176,4 +377,35 @@ mod tests {
         let _chunk_string = format!("{}", chunk_type_1);
         let _are_chunks_equal = chunk_type_1 == chunk_type_2;
     }
+
+    #[test]
+    pub fn test_chunk_type_copy_ord_as_ref() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let copy = chunk_type;
+        assert_eq!(chunk_type, copy);
+        assert_eq!(chunk_type.as_ref(), b"ruSt");
+        assert_eq!(chunk_type.as_str(), "ruSt");
+
+        let mut types: Vec<ChunkType> = ["tEXt", "IHDR", "IDAT"].iter().map(|t| t.parse().unwrap()).collect();
+        types.sort();
+        assert_eq!(types.iter().map(ChunkType::to_string).collect::<Vec<_>>(), ["IDAT", "IHDR", "tEXt"]);
+        assert!(ChunkType::try_from(*b"ru5t").is_err());
+    }
+
+    #[test]
+    fn test_patterns() {
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        let idat = ChunkType::from_str("IDAT").unwrap();
+
+        let glob = ChunkTypePattern::glob("ru??", false).unwrap();
+        assert!(glob.matches(&rust) && !glob.matches(&idat));
+        assert!(!ChunkTypePattern::glob("RU??", false).unwrap().matches(&rust));
+        assert!(ChunkTypePattern::glob("RU??", true).unwrap().matches(&rust));
+
+        let regex = ChunkTypePattern::regex("^[a-z]{2}", false).unwrap();
+        assert!(regex.matches(&rust) && !regex.matches(&idat));
+        assert!(ChunkTypePattern::regex("[", false).is_err());
+
+        assert!(ChunkTypePattern::is_glob("ru??") && !ChunkTypePattern::is_glob("ruSt"));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,245 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::icc::IccProfile;
+use crate::image::ImageHeader;
+use crate::png::Png;
+use crate::text::{TextChunk, TEXT_CHUNK_TYPES};
+use crate::Result;
+
+/// A chunk format with a typed representation, e.g. `ImageHeader` for `IHDR`.
+/// `Png::chunk_as` decodes chunks of any type implementing it, see `CodecRegistry::chunk_as`.
+pub trait ChunkFormat: Sized + fmt::Debug + 'static {
+    /// The chunk types storing this format
+    const CHUNK_TYPES: &'static [&'static str];
+
+    /// Parses the chunk
+    fn decode(chunk: &Chunk) -> Result<Self>;
+
+    /// Builds the chunk storing this value
+    fn encode(&self) -> Result<Chunk>;
+}
+
+/// The object safe counterpart of `ChunkFormat`, so codecs of different types can be stored
+/// together in a `CodecRegistry`. Implemented by `Codec<T>` for every `ChunkFormat`.
+pub trait ChunkCodec {
+    /// The chunk types this codec decodes
+    fn chunk_types(&self) -> &'static [&'static str];
+
+    /// The type of the values `decode` returns
+    fn format(&self) -> TypeId;
+
+    /// Parses the chunk, returning the typed value (downcast it to the format type)
+    fn decode(&self, chunk: &Chunk) -> Result<Box<dyn Any>>;
+
+    /// Parses the chunk and formats the value for humans
+    fn describe(&self, chunk: &Chunk) -> Result<String>;
+}
+
+/// The `ChunkCodec` of a `ChunkFormat`
+pub struct Codec<T>(PhantomData<T>);
+
+impl<T> Default for Codec<T> {
+    fn default() -> Self {
+        Codec(PhantomData)
+    }
+}
+
+impl<T: ChunkFormat> ChunkCodec for Codec<T> {
+    fn chunk_types(&self) -> &'static [&'static str] {
+        T::CHUNK_TYPES
+    }
+
+    fn format(&self) -> TypeId {
+        TypeId::of::<T>()
+    }
+
+    fn decode(&self, chunk: &Chunk) -> Result<Box<dyn Any>> {
+        Ok(Box::new(T::decode(chunk)?))
+    }
+
+    fn describe(&self, chunk: &Chunk) -> Result<String> {
+        Ok(format!("{:?}", T::decode(chunk)?))
+    }
+}
+
+/// Maps chunk types to the codecs that decode them. `CodecRegistry::default()` knows the
+/// standard chunks pngme understands; crates storing their own private chunk formats can
+/// register codecs for them.
+pub struct CodecRegistry {
+    codecs: HashMap<ChunkType, Box<dyn ChunkCodec>>,
+}
+
+impl CodecRegistry {
+    /// A registry without any codec
+    pub fn new() -> CodecRegistry {
+        CodecRegistry { codecs: HashMap::new() }
+    }
+
+    /// A registry with the codec of `T` only, see `Png::chunk_as`
+    pub fn of<T: ChunkFormat>() -> Result<CodecRegistry> {
+        let mut registry = CodecRegistry::new();
+        registry.register::<T>()?;
+        Ok(registry)
+    }
+
+    /// Registers the codec of a `ChunkFormat`, replacing the codecs of its chunk types.
+    /// Fails if one of its chunk types isn't valid.
+    pub fn register<T: ChunkFormat>(&mut self) -> Result<()> {
+        for chunk_type in T::CHUNK_TYPES {
+            self.register_codec(ChunkType::from_str(chunk_type)?, Box::new(Codec::<T>::default()));
+        }
+        Ok(())
+    }
+
+    /// Registers a codec for `chunk_type`, replacing the existing one
+    pub fn register_codec(&mut self, chunk_type: ChunkType, codec: Box<dyn ChunkCodec>) {
+        self.codecs.insert(chunk_type, codec);
+    }
+
+    /// The codec decoding `chunk_type`, if any
+    pub fn codec(&self, chunk_type: &ChunkType) -> Option<&dyn ChunkCodec> {
+        self.codecs.get(chunk_type).map(|codec| codec.as_ref())
+    }
+
+    /// Decodes `chunk`, or returns `None` if no codec knows its type
+    pub fn decode(&self, chunk: &Chunk) -> Option<Result<Box<dyn Any>>> {
+        Some(self.codec(chunk.chunk_type())?.decode(chunk))
+    }
+
+    /// Describes `chunk`, or returns `None` if no codec knows its type
+    pub fn describe(&self, chunk: &Chunk) -> Option<Result<String>> {
+        Some(self.codec(chunk.chunk_type())?.describe(chunk))
+    }
+
+    /// Decodes the first chunk of `png` whose type has a codec of format `T` registered, which
+    /// includes chunk types registered for `T` with `register_codec`. Returns `None` if there
+    /// is no such chunk.
+    pub fn chunk_as<T: ChunkFormat>(&self, png: &Png) -> Option<Result<T>> {
+        let (chunk, codec) = png.chunks().iter().find_map(|chunk| {
+            let codec = self.codec(chunk.chunk_type()).filter(|codec| codec.format() == TypeId::of::<T>())?;
+            Some((chunk, codec))
+        })?;
+        Some(codec.decode(chunk).and_then(|value| match value.downcast::<T>() {
+            Ok(value) => Ok(*value),
+            Err(_) => Err(format!("the codec of {} returned another type", chunk.chunk_type()).into()),
+        }))
+    }
+}
+
+impl Default for CodecRegistry {
+    /// A registry of the built-in codecs: `IHDR`, text chunks and `iCCP`
+    fn default() -> Self {
+        let mut registry = CodecRegistry::new();
+        registry.register::<ImageHeader>().expect("valid built-in chunk types");
+        registry.register::<TextChunk>().expect("valid built-in chunk types");
+        registry.register::<IccProfile>().expect("valid built-in chunk types");
+        registry
+    }
+}
+
+impl ChunkFormat for ImageHeader {
+    const CHUNK_TYPES: &'static [&'static str] = &["IHDR"];
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        ImageHeader::from_data(chunk.data())
+    }
+
+    fn encode(&self) -> Result<Chunk> {
+        Ok(self.to_chunk())
+    }
+}
+
+impl ChunkFormat for TextChunk {
+    const CHUNK_TYPES: &'static [&'static str] = &TEXT_CHUNK_TYPES;
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        TextChunk::from_chunk(chunk)
+    }
+
+    fn encode(&self) -> Result<Chunk> {
+        self.to_chunk()
+    }
+}
+
+impl ChunkFormat for IccProfile {
+    const CHUNK_TYPES: &'static [&'static str] = &["iCCP"];
+
+    fn decode(chunk: &Chunk) -> Result<Self> {
+        IccProfile::from_chunk(chunk)
+    }
+
+    fn encode(&self) -> Result<Chunk> {
+        self.to_chunk()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    /// A private chunk format, as an external crate would define it
+    #[derive(Debug, PartialEq)]
+    struct Level(u16);
+
+    impl ChunkFormat for Level {
+        const CHUNK_TYPES: &'static [&'static str] = &["lvLe"];
+
+        fn decode(chunk: &Chunk) -> Result<Self> {
+            Ok(Level(u16::from_be_bytes(chunk.data().try_into()?)))
+        }
+
+        fn encode(&self) -> Result<Chunk> {
+            Ok(Chunk::new(ChunkType::from_str("lvLe")?, self.0.to_be_bytes().to_vec()))
+        }
+    }
+
+    #[test]
+    fn test_chunk_as() {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.insert_before_end(Level(7).encode().unwrap());
+
+        let header = png.chunk_as::<ImageHeader>().unwrap().unwrap();
+        assert_eq!(header, ImageHeader::from_png(&png).unwrap());
+        assert_eq!(png.chunk_as::<TextChunk>().unwrap().unwrap().keyword(), "Software");
+        assert_eq!(png.chunk_as::<Level>().unwrap().unwrap(), Level(7));
+        assert!(png.chunk_as::<IccProfile>().is_none());
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = CodecRegistry::default();
+        registry.register::<Level>().unwrap();
+        let level = Level(3).encode().unwrap();
+
+        let decoded = registry.decode(&level).unwrap().unwrap();
+        assert_eq!(decoded.downcast_ref::<Level>(), Some(&Level(3)));
+        assert_eq!(registry.describe(&level).unwrap().unwrap(), "Level(3)");
+
+        let png = Png::try_from(DICE).unwrap();
+        assert!(registry.describe(&png.chunks()[0]).unwrap().unwrap().starts_with("ImageHeader"));
+        assert!(CodecRegistry::new().decode(&level).is_none());
+    }
+
+    #[test]
+    fn test_registry_chunk_as() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let level = Chunk::new(ChunkType::from_str("lvLf").unwrap(), 9u16.to_be_bytes().to_vec());
+        png.insert_before_end(level);
+
+        // A second chunk type storing the same format, registered at run time
+        let mut registry = CodecRegistry::of::<Level>().unwrap();
+        assert!(registry.chunk_as::<Level>(&png).is_none());
+        registry.register_codec(ChunkType::from_str("lvLf").unwrap(), Box::new(Codec::<Level>::default()));
+        assert_eq!(registry.chunk_as::<Level>(&png).unwrap().unwrap(), Level(9));
+        assert!(registry.chunk_as::<ImageHeader>(&png).is_none());
+    }
+}
@@ -0,0 +1,347 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::image;
+use crate::png::Png;
+use crate::Result;
+
+/// The version of the encrypted payload layout
+const VERSION: u8 = 1;
+/// The content key is wrapped for X25519 recipients
+const KIND_RECIPIENTS: u8 = 1;
+/// The content key is a raw key shared out of band
+const KIND_KEY: u8 = 2;
+/// The content key is derived from a password with Argon2id
+const KIND_PASSWORD: u8 = 3;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// An ephemeral public key and the wrapped content key
+const STANZA_LEN: usize = 32 + KEY_LEN + TAG_LEN;
+const WRAP_INFO: &[u8] = b"pngme x25519 v1";
+const SALT_LEN: usize = 16;
+/// The Argon2id memory cost (in KiB), number of passes and lanes, followed by the salt
+const PASSWORD_PARAMS_LEN: usize = 3 * 4 + SALT_LEN;
+/// The largest Argon2id memory cost accepted when decrypting (1 GiB), so a crafted payload
+/// can't make decoding exhaust the memory
+const MAX_MEMORY_COST: u32 = 1 << 20;
+const MAX_TIME_COST: u32 = 64;
+const MAX_LANES: u32 = 16;
+
+/// How `encrypt` protects a payload
+pub enum Encryption {
+    /// Any of these X25519 public keys can decrypt the payload
+    Recipients(Vec<PublicKey>),
+    /// A raw 32-byte key, managed outside of pngme
+    Key([u8; KEY_LEN]),
+    /// A password the content key is derived from
+    Password(String),
+}
+
+/// The secret `decrypt` uses to open a payload
+pub enum Secret {
+    /// An X25519 secret key, matching one of the recipients
+    Identity(StaticSecret),
+    /// The raw key the payload was encrypted with
+    Key([u8; KEY_LEN]),
+    /// The password the payload was encrypted with
+    Password(String),
+}
+
+/// The data binding a payload to the image carrying it: the `IHDR` data, followed with
+/// `image_data` by the SHA-256 of the `IDAT` stream. Passed to `encrypt` and `decrypt` as
+/// associated data, it makes a payload copied into another image fail to decrypt.
+/// The image data can't be bound when the payload is hidden in the pixels themselves.
+pub fn carrier_binding(png: &Png, image_data: bool) -> Result<Vec<u8>> {
+    let mut binding = png.chunk_by_type("IHDR").ok_or("image has no IHDR chunk")?.data().to_vec();
+    if image_data {
+        binding.extend(Sha256::digest(image::idat_stream(png)));
+    }
+    Ok(binding)
+}
+
+/// Encrypts `plaintext` with ChaCha20-Poly1305. The layout is:
+/// 1. Version *(1 byte)*
+/// 2. Kind of encryption *(1 byte)*
+/// 3. For `Encryption::Recipients`, the number of recipients *(1 byte)* and for every
+///    recipient an ephemeral public key and the wrapped content key *(80 bytes)*.
+///    For `Encryption::Password`, the Argon2id memory cost in KiB, number of passes and
+///    lanes *(4 bytes each)* and a random salt *(16 bytes)*.
+/// 4. Nonce *(12 bytes)*
+/// 5. Ciphertext with the authentication tag
+///
+/// With recipients, the content key is random and wrapped for every recipient with a key
+/// derived (HKDF-SHA256) from an X25519 exchange between a fresh ephemeral key and the
+/// recipient key. With a raw key, the key itself is the content key. With a password, the
+/// content key is derived from it with Argon2id and the default parameters of the argon2 crate.
+///
+/// Everything before the ciphertext is authenticated, as well as `associated_data`, which
+/// isn't stored (see `carrier_binding`).
+pub fn encrypt(plaintext: &[u8], encryption: &Encryption, associated_data: &[u8]) -> Result<Vec<u8>> {
+    let (content_key, mut data) = match encryption {
+        Encryption::Recipients(recipients) => wrap_for_recipients(recipients)?,
+        Encryption::Key(key) => (*Key::from_slice(key), vec![VERSION, KIND_KEY]),
+        Encryption::Password(password) => derive_for_password(password)?,
+    };
+
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let aad = [&data, associated_data].concat();
+    let ciphertext = ChaCha20Poly1305::new(&content_key)
+        .encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+        .map_err(|_| "encryption failed")?;
+    data.extend(nonce);
+    data.extend(ciphertext);
+    Ok(data)
+}
+
+/// Decrypts a payload encrypted by `encrypt` with the same `associated_data`. Without the
+/// right kind of secret, the error tells which one is needed.
+pub fn decrypt(data: &[u8], secret: Option<&Secret>, associated_data: &[u8]) -> Result<Vec<u8>> {
+    let (content_key, header_len) = open(data, secret)?;
+    let (nonce, ciphertext) = data[header_len..].split_at(NONCE_LEN);
+    let aad = [&data[..header_len], associated_data].concat();
+    ChaCha20Poly1305::new(&content_key)
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| "the encrypted payload was tampered with, moved to another image or the key is wrong".into())
+}
+
+/// Binds a payload encrypted by `encrypt` with `old_data` to `new_data` instead, e.g. when it
+/// moves to a steganography mode binding a different part of the image. The header is kept,
+/// so every recipient can still decrypt it, but `secret` is needed to recover the content key.
+pub fn rebind(data: &[u8], secret: Option<&Secret>, old_data: &[u8], new_data: &[u8]) -> Result<Vec<u8>> {
+    let plaintext = decrypt(data, secret, old_data)?;
+    let (content_key, header_len) = open(data, secret)?;
+
+    let mut rebound = data[..header_len].to_vec();
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let aad = [&rebound, new_data].concat();
+    let ciphertext = ChaCha20Poly1305::new(&content_key)
+        .encrypt(&nonce, Payload { msg: &plaintext, aad: &aad })
+        .map_err(|_| "encryption failed")?;
+    rebound.extend(nonce);
+    rebound.extend(ciphertext);
+    Ok(rebound)
+}
+
+/// Checks the header of an encrypted payload and recovers its content key with `secret`,
+/// returning the key and the length of the header
+fn open(data: &[u8], secret: Option<&Secret>) -> Result<(Key, usize)> {
+    if data.len() < 2 {
+        return Err("encrypted payload is truncated".into());
+    }
+    if data[0] != VERSION {
+        return Err(format!("unsupported encrypted payload version {}", data[0]).into());
+    }
+
+    let (content_key, header_len) = match (data[1], secret) {
+        (KIND_RECIPIENTS, Some(Secret::Identity(identity))) => unwrap_for_identity(data, identity)?,
+        (KIND_KEY, Some(Secret::Key(key))) => (*Key::from_slice(key), 2),
+        (KIND_PASSWORD, Some(Secret::Password(password))) => derive_from_header(data, password)?,
+        (KIND_RECIPIENTS, _) => return Err("the payload is encrypted to X25519 recipients, pass --identity".into()),
+        (KIND_KEY, _) => return Err("the payload is encrypted with a raw key, pass --key-file".into()),
+        (KIND_PASSWORD, _) => return Err("the payload is encrypted with a password, pass --password".into()),
+        (kind, _) => return Err(format!("unsupported encryption kind {}", kind).into()),
+    };
+    if data.len() < header_len + NONCE_LEN + TAG_LEN {
+        return Err("encrypted payload is truncated".into());
+    }
+    Ok((content_key, header_len))
+}
+
+/// Generates a content key and wraps it for every recipient, returning the key and the header
+fn wrap_for_recipients(recipients: &[PublicKey]) -> Result<(Key, Vec<u8>)> {
+    if recipients.is_empty() || recipients.len() > u8::MAX as usize {
+        return Err(format!("a payload can be encrypted to 1 to {} recipients", u8::MAX).into());
+    }
+
+    let content_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    let mut header = vec![VERSION, KIND_RECIPIENTS, recipients.len() as u8];
+    for recipient in recipients {
+        let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(recipient);
+        if !shared.was_contributory() {
+            return Err("invalid recipient key".into());
+        }
+
+        let wrap_key = wrap_key(shared.as_bytes(), &ephemeral_public, recipient)?;
+        let wrapped = ChaCha20Poly1305::new(&wrap_key)
+            .encrypt(&Nonce::default(), content_key.as_slice())
+            .map_err(|_| "encryption failed")?;
+        header.extend(ephemeral_public.as_bytes());
+        header.extend(wrapped);
+    }
+    Ok((content_key, header))
+}
+
+/// Finds the content key wrapped for `identity`, returning it and the length of the header
+fn unwrap_for_identity(data: &[u8], identity: &StaticSecret) -> Result<(Key, usize)> {
+    let count = *data.get(2).ok_or("encrypted payload is truncated")? as usize;
+    let header_len = 3 + count * STANZA_LEN;
+    let stanzas = data.get(3..header_len).ok_or("encrypted payload is truncated")?;
+
+    let public = PublicKey::from(identity);
+    let content_key = stanzas
+        .chunks(STANZA_LEN)
+        .find_map(|stanza| {
+            let ephemeral_public = PublicKey::from(<[u8; 32]>::try_from(&stanza[..32]).ok()?);
+            let shared = identity.diffie_hellman(&ephemeral_public);
+            let wrap_key = wrap_key(shared.as_bytes(), &ephemeral_public, &public).ok()?;
+            ChaCha20Poly1305::new(&wrap_key).decrypt(&Nonce::default(), &stanza[32..]).ok()
+        })
+        .ok_or("the payload isn't encrypted to this key")?;
+    Ok((*Key::from_slice(&content_key), header_len))
+}
+
+/// Derives a content key from `password` with a fresh salt, returning the key and the header
+fn derive_for_password(password: &str) -> Result<(Key, Vec<u8>)> {
+    let params = Params::default();
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+
+    let mut header = vec![VERSION, KIND_PASSWORD];
+    for value in [params.m_cost(), params.t_cost(), params.p_cost()] {
+        header.extend(value.to_be_bytes());
+    }
+    header.extend(salt);
+    Ok((password_key(password, params, &salt)?, header))
+}
+
+/// Derives the content key from `password` with the parameters and salt stored in the header,
+/// returning it and the length of the header
+fn derive_from_header(data: &[u8], password: &str) -> Result<(Key, usize)> {
+    let header_len = 2 + PASSWORD_PARAMS_LEN;
+    let stored = data.get(2..header_len).ok_or("encrypted payload is truncated")?;
+    let value = |index: usize| u32::from_be_bytes(stored[index * 4..index * 4 + 4].try_into().unwrap());
+    let (memory, passes, lanes) = (value(0), value(1), value(2));
+    if memory > MAX_MEMORY_COST || passes > MAX_TIME_COST || lanes > MAX_LANES {
+        return Err("the password derivation parameters of the payload are too expensive".into());
+    }
+
+    let params = Params::new(memory, passes, lanes, Some(KEY_LEN))
+        .map_err(|e| format!("invalid password derivation parameters: {}", e))?;
+    Ok((password_key(password, params, &stored[12..])?, header_len))
+}
+
+fn password_key(password: &str, params: Params, salt: &[u8]) -> Result<Key> {
+    let mut key = Key::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+fn wrap_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> Result<Key> {
+    let salt: Vec<u8> = ephemeral.as_bytes().iter().chain(recipient.as_bytes()).copied().collect();
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(WRAP_INFO, &mut key)
+        .map_err(|_| "key derivation failed")?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity() -> StaticSecret {
+        StaticSecret::random_from_rng(OsRng)
+    }
+
+    #[test]
+    fn test_multiple_recipients() {
+        let (alice, bob, eve) = (identity(), identity(), identity());
+        let encryption = Encryption::Recipients(vec![PublicKey::from(&alice), PublicKey::from(&bob)]);
+
+        let data = encrypt(b"team secret", &encryption, b"").unwrap();
+
+        assert_eq!(data.len(), 3 + 2 * STANZA_LEN + NONCE_LEN + 11 + TAG_LEN);
+        assert_eq!(decrypt(&data, Some(&Secret::Identity(alice)), b"").unwrap(), b"team secret");
+        assert_eq!(decrypt(&data, Some(&Secret::Identity(bob)), b"").unwrap(), b"team secret");
+        let error = decrypt(&data, Some(&Secret::Identity(eve)), b"").unwrap_err();
+        assert_eq!(error.to_string(), "the payload isn't encrypted to this key");
+    }
+
+    #[test]
+    fn test_raw_key() {
+        let key = [7; KEY_LEN];
+        let data = encrypt(b"secret", &Encryption::Key(key), b"").unwrap();
+
+        assert_eq!(data.len(), 2 + NONCE_LEN + 6 + TAG_LEN);
+        assert_eq!(decrypt(&data, Some(&Secret::Key(key)), b"").unwrap(), b"secret");
+        assert!(decrypt(&data, Some(&Secret::Key([8; KEY_LEN])), b"").is_err());
+        let error = decrypt(&data, Some(&Secret::Identity(identity())), b"").unwrap_err();
+        assert_eq!(error.to_string(), "the payload is encrypted with a raw key, pass --key-file");
+    }
+
+    #[test]
+    fn test_password() {
+        let password = Encryption::Password("correct horse".to_owned());
+        let data = encrypt(b"secret", &password, b"image").unwrap();
+
+        assert_eq!(data.len(), 2 + PASSWORD_PARAMS_LEN + NONCE_LEN + 6 + TAG_LEN);
+        assert_eq!(decrypt(&data, Some(&Secret::Password("correct horse".to_owned())), b"image").unwrap(), b"secret");
+        assert!(decrypt(&data, Some(&Secret::Password("battery staple".to_owned())), b"image").is_err());
+        let error = decrypt(&data, None, b"image").unwrap_err();
+        assert_eq!(error.to_string(), "the payload is encrypted with a password, pass --password");
+
+        // A fresh salt every time
+        assert_ne!(data[..2 + PASSWORD_PARAMS_LEN], encrypt(b"secret", &password, b"image").unwrap()[..2 + PASSWORD_PARAMS_LEN]);
+
+        let mut expensive = data.clone();
+        expensive[2..6].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decrypt(&expensive, Some(&Secret::Password("correct horse".to_owned())), b"image").is_err());
+    }
+
+    #[test]
+    fn test_tampering() {
+        let alice = identity();
+        let mut data = encrypt(b"secret", &Encryption::Recipients(vec![PublicKey::from(&alice)]), b"").unwrap();
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(decrypt(&data, Some(&Secret::Identity(alice.clone())), b"").is_err());
+        assert!(decrypt(&data[..10], Some(&Secret::Identity(alice)), b"").is_err());
+    }
+
+    #[test]
+    fn test_no_recipients() {
+        assert!(encrypt(b"secret", &Encryption::Recipients(Vec::new()), b"").is_err());
+    }
+
+    #[test]
+    fn test_rebind() {
+        let (alice, bob) = (identity(), identity());
+        let encryption = Encryption::Recipients(vec![PublicKey::from(&alice), PublicKey::from(&bob)]);
+        let data = encrypt(b"secret", &encryption, b"old").unwrap();
+
+        let alice = Secret::Identity(alice);
+        assert!(rebind(&data, None, b"old", b"new").is_err());
+        assert!(rebind(&data, Some(&alice), b"wrong", b"new").is_err());
+        let rebound = rebind(&data, Some(&alice), b"old", b"new").unwrap();
+
+        assert_eq!(rebound[..3 + 2 * STANZA_LEN], data[..3 + 2 * STANZA_LEN]);
+        assert_eq!(decrypt(&rebound, Some(&Secret::Identity(bob)), b"new").unwrap(), b"secret");
+        assert!(decrypt(&rebound, Some(&alice), b"old").is_err());
+    }
+
+    #[test]
+    fn test_carrier_binding() {
+        let alice = identity();
+        let encryption = Encryption::Recipients(vec![PublicKey::from(&alice)]);
+        let dice = Png::try_from(&include_bytes!("../dice.png")[..]).unwrap();
+        let other = crate::generate::generate(&Default::default()).unwrap();
+
+        let data = encrypt(b"secret", &encryption, &carrier_binding(&dice, true).unwrap()).unwrap();
+
+        let secret = Secret::Identity(alice);
+        assert_eq!(decrypt(&data, Some(&secret), &carrier_binding(&dice, true).unwrap()).unwrap(), b"secret");
+        assert!(decrypt(&data, Some(&secret), &carrier_binding(&dice, false).unwrap()).is_err());
+        assert!(decrypt(&data, Some(&secret), &carrier_binding(&other, true).unwrap()).is_err());
+    }
+}
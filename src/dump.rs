@@ -0,0 +1,157 @@
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::keys;
+use crate::png::Png;
+use crate::Result;
+
+/// A whole PNG file as a JSON document, so it can be inspected and edited as text.
+/// `Dump::to_png` rebuilds the file byte for byte.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dump {
+    /// The PNG signature, in hex
+    pub signature: String,
+    pub chunks: Vec<ChunkDump>,
+    /// The data after `IEND`, in base64
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub trailer: String,
+}
+
+/// A chunk of a `Dump`. Only `type` and `data` are read back: the flags, offset, length and CRC
+/// are there for readers and are recomputed, so the data can be edited freely.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkDump {
+    #[serde(rename = "type")]
+    pub chunk_type: String,
+    #[serde(default)]
+    pub critical: bool,
+    #[serde(default)]
+    pub public: bool,
+    #[serde(default)]
+    pub safe_to_copy: bool,
+    /// The offset of the chunk (its length field) in the file
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub length: u32,
+    #[serde(default)]
+    pub crc: u32,
+    /// The data, in base64
+    pub data: String,
+}
+
+impl Dump {
+    /// Dumps every chunk of `png`
+    pub fn from_png(png: &Png) -> Dump {
+        let mut offset = png.header().len();
+        let chunks = png.chunks().iter()
+            .map(|chunk| {
+                let dump = ChunkDump {
+                    chunk_type: chunk.chunk_type().to_string(),
+                    critical: chunk.chunk_type().is_critical(),
+                    public: chunk.chunk_type().is_public(),
+                    safe_to_copy: chunk.chunk_type().is_safe_to_copy(),
+                    offset,
+                    length: chunk.length(),
+                    crc: chunk.crc(),
+                    data: BASE64.encode(chunk.data()),
+                };
+                offset += chunk.length() as usize + 12;
+                dump
+            })
+            .collect();
+
+        Dump {
+            signature: keys::to_hex(&Png::STANDARD_HEADER),
+            chunks,
+            trailer: BASE64.encode(png.trailer()),
+        }
+    }
+
+    /// Rebuilds the `Png`
+    pub fn to_png(&self) -> Result<Png> {
+        if self.signature != keys::to_hex(&Png::STANDARD_HEADER) {
+            return Err(format!("invalid PNG signature {}", self.signature).into());
+        }
+
+        let mut chunks = Vec::with_capacity(self.chunks.len());
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let data = BASE64.decode(&chunk.data)
+                .map_err(|e| format!("chunk #{} ({}): invalid base64 data: {}", index, chunk.chunk_type, e))?;
+            chunks.push(Chunk::new(ChunkType::from_str(&chunk.chunk_type)?, data));
+        }
+
+        let mut png = Png::from_chunks(chunks);
+        png.set_trailer(BASE64.decode(&self.trailer).map_err(|e| format!("invalid base64 trailer: {}", e))?);
+        Ok(png)
+    }
+}
+
+/// Describes a decoded payload as JSON: its length, its data in base64 and, if it is UTF-8,
+/// its text
+pub fn payload_json(payload: &[u8]) -> Value {
+    json!({
+        "length": payload.len(),
+        "data": payload_base64(payload),
+        "text": std::str::from_utf8(payload).ok(),
+    })
+}
+
+/// Formats a decoded payload as lowercase hex, for payloads that aren't text
+pub fn payload_hex(payload: &[u8]) -> String {
+    keys::to_hex(payload)
+}
+
+/// Formats a decoded payload in base64, for payloads that aren't text
+pub fn payload_base64(payload: &[u8]) -> String {
+    BASE64.encode(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[test]
+    fn test_round_trip() {
+        let mut bytes = DICE.to_vec();
+        bytes.extend(b"trailing");
+        let png = Png::try_from(&bytes[..]).unwrap();
+
+        let json = serde_json::to_string(&Dump::from_png(&png)).unwrap();
+        let dump: Dump = serde_json::from_str(&json).unwrap();
+        assert_eq!(dump.chunks[0].chunk_type, "IHDR");
+        assert!(dump.chunks[0].critical);
+        assert_eq!(dump.chunks[0].offset, 8);
+        assert_eq!(dump.chunks[1].offset, 8 + 13 + 12);
+        assert_eq!(dump.to_png().unwrap().as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_edited_dump() {
+        let json = r#"{
+            "signature": "89504e470d0a1a0a",
+            "chunks": [{ "type": "ruSt", "data": "aGk=" }, { "type": "IEND", "data": "" }]
+        }"#;
+        let png = serde_json::from_str::<Dump>(json).unwrap().to_png().unwrap();
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"hi");
+        assert!(png.trailer().is_empty());
+
+        let json = json.replace("aGk=", "not base64!");
+        assert!(serde_json::from_str::<Dump>(&json).unwrap().to_png().is_err());
+    }
+
+    #[test]
+    fn test_payload_json() {
+        assert_eq!(payload_json(b"hi"), json!({ "length": 2, "data": "aGk=", "text": "hi" }));
+        assert_eq!(payload_json(&[0xff])["text"], Value::Null);
+    }
+}
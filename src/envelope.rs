@@ -0,0 +1,797 @@
+use std::str::FromStr;
+
+use crc::{self, Crc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::attest::ATTESTATION_CHUNK_TYPE;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::image::inflate_limited;
+use crate::png::Png;
+use crate::provenance::PROVENANCE_CHUNK_TYPE;
+use crate::Result;
+
+const CRC_HDLC: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// The serialization formats of structured payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StructFormat {
+    /// Compact binary CBOR (RFC 8949)
+    #[default]
+    Cbor,
+    /// Human readable JSON
+    Json,
+}
+
+impl FromStr for StructFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cbor" => Ok(StructFormat::Cbor),
+            "json" => Ok(StructFormat::Json),
+            _ => Err(format!("unknown format '{}' (expected cbor or json)", s)),
+        }
+    }
+}
+
+/// Every payload written by pngme is wrapped in an envelope so it can be recognized later,
+/// whatever chunk type or steganography mode was used. The layout is:
+/// 1. Magic bytes `PNGME` *(5 bytes)*
+/// 2. Format version *(1 byte)*
+/// 3. Flags describing how the payload is encoded *(1 byte)*
+/// 4. Length of the payload *(4 bytes)*
+/// 5. CRC of the payload *(4 bytes)*
+/// 6. The payload itself *(`length` bytes)*
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    flags: u8,
+    payload: Vec<u8>,
+}
+
+impl Envelope {
+    pub const MAGIC: [u8; 5] = *b"PNGME";
+    pub const VERSION: u8 = 1;
+    /// The size of everything stored before the payload
+    pub const HEADER_LEN: usize = 15;
+    /// The largest size a compressed payload may inflate to, far above any message but low
+    /// enough that a small zlib bomb can't exhaust the memory
+    pub const MAX_INFLATED_LEN: usize = 256 << 20;
+
+    /// Set when the payload is one [`Part`] of a payload split across several files
+    pub const FLAG_PART: u8 = 0b0000_0001;
+    /// Set when the payload is a value serialized as JSON
+    pub const FLAG_JSON: u8 = 0b0000_0010;
+    /// Set when the payload is a value serialized as CBOR
+    pub const FLAG_CBOR: u8 = 0b0000_0100;
+    /// Set when the payload is encrypted, see [`crate::crypto`]
+    pub const FLAG_ENCRYPTED: u8 = 0b0000_1000;
+    /// Set when the payload is an [`EmbeddedFile`], a file stored with its name
+    pub const FLAG_FILE: u8 = 0b0001_0000;
+    /// Set when the payload is the [`Manifest`] of a payload split into several chunks
+    pub const FLAG_MANIFEST: u8 = 0b0010_0000;
+    /// Set when the payload is zlib compressed (before being encrypted, if it is)
+    pub const FLAG_COMPRESSED: u8 = 0b0100_0000;
+    /// Set when the payload is a [`Replica`], one of the extra copies of a payload
+    pub const FLAG_REPLICA: u8 = 0b1000_0000;
+
+    /// Wraps `payload` without any flags set
+    pub fn new(payload: Vec<u8>) -> Envelope {
+        Envelope { flags: 0, payload }
+    }
+
+    /// Wraps `payload` with the given flags
+    pub fn with_flags(flags: u8, payload: Vec<u8>) -> Envelope {
+        Envelope { flags, payload }
+    }
+
+    /// Serializes `value` and wraps it, flagging the format so `to_struct` can read it back
+    pub fn from_struct<T: Serialize>(value: &T, format: StructFormat) -> Result<Envelope> {
+        match format {
+            StructFormat::Json => Ok(Envelope::with_flags(Self::FLAG_JSON, serde_json::to_vec(value)?)),
+            StructFormat::Cbor => {
+                let mut payload = Vec::new();
+                ciborium::into_writer(value, &mut payload)?;
+                Ok(Envelope::with_flags(Self::FLAG_CBOR, payload))
+            }
+        }
+    }
+
+    /// Deserializes a payload wrapped by `from_struct`
+    pub fn to_struct<T: DeserializeOwned>(&self) -> Result<T> {
+        if self.flags & Self::FLAG_JSON != 0 {
+            Ok(serde_json::from_slice(&self.payload)?)
+        } else if self.flags & Self::FLAG_CBOR != 0 {
+            Ok(ciborium::from_reader(&self.payload[..])?)
+        } else {
+            Err("the payload is not a serialized value".into())
+        }
+    }
+
+    /// Returns true if `data` starts with the envelope magic bytes
+    pub fn is_envelope(data: &[u8]) -> bool {
+        data.starts_with(&Self::MAGIC)
+    }
+
+    /// Parses an envelope, checking its version, length and CRC. Bytes after the payload are ignored.
+    pub fn from_bytes(data: &[u8]) -> Result<Envelope> {
+        if !Self::is_envelope(data) || data.len() < Self::HEADER_LEN {
+            return Err("not a pngme payload".into());
+        }
+        if data[5] != Self::VERSION {
+            return Err(format!("unsupported pngme payload version {}", data[5]).into());
+        }
+
+        let flags = data[6];
+        let length = u32::from_be_bytes(data[7..11].try_into()?) as usize;
+        let crc = u32::from_be_bytes(data[11..15].try_into()?);
+
+        let payload = data.get(Self::HEADER_LEN..Self::HEADER_LEN + length)
+            .ok_or("pngme payload is truncated")?;
+        if CRC_HDLC.checksum(payload) != crc {
+            return Err("pngme payload is corrupted (CRC mismatch)".into());
+        }
+
+        Ok(Envelope { flags, payload: payload.to_vec() })
+    }
+
+    /// Parses `data` if it is an envelope, or wraps `data` itself otherwise,
+    /// so data embedded without an envelope (e.g. by older versions) can still be read
+    pub fn open(data: Vec<u8>) -> Result<Envelope> {
+        if Self::is_envelope(&data) {
+            Envelope::from_bytes(&data)
+        } else {
+            Ok(Envelope::new(data))
+        }
+    }
+
+    /// Returns true if the payload is one part of a split payload
+    pub fn is_part(&self) -> bool {
+        self.flags & Self::FLAG_PART != 0
+    }
+
+    /// Returns true if the payload is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & Self::FLAG_ENCRYPTED != 0
+    }
+
+    /// Returns true if the payload is an [`EmbeddedFile`]
+    pub fn is_file(&self) -> bool {
+        self.flags & Self::FLAG_FILE != 0
+    }
+
+    /// Returns true if the payload is a [`Manifest`]
+    pub fn is_manifest(&self) -> bool {
+        self.flags & Self::FLAG_MANIFEST != 0
+    }
+
+    /// Returns true if the payload is a [`Replica`]
+    pub fn is_replica(&self) -> bool {
+        self.flags & Self::FLAG_REPLICA != 0
+    }
+
+    /// Returns true if the payload is zlib compressed
+    pub fn is_compressed(&self) -> bool {
+        self.flags & Self::FLAG_COMPRESSED != 0
+    }
+
+    /// Inflates the payload if it is compressed, clearing [`Envelope::FLAG_COMPRESSED`].
+    /// Encrypted payloads must be decrypted first. Payloads inflating to more than
+    /// [`Envelope::MAX_INFLATED_LEN`] bytes are refused.
+    pub fn decompressed(self) -> Result<Envelope> {
+        if !self.is_compressed() {
+            return Ok(self);
+        }
+        if self.is_encrypted() {
+            return Err("the compressed payload is encrypted, decrypt it first".into());
+        }
+        Ok(Envelope::with_flags(self.flags & !Self::FLAG_COMPRESSED, inflate_limited(&self.payload, Self::MAX_INFLATED_LEN)?))
+    }
+
+    /// The flags describing how the payload is encoded
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// The wrapped payload
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Consumes the envelope, returning the payload
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+
+    /// Returns this envelope as a byte sequence
+    pub fn as_bytes(&self) -> Vec<u8> {
+        Self::MAGIC.into_iter()
+            .chain([Self::VERSION, self.flags])
+            .chain((self.payload.len() as u32).to_be_bytes())
+            .chain(CRC_HDLC.checksum(&self.payload).to_be_bytes())
+            .chain(self.payload.iter().copied())
+            .collect()
+    }
+}
+
+/// One piece of a payload split across several files. Besides its slice of the payload,
+/// every part stores:
+/// 1. The CRC of the whole payload, which identifies the parts that belong together
+///    and checks the reassembled payload *(4 bytes)*
+/// 2. The index of the part *(2 bytes)*
+/// 3. The number of parts *(2 bytes)*
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    id: u32,
+    index: u16,
+    count: u16,
+    data: Vec<u8>,
+}
+
+impl Part {
+    const HEADER_LEN: usize = 8;
+
+    /// Splits `payload` into `count` parts of (almost) equal size
+    pub fn split(payload: &[u8], count: usize) -> Result<Vec<Part>> {
+        if count == 0 || count > u16::MAX as usize {
+            return Err(format!("a payload can be split into 1 to {} parts", u16::MAX).into());
+        }
+
+        let id = CRC_HDLC.checksum(payload);
+        let size = payload.len().div_ceil(count).max(1);
+        let mut parts: Vec<Part> = payload.chunks(size)
+            .map(|data| Part { id, index: 0, count: count as u16, data: data.to_vec() })
+            .collect();
+        // Short payloads leave the last parts empty
+        parts.resize(count, Part { id, index: 0, count: count as u16, data: Vec::new() });
+        for (index, part) in parts.iter_mut().enumerate() {
+            part.index = index as u16;
+        }
+
+        Ok(parts)
+    }
+
+    /// Reassembles a payload from all of its parts, given in any order
+    pub fn combine(mut parts: Vec<Part>) -> Result<Vec<u8>> {
+        let first = parts.first().ok_or("no parts to combine")?;
+        let (id, count) = (first.id, first.count);
+
+        if parts.iter().any(|part| part.id != id) {
+            return Err("the parts belong to different payloads".into());
+        }
+        parts.sort_by_key(|part| part.index);
+        parts.dedup_by_key(|part| part.index);
+        if parts.len() != count as usize {
+            return Err(format!("found {} of {} parts", parts.len(), count).into());
+        }
+
+        let payload: Vec<u8> = parts.into_iter().flat_map(|part| part.data).collect();
+        if CRC_HDLC.checksum(&payload) != id {
+            return Err("the combined payload is corrupted (CRC mismatch)".into());
+        }
+        Ok(payload)
+    }
+
+    /// Reads a part from an envelope with [`Envelope::FLAG_PART`] set
+    pub fn from_envelope(envelope: &Envelope) -> Result<Part> {
+        let payload = envelope.payload();
+        if !envelope.is_part() || payload.len() < Self::HEADER_LEN {
+            return Err("not a part of a split payload".into());
+        }
+
+        Ok(Part {
+            id: u32::from_be_bytes(payload[0..4].try_into()?),
+            index: u16::from_be_bytes(payload[4..6].try_into()?),
+            count: u16::from_be_bytes(payload[6..8].try_into()?),
+            data: payload[Self::HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Wraps this part in an envelope
+    pub fn to_envelope(&self) -> Envelope {
+        let payload = self.id.to_be_bytes().into_iter()
+            .chain(self.index.to_be_bytes())
+            .chain(self.count.to_be_bytes())
+            .chain(self.data.iter().copied())
+            .collect();
+        Envelope::with_flags(Envelope::FLAG_PART, payload)
+    }
+
+    /// The position of this part, starting at 0
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// The number of parts the payload was split into
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+}
+
+/// Describes a payload split into several chunks of the same type (see
+/// `stego::ChunkBackend::with_pieces`). The manifest goes first, in an envelope with
+/// [`Envelope::FLAG_MANIFEST`] set, and stores:
+/// 1. The number of pieces *(4 bytes)*
+/// 2. The size of the whole payload *(8 bytes)*
+/// 3. The CRC of the whole payload *(4 bytes)*
+///
+/// Every piece follows in a chunk of its own, starting with its index *(4 bytes)*.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Manifest {
+    count: u32,
+    size: u64,
+    crc: u32,
+}
+
+impl Manifest {
+    pub const MAX_PIECES: usize = u16::MAX as usize;
+    const LEN: usize = 16;
+
+    /// Splits `payload` into `count` pieces of (almost) equal size, returning the manifest and
+    /// the data of the piece chunks
+    pub fn split(payload: &[u8], count: usize) -> Result<(Manifest, Vec<Vec<u8>>)> {
+        if count == 0 || count > Self::MAX_PIECES {
+            return Err(format!("a payload can be split into 1 to {} chunks", Self::MAX_PIECES).into());
+        }
+
+        let size = payload.len().div_ceil(count).max(1);
+        let mut pieces: Vec<Vec<u8>> = payload.chunks(size)
+            .map(|data| data.to_vec())
+            .collect();
+        // Short payloads leave the last pieces empty
+        pieces.resize(count, Vec::new());
+        let pieces = pieces.into_iter()
+            .enumerate()
+            .map(|(index, data)| (index as u32).to_be_bytes().into_iter().chain(data).collect())
+            .collect();
+
+        let manifest = Manifest { count: count as u32, size: payload.len() as u64, crc: CRC_HDLC.checksum(payload) };
+        Ok((manifest, pieces))
+    }
+
+    /// Reassembles the payload from the data of its piece chunks, given in any order
+    pub fn combine<'a>(&self, pieces: impl IntoIterator<Item = &'a [u8]>) -> Result<Vec<u8>> {
+        let pieces: Vec<&[u8]> = pieces.into_iter().collect();
+        if pieces.len() < self.count as usize {
+            return Err(format!("found {} of {} chunks of the split payload", pieces.len(), self.count).into());
+        }
+
+        let mut found: Vec<Option<&[u8]>> = vec![None; self.count as usize];
+        for piece in pieces {
+            let Some(index) = piece.get(0..4) else { continue };
+            let index = u32::from_be_bytes(index.try_into()?) as usize;
+            if let Some(slot @ None) = found.get_mut(index) {
+                *slot = Some(&piece[4..]);
+            }
+        }
+
+        let missing = found.iter().filter(|piece| piece.is_none()).count();
+        if missing > 0 {
+            return Err(format!("found {} of {} chunks of the split payload", self.count as usize - missing, self.count).into());
+        }
+        let payload: Vec<u8> = found.into_iter().flatten().flatten().copied().collect();
+        if payload.len() as u64 != self.size || CRC_HDLC.checksum(&payload) != self.crc {
+            return Err("the split payload is corrupted (size or CRC mismatch)".into());
+        }
+        Ok(payload)
+    }
+
+    /// Reads a manifest from an envelope with [`Envelope::FLAG_MANIFEST`] set. The number of
+    /// pieces is checked against `MAX_PIECES`, it comes from the file.
+    pub fn from_envelope(envelope: &Envelope) -> Result<Manifest> {
+        let payload = envelope.payload();
+        if !envelope.is_manifest() || payload.len() < Self::LEN {
+            return Err("not the manifest of a split payload".into());
+        }
+
+        let count = u32::from_be_bytes(payload[0..4].try_into()?);
+        if count == 0 || count as usize > Self::MAX_PIECES {
+            return Err(format!("invalid manifest: a payload can't be split into {} chunks", count).into());
+        }
+        Ok(Manifest {
+            count,
+            size: u64::from_be_bytes(payload[4..12].try_into()?),
+            crc: u32::from_be_bytes(payload[12..16].try_into()?),
+        })
+    }
+
+    /// Wraps this manifest in an envelope
+    pub fn to_envelope(&self) -> Envelope {
+        let payload = self.count.to_be_bytes().into_iter()
+            .chain(self.size.to_be_bytes())
+            .chain(self.crc.to_be_bytes())
+            .collect();
+        Envelope::with_flags(Envelope::FLAG_MANIFEST, payload)
+    }
+
+    /// The number of pieces
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The size of the whole payload
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// An extra copy of a payload stored several times (see `stego::ChunkBackend::with_copies`).
+/// The first copy is stored as is, every other one in an envelope with
+/// [`Envelope::FLAG_REPLICA`] set storing, before the copied data:
+/// 1. The index of the copy *(1 byte)*
+/// 2. The number of copies *(1 byte)*
+///
+/// The index tells the copies apart from unrelated chunks of the same types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replica {
+    index: u8,
+    count: u8,
+    data: Vec<u8>,
+}
+
+impl Replica {
+    const HEADER_LEN: usize = 2;
+
+    /// Creates the copy at `index` (from 1, the first copy isn't a replica) of `count` copies
+    pub fn new(index: usize, count: usize, data: Vec<u8>) -> Result<Replica> {
+        if index == 0 || index >= count || count > u8::MAX as usize {
+            return Err(format!("invalid copy {} of {}", index, count).into());
+        }
+        Ok(Replica { index: index as u8, count: count as u8, data })
+    }
+
+    /// Reads a replica from an envelope with [`Envelope::FLAG_REPLICA`] set
+    pub fn from_envelope(envelope: &Envelope) -> Result<Replica> {
+        let payload = envelope.payload();
+        if !envelope.is_replica() || payload.len() < Self::HEADER_LEN {
+            return Err("not a copy of a payload".into());
+        }
+        Replica::new(payload[0] as usize, payload[1] as usize, payload[Self::HEADER_LEN..].to_vec())
+    }
+
+    /// Wraps this replica in an envelope
+    pub fn to_envelope(&self) -> Envelope {
+        let payload = [self.index, self.count].into_iter()
+            .chain(self.data.iter().copied())
+            .collect();
+        Envelope::with_flags(Envelope::FLAG_REPLICA, payload)
+    }
+
+    /// The position of this copy, from 1
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    /// The number of copies of the payload
+    pub fn count(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Consumes the replica, returning the copied data
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// A file hidden with its name, so it can be written back under the same name. Before the
+/// content, the payload of an envelope with [`Envelope::FLAG_FILE`] set stores:
+/// 1. The length of the file name *(2 bytes)*
+/// 2. The file name, UTF-8 encoded
+/// 3. The size of the content *(8 bytes)*
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddedFile {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl EmbeddedFile {
+    /// `name` must be a plain file name, without any directory, so extracting the file can't
+    /// write outside of the target directory
+    pub fn new(name: &str, data: Vec<u8>) -> Result<EmbeddedFile> {
+        let plain = !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0']);
+        if !plain || name.len() > u16::MAX as usize {
+            return Err(format!("invalid embedded file name '{}'", name).into());
+        }
+        Ok(EmbeddedFile { name: name.to_owned(), data })
+    }
+
+    /// Reads a file from the payload of an envelope with [`Envelope::FLAG_FILE`] set
+    pub fn from_bytes(payload: &[u8]) -> Result<EmbeddedFile> {
+        let truncated = || "embedded file is truncated";
+        let name_len = u16::from_be_bytes(payload.get(0..2).ok_or_else(truncated)?.try_into()?) as usize;
+        let name = std::str::from_utf8(payload.get(2..2 + name_len).ok_or_else(truncated)?)?;
+        let size = payload.get(2 + name_len..10 + name_len).ok_or_else(truncated)?;
+        let data = &payload[10 + name_len..];
+        if u64::from_be_bytes(size.try_into()?) != data.len() as u64 {
+            return Err(format!("embedded file {} is truncated", name).into());
+        }
+        EmbeddedFile::new(name, data.to_vec())
+    }
+
+    /// The payload to wrap in an envelope with [`Envelope::FLAG_FILE`] set
+    pub fn as_bytes(&self) -> Vec<u8> {
+        (self.name.len() as u16).to_be_bytes().into_iter()
+            .chain(self.name.bytes())
+            .chain((self.data.len() as u64).to_be_bytes())
+            .chain(self.data.iter().copied())
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The content of the file
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consumes the file, returning its content
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Where an envelope was found in a `Png`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// The data of the chunk at this index
+    Chunk(usize),
+    /// The data after `IEND`
+    Trailer,
+}
+
+/// Finds every envelope stored at the start of a chunk's data or after `IEND`.
+/// Damaged envelopes are returned as errors so callers can report them.
+pub fn find_all(png: &Png) -> Vec<(Location, Result<Envelope>)> {
+    let chunks = png.chunks().iter()
+        .enumerate()
+        .map(|(index, chunk)| (Location::Chunk(index), chunk.data()));
+    let trailer = std::iter::once((Location::Trailer, png.trailer()));
+
+    chunks.chain(trailer)
+        .filter(|(_, data)| Envelope::is_envelope(data))
+        .map(|(location, data)| (location, Envelope::from_bytes(data)))
+        .collect()
+}
+
+/// What `wipe` removed from a `Png`
+#[derive(Debug, Default)]
+pub struct Wiped {
+    pub chunks: Vec<Chunk>,
+    /// The data removed from after `IEND`
+    pub trailer: Vec<u8>,
+}
+
+/// Removes everything pngme stored in chunks or after `IEND`, without knowing the chunk types
+/// used: the chunks holding an envelope and every other chunk of their types (such as the
+/// pieces of an interleaved payload), attestations, provenance records, and an envelope after
+/// `IEND`. Critical chunks are never removed.
+pub fn wipe(png: &mut Png) -> Wiped {
+    let mut types: Vec<ChunkType> = find_all(png).into_iter()
+        .filter_map(|(location, _)| match location {
+            Location::Chunk(index) => Some(*png.chunks()[index].chunk_type()),
+            Location::Trailer => None,
+        })
+        .collect();
+    types.extend([ATTESTATION_CHUNK_TYPE, PROVENANCE_CHUNK_TYPE].iter().filter_map(|t| ChunkType::from_str(t).ok()));
+
+    let (chunks, kept) = std::mem::take(png.chunks_mut())
+        .into_iter()
+        .partition(|chunk| !chunk.chunk_type().is_critical() && types.contains(chunk.chunk_type()));
+    *png.chunks_mut() = kept;
+
+    let mut trailer = Vec::new();
+    if Envelope::is_envelope(png.trailer()) {
+        trailer = png.trailer().to_vec();
+        png.set_trailer(Vec::new());
+    }
+    Wiped { chunks, trailer }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stego::{InterleavedBackend, StegoBackend};
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct SaveGame {
+        level: u32,
+        player: String,
+        inventory: Vec<String>,
+    }
+
+    #[test]
+    fn test_struct_roundtrip() {
+        let save = SaveGame { level: 7, player: "Ferris".to_owned(), inventory: vec!["crab claw".to_owned()] };
+
+        for format in [StructFormat::Cbor, StructFormat::Json] {
+            let bytes = Envelope::from_struct(&save, format).unwrap().as_bytes();
+            let envelope = Envelope::from_bytes(&bytes).unwrap();
+            assert_eq!(envelope.to_struct::<SaveGame>().unwrap(), save);
+        }
+
+        let json = Envelope::from_struct(&save, StructFormat::Json).unwrap();
+        assert_eq!(json.flags(), Envelope::FLAG_JSON);
+        assert!(json.payload().starts_with(b"{\"level\":7"));
+        assert!(Envelope::new(b"plain".to_vec()).to_struct::<SaveGame>().is_err());
+        assert!(json.to_struct::<Vec<u8>>().is_err());
+    }
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let envelope = Envelope::new(b"secret".to_vec());
+        let bytes = envelope.as_bytes();
+
+        assert_eq!(bytes.len(), Envelope::HEADER_LEN + 6);
+        assert!(Envelope::is_envelope(&bytes));
+        assert_eq!(Envelope::from_bytes(&bytes).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_decompressed() {
+        let message = "a message that compresses well ".repeat(10);
+        let compressed = crate::image::deflate(message.as_bytes()).unwrap();
+        let envelope = Envelope::with_flags(Envelope::FLAG_COMPRESSED, compressed);
+
+        assert!(envelope.is_compressed());
+        assert_eq!(envelope.clone().decompressed().unwrap(), Envelope::new(message.into_bytes()));
+        assert_eq!(Envelope::new(b"plain".to_vec()).decompressed().unwrap().payload(), b"plain");
+
+        let encrypted = Envelope::with_flags(envelope.flags() | Envelope::FLAG_ENCRYPTED, envelope.into_payload());
+        assert!(encrypted.decompressed().is_err());
+    }
+
+    #[test]
+    fn test_inflate_limited() {
+        let compressed = crate::image::deflate(&[0; 1000]).unwrap();
+
+        assert_eq!(inflate_limited(&compressed, 1000).unwrap().len(), 1000);
+        assert!(inflate_limited(&compressed, 999).unwrap_err().to_string().contains("more than 999 bytes"));
+    }
+
+    #[test]
+    fn test_corrupted_envelope() {
+        let mut bytes = Envelope::new(b"secret".to_vec()).as_bytes();
+        bytes[Envelope::HEADER_LEN] ^= 1;
+        assert!(Envelope::from_bytes(&bytes).is_err());
+
+        let bytes = Envelope::new(b"secret".to_vec()).as_bytes();
+        assert!(Envelope::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_open_plain_data() {
+        assert_eq!(Envelope::open(b"plain".to_vec()).unwrap().payload(), b"plain");
+        let wrapped = Envelope::new(b"wrapped".to_vec()).as_bytes();
+        assert_eq!(Envelope::open(wrapped).unwrap().payload(), b"wrapped");
+    }
+
+    #[test]
+    fn test_split_and_combine() {
+        let payload = b"a payload striped across several files".to_vec();
+        let mut parts: Vec<Part> = Part::split(&payload, 3).unwrap()
+            .iter()
+            .map(|part| Envelope::from_bytes(&part.to_envelope().as_bytes()).unwrap())
+            .map(|envelope| Part::from_envelope(&envelope).unwrap())
+            .collect();
+        parts.reverse();
+
+        assert_eq!(parts.len(), 3);
+        assert_eq!(Part::combine(parts.clone()).unwrap(), payload);
+
+        parts.remove(1);
+        assert!(Part::combine(parts).is_err());
+    }
+
+    #[test]
+    fn test_split_short_payload() {
+        let parts = Part::split(b"ab", 4).unwrap();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[3].index(), 3);
+        assert_eq!(Part::combine(parts).unwrap(), b"ab");
+    }
+
+    #[test]
+    fn test_combine_parts_of_different_payloads() {
+        let mut parts = Part::split(b"first payload", 2).unwrap();
+        parts[1] = Part::split(b"second payload", 2).unwrap().remove(1);
+        assert!(Part::combine(parts).is_err());
+    }
+
+    #[test]
+    fn test_manifest() {
+        let payload: Vec<u8> = (0..100).collect();
+        let (manifest, mut pieces) = Manifest::split(&payload, 3).unwrap();
+        assert_eq!((manifest.count(), manifest.size()), (3, 100));
+        assert_eq!(pieces[0][..4], [0, 0, 0, 0]);
+        assert_eq!(pieces[2].len(), 4 + 32);
+
+        let manifest = Manifest::from_envelope(&Envelope::from_bytes(&manifest.to_envelope().as_bytes()).unwrap()).unwrap();
+        pieces.reverse();
+        assert_eq!(manifest.combine(pieces.iter().map(Vec::as_slice)).unwrap(), payload);
+
+        let error = manifest.combine(pieces[..2].iter().map(Vec::as_slice)).unwrap_err();
+        assert_eq!(error.to_string(), "found 2 of 3 chunks of the split payload");
+        pieces[0][10] ^= 1;
+        assert!(manifest.combine(pieces.iter().map(Vec::as_slice)).is_err());
+        assert!(Manifest::split(&payload, 0).is_err());
+    }
+
+    #[test]
+    fn test_manifest_count_from_file() {
+        for count in [0, Manifest::MAX_PIECES as u32 + 1, u32::MAX] {
+            let payload = count.to_be_bytes().into_iter().chain([0; 12]).collect();
+            let envelope = Envelope::with_flags(Envelope::FLAG_MANIFEST, payload);
+            assert!(Manifest::from_envelope(&envelope).is_err(), "{}", count);
+        }
+    }
+
+    #[test]
+    fn test_replica() {
+        let replica = Replica::new(2, 3, b"copy".to_vec()).unwrap();
+        let envelope = Envelope::from_bytes(&replica.to_envelope().as_bytes()).unwrap();
+        assert!(envelope.is_replica());
+        assert_eq!(Replica::from_envelope(&envelope).unwrap(), replica);
+        assert_eq!((replica.index(), replica.count()), (2, 3));
+
+        assert!(Replica::new(0, 3, Vec::new()).is_err());
+        assert!(Replica::new(3, 3, Vec::new()).is_err());
+        assert!(Replica::from_envelope(&Envelope::new(vec![1, 2])).is_err());
+    }
+
+    #[test]
+    fn test_embedded_file() {
+        let file = EmbeddedFile::new("report.pdf", vec![0x25, 0x50, 0x44, 0x46, 0]).unwrap();
+        let bytes = file.as_bytes();
+        assert_eq!(bytes.len(), 2 + 10 + 8 + 5);
+        assert_eq!(EmbeddedFile::from_bytes(&bytes).unwrap(), file);
+        assert!(EmbeddedFile::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(EmbeddedFile::from_bytes(&bytes[..5]).is_err());
+
+        for name in ["", "..", "../secret", "dir/file", "C:\\file"] {
+            assert!(EmbeddedFile::new(name, Vec::new()).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_find_all() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let wrapped = Envelope::new(b"one".to_vec()).as_bytes();
+        png.insert_before_end(Chunk::new(ChunkType::from_str("ruSt").unwrap(), wrapped));
+        png.insert_before_end(Chunk::new(ChunkType::from_str("abCd").unwrap(), b"not mine".to_vec()));
+        png.set_trailer(Envelope::new(b"two".to_vec()).as_bytes());
+
+        let found = find_all(&png);
+
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, Location::Chunk(png.chunks().len() - 3));
+        assert_eq!(found[0].1.as_ref().unwrap().payload(), b"one");
+        assert_eq!(found[1].0, Location::Trailer);
+        assert_eq!(found[1].1.as_ref().unwrap().payload(), b"two");
+    }
+
+    #[test]
+    fn test_wipe() {
+        let original = Png::try_from(DICE).unwrap();
+        let mut png = Png::try_from(DICE).unwrap();
+        let backend = InterleavedBackend::new(ChunkType::from_str("inTl").unwrap());
+        backend.embed(&mut png, &Envelope::new(vec![7; 100]).as_bytes()).unwrap();
+        png.insert_before_end(Chunk::new(ChunkType::from_str("prVn").unwrap(), b"{}".to_vec()));
+        png.set_trailer(Envelope::new(b"eof".to_vec()).as_bytes());
+
+        let wiped = wipe(&mut png);
+        assert!(wiped.chunks.len() > 2);
+        assert!(!wiped.trailer.is_empty());
+        assert_eq!(png.as_bytes(), original.as_bytes());
+
+        png.set_trailer(b"not mine".to_vec());
+        let wiped = wipe(&mut png);
+        assert!(wiped.chunks.is_empty() && wiped.trailer.is_empty());
+        assert_eq!(png.trailer(), b"not mine");
+    }
+}
@@ -0,0 +1,81 @@
+use std::{fmt, io};
+
+use crate::png::{ChunkParseError, LimitExceeded};
+
+/// Failures of the PNG container that library users may want to tell apart. The parsers
+/// (`Png::try_from`, `Chunk::try_from` and `ChunkType::from_str`) return them as is, the other
+/// functions of the crate return them boxed in [`crate::Error`], so match on them with
+/// `err.downcast_ref::<PngMeError>()`.
+#[derive(thiserror::Error)]
+pub enum PngMeError {
+    /// A chunk type that isn't made of four ASCII letters
+    #[error("invalid chunk type")]
+    InvalidChunkType,
+    /// A chunk whose CRC doesn't match its type and data
+    #[error("invalid chunk CRC (expected {expected}, found {actual})")]
+    CrcMismatch {
+        /// The CRC computed from the type and data
+        expected: u32,
+        /// The CRC stored in the file
+        actual: u32,
+    },
+    /// There is no chunk of this type. `similar` is the type of a chunk that only differs in
+    /// case, if there is one, since the case of chunk types matters.
+    #[error("no {chunk_type} chunk found{}", similar_hint(.similar))]
+    ChunkNotFound {
+        chunk_type: String,
+        similar: Option<String>,
+    },
+    /// The input doesn't start with the PNG signature
+    #[error("invalid PNG header")]
+    InvalidSignature,
+    /// A chunk claiming a length over 2^31 - 1 bytes, the most the PNG spec allows
+    #[error("chunk length exceeds 2^31 - 1")]
+    ChunkTooLong,
+    /// Parsing hit one of the `png::ParseLimits`
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+    /// A chunk of a PNG file couldn't be read, `ChunkParseError::source` says why
+    #[error(transparent)]
+    ChunkParse(Box<ChunkParseError>),
+    /// Reading failed, e.g. the input ended in the middle of a chunk
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Recovers the typed error of the parsers, which pass their errors around boxed. Any other
+/// error is malformed data.
+impl From<crate::Error> for PngMeError {
+    fn from(err: crate::Error) -> PngMeError {
+        let err = match err.downcast::<PngMeError>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<io::Error>() {
+            Ok(err) => return PngMeError::Io(*err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<LimitExceeded>() {
+            Ok(err) => return PngMeError::LimitExceeded(*err),
+            Err(err) => err,
+        };
+        match err.downcast::<ChunkParseError>() {
+            Ok(err) => PngMeError::ChunkParse(err),
+            Err(err) => PngMeError::Io(io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+    }
+}
+
+fn similar_hint(similar: &Option<String>) -> String {
+    match similar {
+        Some(similar) => format!(", but there is a {} chunk (chunk types are case-sensitive, see --ignore-case)", similar),
+        None => String::new(),
+    }
+}
+
+// Formatted like the string errors, as `main` prints errors with `Debug`
+impl fmt::Debug for PngMeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
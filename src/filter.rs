@@ -0,0 +1,171 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::report::sha256_hex;
+use crate::Result;
+
+/// The data `clean` removes from a `Png`: its private chunks, each with its position
+/// in the chunk list, and the data after `IEND`
+#[derive(Debug, Clone, Default)]
+pub struct Stash {
+    chunks: Vec<(usize, Chunk)>,
+    trailer: Vec<u8>,
+}
+
+impl Stash {
+    /// Returns true if nothing was removed
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.trailer.is_empty()
+    }
+
+    /// Returns this stash as a byte sequence: the number of chunks, then the position
+    /// and bytes of every chunk, then the data after `IEND`
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.chunks.len() as u32).to_be_bytes().to_vec();
+        for (index, chunk) in &self.chunks {
+            bytes.extend((*index as u32).to_be_bytes());
+            chunk.extend_bytes(&mut bytes);
+        }
+        bytes.extend(&self.trailer);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Stash {
+    type Error = crate::Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let count = read_u32(&mut reader)?;
+
+        let mut chunks = Vec::new();
+        for _ in 0..count {
+            let index = read_u32(&mut reader)? as usize;
+            let (chunk, _) = Chunk::read_from(&mut reader)?;
+            chunks.push((index, chunk));
+        }
+
+        Ok(Stash { chunks, trailer: reader.to_vec() })
+    }
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    let bytes = reader.get(..4).ok_or("truncated stash")?;
+    let value = u32::from_be_bytes(bytes.try_into()?);
+    *reader = &reader[4..];
+    Ok(value)
+}
+
+/// Removes the private chunks and the data after `IEND` from `png`, returning what was removed
+pub fn clean(png: &mut Png) -> Stash {
+    let mut stash = Stash::default();
+    let mut index = 0;
+    png.chunks_mut().retain(|chunk| {
+        let private = !chunk.chunk_type().is_public();
+        if private {
+            stash.chunks.push((index, chunk.clone()));
+        }
+        index += 1;
+        !private
+    });
+    stash.trailer = png.trailer().to_vec();
+    png.set_trailer(Vec::new());
+    stash
+}
+
+/// Puts the data removed by `clean` back into `png`
+pub fn smudge(png: &mut Png, stash: Stash) {
+    let chunks = png.chunks_mut();
+    for (index, chunk) in stash.chunks {
+        chunks.insert(index.min(chunks.len()), chunk);
+    }
+    png.set_trailer(stash.trailer);
+}
+
+/// The name a stash is saved under in the stash directory: the SHA-256 of the cleaned file
+pub fn stash_key(clean_bytes: &[u8]) -> String {
+    sha256_hex(clean_bytes)
+}
+
+/// Git clean filter: strips the private chunks and the data after `IEND` from a PNG file,
+/// saving them in `stash_dir` so `smudge_file` can restore them. Input that isn't a PNG file
+/// is returned unchanged, so the filter never breaks a commit.
+pub fn clean_file<P: AsRef<Path>>(input: &[u8], stash_dir: P) -> Result<Vec<u8>> {
+    let mut png = match Png::try_from(input) {
+        Ok(png) => png,
+        Err(_) => return Ok(input.to_vec()),
+    };
+
+    let stash = clean(&mut png);
+    let output = png.as_bytes();
+    if !stash.is_empty() {
+        fs::create_dir_all(&stash_dir)?;
+        fs::write(stash_dir.as_ref().join(stash_key(&output)), stash.as_bytes())?;
+    }
+    Ok(output)
+}
+
+/// Git smudge filter: restores the data `clean_file` stripped from a PNG file.
+/// Input without a saved stash (e.g. in a fresh clone) is returned unchanged.
+pub fn smudge_file<P: AsRef<Path>>(input: &[u8], stash_dir: P) -> Result<Vec<u8>> {
+    let stash_path = stash_dir.as_ref().join(stash_key(input));
+    let (Ok(mut png), Ok(stash)) = (Png::try_from(input), fs::read(stash_path)) else {
+        return Ok(input.to_vec());
+    };
+
+    smudge(&mut png, Stash::try_from(&stash[..])?);
+    Ok(png.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    fn png_with_payload() -> Png {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.chunks_mut().insert(1, Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"one".to_vec()));
+        png.insert_before_end(Chunk::new(ChunkType::from_str("abCd").unwrap(), b"two".to_vec()));
+        png.set_trailer(b"three".to_vec());
+        png
+    }
+
+    #[test]
+    fn test_clean_and_smudge() {
+        let original = png_with_payload();
+        let mut png = png_with_payload();
+
+        let stash = clean(&mut png);
+        assert_eq!(png.as_bytes(), DICE);
+        assert_eq!(stash.chunks.len(), 2);
+
+        smudge(&mut png, Stash::try_from(&stash.as_bytes()[..]).unwrap());
+        assert_eq!(png.as_bytes(), original.as_bytes());
+    }
+
+    #[test]
+    fn test_filter_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = png_with_payload().as_bytes();
+
+        let cleaned = clean_file(&original, dir.path()).unwrap();
+        assert_eq!(cleaned, DICE);
+        assert_eq!(smudge_file(&cleaned, dir.path()).unwrap(), original);
+    }
+
+    #[test]
+    fn test_filter_passes_other_input_through() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert_eq!(clean_file(b"not a png", dir.path()).unwrap(), b"not a png");
+        assert_eq!(clean_file(DICE, dir.path()).unwrap(), DICE);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert_eq!(smudge_file(DICE, dir.path()).unwrap(), DICE);
+    }
+}
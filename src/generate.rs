@@ -0,0 +1,221 @@
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::image::{ImageHeader, RawImage};
+use crate::png::Png;
+use crate::Result;
+
+/// The patterns `generate` can draw
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pattern {
+    /// Random samples, which compress badly like real photos
+    #[default]
+    Noise,
+    /// Samples growing from the top left to the bottom right corner
+    Gradient,
+    /// A checkerboard of 8x8 pixel squares
+    Checker,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "noise" => Ok(Pattern::Noise),
+            "gradient" => Ok(Pattern::Gradient),
+            "checker" => Ok(Pattern::Checker),
+            _ => Err(format!("unknown pattern '{}' (expected noise, gradient or checker)", s)),
+        }
+    }
+}
+
+/// The properties of a generated test image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenOptions {
+    pub width: u32,
+    pub height: u32,
+    /// Seeds the random samples of `Pattern::Noise` and the palette colors
+    pub seed: u64,
+    pub pattern: Pattern,
+    pub color_type: u8,
+    pub bit_depth: u8,
+}
+
+impl Default for GenOptions {
+    fn default() -> Self {
+        GenOptions {
+            width: 64,
+            height: 64,
+            seed: 0,
+            pattern: Pattern::default(),
+            color_type: ImageHeader::RGB,
+            bit_depth: 8,
+        }
+    }
+}
+
+/// Parses a color type name: gray, rgb, palette, gray-alpha or rgba
+pub fn parse_color_type(name: &str) -> std::result::Result<u8, String> {
+    match name {
+        "gray" => Ok(ImageHeader::GRAYSCALE),
+        "rgb" => Ok(ImageHeader::RGB),
+        "palette" => Ok(ImageHeader::PALETTE),
+        "gray-alpha" => Ok(ImageHeader::GRAYSCALE_ALPHA),
+        "rgba" => Ok(ImageHeader::RGBA),
+        _ => Err(format!("unknown color type '{}' (expected gray, rgb, palette, gray-alpha or rgba)", name)),
+    }
+}
+
+/// Generates a test image. The same options always give the same file, so generated images
+/// can serve as reproducible fixtures.
+pub fn generate(options: &GenOptions) -> Result<Png> {
+    let valid_depths: &[u8] = match options.color_type {
+        ImageHeader::GRAYSCALE => &[1, 2, 4, 8, 16],
+        ImageHeader::PALETTE => &[1, 2, 4, 8],
+        ImageHeader::RGB | ImageHeader::GRAYSCALE_ALPHA | ImageHeader::RGBA => &[8, 16],
+        _ => return Err(format!("invalid color type {}", options.color_type).into()),
+    };
+    if !valid_depths.contains(&options.bit_depth) {
+        return Err(format!("bit depth {} is invalid for color type {}", options.bit_depth, options.color_type).into());
+    }
+    if options.width == 0 || options.height == 0 {
+        return Err("images must be at least 1x1 pixels".into());
+    }
+
+    let header = ImageHeader::new(options.width, options.height, options.bit_depth, options.color_type);
+    let mut rng = SplitMix64(options.seed);
+    let max = (1u32 << options.bit_depth) - 1;
+
+    let mut data = Vec::with_capacity(header.stride() * options.height as usize);
+    for y in 0..options.height {
+        let mut row = BitWriter::new(options.bit_depth);
+        for x in 0..options.width {
+            for channel in 0..header.channels() {
+                let sample = match options.pattern {
+                    Pattern::Noise => rng.next() as u32 & max,
+                    Pattern::Gradient => gradient(options, x, y, channel, max),
+                    Pattern::Checker if (x / 8 + y / 8) % 2 == 0 => max,
+                    Pattern::Checker => 0,
+                };
+                row.write(sample);
+            }
+        }
+        data.extend(row.finish());
+    }
+
+    let mut png = RawImage::new(header, data)?.to_png()?;
+    if options.color_type == ImageHeader::PALETTE {
+        let palette = (0..=max).flat_map(|_| rng.next().to_be_bytes().into_iter().take(3)).collect();
+        png.chunks_mut().insert(1, Chunk::new(ChunkType::PLTE, palette));
+    }
+    Ok(png)
+}
+
+/// Gradient samples: the first channel grows from left to right, the second from top to
+/// bottom and the others along the diagonal
+fn gradient(options: &GenOptions, x: u32, y: u32, channel: usize, max: u32) -> u32 {
+    let (position, length) = match channel {
+        0 => (x as u64, options.width as u64),
+        1 => (y as u64, options.height as u64),
+        _ => (x as u64 + y as u64, options.width as u64 + options.height as u64 - 1),
+    };
+    (position * max as u64 / (length - 1).max(1)) as u32
+}
+
+/// The SplitMix64 generator: tiny, fast and fully determined by its seed
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Packs the samples of a scanline: most significant bits first below 8 bits per sample,
+/// big endian at 16 bits per sample
+struct BitWriter {
+    bit_depth: u8,
+    bytes: Vec<u8>,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new(bit_depth: u8) -> BitWriter {
+        BitWriter { bit_depth, bytes: Vec::new(), bits: 0 }
+    }
+
+    fn write(&mut self, sample: u32) {
+        match self.bit_depth {
+            16 => self.bytes.extend((sample as u16).to_be_bytes()),
+            8 => self.bytes.push(sample as u8),
+            depth => {
+                if self.bits.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                let shift = 8 - depth as u32 - self.bits % 8;
+                *self.bytes.last_mut().unwrap() |= (sample as u8) << shift;
+                self.bits += depth as u32;
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_every_color_type_and_bit_depth() {
+        let combinations = [(0, 1), (0, 2), (0, 4), (0, 8), (0, 16), (2, 8), (2, 16), (3, 1), (3, 2), (3, 4), (3, 8), (4, 8), (4, 16), (6, 8), (6, 16)];
+        for (color_type, bit_depth) in combinations {
+            for pattern in [Pattern::Noise, Pattern::Gradient, Pattern::Checker] {
+                let options = GenOptions { width: 13, height: 7, color_type, bit_depth, pattern, ..GenOptions::default() };
+                let png = Png::try_from(&generate(&options).unwrap().as_bytes()[..]).unwrap();
+
+                let image = RawImage::from_png(&png).unwrap();
+                assert_eq!(*image.header(), ImageHeader::new(13, 7, bit_depth, color_type));
+                assert_eq!(png.chunk_by_type("PLTE").is_some(), color_type == ImageHeader::PALETTE);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let options = GenOptions { seed: 42, ..GenOptions::default() };
+        let first = generate(&options).unwrap().as_bytes();
+
+        assert_eq!(generate(&options).unwrap().as_bytes(), first);
+        assert_ne!(generate(&GenOptions { seed: 43, ..options }).unwrap().as_bytes(), first);
+    }
+
+    #[test]
+    fn test_patterns() {
+        let options = GenOptions { width: 16, height: 16, color_type: ImageHeader::GRAYSCALE, ..GenOptions::default() };
+
+        let checker = generate(&GenOptions { pattern: Pattern::Checker, ..options }).unwrap();
+        let pixels = RawImage::from_png(&checker).unwrap().data().to_vec();
+        assert_eq!((pixels[0], pixels[8], pixels[8 * 16], pixels[8 * 16 + 8]), (255, 0, 0, 255));
+
+        let gradient = generate(&GenOptions { pattern: Pattern::Gradient, ..options }).unwrap();
+        let pixels = RawImage::from_png(&gradient).unwrap().data().to_vec();
+        assert_eq!((pixels[0], pixels[255]), (0, 255));
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        assert!(generate(&GenOptions { bit_depth: 4, ..GenOptions::default() }).is_err());
+        assert!(generate(&GenOptions { color_type: 5, ..GenOptions::default() }).is_err());
+        assert!(generate(&GenOptions { width: 0, ..GenOptions::default() }).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::image::{deflate, inflate};
+use crate::png::Png;
+use crate::text::{latin1, validate_keyword};
+use crate::Result;
+
+/// An embedded ICC color profile as stored in the `iCCP` chunk. See the PNG spec for more details.
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.iCCP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccProfile {
+    name: String,
+    profile: Vec<u8>,
+}
+
+impl IccProfile {
+    /// Creates a profile entry. The name follows the rules of text chunk keywords.
+    pub fn new(name: &str, profile: Vec<u8>) -> Result<IccProfile> {
+        validate_keyword(name)?;
+        Ok(IccProfile { name: name.to_owned(), profile })
+    }
+
+    /// Reads the profile from the `iCCP` chunk of `png`
+    pub fn from_png(png: &Png) -> Result<IccProfile> {
+        let chunk = png.chunk_by_type("iCCP").ok_or("no ICC profile (iCCP chunk) found")?;
+        IccProfile::from_chunk(chunk)
+    }
+
+    /// Parses an `iCCP` chunk: profile name, null separator, compression method (0),
+    /// zlib compressed profile
+    pub fn from_chunk(chunk: &Chunk) -> Result<IccProfile> {
+        let data = chunk.data();
+        let end = data.iter().position(|&byte| byte == 0).ok_or("invalid iCCP chunk")?;
+        let name: String = data[..end].iter().map(|&byte| byte as char).collect();
+
+        match data.get(end + 1) {
+            Some(0) => {}
+            Some(_) => return Err("unknown iCCP compression method".into()),
+            None => return Err("invalid iCCP chunk".into()),
+        }
+
+        Ok(IccProfile { name, profile: inflate(&data[end + 2..])? })
+    }
+
+    /// The name of the profile
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The uncompressed ICC profile
+    pub fn profile(&self) -> &[u8] {
+        &self.profile
+    }
+
+    /// Builds the `iCCP` chunk storing this profile
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let mut data = latin1(&self.name)?;
+        data.extend([0, 0]);
+        data.extend(deflate(&self.profile)?);
+        Ok(Chunk::new(ChunkType::from_str("iCCP")?, data))
+    }
+
+    /// Stores this profile in `png` right after `IHDR`, replacing any existing `iCCP` chunk.
+    /// An `sRGB` chunk is removed as well, since it must not be present together with `iCCP`.
+    pub fn set_in_png(&self, png: &mut Png) -> Result<()> {
+        let chunk = self.to_chunk()?;
+        let chunks = png.chunks_mut();
+        chunks.retain(|chunk| !matches!(chunk.chunk_type().as_str(), "iCCP" | "sRGB"));
+
+        let position = chunks.iter()
+            .position(|chunk| chunk.chunk_type() == &ChunkType::IHDR)
+            .map_or(0, |position| position + 1);
+        chunks.insert(position, chunk);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[test]
+    fn test_icc_roundtrip() {
+        let profile = IccProfile::new("Display P3", b"fake icc profile".repeat(10)).unwrap();
+        let chunk = profile.to_chunk().unwrap();
+
+        assert_eq!(chunk.chunk_type().to_string(), "iCCP");
+        assert!(chunk.data().starts_with(b"Display P3\0\0"));
+        assert_eq!(IccProfile::from_chunk(&chunk).unwrap(), profile);
+    }
+
+    #[test]
+    fn test_set_in_png() {
+        let mut png = Png::try_from(DICE).unwrap();
+        assert!(IccProfile::from_png(&png).is_err());
+
+        let first = IccProfile::new("first", vec![1, 2, 3]).unwrap();
+        let second = IccProfile::new("second", vec![4, 5, 6]).unwrap();
+        first.set_in_png(&mut png).unwrap();
+        second.set_in_png(&mut png).unwrap();
+
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(reparsed.chunks()[1].chunk_type().to_string(), "iCCP");
+        assert_eq!(reparsed.type_counts()[&ChunkType::from_str("iCCP").unwrap()], 1);
+        assert_eq!(IccProfile::from_png(&reparsed).unwrap(), second);
+    }
+
+    #[test]
+    fn test_invalid_profile_name() {
+        assert!(IccProfile::new("", vec![1]).is_err());
+    }
+}
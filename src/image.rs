@@ -0,0 +1,503 @@
+use std::io::{Read, Write};
+
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+/// The image properties stored in the `IHDR` chunk. See the PNG spec for more details.
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: u8,
+}
+
+impl ImageHeader {
+    pub const GRAYSCALE: u8 = 0;
+    pub const RGB: u8 = 2;
+    pub const PALETTE: u8 = 3;
+    pub const GRAYSCALE_ALPHA: u8 = 4;
+    pub const RGBA: u8 = 6;
+
+    /// Creates a non-interlaced header with the default compression and filter methods
+    pub fn new(width: u32, height: u32, bit_depth: u8, color_type: u8) -> ImageHeader {
+        ImageHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }
+    }
+
+    /// Reads the header from the `IHDR` chunk of `png`
+    pub fn from_png(png: &Png) -> Result<ImageHeader> {
+        let chunk = png.chunk_by_type("IHDR").ok_or("IHDR chunk not found")?;
+        ImageHeader::from_data(chunk.data())
+    }
+
+    /// Parses the 13 data bytes of an `IHDR` chunk
+    pub fn from_data(data: &[u8]) -> Result<ImageHeader> {
+        if data.len() != 13 {
+            return Err("invalid IHDR length".into());
+        }
+
+        Ok(ImageHeader {
+            width: u32::from_be_bytes(data[0..4].try_into()?),
+            height: u32::from_be_bytes(data[4..8].try_into()?),
+            bit_depth: data[8],
+            color_type: data[9],
+            compression_method: data[10],
+            filter_method: data[11],
+            interlace_method: data[12],
+        })
+    }
+
+    /// Builds the `IHDR` chunk describing this header
+    pub fn to_chunk(&self) -> Chunk {
+        let data: Vec<u8> = self.width.to_be_bytes().into_iter()
+            .chain(self.height.to_be_bytes())
+            .chain([
+                self.bit_depth,
+                self.color_type,
+                self.compression_method,
+                self.filter_method,
+                self.interlace_method,
+            ])
+            .collect();
+
+        Chunk::new(ChunkType::IHDR, data)
+    }
+
+    /// The number of samples stored for every pixel
+    pub fn channels(&self) -> usize {
+        match self.color_type {
+            Self::RGB => 3,
+            Self::GRAYSCALE_ALPHA => 2,
+            Self::RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    /// Returns true if every pixel carries an alpha sample
+    pub fn has_alpha(&self) -> bool {
+        matches!(self.color_type, Self::GRAYSCALE_ALPHA | Self::RGBA)
+    }
+
+    /// The number of bits used by one pixel
+    pub fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// The number of bytes in one scanline, not counting the filter type byte
+    pub fn stride(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// The distance in bytes between corresponding bytes of neighbouring pixels, used by the filters
+    fn filter_unit(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+}
+
+/// The unfiltered pixel data of a PNG image: all scanlines concatenated without filter type bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawImage {
+    header: ImageHeader,
+    data: Vec<u8>,
+}
+
+impl RawImage {
+    /// Creates an image from unfiltered scanlines. The length of `data` must match the header.
+    pub fn new(header: ImageHeader, data: Vec<u8>) -> Result<RawImage> {
+        if data.len() != header.stride() * header.height as usize {
+            return Err("pixel data does not match the image dimensions".into());
+        }
+        Ok(RawImage { header, data })
+    }
+
+    /// Decompresses and unfilters the `IDAT` chunks of `png`
+    pub fn from_png(png: &Png) -> Result<RawImage> {
+        let header = ImageHeader::from_png(png)?;
+        if header.interlace_method != 0 {
+            return Err("interlaced images are not supported".into());
+        }
+
+        let filtered = inflate(&idat_stream(png))?;
+        let data = unfilter(&header, &filtered)?;
+        Ok(RawImage { header, data })
+    }
+
+    /// Decodes the image data of an Apple CgBI PNG: the `IDAT` stream is raw deflate without
+    /// the zlib header and pixels are stored as BGRA with premultiplied alpha. The returned
+    /// image uses regular RGBA samples.
+    fn from_cgbi(png: &Png) -> Result<RawImage> {
+        let header = ImageHeader::from_png(png)?;
+        if header.interlace_method != 0 {
+            return Err("interlaced images are not supported".into());
+        }
+        if header.color_type != ImageHeader::RGBA || header.bit_depth != 8 {
+            return Err("only 8 bit RGBA CgBI images are supported".into());
+        }
+
+        let mut filtered = Vec::new();
+        DeflateDecoder::new(&idat_stream(png)[..]).read_to_end(&mut filtered)?;
+        let mut data = unfilter(&header, &filtered)?;
+
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+            let alpha = pixel[3] as u32;
+            for sample in &mut pixel[..3] {
+                if let Some(straight) = (*sample as u32 * 255 + alpha / 2).checked_div(alpha) {
+                    *sample = straight.min(255) as u8;
+                }
+            }
+        }
+
+        Ok(RawImage { header, data })
+    }
+
+    /// The header describing this image
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+
+    /// The unfiltered scanlines of this image
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Mutable access to the unfiltered scanlines of this image
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Compresses this image into a single `IDAT` chunk that replaces the `IDAT` chunks of `png`.
+    /// The new chunk takes the position of the first old one, or goes right before `IEND`.
+    pub fn write_to_png(&self, png: &mut Png) -> Result<()> {
+        let idat = Chunk::new(ChunkType::IDAT, deflate(&self.filter())?);
+        replace_idat(png, vec![idat]);
+        Ok(())
+    }
+
+    /// Builds a complete `Png` (`IHDR`, `IDAT`, `IEND`) from this image
+    pub fn to_png(&self) -> Result<Png> {
+        let mut png = Png::from_chunks(vec![
+            self.header.to_chunk(),
+            Chunk::new(ChunkType::IEND, Vec::new()),
+        ]);
+        self.write_to_png(&mut png)?;
+        Ok(png)
+    }
+
+    /// Prepends the filter type byte `None` to every scanline
+    fn filter(&self) -> Vec<u8> {
+        let stride = self.header.stride();
+        let mut filtered = Vec::with_capacity(self.data.len() + self.header.height as usize);
+        for row in self.data.chunks(stride.max(1)) {
+            filtered.push(0);
+            filtered.extend_from_slice(row);
+        }
+        filtered
+    }
+}
+
+/// Returns true if `png` is an Apple CgBI ("iOS optimized") PNG, which starts with a `CgBI`
+/// chunk and can't be decoded by regular PNG decoders
+pub fn is_cgbi(png: &Png) -> bool {
+    png.chunks().first().is_some_and(|chunk| chunk.chunk_type() == "CgBI")
+}
+
+/// Converts an Apple CgBI PNG into a standard PNG: removes the `CgBI` chunk and re-encodes
+/// the image data as a zlib stream of straight (not premultiplied) RGBA pixels.
+pub fn convert_from_cgbi(png: &mut Png) -> Result<()> {
+    if !is_cgbi(png) {
+        return Err("not a CgBI image".into());
+    }
+
+    let image = RawImage::from_cgbi(png)?;
+    png.chunks_mut().remove(0);
+    image.write_to_png(png)
+}
+
+/// Re-deflates the `IDAT` stream of `png` at maximum compression, using zopfli if `zopfli` is
+/// set (requires the `zopfli` feature). The new stream replaces the old `IDAT` chunks only if it
+/// is smaller. Returns the size of the image data before and after.
+pub fn optimize(png: &mut Png, zopfli: bool) -> Result<(usize, usize)> {
+    let stream = idat_stream(png);
+    if stream.is_empty() {
+        return Err("image has no IDAT chunk".into());
+    }
+    let filtered = inflate(&stream)?;
+
+    let optimized = if zopfli {
+        deflate_zopfli(&filtered)?
+    } else {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&filtered)?;
+        encoder.finish()?
+    };
+
+    if optimized.len() >= stream.len() {
+        return Ok((stream.len(), stream.len()));
+    }
+
+    let size = optimized.len();
+    replace_idat(png, vec![Chunk::new(ChunkType::IDAT, optimized)]);
+    Ok((stream.len(), size))
+}
+
+#[cfg(feature = "zopfli")]
+fn deflate_zopfli(data: &[u8]) -> Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    zopfli::compress(zopfli::Options::default(), zopfli::Format::Zlib, data, &mut compressed)?;
+    Ok(compressed)
+}
+
+#[cfg(not(feature = "zopfli"))]
+fn deflate_zopfli(_data: &[u8]) -> Result<Vec<u8>> {
+    Err("pngme was built without the zopfli feature".into())
+}
+
+/// Concatenates the data of all `IDAT` chunks of `png`
+pub(crate) fn idat_stream(png: &Png) -> Vec<u8> {
+    png.chunks().iter()
+        .filter(|chunk| chunk.chunk_type() == &ChunkType::IDAT)
+        .flat_map(|chunk| chunk.data().iter().copied())
+        .collect()
+}
+
+/// Removes every `IDAT` chunk of `png` and inserts `idat` where the first one used to be
+pub(crate) fn replace_idat(png: &mut Png, idat: Vec<Chunk>) {
+    let chunks = png.chunks_mut();
+    let position = chunks.iter()
+        .position(|chunk| chunk.chunk_type() == &ChunkType::IDAT)
+        .or_else(|| chunks.iter().position(|chunk| chunk.chunk_type() == &ChunkType::IEND))
+        .unwrap_or(chunks.len());
+
+    chunks.retain(|chunk| chunk.chunk_type() != &ChunkType::IDAT);
+    chunks.splice(position..position, idat);
+}
+
+/// Decompresses a zlib stream
+pub(crate) fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+/// Decompresses a zlib stream like `inflate`, failing once more than `max_len` bytes come out
+/// so a small stream can't expand to fill the memory
+pub(crate) fn inflate_limited(data: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(data).take(max_len as u64 + 1).read_to_end(&mut decoded)?;
+    if decoded.len() > max_len {
+        return Err(format!("the compressed data inflates to more than {} bytes", max_len).into());
+    }
+    Ok(decoded)
+}
+
+/// Compresses `data` into a zlib stream
+pub(crate) fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Reverses the scanline filters described in the PNG spec and strips the filter type bytes.
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Filters.html
+fn unfilter(header: &ImageHeader, filtered: &[u8]) -> Result<Vec<u8>> {
+    let stride = header.stride();
+    let unit = header.filter_unit();
+    let height = header.height as usize;
+
+    if filtered.len() < (stride + 1) * height {
+        return Err("image data is shorter than the image dimensions".into());
+    }
+
+    let mut data = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter_type = filtered[y * (stride + 1)];
+        let line = &filtered[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (previous, current) = data.split_at_mut(y * stride);
+        let above = if y > 0 { &previous[(y - 1) * stride..] } else { &[][..] };
+        let current = &mut current[..stride];
+
+        for x in 0..stride {
+            let a = if x >= unit { current[x - unit] } else { 0 };
+            let b = above.get(x).copied().unwrap_or(0);
+            let c = if x >= unit { above.get(x - unit).copied().unwrap_or(0) } else { 0 };
+
+            let predictor = match filter_type {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err("invalid scanline filter type".into()),
+            };
+            current[x] = line[x].wrapping_add(predictor);
+        }
+    }
+
+    Ok(data)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[test]
+    fn test_header_from_png() {
+        let png = Png::try_from(DICE).unwrap();
+        let header = ImageHeader::from_png(&png).unwrap();
+
+        assert_eq!(header, ImageHeader::new(671, 448, 8, ImageHeader::RGB));
+        assert_eq!(header.stride(), 671 * 3);
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = ImageHeader::new(3, 2, 16, ImageHeader::RGBA);
+        assert_eq!(ImageHeader::from_data(header.to_chunk().data()).unwrap(), header);
+    }
+
+    #[test]
+    fn test_decode_reencode_keeps_pixels() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let image = RawImage::from_png(&png).unwrap();
+
+        image.write_to_png(&mut png).unwrap();
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+
+        assert_eq!(RawImage::from_png(&reparsed).unwrap(), image);
+        assert_eq!(reparsed.type_counts()[&ChunkType::IDAT], 1);
+    }
+
+    #[test]
+    fn test_optimize() {
+        let header = ImageHeader::new(64, 64, 8, ImageHeader::GRAYSCALE);
+        let image = RawImage::new(header, vec![7; 64 * 64]).unwrap();
+        let filtered = image.filter();
+
+        // A stored (uncompressed) zlib stream leaves plenty of room for optimization
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::none());
+        encoder.write_all(&filtered).unwrap();
+        let stored = encoder.finish().unwrap();
+
+        let mut png = image.to_png().unwrap();
+        replace_idat(&mut png, vec![Chunk::new(ChunkType::IDAT, stored.clone())]);
+
+        let (before, after) = optimize(&mut png, false).unwrap();
+        assert_eq!(before, stored.len());
+        assert!(after < before);
+        assert_eq!(idat_stream(&png).len(), after);
+        assert_eq!(RawImage::from_png(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_optimize_keeps_smaller_stream() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let stream = idat_stream(&png);
+        let (before, after) = optimize(&mut png, false).unwrap();
+
+        assert!(after <= before);
+        if after == before {
+            assert_eq!(idat_stream(&png), stream);
+        }
+    }
+
+    fn cgbi_png(image: &RawImage) -> Png {
+        let mut data = image.data().to_vec();
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+            let alpha = pixel[3] as u32;
+            for sample in &mut pixel[..3] {
+                *sample = (*sample as u32 * alpha / 255) as u8;
+            }
+        }
+        let cgbi = RawImage::new(*image.header(), data).unwrap();
+
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&cgbi.filter()).unwrap();
+        let stream = encoder.finish().unwrap();
+
+        let mut png = image.to_png().unwrap();
+        replace_idat(&mut png, vec![Chunk::new(ChunkType::IDAT, stream)]);
+        png.chunks_mut().insert(0, Chunk::new(ChunkType::from_str("CgBI").unwrap(), vec![0x50, 0, 0x20, 6]));
+        png
+    }
+
+    #[test]
+    fn test_convert_from_cgbi() {
+        let header = ImageHeader::new(4, 2, 8, ImageHeader::RGBA);
+        let data = vec![
+            10, 20, 30, 255, 40, 50, 60, 255, 70, 80, 90, 255, 0, 0, 0, 0,
+            255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 1, 2, 3, 255,
+        ];
+        let image = RawImage::new(header, data).unwrap();
+        let mut png = cgbi_png(&image);
+
+        assert!(is_cgbi(&png));
+        assert!(RawImage::from_png(&png).is_err());
+
+        convert_from_cgbi(&mut png).unwrap();
+
+        assert!(!is_cgbi(&png));
+        assert_eq!(png.chunks()[0].chunk_type().to_string(), "IHDR");
+        assert_eq!(RawImage::from_png(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_convert_regular_png_fails() {
+        let mut png = Png::try_from(DICE).unwrap();
+        assert!(!is_cgbi(&png));
+        assert!(convert_from_cgbi(&mut png).is_err());
+    }
+
+    #[test]
+    fn test_unfilter() {
+        let header = ImageHeader::new(2, 2, 8, ImageHeader::GRAYSCALE);
+        // Row 1 uses Sub, row 2 uses Up
+        let filtered = [1, 10, 5, 2, 1, 1];
+        assert_eq!(unfilter(&header, &filtered).unwrap(), vec![10, 15, 11, 16]);
+    }
+
+    #[test]
+    fn test_image_size_mismatch() {
+        let header = ImageHeader::new(2, 2, 8, ImageHeader::RGB);
+        assert!(RawImage::new(header, vec![0; 11]).is_err());
+        assert!(RawImage::new(header, vec![0; 12]).is_ok());
+    }
+}
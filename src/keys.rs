@@ -0,0 +1,243 @@
+use std::fs;
+use std::path::Path;
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::report::sha256_hex;
+use crate::Result;
+
+/// The label of secret (signing) key files
+pub const SECRET_KEY_LABEL: &str = "pngme-ed25519-secret-key";
+
+/// The label of public (verifying) key files
+pub const PUBLIC_KEY_LABEL: &str = "pngme-ed25519-public-key";
+
+/// The label of secret encryption keys
+pub const X25519_SECRET_KEY_LABEL: &str = "pngme-x25519-secret-key";
+
+/// The label of public encryption keys
+pub const X25519_PUBLIC_KEY_LABEL: &str = "pngme-x25519-public-key";
+
+/// A signing key and optionally an encryption key, as written by `keygen`. Key files hold one
+/// key per line: a label naming the kind of key, a space and the hex encoded 32-byte key.
+pub struct Keypair {
+    pub signing: SigningKey,
+    pub encryption: Option<StaticSecret>,
+}
+
+impl Keypair {
+    /// Generates a random Ed25519 signing key and, with `x25519`, a random X25519 encryption key
+    pub fn generate(x25519: bool) -> Keypair {
+        Keypair {
+            signing: generate_signing_key(),
+            encryption: x25519.then(|| StaticSecret::random_from_rng(OsRng)),
+        }
+    }
+
+    /// Parses a secret key file, which may or may not hold an encryption key
+    pub fn from_secret_file(file: &str) -> Result<Keypair> {
+        Ok(Keypair {
+            signing: parse_signing_key(file)?,
+            encryption: match find_line(file, X25519_SECRET_KEY_LABEL) {
+                Some(_) => Some(parse_x25519_secret(file)?),
+                None => None,
+            },
+        })
+    }
+
+    /// The content of the secret key file
+    pub fn secret_file(&self) -> String {
+        let mut file = secret_key_to_string(&self.signing);
+        if let Some(secret) = &self.encryption {
+            file.push_str(&format!("{} {}\n", X25519_SECRET_KEY_LABEL, to_hex(secret.as_bytes())));
+        }
+        file
+    }
+
+    /// The content of the public key file
+    pub fn public_file(&self) -> String {
+        let mut file = public_key_to_string(&self.signing.verifying_key());
+        if let Some(secret) = &self.encryption {
+            file.push_str(&format!("{} {}\n", X25519_PUBLIC_KEY_LABEL, to_hex(PublicKey::from(secret).as_bytes())));
+        }
+        file
+    }
+}
+
+/// Generates a new random signing key
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// Formats a signing key for a key file: its label and the hex encoded key on one line
+pub fn secret_key_to_string(key: &SigningKey) -> String {
+    format!("{} {}\n", SECRET_KEY_LABEL, to_hex(key.as_bytes()))
+}
+
+/// Formats a verifying key for a key file: its label and the hex encoded key on one line
+pub fn public_key_to_string(key: &VerifyingKey) -> String {
+    format!("{} {}\n", PUBLIC_KEY_LABEL, to_hex(key.as_bytes()))
+}
+
+/// Parses a secret key file
+pub fn parse_signing_key(file: &str) -> Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&parse_key(file, SECRET_KEY_LABEL)?))
+}
+
+/// Parses a public key file, or derives the public key from a secret key file
+pub fn parse_verifying_key(file: &str) -> Result<VerifyingKey> {
+    if find_line(file, SECRET_KEY_LABEL).is_some() {
+        return Ok(parse_signing_key(file)?.verifying_key());
+    }
+    Ok(VerifyingKey::from_bytes(&parse_key(file, PUBLIC_KEY_LABEL)?)?)
+}
+
+/// Parses the encryption key of a secret key file
+pub fn parse_x25519_secret(file: &str) -> Result<StaticSecret> {
+    Ok(StaticSecret::from(parse_key(file, X25519_SECRET_KEY_LABEL)?))
+}
+
+/// Parses the encryption key of a public key file, or derives it from a secret key file
+pub fn parse_x25519_public(file: &str) -> Result<PublicKey> {
+    if find_line(file, X25519_SECRET_KEY_LABEL).is_some() {
+        return Ok(PublicKey::from(&parse_x25519_secret(file)?));
+    }
+    Ok(PublicKey::from(parse_key(file, X25519_PUBLIC_KEY_LABEL)?))
+}
+
+/// Reads a secret key file
+pub fn read_signing_key<P: AsRef<Path>>(path: P) -> Result<SigningKey> {
+    read_key_file(path, parse_signing_key)
+}
+
+/// Reads a public key file (or a secret key file)
+pub fn read_verifying_key<P: AsRef<Path>>(path: P) -> Result<VerifyingKey> {
+    read_key_file(path, parse_verifying_key)
+}
+
+/// Reads the encryption key of a secret key file
+pub fn read_x25519_secret<P: AsRef<Path>>(path: P) -> Result<StaticSecret> {
+    read_key_file(path, parse_x25519_secret)
+}
+
+/// Reads the encryption key of a public key file (or a secret key file)
+pub fn read_x25519_public<P: AsRef<Path>>(path: P) -> Result<PublicKey> {
+    read_key_file(path, parse_x25519_public)
+}
+
+fn read_key_file<P: AsRef<Path>, T>(path: P, parse: fn(&str) -> Result<T>) -> Result<T> {
+    parse(&fs::read_to_string(&path)?)
+        .map_err(|e| format!("{}: {}", path.as_ref().display(), e).into())
+}
+
+/// Reads a raw 32-byte key managed outside of pngme: either the 32 bytes themselves or
+/// 64 hex digits (surrounding whitespace is ignored)
+pub fn read_raw_key<P: AsRef<Path>>(path: P) -> Result<[u8; 32]> {
+    parse_raw_key(&fs::read(&path)?).map_err(|e| format!("{}: {}", path.as_ref().display(), e).into())
+}
+
+/// Parses a raw key, see `read_raw_key`
+pub fn parse_raw_key(bytes: &[u8]) -> Result<[u8; 32]> {
+    if let Ok(key) = <[u8; 32]>::try_from(bytes) {
+        return Ok(key);
+    }
+    let hex = std::str::from_utf8(bytes).map_err(|_| "raw keys must be 32 bytes or 64 hex digits")?;
+    from_hex(hex.trim())
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| "raw keys must be 32 bytes or 64 hex digits".into())
+}
+
+/// The fingerprint identifying a public key: the hex encoded SHA-256 of the key
+pub fn fingerprint(key: &VerifyingKey) -> String {
+    sha256_hex(key.as_bytes())
+}
+
+/// Returns the key stored on the line with `label`
+fn find_line<'a>(file: &'a str, label: &str) -> Option<&'a str> {
+    file.lines().find_map(|line| {
+        let (found, key) = line.trim().split_once(' ')?;
+        (found == label).then_some(key.trim())
+    })
+}
+
+fn parse_key(file: &str, label: &str) -> Result<[u8; 32]> {
+    let key = find_line(file, label).ok_or_else(|| format!("no {} found in the key file", label))?;
+    from_hex(key)?.try_into().map_err(|_| "keys must be 32 bytes long".into())
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err("invalid hex string".into());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| "invalid hex string".into()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_files() {
+        let key = generate_signing_key();
+
+        let secret = secret_key_to_string(&key);
+        let public = public_key_to_string(&key.verifying_key());
+
+        assert!(secret.starts_with("pngme-ed25519-secret-key "));
+        assert_eq!(parse_signing_key(&secret).unwrap().as_bytes(), key.as_bytes());
+        assert_eq!(parse_verifying_key(&public).unwrap(), key.verifying_key());
+        assert_eq!(parse_verifying_key(&secret).unwrap(), key.verifying_key());
+        assert!(parse_signing_key(&public).is_err());
+    }
+
+    #[test]
+    fn test_keypair_files() {
+        let keypair = Keypair::generate(true);
+        let (secret, public) = (keypair.secret_file(), keypair.public_file());
+
+        assert_eq!(secret.lines().count(), 2);
+        assert_eq!(parse_verifying_key(&public).unwrap(), keypair.signing.verifying_key());
+        let encryption = keypair.encryption.as_ref().unwrap();
+        assert_eq!(parse_x25519_secret(&secret).unwrap().as_bytes(), encryption.as_bytes());
+        assert_eq!(parse_x25519_public(&public).unwrap(), PublicKey::from(encryption));
+        assert_eq!(parse_x25519_public(&secret).unwrap(), PublicKey::from(encryption));
+        assert!(parse_x25519_secret(&public).is_err());
+
+        let signing_only = Keypair::generate(false);
+        assert_eq!(signing_only.secret_file().lines().count(), 1);
+        assert!(parse_x25519_public(&signing_only.public_file()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_key_files() {
+        assert!(parse_signing_key("").is_err());
+        assert!(parse_signing_key("pngme-ed25519-secret-key 00ff").is_err());
+        assert!(parse_signing_key(&format!("pngme-ed25519-secret-key {}", "zz".repeat(32))).is_err());
+    }
+
+    #[test]
+    fn test_raw_keys() {
+        let key = [0xab; 32];
+        assert_eq!(parse_raw_key(&key).unwrap(), key);
+        assert_eq!(parse_raw_key(format!("{}\n", to_hex(&key)).as_bytes()).unwrap(), key);
+        assert!(parse_raw_key(b"abab").is_err());
+        assert!(parse_raw_key(&[0xff; 31]).is_err());
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00AB10").unwrap(), [0, 0xab, 0x10]);
+        assert!(from_hex("abc").is_err());
+    }
+}
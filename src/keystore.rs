@@ -0,0 +1,236 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::keys::{self, Keypair};
+use crate::Result;
+
+/// A directory of named keys: `<name>.key` holds the secret keys of an identity (if known)
+/// and `<name>.pub` its public keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+/// A key in a keystore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEntry {
+    pub name: String,
+    /// True if the secret keys are stored as well
+    pub has_secret: bool,
+    /// The fingerprint of the signing key, see `keys::fingerprint`
+    pub fingerprint: String,
+    /// True if the entry has an X25519 encryption key
+    pub has_x25519: bool,
+}
+
+impl Keystore {
+    /// Opens the keystore in `dir`, which is created when the first key is imported
+    pub fn new<P: AsRef<Path>>(dir: P) -> Keystore {
+        Keystore { dir: dir.as_ref().to_path_buf() }
+    }
+
+    /// The default keystore directory: `$XDG_CONFIG_HOME/pngme/keys`, or `~/.config/pngme/keys`
+    pub fn default_dir() -> Option<PathBuf> {
+        let config = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config.join("pngme").join("keys"))
+    }
+
+    /// The directory of this keystore
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The path of the secret key file of `name`
+    pub fn secret_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.key", name))
+    }
+
+    /// The path of the public key file of `name`
+    pub fn public_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.pub", name))
+    }
+
+    /// Lists the keys, sorted by name
+    pub fn list(&self) -> Result<Vec<KeyEntry>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().is_some_and(|extension| extension == "pub") {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                entries.push(self.entry(&name)?);
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// Imports a secret or public key file under `name`. The public keys of a secret key file
+    /// are stored as well. Existing keys with that name are replaced, but importing a public
+    /// key over a stored secret key, which deletes it, needs `force`.
+    pub fn import(&self, name: &str, key_file: &str, force: bool) -> Result<KeyEntry> {
+        validate_name(name)?;
+        fs::create_dir_all(&self.dir)?;
+
+        if key_file.contains(keys::SECRET_KEY_LABEL) {
+            let keypair = Keypair::from_secret_file(key_file)?;
+            write_private(&self.secret_path(name), &keypair.secret_file())?;
+            fs::write(self.public_path(name), keypair.public_file())?;
+        } else {
+            keys::parse_verifying_key(key_file)?;
+            if key_file.contains(keys::X25519_PUBLIC_KEY_LABEL) {
+                keys::parse_x25519_public(key_file)?;
+            }
+            if self.secret_path(name).exists() {
+                if !force {
+                    return Err(format!(
+                        "'{}' has a secret key, importing a public key would delete it (pass --force to do so)",
+                        name,
+                    ).into());
+                }
+                fs::remove_file(self.secret_path(name))?;
+            }
+            fs::write(self.public_path(name), key_file)?;
+        }
+        self.entry(name)
+    }
+
+    /// Returns the public key file of `name`, or with `secret` its secret key file
+    pub fn export(&self, name: &str, secret: bool) -> Result<String> {
+        let path = if secret { self.secret_path(name) } else { self.public_path(name) };
+        fs::read_to_string(&path).map_err(|_| {
+            let kind = if secret { "secret key" } else { "key" };
+            format!("no {} named '{}' in {}", kind, name, self.dir.display()).into()
+        })
+    }
+
+    /// Resolves a `--key` argument: an existing file is used as is, anything else is looked up
+    /// by name in the keystore (its secret key file with `secret`, else its public key file)
+    pub fn resolve<P: AsRef<Path>>(&self, key: P, secret: bool) -> PathBuf {
+        let key = key.as_ref();
+        match key.to_str() {
+            Some(name) if !key.exists() && validate_name(name).is_ok() => {
+                if secret { self.secret_path(name) } else { self.public_path(name) }
+            }
+            _ => key.to_path_buf(),
+        }
+    }
+
+    fn entry(&self, name: &str) -> Result<KeyEntry> {
+        let public = self.export(name, false)?;
+        Ok(KeyEntry {
+            name: name.to_owned(),
+            has_secret: self.secret_path(name).exists(),
+            fingerprint: keys::fingerprint(&keys::parse_verifying_key(&public)?),
+            has_x25519: keys::parse_x25519_public(&public).is_ok(),
+        })
+    }
+}
+
+/// Key names are letters, digits, `.`, `_` and `-`, and don't start with a dot
+fn validate_name(name: &str) -> Result<()> {
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if name.is_empty() || name.starts_with('.') || !valid {
+        return Err(format!("invalid key name '{}' (use letters, digits, '.', '_' and '-')", name).into());
+    }
+    Ok(())
+}
+
+/// Writes a file only its owner can read (on Unix). The permissions of an existing file are
+/// restricted as well, before anything is written.
+pub(crate) fn write_private(path: &Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_and_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = Keystore::new(dir.path().join("keys"));
+        assert!(keystore.list().unwrap().is_empty());
+
+        let alice = Keypair::generate(true);
+        let bob = Keypair::generate(false);
+        keystore.import("alice", &alice.secret_file(), false).unwrap();
+        keystore.import("bob", &bob.public_file(), false).unwrap();
+
+        let entries = keystore.list().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "alice");
+        assert!(entries[0].has_secret && entries[0].has_x25519);
+        assert_eq!(entries[0].fingerprint, keys::fingerprint(&alice.signing.verifying_key()));
+        assert!(!entries[1].has_secret && !entries[1].has_x25519);
+
+        assert_eq!(keystore.export("alice", false).unwrap(), alice.public_file());
+        assert_eq!(keystore.export("alice", true).unwrap(), alice.secret_file());
+        assert!(keystore.export("bob", true).is_err());
+    }
+
+    #[test]
+    fn test_invalid_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = Keystore::new(dir.path());
+
+        assert!(keystore.import("../escape", &Keypair::generate(false).public_file(), false).is_err());
+        assert!(keystore.import("garbage", "not a key", false).is_err());
+        assert!(keystore.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_public_import_keeps_secret_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = Keystore::new(dir.path());
+        let alice = Keypair::generate(true);
+        keystore.import("alice", &alice.secret_file(), false).unwrap();
+
+        let other = Keypair::generate(true).public_file();
+        assert!(keystore.import("alice", &other, false).is_err());
+        assert_eq!(keystore.export("alice", true).unwrap(), alice.secret_file());
+        assert!(!keystore.import("alice", &other, true).unwrap().has_secret);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private_restricts_existing_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("alice.key");
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, "secret").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = Keystore::new(dir.path());
+        let file = dir.path().join("ci.key");
+        fs::write(&file, "").unwrap();
+
+        assert_eq!(keystore.resolve(&file, true), file);
+        assert_eq!(keystore.resolve("alice", true), dir.path().join("alice.key"));
+        assert_eq!(keystore.resolve("alice", false), dir.path().join("alice.pub"));
+    }
+}
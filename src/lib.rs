@@ -1,52 +1,1613 @@
+pub mod archive;
+pub mod attest;
+pub mod batch;
 pub mod chunk;
 pub mod chunk_type;
+pub mod codec;
+pub mod crypto;
+pub mod dump;
+pub mod envelope;
+pub mod error;
+pub mod filter;
+pub mod generate;
+pub mod icc;
+pub mod image;
+pub mod keys;
+pub mod keystore;
+pub mod license;
+#[cfg(all(feature = "mount", target_os = "linux"))]
+pub mod mount;
+pub mod plugin;
 pub mod png;
+pub mod positioned;
+pub mod provenance;
+pub mod qr;
+pub mod remote;
+pub mod report;
+pub mod rules;
+pub mod scan;
+pub mod session;
+pub mod sniff;
+pub mod stego;
+pub mod template;
+pub mod text;
+pub mod visit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-use std::{path::Path, str::FromStr};
-use png::Png;
+use std::{fs, io::{Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, str::FromStr};
 use chunk::Chunk;
-use chunk_type::ChunkType;
+use attest::Attestation;
+use crypto::{Encryption, Secret};
+use dump::Dump;
+use envelope::{EmbeddedFile, Envelope, Location, Manifest, Part, Replica, StructFormat};
+use error::PngMeError;
+use generate::GenOptions;
+use icc::IccProfile;
+use keystore::{KeyEntry, Keystore};
+use license::License;
+use plugin::{Outcome, PluginRegistry};
+use png::{ChunkListing, InvalidLength, LimitExceeded, ParseLimits, Png, Violation};
+use qr::QrOptions;
+use report::{DocumentFormat, Report};
+use rules::Rule;
+use session::Session;
+use scan::{FileReport, FindingKind};
+use chunk_type::{ChunkType, ChunkTypePattern};
+use stego::{AlphaBackend, ChunkBackend, LsbBackend, LsbEstimate, StegoBackend};
+use text::TextChunk;
 
-pub type Error = Box<dyn std::error::Error>;
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Encodes a message into a PNG file and saves the result
+/// Converts an Apple CgBI ("iOS optimized") PNG into a standard PNG and saves the result
+pub fn convert_from_cgbi<P: AsRef<Path>>(session: &Session, file_path: P, output_file: Option<P>) -> Result<()> {
+    let mut png = Png::try_from(&session.read_file(&file_path)?[..])?;
+
+    image::convert_from_cgbi(&mut png)?;
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Moves a payload hidden with the `from` backend to the `to` backend and saves the result.
+/// The envelope is moved as is, so an encrypted payload stays encrypted to the same recipients
+/// or key. If the two backends bind different parts of the image (see
+/// [`crypto::carrier_binding`]), an encrypted payload is rebound, which needs `secret`.
+pub fn convert_mode<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    from: &dyn StegoBackend,
+    to: &dyn StegoBackend,
+    secret: Option<&Secret>,
+    output_file: Option<P>,
+) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+    let data = from.extract(&png)?;
+    let old_binding = crypto::carrier_binding(&png, !from.modifies_image_data())?;
+    from.remove(&mut png)?;
+
+    let envelope = Envelope::open(data.clone())?;
+    let new_binding = crypto::carrier_binding(&png, !to.modifies_image_data())?;
+    if envelope.is_encrypted() && new_binding != old_binding {
+        let payload = crypto::rebind(envelope.payload(), secret, &old_binding, &new_binding)?;
+        to.embed(&mut png, &Envelope::with_flags(envelope.flags(), payload).as_bytes())?;
+    } else {
+        to.embed(&mut png, &data)?;
+    }
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Returns an error if the file at `file_path` is larger than `max_size` bytes.
+/// Only the file metadata is inspected, so oversized files are rejected without being read.
+/// Pipes and other streams have no size until they are read: they are read into the session
+/// (see `Session::read_file_limited`), stopping one byte past the limit.
+pub fn check_file_size<P: AsRef<Path>>(session: &Session, file_path: P, max_size: u64) -> Result<()> {
+    if positioned::is_stream(&file_path) {
+        session.read_file_limited(&file_path, max_size)?;
+    } else if fs::metadata(file_path)?.len() > max_size {
+        return Err(LimitExceeded::TotalSize(max_size as usize).into());
+    }
+    Ok(())
+}
+
+/// Encodes a message into a PNG file and saves the result. Critical and public chunk types are
+/// refused, see [`EncodeOptions`].
 pub fn encode<P: AsRef<Path>>(
+    session: &Session,
     file_path: P,
     chunk_type: &str,
     message: String,
     output_file: Option<P>,
 )-> Result<()> {
-    let mut png = Png::from_file(&file_path)?;
+    encode_with_options(session, file_path, chunk_type, message.as_bytes(), &EncodeOptions::default(), output_file)
+}
+
+/// Options of `encode_with_options`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EncodeOptions {
+    /// Allows hiding the message in a critical or public chunk type such as `IHDR` or `IDAT`,
+    /// which usually breaks the image
+    pub allow_critical: bool,
+}
+
+impl EncodeOptions {
+    /// Refuses a chunk type that isn't ancillary and private unless `allow_critical` is set:
+    /// decoders reject unknown critical chunks, and public types are reserved to the PNG spec
+    /// (every registered type is public)
+    pub fn check(&self, chunk_type: &ChunkType) -> Result<()> {
+        if self.allow_critical {
+            return Ok(());
+        }
+        let reason = match chunk_type.description() {
+            Some(description) => format!("it is a registered chunk type ({})", description),
+            None if chunk_type.is_critical() => "it is critical, decoders reject unknown critical chunks".to_owned(),
+            None if chunk_type.is_public() => "it is public, public types are reserved to the PNG spec".to_owned(),
+            None => return Ok(()),
+        };
+        Err(format!("refusing to hide data in {}: {}; use an ancillary private type such as ruSt, or --force", chunk_type, reason).into())
+    }
+}
 
+/// Encodes a message into a chunk of a PNG file like `encode`, checking the chunk type with
+/// `options`, and saves the result
+pub fn encode_with_options<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    chunk_type: &str,
+    message: &[u8],
+    options: &EncodeOptions,
+    output_file: Option<P>,
+) -> Result<()> {
     let chunk_type = ChunkType::from_str(chunk_type)?;
-    let chunk = Chunk::new(chunk_type, message.into());
+    options.check(&chunk_type)?;
+    encode_with_backend(session, file_path, &ChunkBackend::new(chunk_type), message, output_file)
+}
+
+/// Hides a payload in a PNG file using the given steganography backend and saves the result.
+/// The payload is wrapped in an [`Envelope`] so `decode --auto` can find it later.
+pub fn encode_with_backend<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    payload: &[u8],
+    output_file: Option<P>,
+) -> Result<()> {
+    embed_envelope(session, file_path, backend, &Envelope::new(payload.to_vec()), output_file)
+}
+
+/// Hides a payload read from `message` (a file, stdin, ...) in a PNG file using the given
+/// steganography backend and saves the result, like `encode_with_backend`
+pub fn encode_reader<P: AsRef<Path>, R: Read>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    message: R,
+    output_file: Option<P>,
+) -> Result<()> {
+    encode_with_backend(session, file_path, backend, &read_message(message)?, output_file)
+}
+
+/// Reads a whole message to hide from `message`
+pub fn read_message<R: Read>(mut message: R) -> Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    message.read_to_end(&mut payload)?;
+    Ok(payload)
+}
+
+/// Hides a payload in a chunk of the given type of a PNG file held in memory and returns the
+/// resulting file, without touching the filesystem. The payload is wrapped in an [`Envelope`]
+/// like `encode_with_backend` does.
+pub fn encode_bytes(png_bytes: &[u8], chunk_type: &str, payload: &[u8]) -> Result<Vec<u8>> {
+    let backend = ChunkBackend::new(ChunkType::from_str(chunk_type)?);
+    encode_bytes_with_backend(png_bytes, &backend, payload)
+}
+
+/// Hides a payload in a PNG file held in memory with the given steganography backend and
+/// returns the resulting file, like `encode_bytes`
+pub fn encode_bytes_with_backend(png_bytes: &[u8], backend: &dyn StegoBackend, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut png = Png::try_from(png_bytes)?;
+    backend.embed(&mut png, &Envelope::new(payload.to_vec()).as_bytes())?;
+    Ok(png.as_bytes())
+}
+
+/// Extracts the payload hidden in a chunk of the given type of a PNG file held in memory,
+/// e.g. by `encode_bytes`, without touching the filesystem. Encrypted payloads are refused.
+pub fn decode_bytes(png_bytes: &[u8], chunk_type: &str) -> Result<Vec<u8>> {
+    let backend = ChunkBackend::new(ChunkType::from_str(chunk_type)?);
+    decode_bytes_with_backend(png_bytes, &backend, None)
+}
+
+/// Extracts the payload hidden in a PNG file held in memory with the given steganography
+/// backend, decrypting it with `secret` if it is encrypted
+pub fn decode_bytes_with_backend(png_bytes: &[u8], backend: &dyn StegoBackend, secret: Option<&Secret>) -> Result<Vec<u8>> {
+    let png = Png::try_from(png_bytes)?;
+    open_payload(&png, backend, secret)
+}
+
+/// Appends `data` to the payload hidden in a PNG file with the given steganography backend
+/// (or hides it if there is none yet) and saves the result, e.g. to accumulate log records
+/// in one chunk. The payload is rewritten where it is stored when the backend allows it (see
+/// `StegoBackend::update`). Encrypted, split and serialized payloads can't be appended to.
+pub fn append_with_backend<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    data: &[u8],
+    output_file: Option<P>,
+) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+
+    // The pixel bits always decode to something, only an envelope there is a payload
+    let existing = backend.extract(&png).ok()
+        .filter(|existing| !backend.modifies_image_data() || Envelope::is_envelope(existing));
+    match existing {
+        Some(existing) => {
+            let envelope = Envelope::open(existing)?;
+            if envelope.flags() != 0 {
+                return Err("only plain payloads can be appended to, not encrypted, split or serialized ones".into());
+            }
+            let mut payload = envelope.payload().to_vec();
+            payload.extend(data);
+            backend.update(&mut png, &Envelope::new(payload).as_bytes())?;
+        }
+        None => backend.embed(&mut png, &Envelope::new(data.to_vec()).as_bytes())?,
+    }
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Hides a payload in a PNG file using the given steganography backend and saves the result,
+/// like `encode_with_backend`. With `compress`, the payload is compressed with zlib first and
+/// decoding inflates it again transparently. With `encryption`, it is encrypted (see
+/// [`crypto::encrypt`]) and bound to the image, see [`crypto::carrier_binding`].
+pub fn encode_sealed<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    payload: &[u8],
+    compress: bool,
+    encryption: Option<&Encryption>,
+    output_file: Option<P>,
+) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+
+    let flags = if compress { Envelope::FLAG_COMPRESSED } else { 0 };
+    let envelope = seal(&png, backend, flags, payload, encryption)?;
+    backend.embed(&mut png, &envelope.as_bytes())?;
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Wraps `payload` in an envelope with `flags` for hiding it in `png` with the given
+/// steganography backend, compressing it if [`Envelope::FLAG_COMPRESSED`] is set and then
+/// encrypting it if `encryption` is given
+fn seal(
+    png: &Png,
+    backend: &dyn StegoBackend,
+    flags: u8,
+    payload: &[u8],
+    encryption: Option<&Encryption>,
+) -> Result<Envelope> {
+    let compressed;
+    let payload = if flags & Envelope::FLAG_COMPRESSED != 0 {
+        compressed = image::deflate(payload)?;
+        &compressed[..]
+    } else {
+        payload
+    };
+    let Some(encryption) = encryption else {
+        return Ok(Envelope::with_flags(flags, payload.to_vec()));
+    };
+    let binding = crypto::carrier_binding(png, !backend.modifies_image_data())?;
+    let payload = crypto::encrypt(payload, encryption, &binding)?;
+    Ok(Envelope::with_flags(flags | Envelope::FLAG_ENCRYPTED, payload))
+}
+
+/// Hides a file of any type with its name (see [`EmbeddedFile`]) in a PNG file using the given
+/// steganography backend, encrypting it if `encryption` is given, and saves the result
+pub fn encode_file<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    payload_path: P,
+    encryption: Option<&Encryption>,
+    output_file: Option<P>,
+) -> Result<()> {
+    let payload_path = payload_path.as_ref();
+    let name = payload_path.file_name()
+        .ok_or_else(|| format!("{} is not a file", payload_path.display()))?
+        .to_string_lossy();
+    let file = EmbeddedFile::new(&name, session.read_file(payload_path)?)?;
+
+    let mut png = session.load_png(&file_path)?;
+    let envelope = seal(&png, backend, Envelope::FLAG_FILE, &file.as_bytes(), encryption)?;
+    backend.embed(&mut png, &envelope.as_bytes())?;
+
+    let target = output_file.as_ref().unwrap_or(&file_path).as_ref();
+    session.save_png(&png, target)?;
+    session.status(target, &format!("Embedded {} ({} bytes)", file.name(), file.data().len()));
+    Ok(())
+}
+
+/// Writes a file hidden by `encode_file` into `dir` under its original name, decrypting it
+/// with `secret` if needed. The name comes from the PNG file, so an existing file is only
+/// replaced if `overwrite` is set. Returns the path the file was written to.
+pub fn extract_file<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    dir: P,
+    secret: Option<&Secret>,
+    overwrite: bool,
+) -> Result<PathBuf> {
+    let png = session.load_png(&file_path)?;
+    let envelope = open_envelope(&png, backend, secret)?;
+    if !envelope.is_file() {
+        return Err("the payload isn't a file hidden with encode-file, extract it with decode --save".into());
+    }
+    let file = EmbeddedFile::from_bytes(envelope.payload())?;
+
+    fs::create_dir_all(&dir)?;
+    let path = dir.as_ref().join(file.name());
+    let mut options = fs::OpenOptions::new();
+    match overwrite {
+        true => options.write(true).create(true).truncate(true),
+        false => options.write(true).create_new(true),
+    };
+    let mut output = options.open(&path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => format!("{} already exists, pass --force to overwrite it", path.display()).into(),
+        _ => Error::from(e),
+    })?;
+    output.write_all(file.data())?;
+    Ok(path)
+}
+
+/// Archives a directory (see [`archive::pack`]), optionally encrypts the archive, hides it in
+/// a PNG file using the given steganography backend and saves the result
+pub fn encode_dir<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    dir: P,
+    backend: &dyn StegoBackend,
+    compress: bool,
+    encryption: Option<&Encryption>,
+    output_file: Option<P>,
+) -> Result<()> {
+    let archive = archive::pack(dir, compress)?;
+    let target = output_file.as_ref().unwrap_or(&file_path).as_ref().to_path_buf();
+    encode_sealed(session, file_path, backend, &archive, false, encryption, output_file)?;
+    session.status(&target, &format!("Archived {} bytes", archive.len()));
+    Ok(())
+}
+
+/// Extracts a directory hidden by `encode_dir` into `dir`. An encrypted archive is decrypted
+/// with `secret`. Returns the number of entries extracted.
+pub fn extract_dir<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    dir: P,
+    secret: Option<&Secret>,
+) -> Result<usize> {
+    let payload = decode_to_bytes(session, &file_path, backend, secret)?;
+    archive::unpack(&payload, &dir)
+}
+
+/// Mounts the payload hidden in a PNG file read-only at `mountpoint` (requires the `mount`
+/// feature, Linux only) until it is unmounted. A directory hidden with `encode_dir` is
+/// browsable as is, any other payload shows up as a single file.
+pub fn mount<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    mountpoint: P,
+    secret: Option<&Secret>,
+) -> Result<()> {
+    let png = session.load_png(&file_path)?;
+    let envelope = open_envelope(&png, backend, secret)?;
+    if envelope.is_file() {
+        let file = EmbeddedFile::from_bytes(envelope.payload())?;
+        let name = file.name().to_owned();
+        return mount_tree(archive::Tree::single_file(&name, file.into_data()), mountpoint.as_ref());
+    }
+
+    let payload = envelope.into_payload();
+    let tree = match archive::Tree::from_archive(&payload) {
+        Ok(tree) => tree,
+        Err(_) => {
+            let name = sniff::with_extension("payload", &payload);
+            archive::Tree::single_file(&name.to_string_lossy(), payload)
+        }
+    };
+    mount_tree(tree, mountpoint.as_ref())
+}
+
+#[cfg(all(feature = "mount", target_os = "linux"))]
+fn mount_tree(tree: archive::Tree, mountpoint: &Path) -> Result<()> {
+    mount::mount(tree, mountpoint)
+}
+
+#[cfg(not(all(feature = "mount", target_os = "linux")))]
+fn mount_tree(_tree: archive::Tree, _mountpoint: &Path) -> Result<()> {
+    Err("pngme was built without the mount feature (Linux only)".into())
+}
+
+/// Serializes `value` (e.g. save game data) and hides it in the `chunk_type` chunk of a PNG
+/// file, then saves the result. `decode_struct` reads it back.
+pub fn encode_struct<P: AsRef<Path>, T: serde::Serialize>(
+    session: &Session,
+    file_path: P,
+    chunk_type: &str,
+    value: &T,
+    format: StructFormat,
+    output_file: Option<P>,
+) -> Result<()> {
+    let backend = ChunkBackend::new(ChunkType::from_str(chunk_type)?);
+    embed_envelope(session, file_path, &backend, &Envelope::from_struct(value, format)?, output_file)
+}
+
+/// Reads a value hidden in the `chunk_type` chunk of a PNG file by `encode_struct`
+pub fn decode_struct<P: AsRef<Path>, T: serde::de::DeserializeOwned>(session: &Session, file_path: P, chunk_type: &str) -> Result<T> {
+    let backend = ChunkBackend::new(ChunkType::from_str(chunk_type)?);
+    extract_envelope(&session.load_png(&file_path)?, &backend)?.to_struct()
+}
+
+/// Splits a payload into one part per file and hides each part in its file
+/// using the given steganography backend. The files are modified in place.
+pub fn encode_across<P: AsRef<Path>>(
+    session: &Session,
+    file_paths: &[P],
+    backend: &dyn StegoBackend,
+    payload: &[u8],
+) -> Result<()> {
+    let parts = Part::split(payload, file_paths.len())?;
+    for (file_path, part) in file_paths.iter().zip(parts) {
+        embed_envelope(session, file_path, backend, &part.to_envelope(), None)?;
+    }
+    Ok(())
+}
+
+fn embed_envelope<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    envelope: &Envelope,
+    output_file: Option<P>,
+) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+
+    backend.embed(&mut png, &envelope.as_bytes())?;
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Estimates how much hiding a `message_len` byte message in a PNG file with `backend`
+/// changes the image (see `LsbBackend::estimate`), counting the envelope around the message
+pub fn lsb_estimate<P: AsRef<Path>>(session: &Session, file_path: P, backend: &LsbBackend, message_len: usize) -> Result<LsbEstimate> {
+    let png = session.load_png(&file_path)?;
+    backend.estimate(&png, Envelope::new(vec![0; message_len]).as_bytes().len())
+}
+
+/// Searches for a message hidden in a PNG file and returns the message if one is found
+pub fn decode<P: AsRef<Path>>(session: &Session, file_path: P, chunt_type: &str) -> Result<String> {
+    let backend = ChunkBackend::new(ChunkType::from_str(chunt_type)?);
+    decode_with_backend(session, file_path, &backend, None)
+}
+
+/// Extracts a message hidden with the given steganography backend and returns it.
+/// Encrypted messages are decrypted with `secret`. Payloads that aren't text are refused,
+/// see `decode_to_bytes`.
+pub fn decode_with_backend<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    secret: Option<&Secret>,
+) -> Result<String> {
+    let png = session.load_png(&file_path)?;
+    envelope_text(open_envelope(&png, backend, secret)?)
+}
+
+/// Searches for messages hidden in every chunk of the given type of a PNG file, not only the
+/// first one, and returns the index of each chunk with its message or the reason it couldn't
+/// be decoded
+pub fn decode_all<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    chunk_type: &str,
+    secret: Option<&Secret>,
+) -> Result<Vec<(usize, Result<String>)>> {
+    let png = session.load_png(&file_path)?;
+    let backend = ChunkBackend::new(ChunkType::from_str(chunk_type)?);
+    let indices: Vec<usize> = png.chunks().iter()
+        .enumerate()
+        .filter(|(_, chunk)| chunk_type == chunk.chunk_type())
+        .map(|(index, _)| index)
+        .collect();
+    if indices.is_empty() {
+        return Err(png.chunk_not_found(chunk_type));
+    }
+
+    Ok(indices.iter()
+        .map(|&index| {
+            // Leaves only this chunk of the type, so the backend finds it and nothing else
+            let single = Png::from_chunks(png.chunks().iter()
+                .enumerate()
+                .filter(|&(position, chunk)| position == index || chunk_type != chunk.chunk_type())
+                .map(|(_, chunk)| chunk.clone())
+                .collect());
+            (index, open_envelope(&single, &backend, secret).and_then(envelope_text))
+        })
+        .collect())
+}
+
+/// Returns the text of a decoded envelope, refusing files and binary payloads
+fn envelope_text(envelope: Envelope) -> Result<String> {
+    if envelope.is_file() {
+        let file = EmbeddedFile::from_bytes(envelope.payload())?;
+        return Err(format!("the payload is the file {} ({} bytes), extract it with `pngme extract`", file.name(), file.data().len()).into());
+    }
+    let message = String::from_utf8(envelope.into_payload()).map_err(|e| match sniff::detect(e.as_bytes()) {
+        Some(kind) => format!("the payload is a binary {} file, extract it with --save or print it with --hex", kind.mime_type()),
+        None => format!("the payload is not valid UTF-8 ({}), print it with --hex or --base64 or extract it with --save", e.utf8_error()),
+    })?;
+    Ok(message)
+}
+
+/// Extracts a payload hidden with the given steganography backend and writes it to `payload_path`,
+/// or to stdout for `-`. If `payload_path` has no extension, the one matching the detected file
+/// type is appended. Encrypted payloads are decrypted with `secret`. Returns the path the payload
+/// was written to.
+pub fn extract_with_backend<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    payload_path: P,
+    secret: Option<&Secret>,
+) -> Result<PathBuf> {
+    let payload = decode_to_bytes(session, &file_path, backend, secret)?;
+    let payload_path = if payload_path.as_ref() == Path::new(positioned::STDIO) {
+        payload_path.as_ref().to_path_buf()
+    } else {
+        sniff::with_extension(payload_path, &payload)
+    };
+    save_payload(session, &payload_path, &payload)?;
+    Ok(payload_path)
+}
+
+/// Writes a decoded payload as is to `payload_path`, or to stdout for `-`
+pub fn save_payload<P: AsRef<Path>>(session: &Session, payload_path: P, payload: &[u8]) -> Result<()> {
+    let payload_path = payload_path.as_ref();
+    if payload_path == Path::new(positioned::STDIO) {
+        std::io::stdout().lock().write_all(payload)?;
+    } else {
+        fs::write(payload_path, payload)?;
+    }
+
+    let kind = sniff::detect(payload).map_or("unknown type", |kind| kind.mime_type());
+    let line = format!("Extracted {} bytes ({}) to {}", payload.len(), kind, payload_path.display());
+    session.status(payload_path, &line);
+    Ok(())
+}
+
+/// Extracts a payload hidden with the given steganography backend and returns it, whatever
+/// its content, unwrapping its envelope and decrypting it with `secret` if needed
+pub fn decode_to_bytes<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    backend: &dyn StegoBackend,
+    secret: Option<&Secret>,
+) -> Result<Vec<u8>> {
+    let png = session.load_png(&file_path)?;
+    open_payload(&png, backend, secret)
+}
+
+/// Extracts a payload hidden in `png` with the given steganography backend, unwrapping its
+/// envelope and decrypting it if needed. Only the content of an [`EmbeddedFile`] is returned.
+fn open_payload(png: &Png, backend: &dyn StegoBackend, secret: Option<&Secret>) -> Result<Vec<u8>> {
+    let envelope = open_envelope(png, backend, secret)?;
+    if envelope.is_file() {
+        return Ok(EmbeddedFile::from_bytes(envelope.payload())?.into_data());
+    }
+    Ok(envelope.into_payload())
+}
+
+/// Extracts the envelope hidden in `png` with the given steganography backend, decrypting and
+/// inflating its payload if needed. The other flags of the envelope are kept.
+fn open_envelope(png: &Png, backend: &dyn StegoBackend, secret: Option<&Secret>) -> Result<Envelope> {
+    let envelope = extract_envelope(png, backend)?;
+    if !envelope.is_encrypted() {
+        return envelope.decompressed();
+    }
+    let binding = crypto::carrier_binding(png, !backend.modifies_image_data())?;
+    let payload = crypto::decrypt(envelope.payload(), secret, &binding)?;
+    Envelope::with_flags(envelope.flags() & !Envelope::FLAG_ENCRYPTED, payload).decompressed()
+}
+
+/// Extracts the envelope hidden with the given steganography backend, refusing parts of
+/// split payloads
+fn extract_envelope(png: &Png, backend: &dyn StegoBackend) -> Result<Envelope> {
+    let envelope = Envelope::open(backend.extract(png)?)?;
+    if envelope.is_part() {
+        let part = Part::from_envelope(&envelope)?;
+        return Err(format!(
+            "found part {} of {} of a split payload, decode it with --combine",
+            part.index() + 1,
+            part.count(),
+        ).into());
+    }
+    Ok(envelope)
+}
+
+/// Reassembles a message split across several files with `encode_across` and returns it.
+/// The files may be given in any order.
+pub fn decode_combine<P: AsRef<Path>>(session: &Session, file_paths: &[P], backend: &dyn StegoBackend) -> Result<String> {
+    Ok(String::from_utf8(decode_combine_to_bytes(session, file_paths, backend)?)?)
+}
+
+/// Reassembles a payload split across several files with `encode_across` and returns it as is,
+/// like `decode_combine` without requiring it to be text
+pub fn decode_combine_to_bytes<P: AsRef<Path>>(
+    session: &Session,
+    file_paths: &[P],
+    backend: &dyn StegoBackend,
+) -> Result<Vec<u8>> {
+    let parts = file_paths.iter()
+        .map(|file_path| {
+            let png = session.load_png(file_path)?;
+            Part::from_envelope(&Envelope::open(backend.extract(&png)?)?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Part::combine(parts)
+}
+
+/// Describes every pngme payload stored in the chunks of a PNG file or after `IEND`,
+/// whatever chunk type was used to store it. Returns where each payload was found with its
+/// description, or the reason it couldn't be read.
+pub fn decode_auto<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<Vec<(String, Result<String>)>> {
+    let png = if session.fits_in_memory(&file_path)? {
+        session.load_png(&file_path)?
+    } else {
+        // Payloads are never stored in critical chunks
+        session.stream_png(&file_path, |_, chunk| Ok(!chunk.chunk_type().is_critical()))?
+    };
+    let found = envelope::find_all(&png);
+    if found.is_empty() {
+        return Err("no pngme payload found".into());
+    }
+
+    Ok(found.into_iter()
+        .map(|(location, envelope)| {
+            let location = match location {
+                Location::Chunk(index) => format!("{} chunk #{}", png.chunks()[index].chunk_type(), index),
+                Location::Trailer => "data after IEND".to_owned(),
+            };
+            (location, envelope.and_then(describe_envelope))
+        })
+        .collect())
+}
+
+/// One line describing a payload found by `decode_auto`
+fn describe_envelope(envelope: Envelope) -> Result<String> {
+    if envelope.is_part() {
+        let part = Part::from_envelope(&envelope)?;
+        Ok(format!("part {} of {} of a split payload", part.index() + 1, part.count()))
+    } else if envelope.is_encrypted() {
+        Ok(format!("encrypted payload ({} bytes)", envelope.payload().len()))
+    } else if envelope.is_file() {
+        let file = EmbeddedFile::from_bytes(envelope.payload())?;
+        Ok(format!("file {} ({} bytes)", file.name(), file.data().len()))
+    } else if envelope.is_replica() {
+        let replica = Replica::from_envelope(&envelope)?;
+        Ok(format!("copy {} of {} of a payload", replica.index() + 1, replica.count()))
+    } else if envelope.is_manifest() {
+        let manifest = Manifest::from_envelope(&envelope)?;
+        Ok(format!("payload split into {} chunks ({} bytes)", manifest.count(), manifest.size()))
+    } else {
+        Ok(String::from_utf8_lossy(envelope.decompressed()?.payload()).into_owned())
+    }
+}
+
+/// Returns every pngme payload stored in the chunks of a PNG file or after `IEND` as a JSON
+/// array, like `decode_auto` describes them as text. Encrypted payloads are left as they are.
+pub fn decode_auto_json<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<serde_json::Value> {
+    let png = session.load_png(&file_path)?;
+    let found = envelope::find_all(&png);
+    if found.is_empty() {
+        return Err("no pngme payload found".into());
+    }
+
+    let entries: Vec<serde_json::Value> = found.into_iter()
+        .map(|(location, envelope)| {
+            let (chunk_type, chunk_index) = match location {
+                Location::Chunk(index) => (Some(png.chunks()[index].chunk_type().to_string()), Some(index)),
+                Location::Trailer => (None, None),
+            };
+            let envelope = envelope.and_then(|envelope| {
+                if envelope.is_encrypted() { Ok(envelope) } else { envelope.decompressed() }
+            });
+            match envelope {
+                Ok(envelope) => serde_json::json!({
+                    "chunk_type": chunk_type,
+                    "chunk_index": chunk_index,
+                    "flags": envelope.flags(),
+                    "encrypted": envelope.is_encrypted(),
+                    "payload": dump::payload_json(envelope.payload()),
+                }),
+                Err(e) => serde_json::json!({
+                    "chunk_type": chunk_type,
+                    "chunk_index": chunk_index,
+                    "error": e.to_string(),
+                }),
+            }
+        })
+        .collect();
+    Ok(serde_json::Value::Array(entries))
+}
+
+/// Returns the type of the first chunk of a PNG file matching `chunk_type` when case is
+/// ignored, or `chunk_type` itself if there is none
+pub fn find_chunk_type<P: AsRef<Path>>(session: &Session, file_path: P, chunk_type: &str) -> Result<String> {
+    let png = session.load_png(&file_path)?;
+    Ok(png.chunk_by_type_ignore_case(chunk_type)
+        .map_or_else(|| chunk_type.to_owned(), |chunk| chunk.chunk_type().to_string()))
+}
+
+/// Replaces the message hidden in chunks of the given type of a PNG file and saves the result.
+/// Every chunk of the old payload is removed, its copies and pieces included (see
+/// [`ChunkBackend::stored_positions`]), and the new message takes the place of the first one.
+/// The message is wrapped in an [`Envelope`] like `encode` does, and the chunk type is checked
+/// with `options`.
+pub fn replace<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    chunk_type: &str,
+    message: &[u8],
+    options: &EncodeOptions,
+    output_file: Option<P>,
+) -> Result<()> {
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    options.check(&chunk_type)?;
+    let mut png = session.load_png(&file_path)?;
+    let Some(first) = png.chunks().iter().position(|chunk| chunk.chunk_type() == &chunk_type) else {
+        return Err(png.chunk_not_found(&chunk_type.to_string()));
+    };
+
+    let positions = ChunkBackend::new(chunk_type).stored_positions(&png)?;
+    for &position in positions.iter().rev() {
+        png.chunks_mut().remove(position);
+    }
+    let before = positions.iter().filter(|&&position| position < first).count();
+    png.insert_chunk_at(first - before, Chunk::new(chunk_type, Envelope::new(message.to_vec()).as_bytes()))?;
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Removes a chunk from a PNG file, saves the result
+/// and returns the removed chunk. With `save`, the removed chunk is written there first
+/// (see `save_chunk`), so it isn't lost if that fails.
+pub fn remove<P: AsRef<Path>>(session: &Session, file_path: P, chunk_type: &str, save: Option<&Path>) -> Result<Chunk> {
+    let mut png = session.load_png(&file_path)?;
+    let chunk = png.remove_chunk(chunk_type)?;
+    save_chunk(session, &chunk, save)?;
+    session.save_png(&png, file_path)?;
+    Ok(chunk)
+}
+
+/// Removes the chunk at position `index` (starting at 0) from a PNG file, saves the result
+/// and returns the removed chunk. `save` works like in `remove`.
+pub fn remove_at<P: AsRef<Path>>(session: &Session, file_path: P, index: usize, save: Option<&Path>) -> Result<Chunk> {
+    let mut png = session.load_png(&file_path)?;
+    let chunk = png.remove_chunk_at(index)?;
+    save_chunk(session, &chunk, save)?;
+    session.save_png(&png, file_path)?;
+    Ok(chunk)
+}
+
+/// Writes a removed chunk, with its length, type and CRC, to `path` so `paste` can insert it
+/// again. Nothing is written in a dry run.
+fn save_chunk(session: &Session, chunk: &Chunk, path: Option<&Path>) -> Result<()> {
+    match path {
+        Some(path) if !session.is_dry_run() => Ok(fs::write(path, chunk.as_bytes())?),
+        _ => Ok(()),
+    }
+}
+
+/// Removes every chunk of the given type from a PNG file, saves the result and returns the
+/// removed chunks
+pub fn remove_all<P: AsRef<Path>>(session: &Session, file_path: P, chunk_type: &str) -> Result<Vec<Chunk>> {
+    let mut png = session.load_png(&file_path)?;
+    let chunks = png.remove_chunks_by_type(chunk_type)?;
+    session.save_png(&png, file_path)?;
+    Ok(chunks)
+}
+
+/// Removes every ancillary chunk whose type matches `pattern` from a PNG file and returns
+/// the removed chunks
+pub fn remove_matching<P: AsRef<Path>>(session: &Session, file_path: P, pattern: &ChunkTypePattern) -> Result<Vec<Chunk>> {
+    let mut png = session.load_png(&file_path)?;
+    let chunks = png.remove_matching(pattern);
+    if chunks.is_empty() {
+        return Err("no ancillary chunk matches the pattern".into());
+    }
+    session.save_png(&png, file_path)?;
+    Ok(chunks)
+}
+
+/// Removes every chunk of the given type from a PNG file without leaving its data behind:
+/// the data is first overwritten with zeros in the file itself, at its original offsets (so the
+/// same disk blocks are rewritten and every other byte is left as is), and flushed to disk, then
+/// the chunks are removed unless `keep` is set. Returns the number of redacted chunks.
+pub fn redact<P: AsRef<Path>>(session: &Session, file_path: P, chunk_type: &str, keep: bool) -> Result<usize> {
+    let mut png = session.load_png(&file_path)?;
+
+    let count = png.zero_chunks(chunk_type)?;
+    if count == 0 {
+        return Err(png.chunk_not_found(chunk_type));
+    }
+
+    if session.is_dry_run() {
+        // Only `save_png` reports the changes of a dry run
+        if keep {
+            session.save_png(&png, &file_path)?;
+        }
+    } else {
+        zero_in_place(file_path.as_ref(), &ChunkType::from_str(chunk_type)?, count)?;
+    }
+
+    if !keep {
+        png.chunks_mut().retain(|chunk| chunk.chunk_type() != chunk_type);
+        session.save_png(&png, &file_path)?;
+    }
+    Ok(count)
+}
+
+/// Overwrites the data of every chunk of the given type in the file at `path` with zeros and
+/// updates its CRC, writing only those bytes. The chunks are located by walking the file itself
+/// rather than by serializing a parsed `Png`, whose layout can differ (e.g. after skipping
+/// damaged chunks), and `expected` chunks must be found there.
+fn zero_in_place(path: &Path, chunk_type: &ChunkType, expected: usize) -> Result<()> {
+    let bytes = fs::read(path)?;
+    let mut spans = Vec::new();
+    let mut offset = Png::STANDARD_HEADER.len();
+    while let Some(fields) = bytes.get(offset..offset + 8) {
+        let length = u32::from_be_bytes(fields[..4].try_into()?) as usize;
+        let Some(Ok(chunk)) = bytes.get(offset..offset + 12 + length).map(Chunk::try_from) else {
+            break;
+        };
+        if chunk.chunk_type() == chunk_type {
+            spans.push((offset + 8, length));
+        }
+        offset += 12 + length;
+    }
+    if spans.len() != expected {
+        return Err(format!(
+            "can't redact {} in place: found {} of {} {} chunks in the file, it may be damaged",
+            path.display(), spans.len(), expected, chunk_type,
+        ).into());
+    }
+
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    for (offset, length) in spans {
+        let zeros = vec![0; length];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(&zeros)?;
+        file.write_all(&Chunk::new(*chunk_type, zeros).crc().to_be_bytes())?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Removes everything pngme embedded in a PNG file (see `envelope::wipe`) and saves the
+/// result. Payloads hidden in the pixels by the `lsb` and `alpha` modes can't be undone, so
+/// they are only reported.
+pub fn wipe<P: AsRef<Path>>(session: &Session, file_path: P, output_file: Option<P>) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+    let wiped = envelope::wipe(&mut png);
+    let target = output_file.as_ref().unwrap_or(&file_path).as_ref();
+
+    for chunk in &wiped.chunks {
+        session.status(target, &format!("removed {} chunk ({} bytes)", chunk.chunk_type(), chunk.length()));
+    }
+    if !wiped.trailer.is_empty() {
+        session.status(target, &format!("removed {} bytes after IEND", wiped.trailer.len()));
+    }
+    let backends: [&dyn StegoBackend; 4] = [
+        &LsbBackend::default(),
+        &LsbBackend::default().adaptive(true),
+        &AlphaBackend::default(),
+        &AlphaBackend::default().adaptive(true),
+    ];
+    for backend in backends {
+        if backend.extract(&png).is_ok_and(|data| Envelope::is_envelope(&data)) {
+            session.status(target, "found a payload in the pixels, which can't be removed without the original image");
+        }
+    }
+    if wiped.chunks.is_empty() && wiped.trailer.is_empty() {
+        session.status(target, "no pngme data to remove");
+        return Ok(());
+    }
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Inserts a chunk saved with `remove --save` into a PNG file (at position `index`, or right
+/// before `IEND`) and saves the result. The chunk type is checked with `options`.
+pub fn paste<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    chunk_path: P,
+    index: Option<usize>,
+    options: &EncodeOptions,
+    output_file: Option<P>,
+) -> Result<()> {
+    let chunk = Chunk::try_from(&fs::read(chunk_path)?[..])?;
+    options.check(chunk.chunk_type())?;
+    let mut png = session.load_png(&file_path)?;
+
+    match index {
+        Some(index) => png.insert_chunk_at(index, chunk)?,
+        None => png.insert_before_end(chunk),
+    }
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Stores a keyword/text pair in a textual chunk and saves the result
+pub fn set_text<P: AsRef<Path>>(session: &Session, file_path: P, entry: &TextChunk, output_file: Option<P>) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
 
-    png.append_chunk(chunk);
+    text::set_text(&mut png, entry)?;
 
     match output_file {
-        Some(output_file) => png.to_file(output_file),
-        None => png.to_file(file_path),
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
     }
 }
 
-/// Searches for a message hidden in a PNG file and prints the message if one is found
-pub fn decode<P: AsRef<Path>>(file_path: P, chunt_type: &str) -> Result<()> {
-    let png = Png::from_file(&file_path)?;
-    let chunk = png.chunk_by_type(chunt_type).ok_or("chunk not found")?;
-    println!("{}", chunk.data_as_string()?);
+/// Returns the text entry with `keyword` of a PNG file
+pub fn get_text<P: AsRef<Path>>(session: &Session, file_path: P, keyword: &str) -> Result<TextChunk> {
+    text::get_text(&session.load_png(&file_path)?, keyword)?
+        .ok_or_else(|| format!("no text entry with keyword {}", keyword).into())
+}
+
+/// Returns every text entry of a PNG file, in file order
+pub fn list_text<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<Vec<TextChunk>> {
+    text::entries(&session.load_png(&file_path)?)
+}
+
+/// Stores the conventional `Author`, `Copyright` and `Source` text entries given in a PNG
+/// file and saves the result. Text that isn't representable in Latin-1 is stored in `iTXt`
+/// chunks instead of `tEXt` chunks.
+pub fn credit<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    author: Option<&str>,
+    copyright: Option<&str>,
+    source: Option<&str>,
+    output_file: Option<P>,
+) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+
+    let entries = [("Author", author), ("Copyright", copyright), ("Source", source)];
+    for (keyword, text) in entries {
+        if let Some(text) = text {
+            text::set_text(&mut png, &TextChunk::any_text(keyword, text)?)?;
+        }
+    }
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Recompresses the image data of a PNG file at maximum compression and saves the result
+pub fn optimize<P: AsRef<Path>>(session: &Session, file_path: P, zopfli: bool, output_file: Option<P>) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+
+    let (before, after) = image::optimize(&mut png, zopfli)?;
+    let target = output_file.as_ref().unwrap_or(&file_path).as_ref();
+    session.status(target, &format!("Image data: {} -> {} bytes", before, after));
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Checks a PNG file for damage and for structures of other file formats (polyglots),
+/// returning every problem found (none if the file is fine). A file that doesn't fit in memory (see `Session::fits_in_memory`) is read one chunk at a
+/// time, and the first damaged chunk ends the check.
+pub fn verify<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<Vec<String>> {
+    let (png, mut problems) = if session.fits_in_memory(&file_path)? {
+        let (png, warnings) = Png::from_file_lenient(&file_path)?;
+        (Some(png), warnings.iter().map(ToString::to_string).collect())
+    } else {
+        // The image data makes up most of the file and isn't searched for polyglots anyway
+        match session.stream_png(&file_path, |_, chunk| Ok(chunk.chunk_type() != &ChunkType::IDAT)) {
+            Ok(png) => (Some(png), Vec::new()),
+            Err(e) => (None, vec![e.to_string()]),
+        }
+    };
+
+    if let Some(png) = png {
+        if !png.has_end() {
+            problems.push("missing IEND chunk".to_owned());
+        }
+        problems.extend(png.chunks().iter()
+            .filter_map(|chunk| InvalidLength::check(chunk.chunk_type(), chunk.length()).err())
+            .map(|err| err.to_string()));
+        problems.extend(scan::scan(&png).iter()
+            .filter(|finding| finding.kind == FindingKind::Polyglots)
+            .map(ToString::to_string));
+    }
+    Ok(problems)
+}
+
+/// Checks a PNG file against the structural rules of the PNG spec (see [`Png::validate`]),
+/// returning every violation found (none if the file is valid)
+pub fn validate<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<Vec<Violation>> {
+    let bytes = session.read_file(&file_path)?;
+    let (png, truncated) = Png::from_bytes_unvalidated(&bytes);
+    let mut violations = png.validate();
+    violations.extend(truncated);
+    Ok(violations)
+}
+
+/// Fixes a damaged PNG file and saves the result. Wrong CRCs are recomputed and unreadable
+/// trailing data is dropped; with `add_iend` a missing `IEND` chunk is added.
+pub fn repair<P: AsRef<Path>>(session: &Session, file_path: P, add_iend: bool, output_file: Option<P>) -> Result<()> {
+    let (mut png, unreadable) = Png::from_bytes_unvalidated(&session.read_file(&file_path)?);
+    if png.header() != &Png::STANDARD_HEADER {
+        return Err(PngMeError::InvalidSignature.into());
+    }
+    let target = output_file.as_ref().unwrap_or(&file_path).as_ref();
+    for violation in unreadable {
+        session.status(target, &format!("dropped damaged data: {}", violation));
+    }
+    for index in png.repair_crcs() {
+        session.status(target, &format!("fixed the CRC of chunk #{} ({})", index, png.chunks()[index].chunk_type()));
+    }
+
+    if add_iend && !png.has_end() {
+        png.append_chunk(Chunk::new(ChunkType::IEND, Vec::new()));
+        session.status(target, "added missing IEND chunk");
+    }
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Writes the ICC color profile embedded in a PNG file to `profile_path` and returns it
+pub fn icc_extract<P: AsRef<Path>>(session: &Session, file_path: P, profile_path: P) -> Result<IccProfile> {
+    let png = session.load_png(&file_path)?;
+    let profile = IccProfile::from_png(&png)?;
+    fs::write(profile_path, profile.profile())?;
+    Ok(profile)
+}
+
+/// Embeds the ICC color profile read from `profile_path` into a PNG file and saves the result
+pub fn icc_set<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    profile_path: P,
+    name: &str,
+    output_file: Option<P>,
+) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+
+    let profile = IccProfile::new(name, fs::read(profile_path)?)?;
+    profile.set_in_png(&mut png)?;
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Stores an SPDX license expression and the author in a PNG file and saves the result
+pub fn license_set<P: AsRef<Path>>(session: &Session, file_path: P, license: &License, output_file: Option<P>) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+
+    license.embed(&mut png)?;
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Returns the license and author stored in a PNG file
+pub fn get_license<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<License> {
+    License::from_png(&session.load_png(&file_path)?)
+}
+
+/// Stores the JSON provenance document read from `document_path` in a PNG file and saves
+/// the result. With `schema_path`, the document must match that JSON Schema.
+pub fn provenance_set<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    document_path: P,
+    schema_path: Option<P>,
+    output_file: Option<P>,
+) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+
+    let document = read_json(&document_path)?;
+    let schema = schema_path.map(|path| read_json(&path)).transpose()?;
+    provenance::embed(&mut png, &document, schema.as_ref())?;
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Returns the provenance document stored in a PNG file. With `schema_path`, the document
+/// must match that JSON Schema.
+pub fn get_provenance<P: AsRef<Path>>(session: &Session, file_path: P, schema_path: Option<P>) -> Result<serde_json::Value> {
+    let png = session.load_png(&file_path)?;
+    let schema = schema_path.map(|path| read_json(&path)).transpose()?;
+    provenance::read(&png, schema.as_ref())
+}
+
+fn read_json<P: AsRef<Path>>(path: &P) -> Result<serde_json::Value> {
+    serde_json::from_slice(&fs::read(path)?)
+        .map_err(|e| format!("{}: {}", path.as_ref().display(), e).into())
+}
+
+/// Signs an in-toto statement about a PNG file, with the predicate read from
+/// `predicate_path`, and stores it in the file as a DSSE envelope
+pub fn attest<P: AsRef<Path>>(
+    session: &Session,
+    file_path: P,
+    key_path: P,
+    predicate_path: P,
+    predicate_type: &str,
+    output_file: Option<P>,
+) -> Result<()> {
+    let mut png = session.load_png(&file_path)?;
+
+    let key = keys::read_signing_key(&key_path)?;
+    let predicate = read_json(&predicate_path)?;
+    let name = file_path.as_ref().file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    attest::attest(&mut png, &name, predicate_type, predicate, &key)?;
+
+    match output_file {
+        Some(output_file) => session.save_png(&png, output_file),
+        None => session.save_png(&png, file_path),
+    }
+}
+
+/// Checks that the attestation stored in a PNG file is signed by the key in `key_path`
+/// and that the file wasn't modified since, and returns it
+pub fn verify_attestation<P: AsRef<Path>>(session: &Session, file_path: P, key_path: P) -> Result<Attestation> {
+    let png = session.load_png(&file_path)?;
+    attest::verify(&png, &keys::read_verifying_key(&key_path)?)
+}
+
+/// Generates a signing key (and with `x25519` an encryption key), writing the secret keys to
+/// `key_path` and the public keys to `key_path` with `.pub` appended. Returns the path of the
+/// public key file and the fingerprint of the signing key.
+pub fn keygen<P: AsRef<Path>>(key_path: P, x25519: bool) -> Result<(PathBuf, String)> {
+    let key_path = key_path.as_ref();
+    let mut public_path = key_path.as_os_str().to_owned();
+    public_path.push(".pub");
+    let public_path = PathBuf::from(public_path);
+
+    if key_path.exists() || public_path.exists() {
+        return Err(format!("{} or {} already exists", key_path.display(), public_path.display()).into());
+    }
+
+    let keypair = keys::Keypair::generate(x25519);
+    keystore::write_private(key_path, &keypair.secret_file())?;
+    fs::write(&public_path, keypair.public_file())?;
+    Ok((public_path, keys::fingerprint(&keypair.signing.verifying_key())))
+}
+
+/// Returns the keys of a keystore
+pub fn key_list(keystore: &Keystore) -> Result<Vec<KeyEntry>> {
+    keystore.list()
+}
+
+/// Imports a key file into a keystore under `name` and returns the new entry
+pub fn key_import<P: AsRef<Path>>(keystore: &Keystore, key_path: P, name: &str, force: bool) -> Result<KeyEntry> {
+    keystore.import(name, &fs::read_to_string(key_path)?, force)
+}
+
+/// Writes the public (or with `secret` the secret) key file of `name` to `output_file`, or
+/// returns it without `output_file`
+pub fn key_export<P: AsRef<Path>>(keystore: &Keystore, name: &str, secret: bool, output_file: Option<P>) -> Result<Option<String>> {
+    let key_file = keystore.export(name, secret)?;
+    match output_file {
+        Some(output_file) if secret => keystore::write_private(output_file.as_ref(), &key_file)?,
+        Some(output_file) => fs::write(output_file, key_file)?,
+        None => return Ok(Some(key_file)),
+    }
+    Ok(None)
+}
+
+/// Returns the fingerprint of the signing key in a key file
+pub fn key_fingerprint<P: AsRef<Path>>(key_path: P) -> Result<String> {
+    Ok(keys::fingerprint(&keys::read_verifying_key(key_path)?))
+}
+
+/// Runs a command added by a plugin on a PNG file, saving the file if the plugin modified it
+pub fn run_plugin<P: AsRef<Path>>(session: &Session, registry: &PluginRegistry, verb: &str, file_path: P, args: &[String]) -> Result<()> {
+    let plugin = registry.find(verb).ok_or_else(|| format!("unknown command '{}', see `pngme plugins`", verb))?;
+    let mut png = session.load_png(&file_path)?;
+
+    if plugin.run(verb, &mut png, args)? == Outcome::Modified {
+        session.save_png(&png, file_path)?;
+    }
     Ok(())
 }
 
-/// Removes a chunk from a PNG file and saves the result
-pub fn remove<P: AsRef<Path>>(file_path: P, chunk_type: &str) -> Result<()> {
-    let mut png = Png::from_file(&file_path)?;
-    png.remove_chunk(chunk_type)?;
-    png.to_file(file_path)
+/// Options of `scan`
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Limits applied while parsing each file
+    pub limits: ParseLimits,
+    /// User-supplied patterns to look for
+    pub rules: Vec<Rule>,
 }
 
-/// Prints all of the chunks in a PNG file
-pub fn print_chunks<P: AsRef<Path>>(file_path: P) -> Result<()> {
-    println!("{}", Png::from_file(&file_path)?);
+/// Scans PNG files (directories are searched recursively and glob patterns are expanded, see
+/// [`batch::expand`]) for hidden data. Returns the findings of every file, or the reason it
+/// couldn't be scanned. Files that don't fit in memory (see `Session::fits_in_memory`) are read
+/// one chunk at a time.
+pub fn scan<P: AsRef<Path>>(session: &Session, paths: &[P], options: &ScanOptions) -> Result<Vec<FileReport>> {
+    let mut files = Vec::new();
+    for path in paths {
+        match batch::expand(path)? {
+            Some(found) => files.extend(found),
+            None => files.push(path.as_ref().to_path_buf()),
+        }
+    }
+
+    Ok(files.into_iter()
+        .map(|path| {
+            let png = match session.fits_in_memory(&path) {
+                Ok(true) => session.load_png_with_limits(&path, &options.limits),
+                // Findings give the offsets of their chunks, so every chunk is kept
+                Ok(false) => session.stream_png_with_limits(&path, &options.limits, |_, _| Ok(true)),
+                Err(e) => Err(e),
+            };
+            let findings = png.map(|png| scan::scan_with_rules(&png, &options.rules)).map_err(|e| e.to_string());
+            FileReport { path, findings }
+        })
+        .collect())
+}
+
+/// Writes a forensic report of a PNG file (header, chunk table with offsets, hashes and
+/// entropy, scan findings and pngme payloads) to `output_file`, or returns it without
+/// `output_file`
+pub fn report<P: AsRef<Path>>(session: &Session, file_path: P, format: DocumentFormat, output_file: Option<P>) -> Result<Option<String>> {
+    let bytes = session.read_file(&file_path)?;
+    let png = Png::try_from(&bytes[..])?;
+
+    let name = file_path.as_ref().file_name().unwrap_or_default().to_string_lossy();
+    let report = Report::new(&name, &bytes, &png);
+    let document = match format {
+        DocumentFormat::Markdown => report.to_markdown(),
+        DocumentFormat::Html => report.to_html(),
+    };
+
+    match output_file {
+        Some(output_file) => fs::write(output_file, document)?,
+        None => return Ok(Some(document)),
+    }
+    Ok(None)
+}
+
+/// Generates a reproducible test image and saves it to `output_file`
+pub fn gen_test<P: AsRef<Path>>(session: &Session, output_file: P, options: &GenOptions) -> Result<()> {
+    let png = generate::generate(options)?;
+    session.save_png(&png, &output_file)?;
+    let message = format!("Generated a {}x{} test image ({} bytes)", options.width, options.height, png.as_bytes().len());
+    session.status(output_file.as_ref(), &message);
+    Ok(())
+}
+
+/// Renders a message as a QR code in a new PNG file, encrypted with `encryption` if given
+/// (see `qr::content`)
+pub fn qr<P: AsRef<Path>>(session: &Session, message: &str, encryption: Option<&Encryption>, options: &QrOptions, output_file: P) -> Result<()> {
+    let png = qr::render(&qr::content(message, encryption)?, options)?;
+    session.save_png(&png, &output_file)?;
+    let size = image::ImageHeader::from_png(&png)?.width;
+    session.status(output_file.as_ref(), &format!("Rendered a {}x{} QR code ({} bytes)", size, size, png.as_bytes().len()));
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Decrypts the text of a QR code rendered by `qr` with encryption and returns the message
+pub fn qr_decrypt(text: &str, secret: Option<&Secret>) -> Result<String> {
+    Ok(String::from_utf8(qr::decrypt(text, secret)?)?)
+}
+
+/// Returns a PNG file as a JSON document (see `dump::Dump`)
+pub fn export_json<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<Dump> {
+    Ok(Dump::from_png(&session.load_png(&file_path)?))
+}
+
+/// Rebuilds a PNG file from a JSON document written by `export_json`
+pub fn import_json<P: AsRef<Path>>(session: &Session, dump_path: P, output_file: P) -> Result<()> {
+    let dump: Dump = serde_json::from_slice(&fs::read(dump_path)?)?;
+    session.save_png(&dump.to_png()?, output_file)
+}
+
+/// Reads a PNG file like every command does (from a path, a URL or `-` for stdin). The `Png`
+/// gives access to all of its chunks, and its `Display` implementation lists them.
+pub fn list_chunks<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<Png> {
+    session.load_png(&file_path)
+}
+
+/// Returns the chunks of a PNG file without their data. A file that doesn't fit in memory
+/// (see `Session::fits_in_memory`) is read one chunk at a time.
+pub fn list_chunk_headers<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<ChunkListing> {
+    if session.fits_in_memory(&file_path)? {
+        return Ok(ChunkListing::from(&session.load_png(&file_path)?));
+    }
+
+    let mut chunks = Vec::new();
+    let png = session.stream_png(&file_path, |_, chunk| {
+        chunks.push(chunk.summary());
+        Ok(false)
+    })?;
+    Ok(ChunkListing { chunks, trailer_len: png.trailer().len() })
+}
+
+/// Returns the chunks of a PNG file whose type matches `pattern`
+pub fn list_matching<P: AsRef<Path>>(session: &Session, file_path: P, pattern: &ChunkTypePattern) -> Result<Vec<Chunk>> {
+    let png = session.load_png(&file_path)?;
+    Ok(png.chunks_matching(pattern).cloned().collect())
+}
+
+/// The size of a PNG file and of the data embedded in it, see `size`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSize {
+    pub total: usize,
+    /// The bytes taken by private chunks and data after `IEND`
+    pub embedded: usize,
+}
+
+impl FileSize {
+    /// The size of the file without the embedded data
+    pub fn stripped(&self) -> usize {
+        self.total - self.embedded
+    }
+
+    /// The embedded data in percent of the stripped size
+    pub fn overhead(&self) -> f64 {
+        self.embedded as f64 * 100.0 / self.stripped() as f64
+    }
+}
+
+/// Returns the size of a PNG file and the bytes taken by embedded data (private chunks and
+/// data after `IEND`)
+pub fn size<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<FileSize> {
+    let (total, embedded) = if session.fits_in_memory(&file_path)? {
+        let png = session.load_png(&file_path)?;
+        (png.as_bytes().len(), png.embedded_size())
+    } else {
+        let mut embedded = 0;
+        let png = session.stream_png(&file_path, |_, chunk| {
+            if !chunk.chunk_type().is_public() {
+                embedded += chunk.length() as usize + 12;
+            }
+            Ok(false)
+        })?;
+        (fs::metadata(&file_path)?.len() as usize, embedded + png.trailer().len())
+    };
+    Ok(FileSize { total, embedded })
+}
+
+/// Returns how many chunks of each type a PNG file contains, sorted by type
+pub fn stats<P: AsRef<Path>>(session: &Session, file_path: P) -> Result<Vec<(ChunkType, usize)>> {
+    let png = match session.fits_in_memory(&file_path)? {
+        true => session.load_png(&file_path)?,
+        false => session.stream_png(&file_path, |_, _| Ok(false))?,
+    };
+    let mut counts: Vec<_> = png.type_counts().into_iter().collect();
+    counts.sort_by_key(|(chunk_type, _)| chunk_type.to_string());
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[test]
+    fn test_encode_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dice.png");
+        let output = dir.path().join("out.png");
+        fs::write(&input, DICE).unwrap();
+        let session = Session::new();
+        let backend = ChunkBackend::new(ChunkType::from_str("ruSt").unwrap());
+        let message = b"line one\nline two\n\xff binary".to_vec();
+
+        encode_reader(&session, &input, &backend, &message[..], Some(&output)).unwrap();
+
+        assert_eq!(fs::read(&input).unwrap(), DICE);
+        assert_eq!(decode_to_bytes(&session, &output, &backend, None).unwrap(), message);
+    }
+
+    #[test]
+    fn test_encode_sealed_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dice.png");
+        fs::write(&path, DICE).unwrap();
+        let session = Session::new();
+        let backend = ChunkBackend::new(ChunkType::from_str("ruSt").unwrap());
+        let message = "a message that compresses well ".repeat(100).into_bytes();
+
+        encode_sealed(&session, &path, &backend, &message, true, None, None).unwrap();
+
+        let stored = backend.extract(&session.load_png(&path).unwrap()).unwrap();
+        assert!(Envelope::from_bytes(&stored).unwrap().is_compressed());
+        assert!(stored.len() < message.len());
+        assert_eq!(decode_to_bytes(&session, &path, &backend, None).unwrap(), message);
+    }
+
+    #[test]
+    fn test_decode_combine_to_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_paths: Vec<_> = (0..3).map(|index| dir.path().join(format!("{}.png", index))).collect();
+        for file_path in &file_paths {
+            fs::write(file_path, DICE).unwrap();
+        }
+        let session = Session::new();
+        let backend = ChunkBackend::new(ChunkType::from_str("ruSt").unwrap());
+        let payload = b"\xff\xfe not UTF-8 \x00".to_vec();
+
+        encode_across(&session, &file_paths, &backend, &payload).unwrap();
+
+        assert_eq!(decode_combine_to_bytes(&session, &file_paths, &backend).unwrap(), payload);
+        assert!(decode_combine(&session, &file_paths, &backend).is_err());
+    }
+
+    #[test]
+    fn test_encode_options_check() {
+        let check = |options: &EncodeOptions, chunk_type| options.check(&ChunkType::from_str(chunk_type).unwrap());
+        let strict = EncodeOptions::default();
+
+        // Registered types, critical or not
+        assert!(check(&strict, "IDAT").unwrap_err().to_string().contains("registered chunk type (image data)"));
+        assert!(check(&strict, "tEXt").unwrap_err().to_string().contains("registered chunk type (textual data)"));
+        // Unregistered critical and public types
+        assert!(check(&strict, "RuSt").unwrap_err().to_string().contains("it is critical"));
+        assert!(check(&strict, "rUSt").unwrap_err().to_string().contains("it is public"));
+        // Ancillary private types
+        assert!(check(&strict, "ruSt").is_ok());
+        assert!(check(&strict, "prIV").is_ok());
+
+        let force = EncodeOptions { allow_critical: true };
+        for chunk_type in ["IDAT", "tEXt", "RuSt", "rUSt", "ruSt"] {
+            assert!(check(&force, chunk_type).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_replace_and_paste_check_chunk_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dice.png");
+        let chunk_path = dir.path().join("chunk");
+        fs::write(&path, DICE).unwrap();
+        let session = Session::new();
+        let strict = EncodeOptions::default();
+
+        assert!(replace(&session, &path, "tEXt", b"message", &strict, None).is_err());
+        assert_eq!(fs::read(&path).unwrap(), DICE);
+        replace(&session, &path, "tEXt", b"message", &EncodeOptions { allow_critical: true }, None).unwrap();
+
+        fs::write(&chunk_path, Chunk::new(ChunkType::IDAT, vec![0; 4]).as_bytes()).unwrap();
+        assert!(paste(&session, &path, &chunk_path, Some(1), &strict, None).is_err());
+        fs::write(&chunk_path, Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![0; 4]).as_bytes()).unwrap();
+        paste(&session, &path, &chunk_path, Some(1), &strict, None).unwrap();
+        assert_eq!(session.load_png(&path).unwrap().chunks()[1].chunk_type(), "ruSt");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_file_size_of_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("fifo");
+        assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+        let writer = {
+            let fifo = fifo.clone();
+            std::thread::spawn(move || {
+                let mut pipe = fs::OpenOptions::new().write(true).open(fifo).unwrap();
+                let _ = pipe.write_all(&[0; 2048]);
+            })
+        };
+
+        let session = Session::new();
+        let err = check_file_size(&session, &fifo, 1024).unwrap_err();
+        assert!(err.downcast_ref::<LimitExceeded>().is_some());
+        writer.join().unwrap();
+
+        // A stream without an end is rejected as well instead of being read forever
+        assert!(check_file_size(&session, "/dev/zero", 1024).is_err());
+    }
+
+    #[test]
+    fn test_list_chunk_headers_over_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dice.png");
+        let mut bytes = DICE.to_vec();
+        bytes.extend_from_slice(b"trailer");
+        fs::write(&path, &bytes).unwrap();
+
+        let listing = list_chunk_headers(&Session::new(), &path).unwrap();
+        assert_eq!(listing.trailer_len, 7);
+        assert_eq!(list_chunk_headers(&Session::new().with_max_memory(Some(1)), &path).unwrap(), listing);
+        assert_eq!(listing.to_string(), Png::from_bytes_lenient(&bytes).unwrap().0.to_string());
+    }
+
+    #[test]
+    fn test_verify_over_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dice.png");
+        let session = Session::new().with_max_memory(Some(1));
+        fs::write(&path, DICE).unwrap();
+        assert!(verify(&session, &path).unwrap().is_empty());
+
+        // A flipped bit in the data of the tIME chunk
+        let mut damaged = DICE.to_vec();
+        let last = damaged.len() - 20;
+        damaged[last] ^= 1;
+        fs::write(&path, damaged).unwrap();
+        assert!(!verify(&session, &path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_scan_over_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dice.png");
+        let mut png = Png::try_from(DICE).unwrap();
+        png.insert_before_end(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hidden".to_vec()));
+        png.set_trailer(b"PK\x03\x04".to_vec());
+        fs::write(&path, png.as_bytes()).unwrap();
+
+        let options = ScanOptions { limits: ParseLimits::default(), rules: Vec::new() };
+        let loaded = scan(&Session::new(), &[&path], &options).unwrap();
+        let streamed = scan(&Session::new().with_max_memory(Some(1)), &[&path], &options).unwrap();
+        assert_eq!(loaded[0].findings.as_ref().unwrap().len(), 2);
+        assert_eq!(format!("{:?}", streamed), format!("{:?}", loaded));
+
+        let limits = ParseLimits { max_total_size: DICE.len(), ..ParseLimits::default() };
+        let options = ScanOptions { limits, rules: Vec::new() };
+        assert!(scan(&Session::new(), &[&path], &options).unwrap()[0].findings.is_err());
+        assert!(scan(&Session::new().with_max_memory(Some(1)), &[&path], &options).unwrap()[0].findings.is_err());
+    }
+}
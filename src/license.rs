@@ -0,0 +1,145 @@
+use crate::png::Png;
+use crate::text::{self, TextChunk, Translation};
+use crate::Result;
+
+/// The keyword of the text entry holding the license
+pub const LICENSE_KEYWORD: &str = "License";
+
+/// The keyword of the text entry holding the author, as predefined by the PNG spec
+pub const AUTHOR_KEYWORD: &str = "Author";
+
+/// The tag the license entry starts with, as in source files following the SPDX spec
+const SPDX_TAG: &str = "SPDX-License-Identifier:";
+
+/// The license of an image: an SPDX license expression such as `CC-BY-4.0` or
+/// `MIT OR Apache-2.0`, and optionally the author to attribute it to.
+/// https://spdx.github.io/spdx-spec/v2.3/SPDX-license-expressions/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct License {
+    expression: String,
+    author: Option<String>,
+}
+
+impl License {
+    /// Creates a license, checking the syntax of the SPDX expression
+    pub fn new(expression: &str, author: Option<&str>) -> Result<License> {
+        validate_expression(expression)?;
+        Ok(License {
+            expression: expression.trim().to_owned(),
+            author: author.map(str::to_owned),
+        })
+    }
+
+    /// Reads the license and author entries of `png`
+    pub fn from_png(png: &Png) -> Result<License> {
+        let entry = text::get_text(png, LICENSE_KEYWORD)?.ok_or("no license found")?;
+        let expression = entry.text().strip_prefix(SPDX_TAG).unwrap_or(entry.text());
+        let author = text::get_text(png, AUTHOR_KEYWORD)?;
+        License::new(expression, author.as_ref().map(TextChunk::text))
+    }
+
+    /// The SPDX license expression
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// The author to attribute the image to
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    /// The page of the license on spdx.org, if the expression is a single listed license
+    pub fn url(&self) -> Option<String> {
+        let single = !self.expression.contains(|c: char| c.is_whitespace() || c == '(' || c == '+');
+        (single && !self.expression.starts_with("LicenseRef-"))
+            .then(|| format!("https://spdx.org/licenses/{}.html", self.expression))
+    }
+
+    /// Stores the license (and the author, if any) in `png` as international (`iTXt`) text
+    /// entries, so author names may use any characters. Existing entries are replaced.
+    pub fn embed(&self, png: &mut Png) -> Result<()> {
+        let license = format!("{} {}", SPDX_TAG, self.expression);
+        text::set_text(png, &TextChunk::international(LICENSE_KEYWORD, &license, Translation::default(), false)?)?;
+        if let Some(author) = &self.author {
+            text::set_text(png, &TextChunk::international(AUTHOR_KEYWORD, author, Translation::default(), false)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the syntax of an SPDX license expression: license identifiers (optionally followed
+/// by `+`) joined by `AND`, `OR` and `WITH`, grouped with parentheses
+pub fn validate_expression(expression: &str) -> Result<()> {
+    let invalid = || format!("invalid SPDX license expression '{}'", expression);
+    let spaced = expression.replace('(', " ( ").replace(')', " ) ");
+
+    let mut depth = 0usize;
+    let mut expect_operand = true;
+    for token in spaced.split_whitespace() {
+        match token {
+            "(" if expect_operand => depth += 1,
+            ")" if !expect_operand => depth = depth.checked_sub(1).ok_or_else(invalid)?,
+            "AND" | "OR" | "WITH" if !expect_operand => expect_operand = true,
+            _ if expect_operand && is_identifier(token) => expect_operand = false,
+            _ => return Err(invalid().into()),
+        }
+    }
+
+    if expect_operand || depth != 0 {
+        return Err(invalid().into());
+    }
+    Ok(())
+}
+
+/// License identifiers are letters, digits, `.` and `-`, optionally followed by `+`
+fn is_identifier(token: &str) -> bool {
+    let id = token.strip_suffix('+').unwrap_or(token);
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[test]
+    fn test_valid_expressions() {
+        for expression in ["CC-BY-4.0", "MIT OR Apache-2.0", "GPL-2.0+ WITH Classpath-exception-2.0", "(MIT AND CC0-1.0) OR LicenseRef-Studio"] {
+            assert!(validate_expression(expression).is_ok(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expression in ["", "MIT OR", "MIT Apache-2.0", "(MIT", "MIT)", "CC BY", "MIT and Apache-2.0", "MIT/X11"] {
+            assert!(validate_expression(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_embed_license() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let license = License::new("CC-BY-4.0", Some("Zoë Ångström")).unwrap();
+
+        license.embed(&mut png).unwrap();
+
+        let chunk = png.chunks().iter().find(|chunk| text::keyword_of(chunk).as_deref() == Some("License")).unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        assert!(chunk.data().ends_with(b"SPDX-License-Identifier: CC-BY-4.0"));
+        assert_eq!(License::from_png(&png).unwrap(), license);
+    }
+
+    #[test]
+    fn test_url() {
+        assert_eq!(License::new("CC-BY-4.0", None).unwrap().url().unwrap(), "https://spdx.org/licenses/CC-BY-4.0.html");
+        assert!(License::new("MIT OR Apache-2.0", None).unwrap().url().is_none());
+        assert!(License::new("LicenseRef-Studio", None).unwrap().url().is_none());
+    }
+
+    #[test]
+    fn test_no_license() {
+        assert!(License::from_png(&Png::try_from(DICE).unwrap()).is_err());
+    }
+}
@@ -1,11 +1,67 @@
-use std::path::PathBuf;
-use clap::{Parser, Subcommand};
-use pngme::{self, Result};
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use pngme::{self, EncodeOptions, Result, ScanOptions};
+use pngme::batch::{self, Checkpoint, RunOptions, WalkOptions};
+use pngme::chunk_type::ChunkTypePattern;
+use pngme::crypto::{Encryption, Secret};
+use pngme::dump::{self, Dump};
+use pngme::filter;
+use pngme::generate::{parse_color_type, GenOptions, Pattern};
+use pngme::keys;
+use pngme::keystore::Keystore;
+use pngme::license::License;
+use pngme::plugin::PluginRegistry;
+use pngme::positioned;
+use pngme::png::{LimitExceeded, ParseLimits};
+use pngme::qr::QrOptions;
+use pngme::report::DocumentFormat;
+use pngme::scan::{self, FileReport, FindingKind, ReportFormat};
+use pngme::session::Session;
+use pngme::stego::{AlphaBackend, ChunkBackend, LsbBackend, Mode, StegoBackend};
+use pngme::template::{self, Context};
+use pngme::text::{TextChunk, Translation};
+use serde_json::{json, Value};
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
+#[command(version, propagate_version = true, about = "Hides messages and files in PNG images, and inspects and repairs PNG files")]
 struct Args {
     #[command(subcommand)]
-    command: Commands
+    command: Commands,
+
+    /// Refuses to load input files larger than this size (e.g. 4096, 512K, 10M, 1G)
+    #[arg(long, global = true, env = "PNGME_MAX_FILE_SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Doesn't ask for confirmation before modifying files in place
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Prints the chunks a command would add or remove and the resulting file size instead
+    /// of writing PNG files
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Reads damaged PNG files anyway, skipping corrupt chunks up to the next valid one with a
+    /// warning, e.g. to recover a message from a partially damaged file
+    #[arg(long, global = true)]
+    lenient: bool,
+
+    /// Uploads output files given as http(s) or WebDAV URLs with a PUT request instead of
+    /// refusing them (requires the http feature)
+    #[arg(long, global = true)]
+    upload: bool,
+
+    /// The keystore directory searched for keys given by name (default: ~/.config/pngme/keys)
+    #[arg(long, global = true, env = "PNGME_KEYSTORE", value_name = "DIR")]
+    keystore: Option<PathBuf>,
+
+    /// Looks chunk types up ignoring their case (decode, remove, replace and redact), e.g. rust
+    /// finds ruSt
+    #[arg(short, long, global = true)]
+    ignore_case: bool,
 }
 
 
@@ -13,29 +69,1142 @@ struct Args {
 enum Commands {
     /// Encodes a message into a PNG file
     Encode {
+        /// A PNG file, or a directory or quoted glob pattern (e.g. './assets/**/*.png') to
+        /// process every PNG file it names
         file_path: PathBuf,
+        /// Chunk type that stores the message (only used by the chunk and interleave modes)
         chunk_type: String,
-        message: String,
+        /// The message to hide
+        #[arg(required_unless_present = "message_file")]
+        message: Option<String>,
+        #[arg(conflicts_with = "output")]
+        output_file: Option<PathBuf>,
+        /// Reads the message from this file ('-' for stdin) instead of the command line, for
+        /// multi-line or binary messages
+        #[arg(long, value_name = "PATH", conflicts_with = "message")]
+        message_file: Option<PathBuf>,
+        /// Saves the result to this file instead of modifying FILE_PATH, same as OUTPUT_FILE
+        /// (which can't follow --message-file)
+        #[arg(short, long, value_name = "PATH", conflicts_with = "across")]
+        output: Option<PathBuf>,
+        /// Steganography technique: chunk, lsb, alpha, eof or interleave
+        #[arg(long, default_value = "chunk")]
+        mode: Mode,
+        /// Splits the message across FILE_PATH and these files (modified in place)
+        #[arg(long, num_args = 1.., value_name = "FILES", conflicts_with = "output_file")]
+        across: Vec<PathBuf>,
+        /// Stores this many copies of the message in chunks of different types (chunk mode only)
+        #[arg(long, default_value_t = 1)]
+        copies: usize,
+        /// Splits the message into N chunks of the same type behind a manifest chunk (chunk
+        /// mode only)
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        split: usize,
+        /// Hides the message in the lowest N bits of every sample, 1 to 4 (lsb mode only).
+        /// More bits fit more data but change the image more.
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=4))]
+        bits: u8,
+        /// Hides the message in the textured regions of the image only, where changes are harder
+        /// to detect, at the cost of capacity (lsb and alpha modes only)
+        #[arg(long)]
+        adaptive: bool,
+        /// Encrypts the message to this X25519 key (a key file or a keystore name), can be
+        /// given several times so any of the recipients can decrypt it
+        #[arg(long, value_name = "KEY", conflicts_with = "across")]
+        recipient: Vec<PathBuf>,
+        /// Encrypts the message with a raw 32-byte key read from this file (binary or hex)
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["across", "recipient"])]
+        key_file: Option<PathBuf>,
+        /// Encrypts the message with a key derived from a password, read from the terminal or
+        /// PNGME_PASSWORD
+        #[arg(long, conflicts_with_all = ["across", "recipient", "key_file"])]
+        password: bool,
+        /// Appends the message to the payload already hidden in the file instead of adding
+        /// another one
+        #[arg(long, conflicts_with_all = ["across", "recipient", "key_file", "password"])]
+        append_data: bool,
+        /// Compresses the message with zlib before hiding it, decode inflates it again
+        #[arg(long, conflicts_with_all = ["across", "append_data"])]
+        compress: bool,
+        /// Copies FILE_PATH (and the --across files) to FILE_PATH.bak (or with the given suffix, e.g. --backup=.orig)
+        /// before modifying it in place
+        #[arg(long, value_name = "SUFFIX", num_args = 0..=1, require_equals = true, default_missing_value = ".bak")]
+        backup: Option<String>,
+        /// Expands {hostname}, {date}, {datetime}, {file} and {env:VAR} in the message
+        /// ({{ and }} for literal braces)
+        #[arg(long)]
+        template: bool,
+        /// Hides the message even in a critical or public chunk type such as IHDR or IDAT,
+        /// which usually breaks the image
+        #[arg(long)]
+        force: bool,
+    },
+    /// Hides a whole directory, archived with tar, in a PNG file
+    EncodeDir {
+        file_path: PathBuf,
+        dir: PathBuf,
+        output_file: Option<PathBuf>,
+        /// Chunk type that stores the archive (only used by the chunk and interleave modes)
+        #[arg(long, value_name = "TYPE", default_value = "ruSt")]
+        chunk_type: String,
+        /// Steganography technique: chunk, lsb, alpha, eof or interleave
+        #[arg(long, default_value = "chunk")]
+        mode: Mode,
+        /// Compresses the archive with gzip
+        #[arg(long)]
+        compress: bool,
+        /// Splits the archive into N chunks of the same type behind a manifest chunk (chunk
+        /// mode only)
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        split: usize,
+        /// Encrypts the archive to this X25519 key (a key file or a keystore name), can be
+        /// given several times
+        #[arg(long, value_name = "KEY")]
+        recipient: Vec<PathBuf>,
+        /// Encrypts the archive with a raw 32-byte key read from this file (binary or hex)
+        #[arg(long, value_name = "PATH", conflicts_with = "recipient")]
+        key_file: Option<PathBuf>,
+        /// Encrypts the archive with a key derived from a password, read from the terminal or
+        /// PNGME_PASSWORD
+        #[arg(long, conflicts_with_all = ["recipient", "key_file"])]
+        password: bool,
+    },
+    /// Hides a file of any type in a PNG file, with its name and size
+    EncodeFile {
+        file_path: PathBuf,
+        /// Chunk type that stores the file (only used by the chunk and interleave modes)
+        chunk_type: String,
+        payload_path: PathBuf,
         output_file: Option<PathBuf>,
+        /// Steganography technique: chunk, lsb, alpha, eof or interleave
+        #[arg(long, default_value = "chunk")]
+        mode: Mode,
+        /// Splits the file into N chunks of the same type behind a manifest chunk (chunk mode
+        /// only)
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        split: usize,
+        /// Encrypts the file to this X25519 key (a key file or a keystore name), can be
+        /// given several times
+        #[arg(long, value_name = "KEY")]
+        recipient: Vec<PathBuf>,
+        /// Encrypts the file with a raw 32-byte key read from this file (binary or hex)
+        #[arg(long, value_name = "PATH", conflicts_with = "recipient")]
+        key_file: Option<PathBuf>,
+        /// Encrypts the file with a key derived from a password, read from the terminal or
+        /// PNGME_PASSWORD
+        #[arg(long, conflicts_with_all = ["recipient", "key_file"])]
+        password: bool,
+    },
+    /// Writes a file hidden with `encode-file` back to disk under its original name
+    Extract {
+        file_path: PathBuf,
+        /// Chunk type that stores the file (only used by the chunk and interleave modes)
+        chunk_type: String,
+        /// Steganography technique: chunk, lsb, alpha, eof or interleave
+        #[arg(long, default_value = "chunk")]
+        mode: Mode,
+        /// The directory the file is written to, created if needed
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        out_dir: PathBuf,
+        /// Overwrites a file of the same name in the directory
+        #[arg(long)]
+        force: bool,
+        /// Decrypts the file with this X25519 secret key (a key file or a keystore name)
+        #[arg(long, value_name = "KEY")]
+        identity: Option<PathBuf>,
+        /// Decrypts the file with a raw 32-byte key read from this file (binary or hex)
+        #[arg(long, value_name = "PATH", conflicts_with = "identity")]
+        key_file: Option<PathBuf>,
+        /// Decrypts the file with a password, read from the terminal or PNGME_PASSWORD
+        #[arg(long, conflicts_with_all = ["identity", "key_file"])]
+        password: bool,
     },
     /// Searches for a message hidden in a PNG file
     Decode {
+        /// A PNG file, or a directory or quoted glob pattern (e.g. './assets/**/*.png') to
+        /// process every PNG file it names
+        file_path: PathBuf,
+        /// Chunk type that stores the message (only used by the chunk and interleave modes)
+        #[arg(required_unless_present = "auto")]
+        chunk_type: Option<String>,
+        /// Steganography technique: chunk, lsb, alpha, eof or interleave
+        #[arg(long, default_value = "chunk")]
+        mode: Mode,
+        /// The number of low bits per sample the message was hidden in with `encode --bits`
+        /// (lsb mode only)
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=4))]
+        bits: u8,
+        /// Reads a message hidden with `encode --adaptive` (lsb and alpha modes only)
+        #[arg(long)]
+        adaptive: bool,
+        /// Decodes every pngme payload found in any chunk or after IEND
+        #[arg(long, conflicts_with_all = ["chunk_type", "mode", "combine", "bits", "adaptive"])]
+        auto: bool,
+        /// Reassembles a message split with `encode --across` from FILE_PATH and these files
+        #[arg(long, num_args = 1.., value_name = "FILES", conflicts_with = "save")]
+        combine: Vec<PathBuf>,
+        /// Writes the payload to a file instead of printing it (an extension matching
+        /// the detected file type is appended if the path has none), or - for the raw payload
+        /// on stdout
+        #[arg(long, conflicts_with = "auto")]
+        save: Option<PathBuf>,
+        /// Writes the raw payload to exactly this path instead of printing it, or - for stdout
+        #[arg(short, long, value_name = "PATH", conflicts_with_all = ["auto", "save"])]
+        output: Option<PathBuf>,
+        /// Extracts a directory hidden with `encode-dir` into this directory
+        #[arg(long, value_name = "DIR", requires = "chunk_type", conflicts_with_all = ["auto", "combine", "save", "output"])]
+        extract_dir: Option<PathBuf>,
+        /// Decrypts the message with this X25519 secret key (a key file or a keystore name)
+        #[arg(long, value_name = "KEY", conflicts_with_all = ["auto", "combine"])]
+        identity: Option<PathBuf>,
+        /// Decrypts the message with a raw 32-byte key read from this file (binary or hex)
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["auto", "combine", "identity"])]
+        key_file: Option<PathBuf>,
+        /// Decrypts the message with a password, read from the terminal or PNGME_PASSWORD
+        #[arg(long, conflicts_with_all = ["auto", "combine", "identity", "key_file"])]
+        password: bool,
+        /// Output format: text, or json with the payload in base64
+        #[arg(long, value_enum, default_value = "text", conflicts_with_all = ["save", "output", "extract_dir"])]
+        format: OutputFormat,
+        /// Decodes every chunk of the type instead of the first one, printing each message
+        /// with the position of its chunk (chunk mode only)
+        #[arg(long, conflicts_with_all = ["auto", "combine", "save", "output", "extract_dir", "format", "encoding"])]
+        all: bool,
+        /// Writes the payload as is to stdout, even if it isn't text
+        #[arg(long, group = "encoding", conflicts_with_all = ["auto", "save", "output", "extract_dir", "format"])]
+        raw: bool,
+        /// Prints the payload as hex, even if it isn't text
+        #[arg(long, group = "encoding", conflicts_with_all = ["auto", "save", "output", "extract_dir", "format"])]
+        hex: bool,
+        /// Prints the payload in base64, even if it isn't text
+        #[arg(long, group = "encoding", conflicts_with_all = ["auto", "save", "output", "extract_dir", "format"])]
+        base64: bool,
+    },
+    /// Mounts the payload hidden in a PNG file as a read-only filesystem, to browse a
+    /// directory hidden with `encode-dir` without extracting it (requires the mount feature,
+    /// Linux only)
+    Mount {
         file_path: PathBuf,
+        mountpoint: PathBuf,
+        /// Chunk type that stores the payload (only used by the chunk and interleave modes)
+        #[arg(long, value_name = "TYPE", default_value = "ruSt")]
         chunk_type: String,
+        /// Steganography technique: chunk, lsb, alpha, eof or interleave
+        #[arg(long, default_value = "chunk")]
+        mode: Mode,
+        /// Decrypts the payload with this X25519 secret key (a key file or a keystore name)
+        #[arg(long, value_name = "KEY")]
+        identity: Option<PathBuf>,
+        /// Decrypts the payload with a raw 32-byte key read from this file (binary or hex)
+        #[arg(long, value_name = "PATH", conflicts_with = "identity")]
+        key_file: Option<PathBuf>,
+        /// Decrypts the payload with a password, read from the terminal or PNGME_PASSWORD
+        #[arg(long, conflicts_with_all = ["identity", "key_file"])]
+        password: bool,
     },
-    /// Removes a chunk from a PNG file
+    /// Replaces the message hidden in chunks of a type, removing all of its copies and pieces,
+    /// in place of the first chunk
+    Replace {
+        file_path: PathBuf,
+        chunk_type: String,
+        message: String,
+        output_file: Option<PathBuf>,
+        /// Hides the message even in a critical or public chunk type such as IHDR or IDAT,
+        /// which usually breaks the image
+        #[arg(long)]
+        force: bool,
+    },
+    /// Removes a chunk from a PNG file. A glob such as 'ru??' removes every ancillary chunk
+    /// whose type matches it.
     Remove {
+        file_path: PathBuf,
+        #[arg(required_unless_present = "index")]
+        chunk_type: Option<String>,
+        /// Removes the chunk at this position (starting at 0 for IHDR) instead of one of a type,
+        /// to pick one of several chunks sharing a type
+        #[arg(long, value_name = "N", conflicts_with_all = ["chunk_type", "all"])]
+        index: Option<usize>,
+        /// Prints the removed chunk
+        #[arg(long)]
+        print: bool,
+        /// Saves the removed chunk (length, type, data and CRC) to a file, see `paste`
+        #[arg(long)]
+        save: Option<PathBuf>,
+        /// Removes every chunk of the type instead of the first one
+        #[arg(long, conflicts_with = "save")]
+        all: bool,
+        /// Copies FILE_PATH to FILE_PATH.bak (or with the given suffix, e.g. --backup=.orig)
+        /// before modifying it in place
+        #[arg(long, value_name = "SUFFIX", num_args = 0..=1, require_equals = true, default_missing_value = ".bak")]
+        backup: Option<String>,
+    },
+    /// Overwrites the data of every chunk of a type with zeros on disk, then removes the chunks
+    Redact {
         file_path: PathBuf,
         chunk_type: String,
+        /// Keeps the zeroed chunks instead of removing them, so the file size doesn't change
+        #[arg(long)]
+        keep: bool,
+    },
+    /// Removes every payload, attestation and provenance record pngme embedded in a PNG file,
+    /// whatever chunk types were used
+    Wipe {
+        file_path: PathBuf,
+        output_file: Option<PathBuf>,
+    },
+    /// Inserts a chunk saved with `remove --save` into a PNG file
+    Paste {
+        file_path: PathBuf,
+        chunk_file: PathBuf,
+        output_file: Option<PathBuf>,
+        /// Inserts the chunk at this position (starting at 0) instead of right before IEND
+        #[arg(long, value_name = "N")]
+        at: Option<usize>,
+        /// Inserts the chunk even if its type is critical or public, such as IHDR or IDAT,
+        /// which usually breaks the image
+        #[arg(long)]
+        force: bool,
+    },
+    /// Reads, stores or lists the keyword/text pairs of the standard textual chunks
+    Text {
+        #[command(subcommand)]
+        command: TextCommands,
+    },
+    /// Stores the author, copyright notice and source URL as standard text entries
+    Credit {
+        file_path: PathBuf,
+        output_file: Option<PathBuf>,
+        /// The name of the image's creator (Author)
+        #[arg(long, required_unless_present_any = ["copyright", "url"])]
+        author: Option<String>,
+        /// The copyright notice, e.g. "© 2024 Jane Doe" (Copyright)
+        #[arg(long)]
+        copyright: Option<String>,
+        /// Where the image comes from (Source)
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// Recompresses the image data at maximum compression to offset embedded payloads
+    Optimize {
+        file_path: PathBuf,
+        output_file: Option<PathBuf>,
+        /// Uses the much slower but stronger zopfli compressor (requires the zopfli feature)
+        #[arg(long)]
+        zopfli: bool,
+    },
+    /// Converts a PNG file into a standard PNG, or moves a hidden payload to another
+    /// steganography mode
+    Convert {
+        file_path: PathBuf,
+        output_file: Option<PathBuf>,
+        /// Converts an Apple CgBI ("iOS optimized") PNG
+        #[arg(long, required_unless_present = "from", conflicts_with = "from")]
+        from_cgbi: bool,
+        /// The mode the payload is hidden with: chunk, lsb, alpha, eof or interleave
+        #[arg(long, requires = "to")]
+        from: Option<Mode>,
+        /// The mode the payload is moved to
+        #[arg(long, requires = "from")]
+        to: Option<Mode>,
+        /// Chunk type storing the payload (only used by the chunk and interleave modes)
+        #[arg(long, value_name = "TYPE", default_value = "ruSt")]
+        chunk_type: String,
+        /// Rebinds an encrypted payload with this X25519 secret key, when the new mode binds
+        /// another part of the image
+        #[arg(long, value_name = "KEY")]
+        identity: Option<PathBuf>,
+        /// Rebinds an encrypted payload with a raw 32-byte key read from this file
+        #[arg(long, value_name = "PATH", conflicts_with = "identity")]
+        key_file: Option<PathBuf>,
+        /// Rebinds an encrypted payload with a password, read from the terminal or PNGME_PASSWORD
+        #[arg(long, conflicts_with_all = ["identity", "key_file"])]
+        password: bool,
+    },
+    /// Checks a PNG file for damage such as truncated chunks or a missing IEND chunk
+    Verify {
+        file_path: PathBuf,
+    },
+    /// Checks a PNG file against the structural rules of the PNG spec (signature, chunk order,
+    /// CRCs, data after IEND) and reports every violation
+    Validate {
+        file_path: PathBuf,
+    },
+    /// Fixes a damaged PNG file
+    Repair {
+        file_path: PathBuf,
+        output_file: Option<PathBuf>,
+        /// Adds the IEND chunk if it is missing
+        #[arg(long)]
+        add_iend: bool,
+    },
+    /// Extracts or embeds an ICC color profile
+    Icc {
+        #[command(subcommand)]
+        command: IccCommands,
+    },
+    /// Stores or shows the license of an image
+    License {
+        #[command(subcommand)]
+        command: LicenseCommands,
+    },
+    /// Stores or shows a JSON document describing the provenance of an image
+    Provenance {
+        #[command(subcommand)]
+        command: ProvenanceCommands,
+    },
+    /// Signs a build provenance attestation (in-toto statement in a DSSE envelope) and stores it
+    Attest {
+        file_path: PathBuf,
+        output_file: Option<PathBuf>,
+        /// The secret key file to sign with, or the name of a keystore key
+        #[arg(long, value_name = "KEY")]
+        key: PathBuf,
+        /// The JSON predicate of the statement, e.g. SLSA build provenance
+        #[arg(long, value_name = "FILE")]
+        predicate: PathBuf,
+        /// The type of the predicate
+        #[arg(long, default_value = pngme::attest::SLSA_PROVENANCE)]
+        predicate_type: String,
+    },
+    /// Checks the signature of a stored attestation and that the image wasn't modified since
+    VerifyAttestation {
+        file_path: PathBuf,
+        /// The public key file of the signer, or the name of a keystore key
+        #[arg(long, value_name = "KEY")]
+        key: PathBuf,
+    },
+    /// Generates a reproducible test image, e.g. to try out payload capacity
+    GenTest {
+        output_file: PathBuf,
+        /// The image dimensions
+        #[arg(long, value_name = "WxH", default_value = "64x64", value_parser = parse_dimensions)]
+        size: (u32, u32),
+        /// Seeds the random samples of the noise pattern and the palette colors
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// noise, gradient or checker
+        #[arg(long, default_value = "noise")]
+        pattern: Pattern,
+        /// gray, rgb, palette, gray-alpha or rgba
+        #[arg(long, default_value = "rgb", value_parser = parse_color_type)]
+        color_type: u8,
+        /// Bits per sample (1, 2, 4, 8 or 16 depending on the color type)
+        #[arg(long, default_value_t = 8)]
+        bit_depth: u8,
+    },
+    /// Renders a message as a QR code in a new PNG file, for data that should be easy to scan
+    /// rather than hidden
+    Qr {
+        message: String,
+        output_file: PathBuf,
+        /// The width of a module (a square of the code), in pixels
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..=64))]
+        scale: u32,
+        /// The width of the blank margin around the code, in modules
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(0..=1024))]
+        border: u32,
+        /// Encrypts the message to this X25519 key (a key file or a keystore name), can be
+        /// given several times so any of the recipients can decrypt it. The code then holds
+        /// text to decrypt with `qr-decrypt`.
+        #[arg(long, value_name = "KEY")]
+        recipient: Vec<PathBuf>,
+        /// Encrypts the message with a raw 32-byte key read from this file (binary or hex)
+        #[arg(long, value_name = "PATH", conflicts_with = "recipient")]
+        key_file: Option<PathBuf>,
+        /// Encrypts the message with a key derived from a password, read from the terminal or
+        /// PNGME_PASSWORD
+        #[arg(long, conflicts_with_all = ["recipient", "key_file"])]
+        password: bool,
+    },
+    /// Decrypts the text of a QR code rendered with `qr --recipient`, `--key-file` or `--password`
+    QrDecrypt {
+        /// The text read from the QR code, starting with pngme:
+        text: String,
+        /// Decrypts the message with this X25519 secret key (a key file or a keystore name)
+        #[arg(long, value_name = "KEY")]
+        identity: Option<PathBuf>,
+        /// Decrypts the message with a raw 32-byte key read from this file (binary or hex)
+        #[arg(long, value_name = "PATH", conflicts_with = "identity")]
+        key_file: Option<PathBuf>,
+        /// Decrypts the message with a password, read from the terminal or PNGME_PASSWORD
+        #[arg(long, conflicts_with_all = ["identity", "key_file"])]
+        password: bool,
+    },
+    /// Generates a key pair for attest and the encryption features
+    Keygen {
+        /// The secret key file to write (the public key goes to the same path with .pub appended)
+        #[arg(long, value_name = "FILE")]
+        out: PathBuf,
+        /// Also generates an X25519 encryption key
+        #[arg(long)]
+        x25519: bool,
+    },
+    /// Manages the keys of the keystore
+    Key {
+        #[command(subcommand)]
+        command: KeyCommands,
+    },
+    /// Runs a command on every PNG file in a directory tree
+    Batch {
+        dir: PathBuf,
+        #[arg(value_enum)]
+        action: BatchAction,
+        /// Follows symbolic links to files and directories (skipped by default)
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Includes files and directories whose name starts with a dot
+        #[arg(long)]
+        include_hidden: bool,
+        /// Skips files and directories matching this glob, relative to DIR (can be repeated)
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+        /// Records the processed files in this file (deleted once every file succeeded)
+        #[arg(long, value_name = "FILE", default_value = ".pngme-checkpoint")]
+        checkpoint: PathBuf,
+        /// Skips the files recorded in the checkpoint by an interrupted run
+        #[arg(long)]
+        resume: bool,
+        /// The number of files processed in parallel
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
+        /// Limits the memory taken by the files being processed (e.g. 512M, 2G) by processing
+        /// fewer of them in parallel. Files too large for it are read one chunk at a time.
+        #[arg(long, env = "PNGME_MAX_MEMORY", value_parser = parse_size)]
+        max_memory: Option<u64>,
+    },
+    /// Git filter that strips (clean) or restores (smudge) private chunks and data after IEND,
+    /// reading a PNG file from stdin and writing the result to stdout
+    Filter {
+        /// Strips the embedded data, saving it in the stash
+        #[arg(long, required_unless_present = "smudge", conflicts_with = "smudge")]
+        clean: bool,
+        /// Restores the embedded data saved in the stash
+        #[arg(long)]
+        smudge: bool,
+        /// Directory where stripped data is kept
+        #[arg(long, env = "PNGME_STASH", default_value = ".git/pngme-stash")]
+        stash: PathBuf,
+    },
+    /// Scans PNG files and directories for hidden data
+    Scan {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Exits with an error if denied hidden data is found or a file can't be scanned
+        #[arg(long)]
+        ci: bool,
+        /// The kinds of hidden data that fail the scan in CI mode: executables, polyglots,
+        /// private-chunks, unknown-chunks, eof-data, rule-matches (all of them by default)
+        #[arg(long, value_delimiter = ',', value_name = "KINDS")]
+        deny: Vec<FindingKind>,
+        /// Output format: text, sarif or json
+        #[arg(long, default_value = "text")]
+        format: ReportFormat,
+        /// Also looks for the patterns of this rules file
+        #[arg(long, value_name = "FILE")]
+        rules: Option<PathBuf>,
+        /// Refuses files whose IHDR or IEND chunk doesn't have the length set by the spec
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Writes a forensic report of a PNG file, ready to attach to an incident ticket
+    Report {
+        file_path: PathBuf,
+        output_file: Option<PathBuf>,
+        /// Document format: md or html
+        #[arg(long, default_value = "md")]
+        format: DocumentFormat,
+    },
+    /// Dumps a PNG file (signature, every chunk and the data after IEND) to stdout
+    Export {
+        file_path: PathBuf,
+        /// Dumps the file as JSON, with the chunk data in base64
+        #[arg(long, required = true)]
+        json: bool,
+    },
+    /// Rebuilds a PNG file from a JSON dump written by `export --json`
+    Import {
+        dump_path: PathBuf,
+        output_file: PathBuf,
     },
     /// Prints all of the chunks in a PNG file
     Print {
+        /// A PNG file, or a directory or quoted glob pattern (e.g. './assets/**/*.png') to
+        /// process every PNG file it names
+        file_path: PathBuf,
+        /// Prints only the chunks whose type matches this glob, e.g. 'ru??'
+        #[arg(long = "type", value_name = "GLOB")]
+        type_glob: Option<String>,
+        /// Prints only the chunks whose type matches this regular expression
+        #[arg(long, value_name = "REGEX", conflicts_with = "type_glob")]
+        type_regex: Option<String>,
+        /// Output format: text, or json with the type, flags, offset, length, CRC and data
+        /// (in base64) of every chunk
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Prints how many chunks of each type a PNG file contains
+    Stats {
+        file_path: PathBuf,
+    },
+    /// Prints how much embedded data adds to the size of a PNG file
+    Size {
+        file_path: PathBuf,
+    },
+    /// Writes manual pages for pngme and each of its commands
+    Man {
+        /// The directory the pages are written to, created if needed
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        out_dir: PathBuf,
+    },
+    /// Lists the plugins and the commands they add
+    Plugins,
+    /// A command added by a plugin: VERB FILE_PATH [ARGS]...
+    #[command(external_subcommand)]
+    Plugin(Vec<String>),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum IccCommands {
+    /// Writes the embedded ICC profile to a file
+    Extract {
+        file_path: PathBuf,
+        profile_path: PathBuf,
+    },
+    /// Embeds an ICC profile, replacing the existing one
+    Set {
+        file_path: PathBuf,
+        profile_path: PathBuf,
+        output_file: Option<PathBuf>,
+        /// The name stored with the profile
+        #[arg(long, default_value = "ICC Profile")]
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum TextCommands {
+    /// Prints the text stored with a keyword
+    Get {
         file_path: PathBuf,
+        keyword: String,
+    },
+    /// Stores a keyword/text pair, replacing the entries with the same keyword
+    Set {
+        file_path: PathBuf,
+        keyword: String,
+        text: String,
+        output_file: Option<PathBuf>,
+        /// Compresses the text (writes a zTXt chunk unless an iTXt chunk is requested)
+        #[arg(long)]
+        compressed: bool,
+        /// Writes an international iTXt chunk, which allows any UTF-8 text
+        #[arg(long)]
+        itxt: bool,
+        /// Language tag of the text, e.g. en-US (implies --itxt)
+        #[arg(long)]
+        lang: Option<String>,
+        /// The keyword translated into the language of the text (implies --itxt)
+        #[arg(long)]
+        translated_keyword: Option<String>,
+    },
+    /// Prints every keyword/text pair (tEXt, zTXt and iTXt chunks)
+    List {
+        file_path: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum LicenseCommands {
+    /// Stores an SPDX license expression and the author, replacing the existing ones
+    Set {
+        file_path: PathBuf,
+        output_file: Option<PathBuf>,
+        /// SPDX license expression, e.g. CC-BY-4.0 or "MIT OR Apache-2.0"
+        #[arg(long)]
+        spdx: String,
+        /// The author to attribute the image to
+        #[arg(long)]
+        author: Option<String>,
+    },
+    /// Prints the stored license and author
+    Show {
+        file_path: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ProvenanceCommands {
+    /// Stores a JSON document, replacing the existing one
+    Set {
+        file_path: PathBuf,
+        document_path: PathBuf,
+        output_file: Option<PathBuf>,
+        /// Refuses documents that don't match this JSON Schema
+        #[arg(long, value_name = "FILE")]
+        schema: Option<PathBuf>,
+    },
+    /// Prints the stored JSON document
+    Show {
+        file_path: PathBuf,
+        /// Fails if the document doesn't match this JSON Schema
+        #[arg(long, value_name = "FILE")]
+        schema: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum KeyCommands {
+    /// Lists the keys with their fingerprints
+    List,
+    /// Adds a secret or public key file to the keystore, replacing the key with the same name
+    Import {
+        key_path: PathBuf,
+        /// The name of the key (defaults to the file name without extension)
+        #[arg(long)]
+        name: Option<String>,
+        /// Imports a public key even over a stored secret key of the same name, deleting it
+        #[arg(long)]
+        force: bool,
+    },
+    /// Prints the public key file of a key, e.g. to share it
+    Export {
+        name: String,
+        output_file: Option<PathBuf>,
+        /// Exports the secret key file instead
+        #[arg(long)]
+        secret: bool,
+    },
+    /// Prints the fingerprint of a key file or of a key of the keystore
+    Fingerprint {
+        key: PathBuf,
+    },
+}
+
+/// The output formats of `print` and `decode`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human readable text
+    Text,
+    /// Pretty-printed JSON, for jq and other tools
+    Json,
+}
+
+/// The commands `batch` can run on every file
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum BatchAction {
+    /// Checks every file for damage
+    Verify,
+    /// Decodes every pngme payload (like `decode --auto`)
+    Decode,
+    /// Prints the chunks of every file
+    Print,
+    /// Prints the chunk counts of every file
+    Stats,
+    /// Prints how much embedded data adds to every file
+    Size,
+}
+
+impl Commands {
+    /// The input file the command operates on, if it reads a single one
+    fn file_path(&self) -> Option<&Path> {
+        let file_path = match self {
+            Commands::Encode { file_path, .. }
+            | Commands::Decode { file_path, .. }
+            | Commands::EncodeDir { file_path, .. }
+            | Commands::EncodeFile { file_path, .. }
+            | Commands::Extract { file_path, .. }
+            | Commands::Mount { file_path, .. }
+            | Commands::Replace { file_path, .. }
+            | Commands::Remove { file_path, .. }
+            | Commands::Redact { file_path, .. }
+            | Commands::Wipe { file_path, .. }
+            | Commands::Paste { file_path, .. }
+            | Commands::Credit { file_path, .. }
+            | Commands::Optimize { file_path, .. }
+            | Commands::Convert { file_path, .. }
+            | Commands::Verify { file_path }
+            | Commands::Validate { file_path }
+            | Commands::Attest { file_path, .. }
+            | Commands::VerifyAttestation { file_path, .. }
+            | Commands::Repair { file_path, .. }
+            | Commands::Report { file_path, .. }
+            | Commands::Print { file_path, .. }
+            | Commands::Export { file_path, .. }
+            | Commands::Import { dump_path: file_path, .. }
+            | Commands::Stats { file_path }
+            | Commands::Size { file_path } => file_path,
+            Commands::Icc { command: IccCommands::Extract { file_path, .. } }
+            | Commands::Icc { command: IccCommands::Set { file_path, .. } } => file_path,
+            Commands::Text { command: TextCommands::Get { file_path, .. } }
+            | Commands::Text { command: TextCommands::Set { file_path, .. } }
+            | Commands::Text { command: TextCommands::List { file_path } } => file_path,
+            Commands::License { command: LicenseCommands::Set { file_path, .. } }
+            | Commands::License { command: LicenseCommands::Show { file_path } } => file_path,
+            Commands::Provenance { command: ProvenanceCommands::Set { file_path, .. } }
+            | Commands::Provenance { command: ProvenanceCommands::Show { file_path, .. } } => file_path,
+            // `batch` checks the size of every file it runs on, not the size of its directory
+            Commands::Batch { .. }
+            | Commands::Filter { .. }
+            | Commands::Scan { .. }
+            | Commands::GenTest { .. }
+            | Commands::Qr { .. }
+            | Commands::QrDecrypt { .. }
+            | Commands::Keygen { .. }
+            | Commands::Key { .. }
+            | Commands::Man { .. }
+            | Commands::Plugins
+            | Commands::Plugin(_) => return None,
+        };
+        Some(file_path)
+    }
+
+    /// The memory budget of `batch --max-memory`, over which files are read one chunk at a time
+    fn max_memory(&self) -> Option<u64> {
+        match self {
+            Commands::Batch { max_memory, .. } => *max_memory,
+            _ => None,
+        }
+    }
+
+    /// Returns true if the command prints a JSON document, returned by `run`
+    fn is_json(&self) -> bool {
+        matches!(
+            self,
+            Commands::Print { format: OutputFormat::Json, .. } | Commands::Decode { format: OutputFormat::Json, .. }
+        )
+    }
+
+    /// The input file of the commands that also take a directory or a glob pattern, to run
+    /// on every PNG file it names (see `batch::expand`)
+    fn batch_path_mut(&mut self) -> Option<&mut PathBuf> {
+        match self {
+            Commands::Encode { file_path, .. }
+            | Commands::Decode { file_path, .. }
+            | Commands::Print { file_path, .. } => Some(file_path),
+            _ => None,
+        }
+    }
+
+    /// Refuses the options that name a single input or output file when the command runs on
+    /// several files
+    fn check_batch(&self) -> Result<()> {
+        let single_file = match self {
+            Commands::Encode { output_file, output, across, .. } => {
+                output_file.is_some() || output.is_some() || !across.is_empty()
+            }
+            Commands::Decode { combine, save, output, extract_dir, .. } => {
+                !combine.is_empty() || save.is_some() || output.is_some() || extract_dir.is_some()
+            }
+            _ => false,
+        };
+        if single_file {
+            return Err("OUTPUT_FILE, --across, --combine, --save, --output and --extract-dir take a single file, not a directory or pattern".into());
+        }
+        Ok(())
+    }
+}
+
+/// Parses image dimensions written as WIDTHxHEIGHT, e.g. 640x480
+fn parse_dimensions(size: &str) -> std::result::Result<(u32, u32), String> {
+    size.split_once(['x', 'X'])
+        .and_then(|(width, height)| Some((width.trim().parse().ok()?, height.trim().parse().ok()?)))
+        .ok_or_else(|| format!("invalid dimensions: {} (expected WIDTHxHEIGHT)", size))
+}
+
+/// Parses a byte count with an optional K, M or G suffix (powers of 1024)
+fn parse_size(size: &str) -> std::result::Result<u64, String> {
+    let size = size.trim();
+    let (digits, multiplier) = match size.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+
+    digits.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size: {}", size))
+}
+
+/// With `--ignore-case`, replaces `chunk_type` by the type of a chunk of the file that only
+/// differs in case
+fn matching_case(session: &Session, ignore_case: bool, file_path: &Path, chunk_type: String) -> Result<String> {
+    if ignore_case {
+        pngme::find_chunk_type(session, file_path, &chunk_type)
+    } else {
+        Ok(chunk_type)
+    }
+}
+
+/// Asks the user to confirm a destructive operation. Only asks when stdin is a terminal,
+/// so scripts aren't blocked; `--yes` skips the question.
+fn confirm(yes: bool, question: &str) -> Result<()> {
+    if yes || !io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err("aborted".into()),
+    }
+}
+
+/// With `--backup`, copies a file about to be modified in place to the same path with `suffix`
+/// appended. Streams aren't modified in place, and nothing is modified in a dry run, so no
+/// copy is made then.
+fn backup(session: &Session, file_path: &Path, suffix: Option<&str>) -> Result<()> {
+    let Some(suffix) = suffix else {
+        return Ok(());
+    };
+    if suffix.is_empty() {
+        return Err("the --backup suffix must not be empty".into());
+    }
+    if positioned::is_stream(file_path) || session.is_dry_run() {
+        return Ok(());
+    }
+
+    let mut backup_path = file_path.as_os_str().to_owned();
+    backup_path.push(suffix);
+    fs::copy(file_path, &backup_path)?;
+    Ok(())
+}
+
+/// The backend of `mode`, splitting the payload into `split` chunks with `--split`
+fn split_backend(mode: Mode, chunk_type: &str, split: usize) -> Result<Box<dyn StegoBackend>> {
+    match mode {
+        Mode::Chunk => Ok(Box::new(ChunkBackend::new(chunk_type.parse()?).with_pieces(split)?)),
+        _ if split > 1 => Err("--split is only supported by the chunk mode".into()),
+        _ => mode.backend(chunk_type),
+    }
+}
+
+/// The encryption asked for with `--recipient`, `--key-file` or `--password`, if any
+fn encryption(keystore: &Keystore, recipients: &[PathBuf], key_file: Option<PathBuf>, password: bool) -> Result<Option<Encryption>> {
+    if password {
+        return Ok(Some(Encryption::Password(read_password(true)?)));
+    }
+    if let Some(key_file) = key_file {
+        return Ok(Some(Encryption::Key(keys::read_raw_key(key_file)?)));
     }
+    if recipients.is_empty() {
+        return Ok(None);
+    }
+    let recipients = recipients.iter()
+        .map(|key| keys::read_x25519_public(keystore.resolve(key, false)))
+        .collect::<Result<_>>()?;
+    Ok(Some(Encryption::Recipients(recipients)))
+}
+
+/// The secret given with `--identity`, `--key-file` or `--password`, if any
+fn secret(keystore: &Keystore, identity: Option<PathBuf>, key_file: Option<PathBuf>, password: bool) -> Result<Option<Secret>> {
+    Ok(match (identity, key_file) {
+        (Some(key), _) => Some(Secret::Identity(keys::read_x25519_secret(keystore.resolve(key, true))?)),
+        (_, Some(key_file)) => Some(Secret::Key(keys::read_raw_key(key_file)?)),
+        (None, None) if password => Some(Secret::Password(read_password(false)?)),
+        (None, None) => None,
+    })
+}
+
+/// The password of `--password`: PNGME_PASSWORD if it is set, otherwise read from the terminal
+/// without echoing it, twice when encrypting to catch typos
+fn read_password(encrypting: bool) -> Result<String> {
+    if let Ok(password) = std::env::var("PNGME_PASSWORD") {
+        return Ok(password);
+    }
+    let prompt = |prompt: &str| {
+        rpassword::prompt_password(prompt)
+            .map_err(|e| format!("couldn't read the password from the terminal ({}), set PNGME_PASSWORD instead", e))
+    };
+    let password = prompt("Password: ")?;
+    if encrypting && prompt("Repeat the password: ")? != password {
+        return Err("the passwords don't match".into());
+    }
+    if password.is_empty() {
+        return Err("the password is empty".into());
+    }
+    Ok(password)
+}
+
+/// The plugins adding commands for private chunk types. Plugins from other crates are
+/// registered here.
+fn plugins() -> Result<PluginRegistry> {
+    Ok(PluginRegistry::new())
+}
+
+/// Prints the registered plugins and their commands
+fn print_plugins(registry: &PluginRegistry) {
+    if registry.plugins().next().is_none() {
+        println!("No plugins registered");
+    }
+    for plugin in registry.plugins() {
+        println!("{} ({})", plugin.name(), plugin.chunk_types().join(", "));
+        for verb in plugin.verbs() {
+            println!("  {:<20} {}", verb.name, verb.about);
+        }
+    }
+}
+
+/// Prints every problem `pngme::verify` found in a file. Fails if there is at least one.
+fn print_verify(session: &Session, file_path: &Path) -> Result<()> {
+    let problems = pngme::verify(session, file_path)?;
+    let path = file_path.display();
+    if problems.is_empty() {
+        println!("{}: OK", path);
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}: {}", path, problem);
+    }
+    Err(format!("{} problem(s) found", problems.len()).into())
+}
+
+/// Prints every violation `pngme::validate` found in a file. Fails if there is at least one.
+fn print_validate(session: &Session, file_path: &Path) -> Result<()> {
+    let violations = pngme::validate(session, file_path)?;
+    let path = file_path.display();
+    if violations.is_empty() {
+        println!("{}: valid", path);
+        return Ok(());
+    }
+    for violation in &violations {
+        println!("{}: {}", path, violation);
+    }
+    Err(format!("{} violation(s) found", violations.len()).into())
+}
+
+/// Prints every pngme payload found by `pngme::decode_auto`
+fn print_decode_auto(session: &Session, file_path: &Path) -> Result<()> {
+    for (location, payload) in pngme::decode_auto(session, file_path)? {
+        match payload {
+            Ok(payload) => println!("{}: {}", location, payload),
+            Err(e) => eprintln!("{}: {}", location, e),
+        }
+    }
+    Ok(())
+}
+
+fn print_size(session: &Session, file_path: &Path) -> Result<()> {
+    let size = pngme::size(session, file_path)?;
+    println!("Total size:    {} bytes", size.total);
+    println!("Embedded data: {} bytes", size.embedded);
+    println!("Stripped size: {} bytes", size.stripped());
+    println!("Overhead:      {:.2}%", size.overhead());
+    Ok(())
+}
+
+fn print_stats(session: &Session, file_path: &Path) -> Result<()> {
+    let counts = pngme::stats(session, file_path)?;
+    println!("Chunks: {}", counts.iter().map(|(_, count)| count).sum::<usize>());
+    for (chunk_type, count) in counts {
+        let duplicate = if count > 1 { " (duplicate)" } else { "" };
+        println!("  {}: {}{}", chunk_type, count, duplicate);
+    }
+    Ok(())
+}
+
+/// Prints the findings of `pngme::scan` in `format`. In CI mode, fails if a denied finding
+/// was found or a file couldn't be read.
+fn print_scan(reports: &[FileReport], deny: &[FindingKind], format: ReportFormat, ci: bool) -> Result<()> {
+    let (mut findings, mut denied, mut errors) = (0, 0, 0);
+    for report in reports {
+        match &report.findings {
+            Ok(found) => {
+                findings += found.len();
+                denied += found.iter().filter(|finding| deny.contains(&finding.kind)).count();
+            }
+            Err(_) => errors += 1,
+        }
+    }
+
+    match format {
+        ReportFormat::Text => {
+            for report in reports {
+                let path = report.path.display();
+                match &report.findings {
+                    Ok(found) => for finding in found {
+                        let level = if deny.contains(&finding.kind) { "denied" } else { "found" };
+                        println!("{}: {} {}: {}", path, level, finding.kind, finding);
+                    },
+                    Err(e) => println!("{}: error: {}", path, e),
+                }
+            }
+            println!("Scanned {} file(s): {} finding(s), {} denied, {} error(s)", reports.len(), findings, denied, errors);
+        }
+        ReportFormat::Sarif => println!("{}", serde_json::to_string_pretty(&scan::to_sarif(reports, deny))?),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&scan::to_json(reports, deny))?),
+    }
+
+    if ci && (denied > 0 || errors > 0) {
+        return Err(format!("{} denied finding(s), {} file(s) couldn't be scanned", denied, errors).into());
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+
+    let session = Session::new()
+        .with_dry_run(args.dry_run)
+        .with_lenient(args.lenient)
+        .with_upload(args.upload)
+        .with_max_memory(args.command.max_memory());
+    let files = match args.command.batch_path_mut() {
+        Some(path) => batch::expand(path)?,
+        None => None,
+    };
+    if let Some(files) = files {
+        args.command.check_batch()?;
+        // The JSON documents of the files are gathered into a single array
+        let json = args.command.is_json();
+        let documents = Mutex::new(Vec::new());
+        let result = batch::run_files(&files, &RunOptions { quiet: json, ..RunOptions::default() }, None, |file_path| {
+            let mut args = args.clone();
+            if let Some(path) = args.command.batch_path_mut() {
+                *path = file_path.to_path_buf();
+            }
+            let path = file_path.display().to_string();
+            match run(args, &session) {
+                Ok(Some(document)) => documents.lock().unwrap().push(json!({ "path": path, "result": document })),
+                Ok(None) => {}
+                Err(e) if json => {
+                    documents.lock().unwrap().push(json!({ "path": path, "error": e.to_string() }));
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            }
+            Ok(())
+        });
+        if json {
+            println!("{}", serde_json::to_string_pretty(&documents.into_inner().unwrap())?);
+        }
+        return result;
+    }
+    if let Some(document) = run(args, &session)? {
+        println!("{}", serde_json::to_string_pretty(&document)?);
+    }
+    Ok(())
+}
+
+/// Runs the command on a single input file. The JSON document of a command run with
+/// `--format json` is returned instead of being printed, see `Commands::is_json`.
+fn run(args: Args, session: &Session) -> Result<Option<Value>> {
+    if let (Some(max_file_size), Some(file_path)) = (args.max_file_size, args.command.file_path()) {
+        pngme::check_file_size(session, file_path, max_file_size)?;
+    }
+
+    // A file read from a pipe isn't modified in place, the result goes to stdout instead
+    let yes = args.yes || args.dry_run || args.command.file_path().is_some_and(positioned::is_stream);
+
+    let keystore = match args.keystore.or_else(Keystore::default_dir) {
+        Some(dir) => Keystore::new(dir),
+        None => Keystore::new(".pngme-keys"),
+    };
 
     match args.command {
         Commands::Encode {
@@ -43,11 +1212,498 @@ fn main() -> Result<()> {
             chunk_type,
             message,
             output_file,
-        } => pngme::encode(file_path, &chunk_type, message, output_file)?,
-        Commands::Decode {file_path, chunk_type} => pngme::decode(file_path, &chunk_type)?,
-        Commands::Remove {file_path, chunk_type} => pngme::remove(file_path, &chunk_type)?,
-        Commands::Print {file_path} => pngme::print_chunks(file_path)?,
+            message_file,
+            output,
+            mode,
+            across,
+            copies,
+            split,
+            bits,
+            adaptive,
+            recipient,
+            key_file,
+            password,
+            append_data,
+            compress,
+            backup: backup_suffix,
+            template,
+            force,
+        } => {
+            let output_file = output.or(output_file);
+            let message = match (message_file, message) {
+                (Some(path), _) if path.as_os_str() == positioned::STDIO => {
+                    if file_path.as_os_str() == positioned::STDIO {
+                        return Err("--message-file - and FILE_PATH - can't both read stdin".into());
+                    }
+                    pngme::read_message(io::stdin().lock())?
+                }
+                (Some(path), _) => pngme::read_message(fs::File::open(path)?)?,
+                (None, message) => message.unwrap_or_default().into_bytes(),
+            };
+            let message = if template {
+                let message = String::from_utf8(message).map_err(|_| "--template needs a UTF-8 message")?;
+                template::expand(&message, &Context::current(&file_path)?)?.into_bytes()
+            } else {
+                message
+            };
+            if matches!(mode, Mode::Chunk | Mode::Interleave) {
+                EncodeOptions { allow_critical: force }.check(&chunk_type.parse()?)?;
+            }
+            let backend: Box<dyn StegoBackend> = match mode {
+                _ if bits > 1 && mode != Mode::Lsb => return Err("--bits is only supported by the lsb mode".into()),
+                _ if adaptive && !matches!(mode, Mode::Lsb | Mode::Alpha) => {
+                    return Err("--adaptive is only supported by the lsb and alpha modes".into())
+                }
+                Mode::Chunk => Box::new(ChunkBackend::with_copies(chunk_type.parse()?, copies)?.with_pieces(split)?),
+                _ if copies > 1 => return Err("--copies is only supported by the chunk mode".into()),
+                _ if split > 1 => return Err("--split is only supported by the chunk mode".into()),
+                Mode::Lsb => {
+                    let backend = LsbBackend::with_bits(bits)?.adaptive(adaptive);
+                    if across.is_empty() {
+                        eprintln!("{}", pngme::lsb_estimate(session, &file_path, &backend, message.len())?);
+                    }
+                    Box::new(backend)
+                }
+                Mode::Alpha => Box::new(AlphaBackend::default().adaptive(adaptive)),
+                _ => mode.backend(&chunk_type)?,
+            };
+            let encryption = encryption(&keystore, &recipient, key_file, password)?;
+            if output_file.is_none() {
+                for file_path in std::iter::once(&file_path).chain(&across) {
+                    backup(session, file_path, backup_suffix.as_deref())?;
+                }
+            }
+            if append_data {
+                pngme::append_with_backend(session, file_path, backend.as_ref(), &message, output_file)?
+            } else if compress || encryption.is_some() {
+                pngme::encode_sealed(session, file_path, backend.as_ref(), &message, compress, encryption.as_ref(), output_file)?
+            } else if across.is_empty() {
+                pngme::encode_with_backend(session, file_path, backend.as_ref(), &message, output_file)?
+            } else {
+                let file_paths: Vec<_> = std::iter::once(file_path).chain(across).collect();
+                pngme::encode_across(session, &file_paths, backend.as_ref(), &message)?
+            }
+        }
+        Commands::EncodeDir {file_path, dir, output_file, chunk_type, mode, compress, split, recipient, key_file, password} => {
+            let backend = split_backend(mode, &chunk_type, split)?;
+            let encryption = encryption(&keystore, &recipient, key_file, password)?;
+            pngme::encode_dir(session, file_path, dir, backend.as_ref(), compress, encryption.as_ref(), output_file)?
+        }
+        Commands::EncodeFile {file_path, chunk_type, payload_path, output_file, mode, split, recipient, key_file, password} => {
+            let backend = split_backend(mode, &chunk_type, split)?;
+            let encryption = encryption(&keystore, &recipient, key_file, password)?;
+            pngme::encode_file(session, file_path, backend.as_ref(), payload_path, encryption.as_ref(), output_file)?
+        }
+        Commands::Extract {file_path, chunk_type, mode, out_dir, force, identity, key_file, password} => {
+            let backend = mode.backend(&chunk_type)?;
+            let secret = secret(&keystore, identity, key_file, password)?;
+            let path = pngme::extract_file(session, file_path, backend.as_ref(), out_dir, secret.as_ref(), force)?;
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            println!("Extracted {} ({} bytes) to {}", name, fs::metadata(&path)?.len(), path.display());
+        }
+        Commands::Decode {
+            file_path,
+            chunk_type,
+            mode,
+            bits,
+            adaptive,
+            auto,
+            combine,
+            save,
+            output,
+            extract_dir,
+            identity,
+            key_file,
+            password,
+            format,
+            raw,
+            hex,
+            base64,
+            all,
+        } => match chunk_type {
+            Some(chunk_type) if all => {
+                if mode != Mode::Chunk {
+                    return Err("--all is only supported by the chunk mode".into());
+                }
+                let chunk_type = matching_case(session, args.ignore_case, &file_path, chunk_type)?;
+                let secret = secret(&keystore, identity, key_file, password)?;
+                for (index, message) in pngme::decode_all(session, file_path, &chunk_type, secret.as_ref())? {
+                    match message {
+                        Ok(message) => println!("#{}: {}", index, message),
+                        Err(e) => eprintln!("#{}: {}", index, e),
+                    }
+                }
+            }
+            Some(chunk_type) if !auto => {
+                let chunk_type = matching_case(session, args.ignore_case, &file_path, chunk_type)?;
+                let backend: Box<dyn StegoBackend> = match mode {
+                    Mode::Lsb => Box::new(LsbBackend::with_bits(bits)?.adaptive(adaptive)),
+                    _ if bits > 1 => return Err("--bits is only supported by the lsb mode".into()),
+                    Mode::Alpha => Box::new(AlphaBackend::default().adaptive(adaptive)),
+                    _ if adaptive => return Err("--adaptive is only supported by the lsb and alpha modes".into()),
+                    _ => mode.backend(&chunk_type)?,
+                };
+                let secret = secret(&keystore, identity, key_file, password)?;
+                if let Some(dir) = extract_dir {
+                    let count = pngme::extract_dir(session, file_path.as_path(), backend.as_ref(), &dir, secret.as_ref())?;
+                    println!("Extracted {} entries to {}", count, dir.display());
+                } else if let Some(save) = save {
+                    pngme::extract_with_backend(session, file_path, backend.as_ref(), save, secret.as_ref())?;
+                } else if format == OutputFormat::Json || output.is_some() || raw || hex || base64 {
+                    let payload = if combine.is_empty() {
+                        pngme::decode_to_bytes(session, file_path, backend.as_ref(), secret.as_ref())?
+                    } else {
+                        let file_paths: Vec<_> = std::iter::once(file_path).chain(combine).collect();
+                        pngme::decode_combine_to_bytes(session, &file_paths, backend.as_ref())?
+                    };
+                    match output {
+                        Some(output) => pngme::save_payload(session, output, &payload)?,
+                        None if raw => io::stdout().lock().write_all(&payload)?,
+                        None if hex => println!("{}", dump::payload_hex(&payload)),
+                        None if base64 => println!("{}", dump::payload_base64(&payload)),
+                        None => return Ok(Some(dump::payload_json(&payload))),
+                    }
+                } else if combine.is_empty() {
+                    println!("{}", pngme::decode_with_backend(session, file_path, backend.as_ref(), secret.as_ref())?)
+                } else {
+                    let file_paths: Vec<_> = std::iter::once(file_path).chain(combine).collect();
+                    println!("{}", pngme::decode_combine(session, &file_paths, backend.as_ref())?)
+                }
+            }
+            _ if extract_dir.is_some() => return Err("--extract-dir requires CHUNK_TYPE".into()),
+            _ if format == OutputFormat::Json => return Ok(Some(pngme::decode_auto_json(session, file_path)?)),
+            _ => print_decode_auto(session, &file_path)?,
+        },
+        Commands::Mount {file_path, mountpoint, chunk_type, mode, identity, key_file, password} => {
+            let backend = mode.backend(&chunk_type)?;
+            let secret = secret(&keystore, identity, key_file, password)?;
+            pngme::mount(session, file_path, backend.as_ref(), mountpoint, secret.as_ref())?
+        }
+        Commands::Replace {file_path, chunk_type, message, output_file, force} => {
+            let chunk_type = matching_case(session, args.ignore_case, &file_path, chunk_type)?;
+            let options = EncodeOptions { allow_critical: force };
+            pngme::replace(session, file_path, &chunk_type, message.as_bytes(), &options, output_file)?
+        }
+        Commands::Remove {file_path, chunk_type: Some(chunk_type), print, save, backup: backup_suffix, ..} if ChunkTypePattern::is_glob(&chunk_type) => {
+            if save.is_some() {
+                return Err("--save takes a single chunk type, not a pattern".into());
+            }
+            let pattern = ChunkTypePattern::glob(&chunk_type, args.ignore_case)?;
+            confirm(yes, &format!("Remove every chunk matching {} from {}?", chunk_type, file_path.display()))?;
+            backup(session, &file_path, backup_suffix.as_deref())?;
+            let chunks = pngme::remove_matching(session, file_path, &pattern)?;
+            if print {
+                for chunk in chunks {
+                    println!("Removed {}", chunk);
+                }
+            }
+        }
+        Commands::Remove {file_path, chunk_type: Some(chunk_type), print, backup: backup_suffix, all: true, ..} => {
+            let chunk_type = matching_case(session, args.ignore_case, &file_path, chunk_type)?;
+            confirm(yes, &format!("Remove every {} chunk from {}?", chunk_type, file_path.display()))?;
+            backup(session, &file_path, backup_suffix.as_deref())?;
+            let chunks = pngme::remove_all(session, file_path, &chunk_type)?;
+            if print {
+                for chunk in chunks {
+                    println!("Removed {}", chunk);
+                }
+            }
+        }
+        Commands::Remove {file_path, chunk_type, index, print, save, backup: backup_suffix, ..} => {
+            let chunk = match (index, chunk_type) {
+                (Some(index), _) => {
+                    confirm(yes, &format!("Remove chunk #{} from {}?", index, file_path.display()))?;
+                    backup(session, &file_path, backup_suffix.as_deref())?;
+                    pngme::remove_at(session, file_path, index, save.as_deref())?
+                }
+                (None, Some(chunk_type)) => {
+                    let chunk_type = matching_case(session, args.ignore_case, &file_path, chunk_type)?;
+                    confirm(yes, &format!("Remove the {} chunk from {}?", chunk_type, file_path.display()))?;
+                    backup(session, &file_path, backup_suffix.as_deref())?;
+                    pngme::remove(session, file_path, &chunk_type, save.as_deref())?
+                }
+                (None, None) => unreachable!("clap requires CHUNK_TYPE or --index"),
+            };
+            if print {
+                println!("Removed {}", chunk);
+                match chunk.data_as_string() {
+                    Ok(data) => println!("{}", data),
+                    Err(_) => println!("{}", chunk.data_as_hex()),
+                }
+            }
+        }
+        Commands::Redact {file_path, chunk_type, keep} => {
+            let chunk_type = matching_case(session, args.ignore_case, &file_path, chunk_type)?;
+            confirm(yes, &format!("Redact every {} chunk of {}?", chunk_type, file_path.display()))?;
+            let count = pngme::redact(session, file_path, &chunk_type, keep)?;
+            if !args.dry_run {
+                println!("Redacted {} chunk(s)", count);
+            }
+        }
+        Commands::Wipe {file_path, output_file} => {
+            if output_file.is_none() {
+                confirm(yes, &format!("Remove all pngme data from {}?", file_path.display()))?;
+            }
+            pngme::wipe(session, file_path, output_file)?
+        }
+        Commands::Paste {file_path, chunk_file, output_file, at, force} => {
+            pngme::paste(session, file_path, chunk_file, at, &EncodeOptions { allow_critical: force }, output_file)?
+        }
+        Commands::Text {command} => match command {
+            TextCommands::Get {file_path, keyword} => println!("{}", pngme::get_text(session, file_path, &keyword)?.text()),
+            TextCommands::Set {
+                file_path,
+                keyword,
+                text,
+                output_file,
+                compressed,
+                itxt,
+                lang,
+                translated_keyword,
+            } => {
+                let entry = if itxt || lang.is_some() || translated_keyword.is_some() {
+                    let translation = Translation {
+                        language_tag: lang.unwrap_or_default(),
+                        translated_keyword: translated_keyword.unwrap_or_default(),
+                    };
+                    TextChunk::international(&keyword, &text, translation, compressed)?
+                } else if compressed {
+                    TextChunk::compressed(&keyword, &text)?
+                } else {
+                    TextChunk::new(&keyword, &text)?
+                };
+                pngme::set_text(session, file_path, &entry, output_file)?
+            }
+            TextCommands::List {file_path} => {
+                for entry in pngme::list_text(session, file_path)? {
+                    match entry.translation().filter(|translation| !translation.language_tag.is_empty()) {
+                        Some(translation) => println!("{} [{}]: {}", entry.keyword(), translation.language_tag, entry.text()),
+                        None => println!("{}: {}", entry.keyword(), entry.text()),
+                    }
+                }
+            }
+        },
+        Commands::Credit {file_path, output_file, author, copyright, url} => {
+            pngme::credit(session, file_path, author.as_deref(), copyright.as_deref(), url.as_deref(), output_file)?
+        }
+        Commands::Optimize {file_path, output_file, zopfli} => {
+            pngme::optimize(session, file_path, zopfli, output_file)?
+        }
+        Commands::Convert {file_path, output_file, from_cgbi: _, from, to, chunk_type, identity, key_file, password} => {
+            match (from, to) {
+                (Some(from), Some(to)) => {
+                    let secret = secret(&keystore, identity, key_file, password)?;
+                    let (from, to) = (from.backend(&chunk_type)?, to.backend(&chunk_type)?);
+                    pngme::convert_mode(session, file_path, from.as_ref(), to.as_ref(), secret.as_ref(), output_file)?
+                }
+                _ => pngme::convert_from_cgbi(session, file_path, output_file)?,
+            }
+        }
+        Commands::Verify {file_path} => print_verify(session, &file_path)?,
+        Commands::Validate {file_path} => print_validate(session, &file_path)?,
+        Commands::Repair {file_path, output_file, add_iend} => {
+            if output_file.is_none() {
+                confirm(yes, &format!("Repair {} in place?", file_path.display()))?;
+            }
+            pngme::repair(session, file_path, add_iend, output_file)?
+        }
+        Commands::Icc {command} => match command {
+            IccCommands::Extract {file_path, profile_path} => {
+                let profile = pngme::icc_extract(session, file_path, profile_path)?;
+                println!("Extracted ICC profile '{}' ({} bytes)", profile.name(), profile.profile().len());
+            }
+            IccCommands::Set {file_path, profile_path, output_file, name} => {
+                pngme::icc_set(session, file_path, profile_path, &name, output_file)?
+            }
+        },
+        Commands::License {command} => match command {
+            LicenseCommands::Set {file_path, output_file, spdx, author} => {
+                let license = License::new(&spdx, author.as_deref())?;
+                pngme::license_set(session, file_path, &license, output_file)?
+            }
+            LicenseCommands::Show {file_path} => {
+                let license = pngme::get_license(session, file_path)?;
+                match license.url() {
+                    Some(url) => println!("License: {} ({})", license.expression(), url),
+                    None => println!("License: {}", license.expression()),
+                }
+                if let Some(author) = license.author() {
+                    println!("Author: {}", author);
+                }
+            }
+        },
+        Commands::Provenance {command} => match command {
+            ProvenanceCommands::Set {file_path, document_path, output_file, schema} => {
+                pngme::provenance_set(session, file_path, document_path, schema, output_file)?
+            }
+            ProvenanceCommands::Show {file_path, schema} => {
+                println!("{}", serde_json::to_string_pretty(&pngme::get_provenance(session, file_path, schema)?)?)
+            }
+        },
+        Commands::Attest {file_path, output_file, key, predicate, predicate_type} => {
+            pngme::attest(session, file_path, keystore.resolve(key, true), predicate, &predicate_type, output_file)?
+        }
+        Commands::VerifyAttestation {file_path, key} => {
+            let attestation = pngme::verify_attestation(session, &file_path, &keystore.resolve(key, false))?;
+            println!("{}: attestation OK", file_path.display());
+            println!("Signed by: {}", attestation.keyid);
+            println!("Predicate type: {}", attestation.predicate_type);
+            println!("{}", serde_json::to_string_pretty(&attestation.predicate)?);
+        }
+        Commands::GenTest {output_file, size: (width, height), seed, pattern, color_type, bit_depth} => {
+            let options = GenOptions { width, height, seed, pattern, color_type, bit_depth };
+            pngme::gen_test(session, output_file, &options)?
+        }
+        Commands::Qr {message, output_file, scale, border, recipient, key_file, password} => {
+            let encryption = encryption(&keystore, &recipient, key_file, password)?;
+            pngme::qr(session, &message, encryption.as_ref(), &QrOptions { scale, border }, output_file)?
+        }
+        Commands::QrDecrypt {text, identity, key_file, password} => {
+            println!("{}", pngme::qr_decrypt(&text, secret(&keystore, identity, key_file, password)?.as_ref())?)
+        }
+        Commands::Keygen {out, x25519} => {
+            let (public_path, fingerprint) = pngme::keygen(&out, x25519)?;
+            println!("Secret key: {}", out.display());
+            println!("Public key: {}", public_path.display());
+            println!("Fingerprint: {}", fingerprint);
+        }
+        Commands::Key {command} => match command {
+            KeyCommands::List => {
+                let entries = pngme::key_list(&keystore)?;
+                if entries.is_empty() {
+                    println!("No keys in {}", keystore.dir().display());
+                }
+                for entry in entries {
+                    let kind = if entry.has_secret { "secret" } else { "public" };
+                    let x25519 = if entry.has_x25519 { " +x25519" } else { "" };
+                    println!("{:<20} {:<14} {}", entry.name, format!("{}{}", kind, x25519), entry.fingerprint);
+                }
+            }
+            KeyCommands::Import {key_path, name, force} => {
+                let name = match name {
+                    Some(name) => name,
+                    None => key_path.file_stem().ok_or("missing key name")?.to_string_lossy().into_owned(),
+                };
+                let entry = pngme::key_import(&keystore, key_path, &name, force)?;
+                let kind = if entry.has_secret { "secret" } else { "public" };
+                println!("Imported {} key '{}' ({})", kind, entry.name, entry.fingerprint);
+            }
+            KeyCommands::Export {name, output_file, secret} => {
+                if let Some(key_file) = pngme::key_export(&keystore, &name, secret, output_file)? {
+                    print!("{}", key_file);
+                }
+            }
+            KeyCommands::Fingerprint {key} => println!("{}", pngme::key_fingerprint(keystore.resolve(key, false))?),
+        },
+        Commands::Batch {
+            dir,
+            action,
+            follow_symlinks,
+            include_hidden,
+            exclude,
+            checkpoint,
+            resume,
+            jobs,
+            max_memory,
+        } => {
+            let run = format!("pngme batch {} {:?}", dir.display(), action);
+            let checkpoint = if resume {
+                Checkpoint::resume(checkpoint, &run)?
+            } else {
+                Checkpoint::create(checkpoint, &run)?
+            };
+            let options = WalkOptions { follow_symlinks, include_hidden, exclude };
+            let run_options = RunOptions { jobs, max_memory, ..RunOptions::default() };
+            batch::run(dir, &options, &run_options, Some(checkpoint), |file_path| {
+                if let Some(max_file_size) = args.max_file_size {
+                    pngme::check_file_size(session, file_path, max_file_size)?;
+                }
+                match action {
+                    BatchAction::Verify => print_verify(session, file_path),
+                    BatchAction::Decode => print_decode_auto(session, file_path),
+                    BatchAction::Print => {
+                        print!("{}", pngme::list_chunk_headers(session, file_path)?);
+                        Ok(())
+                    }
+                    BatchAction::Stats => print_stats(session, file_path),
+                    BatchAction::Size => print_size(session, file_path),
+                }
+            })?
+        }
+        Commands::Filter {clean, smudge: _, stash} => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            if let Some(max_file_size) = args.max_file_size.filter(|&max| input.len() as u64 > max) {
+                return Err(LimitExceeded::TotalSize(max_file_size as usize).into());
+            }
+
+            let output = if clean {
+                filter::clean_file(&input, stash)?
+            } else {
+                filter::smudge_file(&input, stash)?
+            };
+            io::stdout().write_all(&output)?;
+        }
+        Commands::Scan {paths, ci, deny, format, rules, strict} => {
+            let deny = if deny.is_empty() { FindingKind::ALL.to_vec() } else { deny };
+            let options = ScanOptions {
+                limits: ParseLimits {
+                    max_total_size: args.max_file_size.map_or(usize::MAX, |size| size as usize),
+                    check_lengths: strict,
+                    ..ParseLimits::default()
+                },
+                rules: match rules {
+                    Some(rules) => pngme::rules::read_rules(rules)?,
+                    None => Vec::new(),
+                },
+            };
+            print_scan(&pngme::scan(session, &paths, &options)?, &deny, format, ci)?
+        }
+        Commands::Report {file_path, output_file, format} => {
+            if let Some(document) = pngme::report(session, file_path, format, output_file)? {
+                print!("{}", document);
+            }
+        }
+        Commands::Export {file_path, json: _} => {
+            println!("{}", serde_json::to_string_pretty(&pngme::export_json(session, file_path)?)?)
+        }
+        Commands::Import {dump_path, output_file} => pngme::import_json(session, dump_path, output_file)?,
+        Commands::Print {file_path, type_glob, type_regex, format} => {
+            let pattern = match (type_glob, type_regex) {
+                (Some(glob), _) => Some(ChunkTypePattern::glob(&glob, args.ignore_case)?),
+                (None, Some(regex)) => Some(ChunkTypePattern::regex(&regex, args.ignore_case)?),
+                (None, None) => None,
+            };
+            if format == OutputFormat::Json {
+                let mut dump = Dump::from_png(&pngme::list_chunks(session, file_path)?);
+                if let Some(pattern) = pattern {
+                    dump.chunks.retain(|chunk| chunk.chunk_type.parse().is_ok_and(|chunk_type| pattern.matches(&chunk_type)));
+                }
+                return Ok(Some(serde_json::to_value(&dump)?));
+            }
+            match pattern {
+                Some(pattern) => {
+                    for chunk in pngme::list_matching(session, file_path, &pattern)? {
+                        println!("{}", chunk);
+                    }
+                }
+                None => println!("{}", pngme::list_chunks(session, file_path)?),
+            }
+        }
+        Commands::Stats {file_path} => print_stats(session, &file_path)?,
+        Commands::Size {file_path} => print_size(session, &file_path)?,
+        Commands::Man {out_dir} => {
+            fs::create_dir_all(&out_dir)?;
+            clap_mangen::generate_to(Args::command(), &out_dir)?;
+            println!("Wrote the manual pages to {}", out_dir.display());
+        }
+        Commands::Plugins => print_plugins(&plugins()?),
+        Commands::Plugin(command) => match &command[..] {
+            [verb, file_path, args @ ..] => pngme::run_plugin(session, &plugins()?, verb, file_path, args)?,
+            [verb] => return Err(format!("'{}' expects a file path", verb).into()),
+            [] => unreachable!(),
+        },
     }
 
-    Ok(())
+    Ok(None)
 }
@@ -0,0 +1,253 @@
+//! A read-only FUSE filesystem serving an archive `Tree`, on top of the `fuser` crate. Only the
+//! requests needed to browse and read files are implemented, everything else is answered
+//! with `ENOSYS` by `fuser`.
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser::consts::FOPEN_KEEP_CACHE;
+use fuser::{
+    FileAttr, FileType, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen, ReplyStatfs,
+    Request, FUSE_ROOT_ID,
+};
+
+use crate::archive::{NodeKind, Tree};
+use crate::Result;
+
+/// How long the kernel may cache names and attributes: nothing ever changes
+const TTL: Duration = Duration::from_secs(3600);
+
+/// Mounts `tree` read-only at `mountpoint` and serves it until the filesystem is unmounted
+/// (with `fusermount -u` or `umount`). Mounting needs root or the `fusermount3` helper.
+pub fn mount<P: AsRef<Path>>(tree: Tree, mountpoint: P) -> Result<()> {
+    let mountpoint = mountpoint.as_ref();
+    if !mountpoint.is_dir() {
+        return Err(format!("{} is not a directory", mountpoint.display()).into());
+    }
+
+    // SAFETY: these calls can't fail
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let options = [
+        MountOption::RO,
+        MountOption::NoSuid,
+        MountOption::NoDev,
+        MountOption::FSName("pngme".to_owned()),
+        MountOption::Subtype("pngme".to_owned()),
+    ];
+    println!(
+        "Mounting at {}, unmount with `fusermount -u {0}` or `umount {0}`",
+        mountpoint.display(),
+    );
+    fuser::mount2(Filesystem { tree, uid, gid }, mountpoint, &options)
+        .map_err(|e| format!("couldn't mount {}: {}", mountpoint.display(), e).into())
+}
+
+struct Filesystem {
+    tree: Tree,
+    uid: u32,
+    gid: u32,
+}
+
+impl Filesystem {
+    /// The index in `tree` of the node with inode number `ino`, if there is one
+    fn index(&self, ino: u64) -> Option<usize> {
+        let index = usize::try_from(ino.checked_sub(FUSE_ROOT_ID)?).ok()?;
+        self.tree.node(index).map(|_| index)
+    }
+
+    /// The child called `name` of the directory `index`, `.` and `..` included
+    fn child(&self, index: usize, name: &OsStr) -> Option<usize> {
+        match name.to_str()? {
+            "." => Some(index),
+            ".." => self.tree.node(index).map(|node| node.parent),
+            name => self.tree.lookup(index, name),
+        }
+    }
+
+    /// The entries of the directory `index`, `.` and `..` included
+    fn entries(&self, index: usize) -> Option<Vec<(usize, &str)>> {
+        let node = self.tree.node(index)?;
+        if !matches!(node.kind, NodeKind::Dir(_)) {
+            return None;
+        }
+        let children = self.tree.entries(index).iter().map(|&child| (child, self.tree.node(child).unwrap().name.as_str()));
+        Some([(index, "."), (node.parent, "..")].into_iter().chain(children).collect())
+    }
+
+    /// At most `size` bytes of the file `index` from `offset`
+    fn read(&self, index: usize, offset: u64, size: u32) -> std::result::Result<&[u8], i32> {
+        match &self.tree.node(index).ok_or(libc::ENOENT)?.kind {
+            NodeKind::File(data) => {
+                let start = usize::try_from(offset).unwrap_or(usize::MAX).min(data.len());
+                Ok(&data[start..data.len().min(start.saturating_add(size as usize))])
+            }
+            _ => Err(libc::EISDIR),
+        }
+    }
+
+    fn file_type(&self, index: usize) -> FileType {
+        match self.tree.node(index).unwrap().kind {
+            NodeKind::Dir(_) => FileType::Directory,
+            NodeKind::File(_) => FileType::RegularFile,
+            NodeKind::Symlink(_) => FileType::Symlink,
+        }
+    }
+
+    /// The attributes of a node, without write permissions
+    fn attr(&self, index: usize) -> FileAttr {
+        let node = self.tree.node(index).unwrap();
+        let (size, nlink) = match &node.kind {
+            NodeKind::Dir(entries) => (0, 2 + entries.len() as u32),
+            NodeKind::File(data) => (data.len() as u64, 1),
+            NodeKind::Symlink(target) => (target.len() as u64, 1),
+        };
+        let mtime = UNIX_EPOCH + Duration::from_secs(node.mtime);
+        FileAttr {
+            ino: index as u64 + FUSE_ROOT_ID,
+            size,
+            blocks: size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: mtime,
+            kind: self.file_type(index),
+            perm: (node.mode & 0o7555) as u16,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    /// Opens a file or directory read-only, as asked by `open` (`directory` false) or
+    /// `opendir`
+    fn open(&self, ino: u64, flags: i32, directory: bool, reply: ReplyOpen) {
+        let Some(index) = self.index(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        let is_dir = self.file_type(index) == FileType::Directory;
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            reply.error(libc::EROFS);
+        } else if is_dir != directory {
+            reply.error(if is_dir { libc::EISDIR } else { libc::ENOTDIR });
+        } else {
+            reply.opened(0, FOPEN_KEEP_CACHE);
+        }
+    }
+}
+
+impl fuser::Filesystem for Filesystem {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.index(parent).and_then(|parent| self.child(parent, name)) {
+            Some(child) => reply.entry(&TTL, &self.attr(child), 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.index(ino) {
+            Some(index) => reply.attr(&TTL, &self.attr(index)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match self.index(ino).map(|index| &self.tree.node(index).unwrap().kind) {
+            Some(NodeKind::Symlink(target)) => reply.data(target.as_bytes()),
+            Some(_) => reply.error(libc::EINVAL),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        Filesystem::open(self, ino, flags, false, reply);
+    }
+
+    fn opendir(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        Filesystem::open(self, ino, flags, true, reply);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let index = self.index(ino).ok_or(libc::ENOENT);
+        match index.and_then(|index| Filesystem::read(self, index, offset.max(0) as u64, size)) {
+            Ok(data) => reply.data(data),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let Some(index) = self.index(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        let Some(entries) = self.entries(index) else {
+            return reply.error(libc::ENOTDIR);
+        };
+        for (position, (child, name)) in entries.into_iter().enumerate().skip(offset.max(0) as usize) {
+            // The offset of an entry is the position of the next one
+            if reply.add(child as u64 + FUSE_ROOT_ID, position as i64 + 1, self.file_type(child), name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        let size: u64 = (0..self.tree.len())
+            .filter_map(|index| match &self.tree.node(index)?.kind {
+                NodeKind::File(data) => Some(data.len() as u64),
+                _ => None,
+            })
+            .sum();
+        reply.statfs(size.div_ceil(512), 0, 0, self.tree.len() as u64, 0, 512, 255, 512);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filesystem() -> Filesystem {
+        Filesystem { tree: Tree::single_file("payload.txt", b"hello world".to_vec()), uid: 1000, gid: 1000 }
+    }
+
+    #[test]
+    fn test_lookup_and_read() {
+        let filesystem = filesystem();
+        let root = filesystem.index(FUSE_ROOT_ID).unwrap();
+        let file = filesystem.child(root, OsStr::new("payload.txt")).unwrap();
+        let attr = filesystem.attr(file);
+        assert_eq!(attr.ino, 2);
+        assert_eq!(attr.size, 11);
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert_eq!(attr.perm, 0o444);
+
+        assert_eq!(filesystem.child(root, OsStr::new("missing")), None);
+        assert_eq!(filesystem.child(root, OsStr::new("..")), Some(root));
+        assert_eq!(filesystem.read(file, 6, 100), Ok(&b"world"[..]));
+        assert_eq!(filesystem.read(file, 50, 100), Ok(&b""[..]));
+        assert_eq!(filesystem.read(root, 0, 100), Err(libc::EISDIR));
+        assert_eq!(filesystem.index(9), None);
+        assert_eq!(filesystem.index(0), None);
+    }
+
+    #[test]
+    fn test_entries() {
+        let filesystem = filesystem();
+        let entries = filesystem.entries(0).unwrap();
+        assert_eq!(entries, [(0, "."), (0, ".."), (1, "payload.txt")]);
+        assert_eq!(filesystem.entries(1), None);
+    }
+}
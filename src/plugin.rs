@@ -0,0 +1,141 @@
+use crate::png::Png;
+use crate::Result;
+
+/// A command a plugin adds to the CLI: `pngme <name> <FILE> [ARGS]...`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verb {
+    pub name: &'static str,
+    /// One line of help shown by `pngme plugins`
+    pub about: &'static str,
+}
+
+/// What a verb did to the image it was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The image was only read
+    Unchanged,
+    /// The image was modified and must be saved
+    Modified,
+}
+
+/// A handler for private chunk types that pngme doesn't know, e.g. a studio plugin that
+/// understands its proprietary asset metadata chunk. Handlers can live in other crates:
+/// they implement this trait and are registered in a `PluginRegistry`.
+pub trait ChunkPlugin {
+    /// The name of the plugin
+    fn name(&self) -> &str;
+
+    /// The chunk types the plugin understands
+    fn chunk_types(&self) -> &[&str];
+
+    /// The commands the plugin adds to the CLI
+    fn verbs(&self) -> &[Verb];
+
+    /// Runs `verb` on `png` with the remaining command line arguments
+    fn run(&self, verb: &str, png: &mut Png, args: &[String]) -> Result<Outcome>;
+}
+
+/// The plugins known to the CLI, looked up by verb
+#[derive(Default)]
+pub struct PluginRegistry {
+    plugins: Vec<Box<dyn ChunkPlugin>>,
+}
+
+impl PluginRegistry {
+    /// A registry without any plugin
+    pub fn new() -> PluginRegistry {
+        PluginRegistry::default()
+    }
+
+    /// Registers a plugin. Fails if one of its verbs is already taken by another plugin.
+    pub fn register(&mut self, plugin: Box<dyn ChunkPlugin>) -> Result<()> {
+        for verb in plugin.verbs() {
+            if let Some(other) = self.find(verb.name) {
+                return Err(format!("plugins {} and {} both define '{}'", other.name(), plugin.name(), verb.name).into());
+            }
+        }
+        self.plugins.push(plugin);
+        Ok(())
+    }
+
+    /// The registered plugins, in registration order
+    pub fn plugins(&self) -> impl Iterator<Item = &dyn ChunkPlugin> {
+        self.plugins.iter().map(|plugin| plugin.as_ref())
+    }
+
+    /// The plugin defining `verb`
+    pub fn find(&self, verb: &str) -> Option<&dyn ChunkPlugin> {
+        self.plugins().find(|plugin| plugin.verbs().iter().any(|v| v.name == verb))
+    }
+
+    /// Runs `verb` on `png`
+    pub fn run(&self, verb: &str, png: &mut Png, args: &[String]) -> Result<Outcome> {
+        self.find(verb)
+            .ok_or_else(|| format!("unknown command '{}'", verb))?
+            .run(verb, png, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    /// Stores an asset id in an `asSt` chunk
+    struct Studio;
+
+    impl ChunkPlugin for Studio {
+        fn name(&self) -> &str {
+            "studio"
+        }
+
+        fn chunk_types(&self) -> &[&str] {
+            &["asSt"]
+        }
+
+        fn verbs(&self) -> &[Verb] {
+            &[
+                Verb { name: "asset-set", about: "Sets the asset id" },
+                Verb { name: "asset-show", about: "Prints the asset id" },
+            ]
+        }
+
+        fn run(&self, verb: &str, png: &mut Png, args: &[String]) -> Result<Outcome> {
+            match verb {
+                "asset-set" => {
+                    let id = args.first().ok_or("missing asset id")?;
+                    png.insert_before_end(Chunk::new(ChunkType::from_str("asSt")?, id.as_bytes().to_vec()));
+                    Ok(Outcome::Modified)
+                }
+                _ => {
+                    png.chunk_by_type("asSt").ok_or("no asset id")?;
+                    Ok(Outcome::Unchanged)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_verbs() {
+        let mut registry = PluginRegistry::new();
+        registry.register(Box::new(Studio)).unwrap();
+        let mut png = Png::try_from(&include_bytes!("../dice.png")[..]).unwrap();
+
+        assert!(registry.run("asset-show", &mut png, &[]).is_err());
+        assert_eq!(registry.run("asset-set", &mut png, &["A-17".to_owned()]).unwrap(), Outcome::Modified);
+        assert_eq!(registry.run("asset-show", &mut png, &[]).unwrap(), Outcome::Unchanged);
+        assert_eq!(png.chunk_by_type("asSt").unwrap().data(), b"A-17");
+        assert!(registry.run("asset-delete", &mut png, &[]).is_err());
+    }
+
+    #[test]
+    fn test_conflicting_verbs() {
+        let mut registry = PluginRegistry::new();
+        registry.register(Box::new(Studio)).unwrap();
+        assert!(registry.register(Box::new(Studio)).is_err());
+        assert_eq!(registry.plugins().count(), 1);
+    }
+}
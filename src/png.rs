@@ -1,15 +1,139 @@
 use std::fmt;
+use std::collections::HashMap;
 use std::convert::{TryFrom, AsRef};
 use std::path::Path;
 use std::str::FromStr;
-use std::fs;
-use std::io::{BufReader, Read};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::{Error, Result};
-use crate::chunk::Chunk;
-use crate::chunk_type::ChunkType;
+use crate::chunk::{Chunk, ChunkSummary};
+use crate::chunk_type::{ChunkType, ChunkTypePattern};
+use crate::codec::{ChunkFormat, CodecRegistry};
+use crate::error::PngMeError;
+use crate::positioned;
+use crate::remote;
+use crate::visit::{self, ChunkVisitor};
 
+/// Distinguishes the temporary files of writes running in parallel
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
 
+/// Writes a file through a temporary file in the same directory, renamed over `path` once it is
+/// complete and synced, so a crash midway leaves the original file intact. The permissions of an
+/// existing file are kept, and a symbolic link is followed rather than replaced. Other existing
+/// targets than regular files, such as devices, are written directly.
+fn write_atomically(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) -> Result<()> {
+    let metadata = fs::metadata(path).ok();
+    if metadata.as_ref().is_some_and(|metadata| !metadata.is_file()) {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write(&mut writer)?;
+        return Ok(writer.flush()?);
+    }
+
+    let path = if path.is_symlink() { fs::canonicalize(path)? } else { path.to_path_buf() };
+    let name = path.file_name().ok_or_else(|| format!("{} is not a file path", path.display()))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed),
+    ));
+
+    let result = (|| -> Result<()> {
+        let mut writer = BufWriter::new(OpenOptions::new().write(true).create_new(true).open(&temp_path)?);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        if let Some(metadata) = metadata {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        Ok(fs::rename(&temp_path, &path)?)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Upper bounds applied while parsing a `Png`, so that hostile input can't make
+/// the parser allocate an unbounded amount of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Maximum number of chunks in the file
+    pub max_chunks: usize,
+    /// Maximum length of the data portion of a single chunk
+    pub max_chunk_len: u32,
+    /// Maximum size of the whole file in bytes
+    pub max_total_size: usize,
+    /// Refuses `IHDR` and `IEND` chunks whose data doesn't have the length set by the spec
+    pub check_lengths: bool,
+}
+
+impl Default for ParseLimits {
+    /// No limits besides the maximum chunk length allowed by the PNG spec (2^31 - 1)
+    fn default() -> Self {
+        ParseLimits {
+            max_chunks: usize::MAX,
+            max_chunk_len: i32::MAX as u32,
+            max_total_size: usize::MAX,
+            check_lengths: false,
+        }
+    }
+}
+
+/// The error returned when parsing hits one of the `ParseLimits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Chunks(usize),
+    ChunkLength(u32),
+    TotalSize(usize),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Chunks(max) => write!(f, "PNG contains more than {} chunks", max),
+            LimitExceeded::ChunkLength(max) => write!(f, "chunk is longer than {} bytes", max),
+            LimitExceeded::TotalSize(max) => write!(f, "PNG is larger than {} bytes", max),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// The error returned when a chunk with a fixed size has the wrong length, a common sign of
+/// corruption or of data smuggled into the chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLength {
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    pub expected: u32,
+}
+
+impl InvalidLength {
+    /// Checks the length of the data of a chunk of type `chunk_type`. Only `IHDR` (13 bytes)
+    /// and `IEND` (empty) have a fixed length.
+    pub fn check(chunk_type: &ChunkType, length: u32) -> std::result::Result<(), InvalidLength> {
+        let expected = match *chunk_type {
+            ChunkType::IHDR => 13,
+            ChunkType::IEND => 0,
+            _ => return Ok(()),
+        };
+        if length == expected {
+            return Ok(());
+        }
+        Err(InvalidLength { chunk_type: *chunk_type, length, expected })
+    }
+}
+
+impl fmt::Display for InvalidLength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} chunk has {} data bytes instead of {}", self.chunk_type, self.length, self.expected)
+    }
+}
+
+impl std::error::Error for InvalidLength {}
 
 /// A PNG container as described by the PNG spec
 /// http://www.libpng.org/pub/png/spec/1.2/PNG-Contents.html
@@ -17,6 +141,7 @@ use crate::chunk_type::ChunkType;
 pub struct Png {
     header: [u8; 8],
     chunks: Vec<Chunk>,
+    trailer: Vec<u8>,
 }
 
 impl Png {
@@ -26,19 +151,186 @@ impl Png {
     pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
         Png {
             header: Self::STANDARD_HEADER,
-            chunks
+            chunks,
+            trailer: Vec::new(),
         }
     }
 
     /// Creates a `Png` from a file path
     pub fn from_file<P: AsRef<Path>>(path: &P) -> Result<Self> {
-        let contents = fs::read(path)?;
-        Png::try_from(&contents[..])
+        let contents = positioned::read_file(path)?;
+        Ok(Png::try_from(&contents[..])?)
+    }
+
+    /// Creates a `Png` from a file path, refusing files that exceed `limits`.
+    /// The file size is checked before anything is read into memory.
+    pub fn from_file_with_limits<P: AsRef<Path>>(path: &P, limits: &ParseLimits) -> Result<Self> {
+        let size = fs::metadata(path)?.len();
+        if size > limits.max_total_size as u64 {
+            return Err(LimitExceeded::TotalSize(limits.max_total_size).into());
+        }
+
+        let contents = positioned::read_file(path)?;
+        Png::from_bytes_with_limits(&contents, limits)
+    }
+
+    /// Parses a `Png` from raw bytes, returning a `LimitExceeded` error as soon as
+    /// the input goes over one of the `limits`.
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: &ParseLimits) -> Result<Self> {
+        let (png, _) = Png::parse(bytes, limits, false)?;
+        Ok(png)
+    }
+
+    /// Parses a `Png` from `reader`, passing every chunk to `visitor` as it is read (see
+    /// `visit::ChunkVisitor`). The visitor can skip chunks or stop early, and only the chunks
+    /// it keeps are held in memory, so files can be filtered or scanned without loading them.
+    pub fn parse_with<R: Read, V: ChunkVisitor + ?Sized>(reader: R, visitor: &mut V) -> Result<Self> {
+        visit::parse_with(reader, visitor, &ParseLimits::default())
+    }
+
+    /// Like `parse_with`, refusing input that exceeds `limits`. `max_total_size` bounds the
+    /// bytes read, skipped chunks and the data after `IEND` included.
+    pub fn parse_with_limits<R: Read, V: ChunkVisitor + ?Sized>(
+        reader: R,
+        visitor: &mut V,
+        limits: &ParseLimits,
+    ) -> Result<Self> {
+        visit::parse_with(reader, visitor, limits)
+    }
+
+    /// Reads the chunks of a PNG file from `reader` one at a time, without holding more than
+    /// one chunk in memory. The header is checked first. Wrap files in a `BufReader`.
+    pub fn chunks_from_reader<R: Read>(reader: R) -> Result<ChunkReader<R>> {
+        ChunkReader::new(reader, ParseLimits::default())
+    }
+
+    /// Parses a `Png` from raw bytes, tolerating damaged input such as a chunk with a bad CRC
+    /// or a bogus length, or a file truncated in the middle of a chunk: the damaged bytes are
+    /// skipped up to the next chunk with a valid CRC and a warning is returned instead of an
+    /// error. Only an invalid header and exceeded limits are still errors.
+    pub fn from_bytes_lenient(bytes: &[u8]) -> Result<(Self, Vec<ParseWarning>)> {
+        Png::parse(bytes, &ParseLimits::default(), true)
+    }
+
+    /// Creates a `Png` from a file path using `from_bytes_lenient`
+    pub fn from_file_lenient<P: AsRef<Path>>(path: &P) -> Result<(Self, Vec<ParseWarning>)> {
+        let contents = positioned::read_file(path)?;
+        Png::from_bytes_lenient(&contents)
+    }
+
+    pub(crate) fn parse(bytes: &[u8], limits: &ParseLimits, lenient: bool) -> Result<(Self, Vec<ParseWarning>)> {
+        if bytes.len() > limits.max_total_size {
+            return Err(LimitExceeded::TotalSize(limits.max_total_size).into());
+        }
+
+        let mut reader = BufReader::new(bytes);
+        let mut header: [u8; 8] = [0; 8];
+
+        reader.read_exact(&mut header)?;
+        if header != Self::STANDARD_HEADER {
+            return Err(PngMeError::InvalidSignature.into());
+        }
+
+        let mut chunks = vec![];
+        let mut warnings = vec![];
+        let mut consumed = header.len();
+
+        while let Err(err) = read_chunks(bytes, &mut consumed, &mut chunks, limits) {
+            // A bogus length is damage like any other, but the other limits stay errors
+            let fatal = matches!(err.downcast_ref(), Some(LimitExceeded::Chunks(_) | LimitExceeded::TotalSize(_)));
+            if !lenient || fatal {
+                return Err(err);
+            }
+            match resync(bytes, consumed + 1, limits.max_chunk_len) {
+                Some(next) => {
+                    warnings.push(ParseWarning {
+                        offset: consumed,
+                        message: format!("{}, skipping {} bytes to the next chunk", err, next - consumed),
+                    });
+                    consumed = next;
+                }
+                None => {
+                    warnings.push(ParseWarning {
+                        offset: consumed,
+                        message: format!("{}, ignoring the remaining {} bytes", err, bytes.len() - consumed),
+                    });
+                    consumed = bytes.len();
+                    break;
+                }
+            }
+        }
+
+        // Chunks appended after `IEND` (as older versions of pngme did) are kept as chunks,
+        // anything else stored there (starting at the first bytes that aren't a valid chunk)
+        // is kept as is in the trailer
+        let mut trailer = Vec::new();
+        while consumed < bytes.len() {
+            let mut extra = vec![];
+            let mut end = consumed;
+            let result = read_chunks(bytes, &mut end, &mut extra, limits);
+            if end == consumed || chunks.len() + extra.len() > limits.max_chunks {
+                trailer = bytes[consumed..].to_vec();
+                break;
+            }
+
+            chunks.append(&mut extra);
+            consumed = end;
+            if result.is_err() {
+                trailer = bytes[consumed..].to_vec();
+                break;
+            }
+        }
+
+        let mut png = Png::from_chunks(chunks);
+        png.trailer = trailer;
+        Ok((png, warnings))
     }
 
-    // Save `Png` to a file path
+    // Save `Png` to a file path.
+    // `-` means stdout.
     pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        Ok(fs::write(path, self.as_bytes())?)
+        if path.as_ref() == Path::new(positioned::STDIO) {
+            let mut stdout = io::stdout().lock();
+            self.write_to(&mut stdout)?;
+            return Ok(stdout.flush()?);
+        }
+        write_atomically(path.as_ref(), |writer| self.write_to(writer))
+    }
+
+    /// Uploads this `Png` to an http(s) URL with a PUT request, see `remote::put`. Requires the
+    /// `http` feature.
+    pub fn write_to_url(&self, url: &str) -> Result<()> {
+        remote::put(url, "image/png", &self.as_bytes())
+    }
+
+    /// Describes what writing this `Png` to `path` would change: the chunks added to or removed
+    /// from the PNG file already there, and the resulting file size
+    pub fn describe_changes(&self, path: &Path) -> String {
+        let size = self.as_bytes().len();
+        let existing = match (remote::url(path), path == Path::new(positioned::STDIO)) {
+            (None, false) => fs::read(path).ok(),
+            _ => None,
+        };
+        let Some(existing) = existing else {
+            return format!("Would write {} bytes to {}", size, path.display());
+        };
+
+        let mut lines = vec![format!("Would change {} from {} to {} bytes", path.display(), existing.len(), size)];
+        let Ok(before) = Png::try_from(&existing[..]) else {
+            return lines.remove(0);
+        };
+        let mut added: Vec<&Chunk> = self.chunks.iter().collect();
+        for chunk in &before.chunks {
+            match added.iter().position(|other| other.chunk_type() == chunk.chunk_type() && other.data() == chunk.data()) {
+                Some(index) => { added.remove(index); }
+                None => lines.push(format!("  - {} chunk ({} bytes)", chunk.chunk_type(), chunk.length())),
+            }
+        }
+        lines.extend(added.iter().map(|chunk| format!("  + {} chunk ({} bytes)", chunk.chunk_type(), chunk.length())));
+        if before.trailer != self.trailer {
+            lines.push(format!("  ~ data after IEND ({} -> {} bytes)", before.trailer.len(), self.trailer.len()));
+        }
+        lines.join("\n")
     }
 
     /// Appends a chunk to the end of this `Png` file's `Chunk` list.
@@ -46,17 +338,96 @@ impl Png {
         self.chunks.push(chunk);
     }
 
+    /// Returns true if this `Png` contains an `IEND` chunk
+    pub fn has_end(&self) -> bool {
+        self.chunks.iter().any(|chunk| chunk.chunk_type() == &ChunkType::IEND)
+    }
+
+    /// Inserts a chunk right before the `IEND` chunk, or appends it if there is no `IEND`.
+    pub fn insert_before_end(&mut self, chunk: Chunk) {
+        let position = self.chunks.iter()
+            .position(|x| x.chunk_type() == "IEND")
+            .unwrap_or(self.chunks.len());
+        self.chunks.insert(position, chunk);
+    }
+
+    /// Inserts a chunk at position `index` in this `Png` list of chunks (0 is before `IHDR`),
+    /// shifting the chunks after it. Fails if `index` is past the end of the list.
+    pub fn insert_chunk_at(&mut self, index: usize, chunk: Chunk) -> Result<()> {
+        if index > self.chunks.len() {
+            return Err(format!("can't insert at chunk #{}, the file has {} chunks", index, self.chunks.len()).into());
+        }
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+
+    /// Removes the chunk at position `index` in this `Png` list of chunks (0 is `IHDR`),
+    /// whatever its type, to pick one of several chunks sharing a type.
+    pub fn remove_chunk_at(&mut self, index: usize) -> Result<Chunk> {
+        if index >= self.chunks.len() {
+            return Err(format!("no chunk #{}, the file has {} chunks", index, self.chunks.len()).into());
+        }
+        Ok(self.chunks.remove(index))
+    }
+
     /// Searches for a `Chunk` with the specified `chunk_type` and removes the first
     /// matching `Chunk` from this `Png` list of chunks.
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
-        let chunk_type = ChunkType::from_str(chunk_type)?;
+        ChunkType::from_str(chunk_type)?;
         let chunk_position = self.chunks.iter()
-            .position(|x| x.chunk_type() == &chunk_type)
-            .ok_or("no chunk with this type")?;
+            .position(|x| chunk_type == x.chunk_type())
+            .ok_or_else(|| self.chunk_not_found(chunk_type))?;
 
         Ok(self.chunks.remove(chunk_position))
     }
 
+    /// Replaces the data of the first `Chunk` with the specified `chunk_type`, keeping its
+    /// position in the list of chunks, and returns the chunk it replaced.
+    pub fn replace_chunk(&mut self, chunk_type: &str, data: Vec<u8>) -> Result<Chunk> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        let position = self.chunks.iter()
+            .position(|x| x.chunk_type() == &chunk_type)
+            .ok_or_else(|| self.chunk_not_found(&chunk_type.to_string()))?;
+
+        Ok(std::mem::replace(&mut self.chunks[position], Chunk::new(chunk_type, data)))
+    }
+
+    /// Removes every `Chunk` with the specified `chunk_type` from this `Png` list of chunks and
+    /// returns them in file order, e.g. all the `tEXt` chunks or all the fragments of a payload.
+    pub fn remove_chunks_by_type(&mut self, chunk_type: &str) -> Result<Vec<Chunk>> {
+        ChunkType::from_str(chunk_type)?;
+        let (removed, kept): (Vec<_>, _) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|chunk| chunk_type == chunk.chunk_type());
+        self.chunks = kept;
+        if removed.is_empty() {
+            return Err(self.chunk_not_found(chunk_type));
+        }
+        Ok(removed)
+    }
+
+    /// Removes every ancillary `Chunk` whose type matches `pattern`. Critical chunks are never
+    /// removed, so a broad pattern can't break the image.
+    pub fn remove_matching(&mut self, pattern: &ChunkTypePattern) -> Vec<Chunk> {
+        let (removed, kept) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|chunk| !chunk.chunk_type().is_critical() && pattern.matches(chunk.chunk_type()));
+        self.chunks = kept;
+        removed
+    }
+
+    /// Overwrites the data of every `Chunk` with the specified `chunk_type` with zeros,
+    /// keeping its length, and returns how many chunks were overwritten.
+    pub fn zero_chunks(&mut self, chunk_type: &str) -> Result<usize> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        let mut count = 0;
+        for chunk in self.chunks.iter_mut().filter(|chunk| chunk.chunk_type() == &chunk_type) {
+            *chunk = Chunk::new(chunk_type, vec![0; chunk.length() as usize]);
+            count += 1;
+        }
+        Ok(count)
+    }
+
     /// The header of this PNG.
     pub fn header(&self) -> &[u8; 8] {
         &self.header
@@ -67,63 +438,543 @@ impl Png {
         &self.chunks
     }
 
+    /// Mutable access to the `Chunk` list for crate internals that rewrite several chunks at once
+    pub(crate) fn chunks_mut(&mut self) -> &mut Vec<Chunk> {
+        &mut self.chunks
+    }
+
+    /// The bytes stored after the `IEND` chunk
+    pub fn trailer(&self) -> &[u8] {
+        &self.trailer
+    }
+
+    /// Replaces the bytes stored after the `IEND` chunk
+    pub fn set_trailer(&mut self, trailer: Vec<u8>) {
+        self.trailer = trailer;
+    }
+
     /// Searches for a `Chunk` with the specified `chunk_type` and returns the first
-    /// matching `Chunk` from this `Png`.
-    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        let chunk_type = ChunkType::from_str(chunk_type).ok()?;
+    /// matching `Chunk` from this `Png`. Accepts a `&ChunkType` as well as a `&str`.
+    pub fn chunk_by_type<T: PartialEq<ChunkType> + ?Sized>(&self, chunk_type: &T) -> Option<&Chunk> {
+        self.chunks.iter()
+            .find(|&x| chunk_type == x.chunk_type())
+    }
+
+    /// The chunks with the specified `chunk_type`, in file order (`chunk_by_type` only returns
+    /// the first one)
+    pub fn chunks_by_type<'a, T: PartialEq<ChunkType> + ?Sized>(&'a self, chunk_type: &'a T) -> impl Iterator<Item = &'a Chunk> {
+        self.chunks.iter().filter(move |chunk| chunk_type == chunk.chunk_type())
+    }
+
+    /// The chunks whose type matches `pattern`
+    pub fn chunks_matching<'a>(&'a self, pattern: &'a ChunkTypePattern) -> impl Iterator<Item = &'a Chunk> {
+        self.chunks.iter().filter(|chunk| pattern.matches(chunk.chunk_type()))
+    }
+
+    /// Like `chunk_by_type`, but ignores the case of the four letters, e.g. `rust` finds `ruSt`
+    pub fn chunk_by_type_ignore_case(&self, chunk_type: &str) -> Option<&Chunk> {
+        self.chunks.iter()
+            .find(|x| x.chunk_type().eq_ignore_case(chunk_type))
+    }
+
+    /// The error for a missing `chunk_type` chunk. If the file has a chunk whose type only
+    /// differs in case, the error names it, since the case of chunk types matters.
+    pub fn chunk_not_found(&self, chunk_type: &str) -> Error {
+        PngMeError::ChunkNotFound {
+            chunk_type: chunk_type.to_owned(),
+            similar: self.chunk_by_type_ignore_case(chunk_type).map(|chunk| chunk.chunk_type().to_string()),
+        }.into()
+    }
+
+    /// Decodes the first chunk stored in format `T` (see `codec::ChunkFormat`), e.g.
+    /// `png.chunk_as::<ImageHeader>()`, with the codec registry of `T`. Returns `None` if
+    /// there is no such chunk. See `CodecRegistry::chunk_as` for chunk types registered at
+    /// run time.
+    pub fn chunk_as<T: ChunkFormat>(&self) -> Option<Result<T>> {
+        match CodecRegistry::of::<T>() {
+            Ok(registry) => registry.chunk_as(self),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// The number of bytes taken by data embedded with pngme: every private chunk
+    /// (including its length, type and CRC fields) plus the data after `IEND`.
+    pub fn embedded_size(&self) -> usize {
         self.chunks.iter()
-            .find(|&x| x.chunk_type() == &chunk_type)
+            .filter(|chunk| !chunk.chunk_type().is_public())
+            .map(|chunk| chunk.length() as usize + 12)
+            .sum::<usize>() + self.trailer.len()
+    }
+
+    /// Counts how many `Chunk`s of each `ChunkType` are stored in this `Png`.
+    pub fn type_counts(&self) -> HashMap<ChunkType, usize> {
+        let mut counts = HashMap::new();
+        for chunk in &self.chunks {
+            *counts.entry(*chunk.chunk_type()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Checks this `Png` against the structural rules of the PNG spec and returns every
+    /// violation found, or nothing for a valid file: the signature, `IHDR` first and `IEND`
+    /// last (each exactly once), the chunks that must come before `PLTE` or the image data,
+    /// consecutive `IDAT` chunks, the length of fixed-size chunks, the CRCs and data after
+    /// `IEND`. See `from_bytes_unvalidated` to check a file this crate can't parse.
+    pub fn validate(&self) -> Vec<Violation> {
+        // Chunks that must come before the palette, and chunks that must come before the image data
+        const BEFORE_PLTE: [&str; 8] = ["cHRM", "cICP", "gAMA", "iCCP", "mDCV", "cLLI", "sBIT", "sRGB"];
+        const BEFORE_IDAT: [&str; 6] = ["tRNS", "bKGD", "hIST", "pHYs", "sPLT", "eXIf"];
+
+        let mut violations = Vec::new();
+        if self.header != Self::STANDARD_HEADER {
+            violations.push(Violation::file("invalid PNG signature"));
+        }
+        match self.chunks.first() {
+            None => violations.push(Violation::file("no chunks")),
+            Some(chunk) if chunk.chunk_type() != &ChunkType::IHDR => {
+                violations.push(Violation::chunk(0, chunk, "the first chunk must be IHDR"))
+            }
+            _ => {}
+        }
+
+        let first = |chunk_type: ChunkType| self.chunks.iter().position(|chunk| chunk.chunk_type() == &chunk_type);
+        let (plte, idat, iend) = (first(ChunkType::PLTE), first(ChunkType::IDAT), first(ChunkType::IEND));
+        if idat.is_none() {
+            violations.push(Violation::file("no IDAT chunk"));
+        }
+        if iend.is_none() {
+            violations.push(Violation::file("no IEND chunk"));
+        }
+
+        let mut previous: Option<&ChunkType> = None;
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let chunk_type = chunk.chunk_type();
+            let name = chunk_type.as_str();
+            if let Err(err) = InvalidLength::check(chunk_type, chunk.length()) {
+                violations.push(Violation::chunk(index, chunk, &format!("{} data bytes instead of {}", err.length, err.expected)));
+            }
+            let expected = Chunk::compute_crc(chunk_type, chunk.data());
+            if chunk.crc() != expected {
+                violations.push(Violation::chunk(index, chunk, &format!("CRC mismatch (expected {}, found {})", expected, chunk.crc())));
+            }
+
+            if iend.is_some_and(|iend| index > iend) {
+                violations.push(Violation::chunk(index, chunk, "comes after IEND"));
+            } else if index > 0 && (*chunk_type == ChunkType::IHDR || (*chunk_type == ChunkType::PLTE && Some(index) != plte)) {
+                violations.push(Violation::chunk(index, chunk, "appears more than once"));
+            } else if BEFORE_PLTE.contains(&name) && plte.is_some_and(|plte| index > plte) {
+                violations.push(Violation::chunk(index, chunk, "must come before PLTE"));
+            } else if (BEFORE_PLTE.contains(&name) || BEFORE_IDAT.contains(&name) || *chunk_type == ChunkType::PLTE)
+                && idat.is_some_and(|idat| index > idat)
+            {
+                violations.push(Violation::chunk(index, chunk, "must come before the IDAT chunks"));
+            } else if *chunk_type == ChunkType::IDAT && previous.is_some_and(|previous| *previous != ChunkType::IDAT) && Some(index) != idat {
+                violations.push(Violation::chunk(index, chunk, "IDAT chunks must be consecutive"));
+            }
+            previous = Some(chunk_type);
+        }
+
+        if !self.trailer.is_empty() {
+            violations.push(Violation::file(&format!("{} bytes of data after IEND", self.trailer.len())));
+        }
+        violations
+    }
+
+    /// Recomputes the CRC of every chunk whose stored CRC doesn't match its type and data (see
+    /// `from_bytes_unvalidated` to read such chunks), and returns the positions of the chunks
+    /// that were fixed
+    pub fn repair_crcs(&mut self) -> Vec<usize> {
+        self.chunks.iter_mut()
+            .enumerate()
+            .filter_map(|(index, chunk)| chunk.recompute_crc().then_some(index))
+            .collect()
+    }
+
+    /// Parses a PNG file keeping everything `validate` checks instead of rejecting it: a wrong
+    /// signature, bad CRCs and chunks after `IEND` are all kept. After `IEND`, bytes that aren't
+    /// a chunk become the trailer; before it, a chunk that can't be read (truncated or with an
+    /// invalid type) is returned as a violation and ends the parsing.
+    pub fn from_bytes_unvalidated(bytes: &[u8]) -> (Png, Vec<Violation>) {
+        let mut header = [0; 8];
+        let mut offset = bytes.len().min(header.len());
+        header[..offset].copy_from_slice(&bytes[..offset]);
+
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut violations = Vec::new();
+        while offset < bytes.len() {
+            let rest = &bytes[offset..];
+            let chunk_type = rest.get(4..8).and_then(|bytes| ChunkType::try_from(<[u8; 4]>::try_from(bytes).ok()?).ok());
+            let length = rest.get(..4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize);
+            let problem = match (chunk_type, length) {
+                (Some(chunk_type), Some(length)) if rest.len() >= 12 + length => {
+                    let crc = &rest[8 + length..12 + length];
+                    let crc = u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]);
+                    chunks.push(Chunk::from_parts_unchecked(chunk_type, rest[8..8 + length].to_vec(), crc));
+                    offset += 12 + length;
+                    continue;
+                }
+                _ if chunks.iter().any(|chunk| chunk.chunk_type() == &ChunkType::IEND) => break,
+                (Some(_), Some(length)) => format!("{} data bytes declared, {} left", length, rest.len().saturating_sub(12)),
+                (None, _) if rest.len() >= 8 => "invalid chunk type".to_owned(),
+                _ => "truncated chunk header".to_owned(),
+            };
+            violations.push(Violation::file(&format!(
+                "unreadable chunk #{} at offset {} ({}), ignoring the remaining {} bytes",
+                chunks.len(), offset, problem, rest.len(),
+            )));
+            offset = bytes.len();
+        }
+
+        let png = Png { header, chunks, trailer: bytes[offset..].to_vec() };
+        (png, violations)
+    }
+
+    /// Checks that `IHDR` and `IEND` occur at most once and only at the start and end of the chunk list.
+    fn check_chunk_order(&self) -> Result<()> {
+        let last = self.chunks.len().saturating_sub(1);
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            match *chunk.chunk_type() {
+                ChunkType::IHDR if index != 0 => return Err("IHDR must be the first chunk".into()),
+                ChunkType::IEND if index != last => return Err("IEND must be the last chunk".into()),
+                _ => {}
+            }
+        }
+        Ok(())
     }
 
     /// Returns this `Png` as a byte sequence.
     /// These bytes will contain the header followed by the bytes of all of the chunks.
     pub fn as_bytes(&self) -> Vec<u8> {
-        self.header.into_iter()
-            .chain(self.chunks.iter()
-                .flat_map(|c| c.as_bytes()))
-            .collect()
+        let mut bytes = Vec::new();
+        self.extend_bytes(&mut bytes);
+        bytes
+    }
+
+    /// The number of bytes `as_bytes` returns
+    pub fn encoded_len(&self) -> usize {
+        self.header.len() + self.chunks.iter().map(Chunk::encoded_len).sum::<usize>() + self.trailer.len()
+    }
+
+    /// Appends the bytes of `as_bytes` to `bytes`, reserving the space once for all the chunks
+    pub fn extend_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.reserve(self.encoded_len());
+        bytes.extend_from_slice(&self.header);
+        for chunk in &self.chunks {
+            chunk.extend_bytes(bytes);
+        }
+        bytes.extend_from_slice(&self.trailer);
+    }
+
+    /// Writes the bytes of `as_bytes` to `writer` chunk by chunk, without building the
+    /// whole file in memory. `writer` should be buffered when the file has many small chunks.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.header)?;
+        for chunk in &self.chunks {
+            chunk.write_to(writer)?;
+        }
+        writer.write_all(&self.trailer)
     }
 }
 
-impl TryFrom<&[u8]> for Png {
-    type Error = Error;
+/// The most bytes `resync` checksums looking for the next chunk. Damaged data can hold many
+/// plausible chunk headers claiming long lengths, checking all of them would take time
+/// quadratic in the file size.
+const RESYNC_BUDGET: usize = 16 << 20;
 
-    fn try_from(value: &[u8]) -> Result<Self> {
-        let mut reader = BufReader::new(value);
-        let mut header: [u8; 8] = [0; 8];
+/// Finds the first offset from `start` where a whole chunk with a valid type and CRC begins,
+/// to carry on parsing after damaged bytes. Chunks longer than `max_chunk_len` aren't
+/// considered, and the search gives up once `RESYNC_BUDGET` bytes were checksummed.
+fn resync(bytes: &[u8], start: usize, max_chunk_len: u32) -> Option<usize> {
+    let mut budget = RESYNC_BUDGET;
+    for offset in start..bytes.len().saturating_sub(11) {
+        let rest = &bytes[offset..];
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        if !rest[4..8].iter().all(u8::is_ascii_alphabetic) || length > max_chunk_len || length as usize > rest.len() - 12 {
+            continue;
+        }
+        let length = length as usize;
+        budget = budget.checked_sub(length)?;
+        let crc = u32::from_be_bytes(rest[8 + length..12 + length].try_into().unwrap_or_default());
+        let valid = ChunkType::try_from([rest[4], rest[5], rest[6], rest[7]])
+            .is_ok_and(|chunk_type| Chunk::compute_crc(&chunk_type, &rest[8..8 + length]) == crc);
+        if valid {
+            return Some(offset);
+        }
+    }
+    None
+}
+
+/// Reads chunks starting at `bytes[*consumed..]` into `chunks` until the input or an `IEND`
+/// chunk ends. `consumed` is advanced past every chunk that was read successfully.
+fn read_chunks(
+    bytes: &[u8],
+    consumed: &mut usize,
+    chunks: &mut Vec<Chunk>,
+    limits: &ParseLimits,
+) -> Result<()> {
+    let mut reader = &bytes[*consumed..];
 
+    while reader.len() >= 4 {
+        if chunks.len() == limits.max_chunks {
+            return Err(LimitExceeded::Chunks(limits.max_chunks).into());
+        }
+
+        let data_length = u32::from_be_bytes(reader[..4].try_into()?);
+        if data_length > limits.max_chunk_len {
+            return Err(LimitExceeded::ChunkLength(limits.max_chunk_len).into());
+        }
+
+        let (chunk, read) = Chunk::read_from(&mut reader)
+            .map_err(Error::from)
+            .and_then(|(chunk, read)| {
+                if limits.check_lengths {
+                    InvalidLength::check(chunk.chunk_type(), chunk.length())?;
+                }
+                Ok((chunk, read))
+            })
+            .map_err(|source| ChunkParseError {
+                index: chunks.len(),
+                offset: *consumed as u64,
+                previous: chunks.last().map(|chunk| *chunk.chunk_type()),
+                source,
+            })?;
+        let is_end = chunk.chunk_type() == "IEND";
+        chunks.push(chunk);
+        *consumed += read;
+
+        if is_end {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// An iterator reading the chunks of a PNG file lazily from any `Read` source, see
+/// `Png::chunks_from_reader`. It ends after `IEND` or at the end of the input, and after the
+/// first error. The data after `IEND` is left in the reader (see `into_inner`).
+pub struct ChunkReader<R> {
+    reader: R,
+    limits: ParseLimits,
+    /// The offset of the next chunk in the input
+    offset: u64,
+    count: usize,
+    previous: Option<ChunkType>,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Checks the PNG header at the start of `reader`, refusing chunks that exceed `limits`
+    /// while iterating. `max_total_size` isn't checked as nothing is buffered.
+    pub fn new(mut reader: R, limits: ParseLimits) -> Result<Self> {
+        let mut header = [0; 8];
         reader.read_exact(&mut header)?;
-        if header != Self::STANDARD_HEADER {
-            return Err("invalid PNG header".into());
+        if header != Png::STANDARD_HEADER {
+            return Err(PngMeError::InvalidSignature.into());
         }
 
-        let mut chunks = vec![];
-        let mut buffer = vec![];
-        let mut length: [u8; 4] = [0; 4];
+        Ok(ChunkReader { reader, limits, offset: header.len() as u64, count: 0, previous: None, done: false })
+    }
+
+    /// The offset in the input of the next chunk
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the reader, positioned after the last chunk read
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next chunk, or returns `None` at the end of the input
+    fn read_chunk(&mut self) -> Result<Option<Chunk>> {
+        let mut fields = [0; 8];
+        let read = visit::read_fields(&mut self.reader, &mut fields).map_err(|e| ChunkParseError {
+            index: self.count,
+            offset: self.offset,
+            previous: self.previous,
+            source: e.into(),
+        })?;
+        if !read {
+            return Ok(None);
+        }
+
+        self.count += 1;
+        if self.count > self.limits.max_chunks {
+            return Err(LimitExceeded::Chunks(self.limits.max_chunks).into());
+        }
+        let length = u32::from_be_bytes(fields[..4].try_into()?);
+        if length > self.limits.max_chunk_len {
+            return Err(LimitExceeded::ChunkLength(self.limits.max_chunk_len).into());
+        }
+
+        let chunk = visit::read_type(&fields, length, &self.limits)
+            .and_then(|chunk_type| Ok(Chunk::read_body(&mut self.reader, chunk_type, length)?))
+            .map_err(|source| ChunkParseError {
+                index: self.count - 1,
+                offset: self.offset,
+                previous: self.previous,
+                source,
+            })?;
+        self.offset += 12 + length as u64;
+        self.previous = Some(*chunk.chunk_type());
+        Ok(Some(chunk))
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = Result<Chunk>;
 
-        while reader.read_exact(&mut length).is_ok() {
-            // (chunk type + CRC)[8 byte] + data[`length` byte]
-            let remainig_chunk_length = 8 + u32::from_be_bytes(length) as usize;
-            buffer.resize(remainig_chunk_length, 0);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
 
-            reader.read_exact(&mut buffer)?;
-            let chunk_data: Vec<_> = length.into_iter()
-                .chain(buffer.iter().copied())
-                .collect();
+        let result = self.read_chunk().transpose();
+        self.done = match &result {
+            Some(Ok(chunk)) => chunk.chunk_type() == "IEND",
+            _ => true,
+        };
+        result
+    }
+}
+
+/// The error returned when a chunk can't be read, saying where it is in the file
+pub struct ChunkParseError {
+    /// The position of the chunk in the file, starting at 0 like the `--index` arguments
+    pub index: usize,
+    /// The offset of the chunk (its length field) in the file
+    pub offset: u64,
+    /// The type of the chunk before it, if there is one
+    pub previous: Option<ChunkType>,
+    pub source: Error,
+}
 
-            chunks.push(Chunk::try_from(&chunk_data[..])?);
+impl fmt::Display for ChunkParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.previous {
+            Some(previous) => write!(f, "chunk #{} (after type {} at offset {})", self.index, previous, self.offset)?,
+            None => write!(f, "chunk #{} (at offset {})", self.index, self.offset)?,
         }
+        write!(f, ": {}", self.source)
+    }
+}
 
-        Ok(Png::from_chunks(chunks))
+// Formatted like the string errors, as `main` prints errors with `Debug`
+impl fmt::Debug for ChunkParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl std::error::Error for ChunkParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// A problem found while parsing in lenient mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// The offset in the input where the problem was found
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offset {}: {}", self.offset, self.message)
+    }
+}
+
+/// A structural rule of the PNG spec broken by a file, see `Png::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The position of the offending chunk (starting at 0), if the violation is about a chunk
+    pub index: Option<usize>,
+    pub chunk_type: Option<ChunkType>,
+    pub message: String,
+}
+
+impl Violation {
+    fn file(message: &str) -> Violation {
+        Violation { index: None, chunk_type: None, message: message.to_owned() }
+    }
+
+    fn chunk(index: usize, chunk: &Chunk, message: &str) -> Violation {
+        Violation { index: Some(index), chunk_type: Some(*chunk.chunk_type()), message: message.to_owned() }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.index, &self.chunk_type) {
+            (Some(index), Some(chunk_type)) => write!(f, "chunk #{} ({}): {}", index, chunk_type, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = PngMeError;
+
+    fn try_from(value: &[u8]) -> std::result::Result<Self, PngMeError> {
+        Ok(Png::from_bytes_with_limits(value, &ParseLimits::default())?)
+    }
+}
+
+impl Extend<Chunk> for Png {
+    fn extend<I: IntoIterator<Item = Chunk>>(&mut self, iter: I) {
+        self.chunks.extend(iter);
+    }
+}
+
+impl FromIterator<Chunk> for Png {
+    fn from_iter<I: IntoIterator<Item = Chunk>>(iter: I) -> Self {
+        Png::from_chunks(iter.into_iter().collect())
+    }
+}
+
+/// Collecting into a `Result` additionally checks the chunk ordering: `IHDR` (if present)
+/// must be the first chunk and `IEND` (if present) must be the last one.
+impl FromIterator<Chunk> for Result<Png> {
+    fn from_iter<I: IntoIterator<Item = Chunk>>(iter: I) -> Self {
+        let png: Png = iter.into_iter().collect();
+        png.check_chunk_order()?;
+        Ok(png)
     }
 }
 
 impl fmt::Display for Png {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        ChunkListing::from(self).fmt(f)
+    }
+}
+
+/// The chunks of a PNG file without their data, e.g. of a file read one chunk at a time.
+/// Its `Display` implementation is the one of `Png`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkListing {
+    pub chunks: Vec<ChunkSummary>,
+    /// The number of bytes after `IEND`
+    pub trailer_len: usize,
+}
+
+impl From<&Png> for ChunkListing {
+    fn from(png: &Png) -> Self {
+        ChunkListing { chunks: png.chunks.iter().map(Chunk::summary).collect(), trailer_len: png.trailer.len() }
+    }
+}
+
+impl fmt::Display for ChunkListing {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "PNG {{")?;
         for chunk in &self.chunks {
             writeln!(f, "  {}", chunk)?;
         }
+        if self.trailer_len > 0 {
+            writeln!(f, "  Trailing data after IEND ({} bytes)", self.trailer_len)?;
+        }
         writeln!(f, "}}")?;
         Ok(())
     }
@@ -137,13 +988,11 @@ mod tests {
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -241,6 +1090,115 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_chunk_by_chunk_type() {
+        let png = testing_png();
+        let chunk_type = ChunkType::from_str("FrSt").unwrap();
+        assert_eq!(png.chunk_by_type(&chunk_type).unwrap().chunk_type(), &chunk_type);
+        assert!(png.chunk_by_type("frst").is_none());
+        assert_eq!(png.chunk_by_type_ignore_case("frst").unwrap().chunk_type(), &chunk_type);
+    }
+
+    #[test]
+    fn test_remove_matching() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("ruSu", "Message").unwrap());
+
+        let everything = ChunkTypePattern::glob("*", false).unwrap();
+        assert_eq!(png.chunks_matching(&everything).count(), png.chunks().len());
+
+        let removed = png.remove_matching(&ChunkTypePattern::glob("?u??", true).unwrap());
+        let types: Vec<String> = removed.iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        // `RuSt` is critical, so it stays
+        assert_eq!(types, ["ruSu"]);
+        assert!(png.chunk_by_type("RuSt").is_some());
+
+        let count = png.chunks().len();
+        assert!(png.remove_matching(&ChunkTypePattern::regex("^[A-Z]", false).unwrap()).is_empty());
+        assert_eq!(png.chunks().len(), count);
+    }
+
+    #[test]
+    fn test_chunk_not_found() {
+        let png = testing_png();
+        assert_eq!(png.chunk_not_found("XxXx").to_string(), "no XxXx chunk found");
+        assert_eq!(
+            png.chunk_not_found("frst").to_string(),
+            "no frst chunk found, but there is a FrSt chunk (chunk types are case-sensitive, see --ignore-case)",
+        );
+        assert!(matches!(
+            png.chunk_not_found("frst").downcast_ref(),
+            Some(PngMeError::ChunkNotFound { chunk_type, similar: Some(similar) }) if chunk_type == "frst" && similar == "FrSt",
+        ));
+    }
+
+    #[test]
+    fn test_error_crosses_threads() {
+        let err = std::thread::spawn(|| Png::try_from(&[0u8; 8][..]).map(|_| ())).join().unwrap().unwrap_err();
+        assert!(matches!(err, PngMeError::InvalidSignature));
+    }
+
+    #[test]
+    fn test_try_from_errors() {
+        let err = Png::try_from(&PNG_FILE[..20]).unwrap_err();
+        assert!(matches!(&err, PngMeError::ChunkParse(err) if matches!(err.source.downcast_ref(), Some(PngMeError::Io(_)))));
+        assert!(matches!(Png::try_from(&PNG_FILE[..4]).unwrap_err(), PngMeError::Io(_)));
+
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        let err = Png::try_from(&bytes[..]).unwrap_err();
+        assert!(matches!(err, PngMeError::LimitExceeded(LimitExceeded::ChunkLength(_))));
+    }
+
+    #[test]
+    fn test_to_file_replaces_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dice.png");
+        fs::write(&path, b"original").unwrap();
+        #[cfg(unix)]
+        fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o640)).unwrap();
+
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.to_file(&path).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), png.as_bytes());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path).unwrap().permissions()) & 0o777, 0o640);
+    }
+
+    #[test]
+    fn test_describe_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dice.png");
+        let mut png = testing_png();
+        png.to_file(&path).unwrap();
+        let size = png.as_bytes().len();
+
+        png.remove_chunk("FrSt").unwrap();
+        png.insert_before_end(chunk_from_strings("ruSt", "hi").unwrap());
+        assert_eq!(
+            png.describe_changes(&path),
+            format!("Would change {} from {} to {} bytes\n  - FrSt chunk (20 bytes)\n  + ruSt chunk (2 bytes)", path.display(), size, size - 18),
+        );
+        assert_eq!(png.describe_changes(&dir.path().join("new.png")), format!("Would write {} bytes to {}", size - 18, dir.path().join("new.png").display()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_to_file_follows_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, link) = (dir.path().join("dice.png"), dir.path().join("link.png"));
+        fs::write(&target, b"original").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.to_file(&link).unwrap();
+
+        assert!(link.is_symlink());
+        assert_eq!(fs::read(&target).unwrap(), png.as_bytes());
+    }
+
     #[test]
     fn test_append_chunk() {
         let mut png = testing_png();
@@ -250,6 +1208,16 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_before_end() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_before_end(chunk_from_strings("TeSt", "Message").unwrap());
+
+        let chunks = png.chunks();
+        assert_eq!(&chunks[chunks.len() - 2].chunk_type().to_string(), "TeSt");
+        assert_eq!(&chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -259,6 +1227,351 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_validate() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(png.validate().is_empty());
+
+        // IHDR, sRGB, gAMA, pHYs, IDAT, RuSt, IEND: move gAMA after IDAT, and add a second
+        // IDAT chunk after RuSt
+        let mut chunks = png.chunks().to_vec();
+        let gama = chunks.remove(2);
+        chunks.insert(4, gama);
+        chunks.insert(6, chunks[3].clone());
+        let mut png = Png::from_chunks(chunks);
+        png.set_trailer(b"xx".to_vec());
+        let violations: Vec<String> = png.validate().iter().map(ToString::to_string).collect();
+        assert_eq!(violations, [
+            "chunk #4 (gAMA): must come before the IDAT chunks",
+            "chunk #6 (IDAT): IDAT chunks must be consecutive",
+            "2 bytes of data after IEND",
+        ]);
+    }
+
+    #[test]
+    fn test_from_bytes_unvalidated() {
+        let mut bytes = PNG_FILE.to_vec();
+        // Corrupt a data byte of the first IDAT chunk, so its CRC doesn't match
+        bytes[100] ^= 1;
+        let (png, violations) = Png::from_bytes_unvalidated(&bytes);
+        assert!(violations.is_empty());
+        let violations = png.validate();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].chunk_type.unwrap().as_str(), "IDAT");
+        assert!(violations[0].message.starts_with("CRC mismatch"));
+
+        let (png, violations) = Png::from_bytes_unvalidated(&PNG_FILE[..PNG_FILE.len() - 100]);
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].message.contains("data bytes declared"));
+        assert!(png.validate().iter().any(|violation| violation.message == "no IEND chunk"));
+    }
+
+    #[test]
+    fn test_repair_crcs() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[100] ^= 1;
+        let (mut png, _) = Png::from_bytes_unvalidated(&bytes);
+        assert_eq!(png.repair_crcs(), [4]);
+        assert!(png.validate().is_empty());
+        assert!(png.repair_crcs().is_empty());
+    }
+
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("FrSt", "Again").unwrap());
+        let data: Vec<String> = png.chunks_by_type("FrSt").map(|chunk| chunk.data_as_string().unwrap()).collect();
+        assert_eq!(data, ["I am the first chunk", "Again"]);
+        assert_eq!(png.chunks_by_type("TeSt").count(), 0);
+    }
+
+    #[test]
+    fn test_replace_chunk() {
+        let mut png = testing_png();
+        let position = png.chunks().iter().position(|chunk| chunk.chunk_type() == "FrSt").unwrap();
+        let count = png.chunks().len();
+
+        let old = png.replace_chunk("FrSt", b"replaced".to_vec()).unwrap();
+        assert_eq!(old.data_as_string().unwrap(), "I am the first chunk");
+        assert_eq!(png.chunks()[position].data_as_string().unwrap(), "replaced");
+        assert_eq!(png.chunks().len(), count);
+        assert!(png.replace_chunk("TeSt", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_chunk_at() {
+        let mut png = testing_png();
+        let count = png.chunks().len();
+        png.insert_chunk_at(1, chunk_from_strings("TeSt", "one").unwrap()).unwrap();
+        png.insert_chunk_at(count + 1, chunk_from_strings("TeSt", "two").unwrap()).unwrap();
+        assert_eq!(png.chunks()[1].data_as_string().unwrap(), "one");
+        assert!(png.insert_chunk_at(count + 3, chunk_from_strings("TeSt", "three").unwrap()).is_err());
+
+        assert_eq!(png.remove_chunk_at(count + 1).unwrap().data_as_string().unwrap(), "two");
+        assert_eq!(png.remove_chunk_at(1).unwrap().data_as_string().unwrap(), "one");
+        assert_eq!(png.chunks().len(), count);
+        assert!(png.remove_chunk_at(count).is_err());
+    }
+
+    #[test]
+    fn test_remove_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "one").unwrap());
+        png.insert_before_end(chunk_from_strings("TeSt", "two").unwrap());
+        let count = png.chunks().len();
+
+        let removed = png.remove_chunks_by_type("TeSt").unwrap();
+        let data: Vec<String> = removed.iter().map(|chunk| chunk.data_as_string().unwrap()).collect();
+        assert_eq!(data, ["one", "two"]);
+        assert_eq!(png.chunks().len(), count - 2);
+        assert!(png.remove_chunks_by_type("TeSt").is_err());
+    }
+
+    #[test]
+    fn test_embedded_size() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        // The test file already contains a `RuSt` chunk with 3 bytes of data
+        assert_eq!(png.embedded_size(), 12 + 3);
+
+        png.append_chunk(chunk_from_strings("ruSt", "Message").unwrap());
+        png.append_chunk(chunk_from_strings("TEST", "Public").unwrap());
+        png.set_trailer(b"appended".to_vec());
+
+        assert_eq!(png.embedded_size(), 12 + 3 + 12 + 7 + 8);
+    }
+
+    #[test]
+    fn test_type_counts() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a duplicate").unwrap());
+
+        let counts = png.type_counts();
+
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&ChunkType::from_str("miDl").unwrap()], 2);
+        assert_eq!(counts[&ChunkType::from_str("FrSt").unwrap()], 1);
+    }
+
+    #[test]
+    fn test_collect_and_extend() {
+        let mut png: Png = testing_chunks().into_iter().collect();
+        png.extend(vec![chunk_from_strings("TeSt", "Message").unwrap()]);
+
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(png.header(), &Png::STANDARD_HEADER);
+    }
+
+    #[test]
+    fn test_collect_checks_order() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        let ordered: Result<Png> = png.chunks().iter().cloned().collect();
+        assert!(ordered.is_ok());
+
+        let reversed: Result<Png> = png.chunks().iter().rev().cloned().collect();
+        assert!(reversed.is_err());
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let limits = ParseLimits { max_chunks: 4, ..ParseLimits::default() };
+        let err = Png::from_bytes_with_limits(&PNG_FILE, &limits).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&LimitExceeded::Chunks(4)));
+
+        let limits = ParseLimits { max_chunk_len: 1024, ..ParseLimits::default() };
+        let err = Png::from_bytes_with_limits(&PNG_FILE, &limits).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&LimitExceeded::ChunkLength(1024)));
+
+        let limits = ParseLimits { max_total_size: 4802, ..ParseLimits::default() };
+        let err = Png::from_bytes_with_limits(&PNG_FILE, &limits).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&LimitExceeded::TotalSize(4802)));
+
+        let limits = ParseLimits { max_chunks: 16, max_chunk_len: 8192, max_total_size: 4803, check_lengths: true };
+        assert!(Png::from_bytes_with_limits(&PNG_FILE, &limits).is_ok());
+    }
+
+    #[test]
+    fn test_chunk_parse_error() {
+        let mut bytes = PNG_FILE.to_vec();
+        // Corrupts the CRC of the third chunk
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let offset = 8 + png.chunks()[..3].iter().map(|chunk| chunk.as_bytes().len()).sum::<usize>();
+        bytes[offset - 1] ^= 0xff;
+
+        let err = Png::try_from(&bytes[..]).unwrap_err();
+        let previous = png.chunks()[1].chunk_type();
+        let offset = offset - png.chunks()[2].as_bytes().len();
+        let crc = png.chunks()[2].crc();
+        assert_eq!(err.to_string(), format!(
+            "chunk #2 (after type {} at offset {}): invalid chunk CRC (expected {}, found {})",
+            previous, offset, crc, crc ^ 0xff,
+        ));
+        let PngMeError::ChunkParse(err) = err else { panic!("{:?}", err) };
+        assert_eq!(err.index, 2);
+        assert!(matches!(
+            err.source.downcast_ref(),
+            Some(&PngMeError::CrcMismatch { expected, actual }) if expected == crc && actual == crc ^ 0xff,
+        ));
+
+        bytes[8 + 4] = b'!';
+        assert!(Png::try_from(&bytes[..]).unwrap_err().to_string().starts_with("chunk #0 (at offset 8): "));
+    }
+
+    #[test]
+    fn test_check_lengths() {
+        let strict = ParseLimits { check_lengths: true, ..ParseLimits::default() };
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.remove_chunk("IEND").unwrap();
+        png.append_chunk(chunk_from_strings("IEND", "hidden").unwrap());
+        let bytes = png.as_bytes();
+
+        assert!(Png::try_from(&bytes[..]).is_ok());
+        let err = Png::from_bytes_with_limits(&bytes, &strict).unwrap_err();
+        assert_eq!(err.to_string(), format!("chunk #6 (after type RuSt at offset {}): IEND chunk has 6 data bytes instead of 0", bytes.len() - 18));
+
+        let ihdr = ChunkType::from_str("IHDR").unwrap();
+        assert!(InvalidLength::check(&ihdr, 13).is_ok());
+        assert_eq!(InvalidLength::check(&ihdr, 17).unwrap_err().expected, 13);
+        assert!(InvalidLength::check(&ChunkType::from_str("tEXt").unwrap(), 17).is_ok());
+    }
+
+    #[test]
+    fn test_trailer_after_iend() {
+        let bytes: Vec<u8> = PNG_FILE.iter()
+            .chain(b"appended".iter())
+            .copied()
+            .collect();
+
+        let png = Png::try_from(&bytes[..]).unwrap();
+
+        assert_eq!(png.trailer(), b"appended");
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_write_to() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.set_trailer(b"appended".to_vec());
+
+        let mut written = Vec::new();
+        png.write_to(&mut written).unwrap();
+        assert_eq!(written, png.as_bytes());
+        assert_eq!(png.encoded_len(), written.len());
+    }
+
+    #[test]
+    fn test_chunks_after_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("ruSt", "Message").unwrap());
+
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+
+        assert!(reparsed.trailer().is_empty());
+        assert_eq!(&reparsed.chunk_by_type("ruSt").unwrap().data_as_string().unwrap(), "Message");
+    }
+
+    #[test]
+    fn test_chunks_and_trailer_after_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("ruSt", "Message").unwrap());
+        png.set_trailer(b"appended".to_vec());
+
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+
+        assert_eq!(reparsed.trailer(), b"appended");
+        assert!(reparsed.chunk_by_type("ruSt").is_some());
+        assert_eq!(reparsed.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_truncated_iend() {
+        let truncated = &PNG_FILE[..PNG_FILE.len() - 6];
+
+        assert!(Png::try_from(truncated).is_err());
+
+        let (png, warnings) = Png::from_bytes_lenient(truncated).unwrap();
+        assert!(!png.has_end());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].offset, PNG_FILE.len() - 12);
+        assert_eq!(png.chunks().len(), Png::try_from(&PNG_FILE[..]).unwrap().chunks().len() - 1);
+    }
+
+    #[test]
+    fn test_lenient_parse_resyncs() {
+        // Corrupt the data of the IDAT chunk (bad CRC) and the length of the RuSt chunk after it
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[100] ^= 1;
+        let rust = PNG_FILE.len() - 12 - 15;
+        bytes[rust] = 0xff;
+        assert!(Png::try_from(&bytes[..]).is_err());
+
+        let (png, warnings) = Png::from_bytes_lenient(&bytes).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("skipping"));
+        let types: Vec<String> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "sRGB", "gAMA", "pHYs", "IEND"]);
+    }
+
+    #[test]
+    fn test_resync_budget() {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"found".to_vec());
+        let mut bytes = Vec::new();
+        for _ in 0..RESYNC_BUDGET >> 20 {
+            // A plausible header claiming 1 MiB, whose CRC doesn't match
+            bytes.extend_from_slice(&(1u32 << 20).to_be_bytes());
+            bytes.extend_from_slice(b"fake");
+        }
+        bytes.resize(bytes.len() + (1 << 20) + 4, 0);
+        let found = bytes.len();
+        bytes.extend_from_slice(&chunk.as_bytes());
+
+        assert_eq!(resync(&bytes, 0, u32::MAX), None);
+        assert_eq!(resync(&bytes, 0, 1 << 19), Some(found));
+    }
+
+    #[test]
+    fn test_lenient_parse_of_valid_file() {
+        let (png, warnings) = Png::from_bytes_lenient(&PNG_FILE).unwrap();
+        assert!(png.has_end());
+        assert!(warnings.is_empty());
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_zero_chunks() {
+        let mut png = testing_png();
+        let size = png.as_bytes().len();
+
+        assert_eq!(png.zero_chunks("miDl").unwrap(), 1);
+        assert_eq!(png.as_bytes().len(), size);
+        assert!(png.chunk_by_type("miDl").unwrap().data().iter().all(|&byte| byte == 0));
+        assert_eq!(png.zero_chunks("ruSt").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_chunks_from_reader() {
+        let bytes: Vec<u8> = PNG_FILE.iter().chain(b"after").copied().collect();
+        let mut reader = Png::chunks_from_reader(&bytes[..]).unwrap();
+        let chunks: Vec<Chunk> = reader.by_ref().collect::<Result<_>>().unwrap();
+        assert_eq!(chunks.len(), Png::try_from(&PNG_FILE[..]).unwrap().chunks().len());
+        assert_eq!(chunks.last().unwrap().chunk_type().to_string(), "IEND");
+        assert_eq!(reader.offset(), PNG_FILE.len() as u64);
+        assert_eq!(reader.into_inner(), b"after");
+
+        // Damaged input yields the chunks before the damage, then one error
+        let results: Vec<Result<Chunk>> = Png::chunks_from_reader(&PNG_FILE[..100]).unwrap().collect();
+        assert!(results[..results.len() - 1].iter().all(|result| result.is_ok()));
+        assert!(results.last().unwrap().as_ref().unwrap_err().to_string().starts_with("chunk #"));
+        assert!(Png::chunks_from_reader(&b"not a png"[..]).is_err());
+    }
+
+    #[test]
+    fn test_few_bytes_after_iend() {
+        let bytes: Vec<u8> = PNG_FILE.iter().chain(&[1, 2]).copied().collect();
+        let png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.trailer(), &[1, 2]);
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -269,7 +1582,7 @@ mod tests {
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
-        let expected: Vec<u8> = PNG_FILE.iter().copied().collect();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
         assert_eq!(actual, expected);
     }
 
@@ -0,0 +1,159 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+
+use crate::png::LimitExceeded;
+use crate::Result;
+
+/// Files at least this large are read with concurrent positioned reads
+pub const PARALLEL_THRESHOLD: u64 = 32 << 20;
+
+/// The size of the segments read concurrently
+const SEGMENT_LEN: usize = 8 << 20;
+
+/// The maximum number of threads reading one file
+const MAX_THREADS: usize = 8;
+
+/// The path standing for stdin (as an input) and stdout (as an output)
+pub const STDIO: &str = "-";
+
+/// Returns true if `path` can only be read once from start to end: `-` (stdin), a pipe or a
+/// FIFO such as the `/dev/fd/63` of a `<(command)` process substitution, or a device
+pub fn is_stream<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    path == Path::new(STDIO) || fs::metadata(path).is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+}
+
+/// Reads a whole file. Large files are split into segments read concurrently with positioned
+/// reads (`pread` on Unix, `seek_read` on Windows) into one buffer, which keeps a fast NVMe
+/// drive busy where a single sequential read can't. Small files are read with `fs::read`.
+///
+/// Streams (see `is_stream`) are read in a single sequential pass. Reading one again doesn't
+/// return the same contents, see `session::Session::read_file`.
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    if is_stream(&path) {
+        return read_stream(path.as_ref());
+    }
+
+    let file = File::open(&path)?;
+    let len = file.metadata()?.len();
+    if len < PARALLEL_THRESHOLD || !cfg!(any(unix, windows)) {
+        return Ok(fs::read(path)?);
+    }
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_THREADS);
+    read_segments(&file, len, SEGMENT_LEN, threads)
+}
+
+fn read_stream(path: &Path) -> Result<Vec<u8>> {
+    read_stream_limited(path, u64::MAX)
+}
+
+/// Reads a stream (see `is_stream`) in a single sequential pass, failing as soon as it is
+/// longer than `max_len` bytes instead of reading it to its end
+pub fn read_stream_limited<P: AsRef<Path>>(path: P, max_len: u64) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    // One byte more than the limit allows tells a stream that exceeds it
+    let limit = max_len.saturating_add(1);
+    let mut contents = Vec::new();
+    if path == Path::new(STDIO) {
+        io::stdin().lock().take(limit).read_to_end(&mut contents)?;
+    } else {
+        File::open(path)?.take(limit).read_to_end(&mut contents)?;
+    }
+    if contents.len() as u64 > max_len {
+        return Err(LimitExceeded::TotalSize(max_len as usize).into());
+    }
+    Ok(contents)
+}
+
+/// Reads `len` bytes of `file` in segments of `segment_len` bytes using `threads` threads
+fn read_segments(file: &File, len: u64, segment_len: usize, threads: usize) -> Result<Vec<u8>> {
+    let len = usize::try_from(len).map_err(|_| "file is too large to be read into memory")?;
+    let mut buffer = vec![0; len];
+
+    let segments: Vec<(usize, &mut [u8])> = buffer.chunks_mut(segment_len)
+        .enumerate()
+        .map(|(index, segment)| (index * segment_len, segment))
+        .collect();
+    let per_thread = segments.len().div_ceil(threads.max(1)).max(1);
+
+    let mut segments = segments.into_iter();
+    thread::scope(|scope| {
+        let mut workers = Vec::new();
+        loop {
+            let batch: Vec<_> = segments.by_ref().take(per_thread).collect();
+            if batch.is_empty() {
+                break;
+            }
+            workers.push(scope.spawn(move || -> io::Result<()> {
+                for (offset, segment) in batch {
+                    read_exact_at(file, segment, offset as u64)?;
+                }
+                Ok(())
+            }));
+        }
+        workers.into_iter().try_for_each(|worker| worker.join().expect("reader thread panicked"))
+    })?;
+
+    Ok(buffer)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buffer.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buffer, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                buffer = &mut buffer[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(_file: &File, _buffer: &mut [u8], _offset: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_read_segments() {
+        let contents: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&contents).unwrap();
+
+        for (segment_len, threads) in [(1000, 3), (4096, 8), (333, 1), (20_000, 4)] {
+            assert_eq!(read_segments(&file, contents.len() as u64, segment_len, threads).unwrap(), contents);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_streams() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert!(is_stream(STDIO));
+        assert!(is_stream("/dev/null"));
+        assert!(!is_stream(file.path()));
+        assert!(!is_stream(file.path().parent().unwrap()));
+        assert!(read_file("/dev/null").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_past_end() {
+        let file = tempfile::tempfile().unwrap();
+        assert!(read_segments(&file, 100, 10, 2).is_err());
+    }
+}
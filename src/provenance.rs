@@ -0,0 +1,110 @@
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+/// The chunk type provenance documents are stored in: ancillary, private and safe to copy,
+/// so editors that don't know it keep it
+pub const PROVENANCE_CHUNK_TYPE: &str = "prVn";
+
+/// Checks `document` against a JSON Schema, listing every violation in the error
+pub fn validate(document: &Value, schema: &Value) -> Result<()> {
+    let validator = jsonschema::validator_for(schema).map_err(|e| format!("invalid JSON Schema: {}", e))?;
+    let errors: Vec<String> = validator.iter_errors(document)
+        .map(|e| {
+            let path = e.instance_path.to_string();
+            format!("{}: {}", if path.is_empty() { "/" } else { &path }, e)
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(format!("provenance doesn't match the schema: {}", errors.join("; ")).into());
+    }
+    Ok(())
+}
+
+/// Stores a JSON document describing where an image comes from (e.g. the model and dataset
+/// that generated it) in `png`, replacing the existing one. The document is validated
+/// against `schema` first, if given.
+pub fn embed(png: &mut Png, document: &Value, schema: Option<&Value>) -> Result<()> {
+    if let Some(schema) = schema {
+        validate(document, schema)?;
+    }
+
+    let chunk = Chunk::new(ChunkType::from_str(PROVENANCE_CHUNK_TYPE)?, serde_json::to_vec(document)?);
+    png.chunks_mut().retain(|chunk| chunk.chunk_type() != PROVENANCE_CHUNK_TYPE);
+    png.insert_before_end(chunk);
+    Ok(())
+}
+
+/// Reads the provenance document of `png`, validating it against `schema` if given
+pub fn read(png: &Png, schema: Option<&Value>) -> Result<Value> {
+    let chunk = png.chunk_by_type(PROVENANCE_CHUNK_TYPE)
+        .ok_or(format!("no provenance ({} chunk) found", PROVENANCE_CHUNK_TYPE))?;
+    let document = serde_json::from_slice(chunk.data())
+        .map_err(|e| format!("invalid provenance document: {}", e))?;
+
+    if let Some(schema) = schema {
+        validate(&document, schema)?;
+    }
+    Ok(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["model", "dataset"],
+            "properties": {
+                "model": { "type": "string" },
+                "dataset": { "type": "string" },
+                "seed": { "type": "integer" },
+            },
+        })
+    }
+
+    #[test]
+    fn test_embed_and_read() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let document = json!({ "model": "diffusion-xl", "dataset": "laion-5b", "seed": 42 });
+
+        embed(&mut png, &json!({ "model": "old", "dataset": "old" }), Some(&schema())).unwrap();
+        embed(&mut png, &document, Some(&schema())).unwrap();
+
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(reparsed.type_counts()[&ChunkType::from_str(PROVENANCE_CHUNK_TYPE).unwrap()], 1);
+        assert_eq!(read(&reparsed, Some(&schema())).unwrap(), document);
+    }
+
+    #[test]
+    fn test_invalid_document() {
+        let mut png = Png::try_from(DICE).unwrap();
+
+        let error = embed(&mut png, &json!({ "model": 7, "seed": 1.5 }), Some(&schema())).unwrap_err().to_string();
+        assert!(error.contains("/model"), "{}", error);
+        assert!(error.contains("/seed"), "{}", error);
+        assert!(error.contains("\"dataset\" is a required property"), "{}", error);
+        assert!(png.chunk_by_type(PROVENANCE_CHUNK_TYPE).is_none());
+
+        embed(&mut png, &json!({ "model": "m" }), None).unwrap();
+        assert!(read(&png, None).is_ok());
+        assert!(read(&png, Some(&schema())).is_err());
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        let error = validate(&json!({}), &json!({ "type": 12 })).unwrap_err().to_string();
+        assert!(error.starts_with("invalid JSON Schema"), "{}", error);
+    }
+}
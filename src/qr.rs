@@ -0,0 +1,121 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use qrcodegen::{QrCode, QrCodeEcc};
+
+use crate::crypto::{self, Encryption, Secret};
+use crate::envelope::Envelope;
+use crate::image::{ImageHeader, RawImage};
+use crate::png::Png;
+use crate::Result;
+
+/// Encrypted messages are rendered as this prefix followed by the encrypted envelope in base64,
+/// so QR code readers show text that can be pasted into `pngme qr-decrypt`
+pub const ENCRYPTED_PREFIX: &str = "pngme:";
+
+/// How `render` draws a QR code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QrOptions {
+    /// The width of a module (a square of the code), in pixels
+    pub scale: u32,
+    /// The width of the blank margin around the code, in modules
+    pub border: u32,
+}
+
+impl Default for QrOptions {
+    fn default() -> Self {
+        // The QR code spec asks for a margin of 4 modules
+        QrOptions { scale: 8, border: 4 }
+    }
+}
+
+/// The text a QR code holds for `message`: the message itself, or with `encryption`, the
+/// encrypted envelope behind `ENCRYPTED_PREFIX`. There is no image to bind the payload to
+/// (see `crypto::carrier_binding`), so it is encrypted without associated data.
+pub fn content(message: &str, encryption: Option<&Encryption>) -> Result<String> {
+    let Some(encryption) = encryption else {
+        return Ok(message.to_owned());
+    };
+    let payload = crypto::encrypt(message.as_bytes(), encryption, &[])?;
+    let envelope = Envelope::with_flags(Envelope::FLAG_ENCRYPTED, payload);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(envelope.as_bytes())))
+}
+
+/// Renders `text` as a black on white QR code with medium error correction, in a new 1-bit
+/// grayscale image
+pub fn render(text: &str, options: &QrOptions) -> Result<Png> {
+    let code = QrCode::encode_text(text, QrCodeEcc::Medium)
+        .map_err(|_| format!("the message ({} bytes) is too long for a QR code", text.len()))?;
+    let size = options.border.checked_mul(2)
+        .and_then(|border| border.checked_add(code.size() as u32))
+        .and_then(|modules| modules.checked_mul(options.scale))
+        .filter(|&size| size > 0 && size <= 1 << 16)
+        .ok_or("the QR code would be too large, use a smaller --scale or --border")?;
+
+    let header = ImageHeader::new(size, size, 1, ImageHeader::GRAYSCALE);
+    let stride = header.stride();
+    let mut data = vec![0xff; stride * size as usize];
+    for y in 0..size {
+        for x in 0..size {
+            // Modules outside of the code are light
+            let module_x = (x / options.scale) as i32 - options.border as i32;
+            let module_y = (y / options.scale) as i32 - options.border as i32;
+            if code.get_module(module_x, module_y) {
+                data[y as usize * stride + x as usize / 8] &= !(0x80 >> (x % 8));
+            }
+        }
+    }
+    RawImage::new(header, data)?.to_png()
+}
+
+/// Decrypts the text of a QR code rendered from `content` with encryption
+pub fn decrypt(text: &str, secret: Option<&Secret>) -> Result<Vec<u8>> {
+    let encoded = text.trim()
+        .strip_prefix(ENCRYPTED_PREFIX)
+        .ok_or_else(|| format!("not an encrypted pngme QR code (expected text starting with {})", ENCRYPTED_PREFIX))?;
+    let envelope = Envelope::open(BASE64.decode(encoded).map_err(|e| format!("invalid base64: {}", e))?)?;
+    if !envelope.is_encrypted() {
+        return Err("the QR code holds an envelope that isn't encrypted".into());
+    }
+    crypto::decrypt(envelope.payload(), secret, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let options = QrOptions { scale: 2, border: 4 };
+        let image = RawImage::from_png(&render("hello", &options).unwrap()).unwrap();
+        let header = image.header();
+        // "hello" fits in a version 1 code, 21 modules wide
+        assert_eq!((header.width, header.height, header.bit_depth), (58, 58, 1));
+
+        let stride = header.stride();
+        let dark = |x: usize, y: usize| image.data()[y * stride + x / 8] & (0x80 >> (x % 8)) == 0;
+        assert!(!dark(0, 0) && !dark(7, 7));
+        // The top left finder pattern: a dark ring around a light ring around a dark square
+        assert!(dark(8, 8) && dark(9, 9) && !dark(10, 10) && dark(12, 12));
+
+        assert!(render(&"x".repeat(3000), &options).is_err());
+        assert!(render("hello", &QrOptions { scale: 0, border: 4 }).is_err());
+    }
+
+    #[test]
+    fn test_render_too_large() {
+        assert!(render("hello", &QrOptions { scale: 1, border: u32::MAX / 2 + 1 }).is_err());
+        assert!(render("hello", &QrOptions { scale: 2, border: u32::MAX / 4 }).is_err());
+        assert!(render("hello", &QrOptions { scale: 64, border: 1024 }).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_content() {
+        assert_eq!(content("plain", None).unwrap(), "plain");
+
+        let text = content("scan me", Some(&Encryption::Key([7; 32]))).unwrap();
+        assert!(text.starts_with(ENCRYPTED_PREFIX));
+        assert_eq!(decrypt(&text, Some(&Secret::Key([7; 32]))).unwrap(), b"scan me");
+        assert!(decrypt(&text, Some(&Secret::Key([8; 32]))).is_err());
+        assert!(decrypt("plain", None).is_err());
+    }
+}
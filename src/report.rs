@@ -0,0 +1,336 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+use crate::envelope::{self, Location};
+use crate::image::ImageHeader;
+use crate::png::Png;
+use crate::scan::{self, Finding};
+use crate::sniff;
+
+/// The document formats of a forensic report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocumentFormat {
+    #[default]
+    Markdown,
+    /// A standalone HTML page
+    Html,
+}
+
+impl FromStr for DocumentFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "md" | "markdown" => Ok(DocumentFormat::Markdown),
+            "html" => Ok(DocumentFormat::Html),
+            _ => Err(format!("unknown format '{}' (expected md or html)", s)),
+        }
+    }
+}
+
+/// A row of the chunk table of a report
+#[derive(Debug, Clone)]
+pub struct ChunkRow {
+    pub index: usize,
+    /// The offset of the chunk (its length field) in the file
+    pub offset: usize,
+    pub chunk_type: String,
+    pub length: u32,
+    pub crc: u32,
+    /// SHA-256 of the chunk data
+    pub sha256: String,
+    /// Shannon entropy of the chunk data in bits per byte (0 to 8)
+    pub entropy: f64,
+}
+
+/// A pngme payload found in the file
+#[derive(Debug, Clone)]
+pub struct PayloadRow {
+    pub location: String,
+    /// The payload size, or why the payload couldn't be read
+    pub size: std::result::Result<usize, String>,
+    pub mime_type: Option<&'static str>,
+}
+
+/// Everything a forensic report says about a PNG file
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub file_name: String,
+    pub file_size: usize,
+    pub sha256: String,
+    pub header: Option<ImageHeader>,
+    pub chunks: Vec<ChunkRow>,
+    pub trailer: Option<(usize, usize, f64)>,
+    pub findings: Vec<Finding>,
+    pub payloads: Vec<PayloadRow>,
+}
+
+impl Report {
+    /// Analyzes `png`, parsed from `bytes`
+    pub fn new(file_name: &str, bytes: &[u8], png: &Png) -> Report {
+        let mut offset = png.header().len();
+        let chunks = png.chunks().iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let row = ChunkRow {
+                    index,
+                    offset,
+                    chunk_type: chunk.chunk_type().to_string(),
+                    length: chunk.length(),
+                    crc: chunk.crc(),
+                    sha256: sha256_hex(chunk.data()),
+                    entropy: entropy(chunk.data()),
+                };
+                offset += chunk.length() as usize + 12;
+                row
+            })
+            .collect();
+
+        let trailer = png.trailer();
+        let payloads = envelope::find_all(png).into_iter()
+            .map(|(location, envelope)| PayloadRow {
+                location: match location {
+                    Location::Chunk(index) => format!("{} chunk #{}", png.chunks()[index].chunk_type(), index),
+                    Location::Trailer => "data after IEND".to_owned(),
+                },
+                mime_type: envelope.as_ref().ok().and_then(|envelope| sniff::detect(envelope.payload())).map(|kind| kind.mime_type()),
+                size: envelope.map(|envelope| envelope.payload().len()).map_err(|e| e.to_string()),
+            })
+            .collect();
+
+        Report {
+            file_name: file_name.to_owned(),
+            file_size: bytes.len(),
+            sha256: sha256_hex(bytes),
+            header: ImageHeader::from_png(png).ok(),
+            chunks,
+            trailer: (!trailer.is_empty()).then(|| (offset, trailer.len(), entropy(trailer))),
+            findings: scan::scan(png),
+            payloads,
+        }
+    }
+
+    /// Renders this report as Markdown
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail
+        let _ = self.write_markdown(&mut out);
+        out
+    }
+
+    fn write_markdown(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "# pngme report: {}\n", self.file_name)?;
+        writeln!(out, "## File\n")?;
+        writeln!(out, "- Size: {} bytes", self.file_size)?;
+        writeln!(out, "- SHA-256: `{}`", self.sha256)?;
+        for (name, value) in self.header_fields() {
+            writeln!(out, "- {}: {}", name, value)?;
+        }
+
+        writeln!(out, "\n## Chunks\n")?;
+        writeln!(out, "| # | Offset | Type | Length | CRC | Entropy | SHA-256 |")?;
+        writeln!(out, "|---|---|---|---|---|---|---|")?;
+        for row in &self.chunks {
+            writeln!(
+                out,
+                "| {} | {} | {} | {} | {:08x} | {:.2} | `{}` |",
+                row.index, row.offset, row.chunk_type, row.length, row.crc, row.entropy, row.sha256,
+            )?;
+        }
+        if let Some((offset, size, entropy)) = self.trailer {
+            writeln!(out, "\n{} bytes after IEND at offset {} (entropy {:.2})", size, offset, entropy)?;
+        }
+
+        writeln!(out, "\n## Findings\n")?;
+        if self.findings.is_empty() {
+            writeln!(out, "No hidden data found.")?;
+        }
+        for finding in &self.findings {
+            writeln!(out, "- **{}**: {}", finding.kind, finding)?;
+        }
+
+        writeln!(out, "\n## pngme payloads\n")?;
+        if self.payloads.is_empty() {
+            writeln!(out, "No pngme payload found.")?;
+        }
+        for payload in &self.payloads {
+            writeln!(out, "- {}: {}", payload.location, payload.summary())?;
+        }
+        Ok(())
+    }
+
+    /// Renders this report as a standalone HTML page
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        let _ = self.write_html(&mut out);
+        out
+    }
+
+    fn write_html(&self, out: &mut String) -> std::fmt::Result {
+        let title = format!("pngme report: {}", escape_html(&self.file_name));
+        writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>", title)?;
+        writeln!(out, "<style>body {{ font-family: sans-serif; }} table {{ border-collapse: collapse; }} \
+            td, th {{ border: 1px solid #999; padding: 2px 6px; }} code {{ font-size: 0.85em; }}</style>")?;
+        writeln!(out, "</head>\n<body>\n<h1>{}</h1>", title)?;
+
+        writeln!(out, "<h2>File</h2>\n<ul>")?;
+        writeln!(out, "<li>Size: {} bytes</li>", self.file_size)?;
+        writeln!(out, "<li>SHA-256: <code>{}</code></li>", self.sha256)?;
+        for (name, value) in self.header_fields() {
+            writeln!(out, "<li>{}: {}</li>", name, value)?;
+        }
+        writeln!(out, "</ul>")?;
+
+        writeln!(out, "<h2>Chunks</h2>\n<table>")?;
+        writeln!(out, "<tr><th>#</th><th>Offset</th><th>Type</th><th>Length</th><th>CRC</th><th>Entropy</th><th>SHA-256</th></tr>")?;
+        for row in &self.chunks {
+            writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:08x}</td><td>{:.2}</td><td><code>{}</code></td></tr>",
+                row.index, row.offset, row.chunk_type, row.length, row.crc, row.entropy, row.sha256,
+            )?;
+        }
+        writeln!(out, "</table>")?;
+        if let Some((offset, size, entropy)) = self.trailer {
+            writeln!(out, "<p>{} bytes after IEND at offset {} (entropy {:.2})</p>", size, offset, entropy)?;
+        }
+
+        writeln!(out, "<h2>Findings</h2>")?;
+        if self.findings.is_empty() {
+            writeln!(out, "<p>No hidden data found.</p>")?;
+        } else {
+            writeln!(out, "<ul>")?;
+            for finding in &self.findings {
+                writeln!(out, "<li><strong>{}</strong>: {}</li>", finding.kind, escape_html(&finding.to_string()))?;
+            }
+            writeln!(out, "</ul>")?;
+        }
+
+        writeln!(out, "<h2>pngme payloads</h2>")?;
+        if self.payloads.is_empty() {
+            writeln!(out, "<p>No pngme payload found.</p>")?;
+        } else {
+            writeln!(out, "<ul>")?;
+            for payload in &self.payloads {
+                writeln!(out, "<li>{}: {}</li>", escape_html(&payload.location), escape_html(&payload.summary()))?;
+            }
+            writeln!(out, "</ul>")?;
+        }
+        writeln!(out, "</body>\n</html>")
+    }
+
+    fn header_fields(&self) -> Vec<(&'static str, String)> {
+        match &self.header {
+            Some(header) => vec![
+                ("Dimensions", format!("{}x{}", header.width, header.height)),
+                ("Bit depth", header.bit_depth.to_string()),
+                ("Color type", header.color_type.to_string()),
+                ("Interlaced", (header.interlace_method != 0).to_string()),
+            ],
+            None => vec![("Image header", "missing or invalid".to_owned())],
+        }
+    }
+}
+
+impl PayloadRow {
+    fn summary(&self) -> String {
+        match (&self.size, self.mime_type) {
+            (Ok(size), Some(mime_type)) => format!("{} bytes ({})", size, mime_type),
+            (Ok(size), None) => format!("{} bytes", size),
+            (Err(e), _) => format!("damaged ({})", e),
+        }
+    }
+}
+
+/// Hex encoded SHA-256 of `bytes`
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Shannon entropy of `bytes` in bits per byte: close to 8 for compressed or encrypted data
+pub fn entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0usize; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+
+    let len = bytes.len() as f64;
+    counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::envelope::Envelope;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    fn report() -> Report {
+        let mut png = Png::try_from(DICE).unwrap();
+        let payload = Envelope::new(b"secret".to_vec()).as_bytes();
+        png.insert_before_end(Chunk::new(ChunkType::from_str("ruSt").unwrap(), payload));
+        let bytes = png.as_bytes();
+        Report::new("<dice>.png", &bytes, &png)
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(&[]).to_string(), "0");
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert_eq!(entropy(&(0..=255).collect::<Vec<u8>>()), 8.0);
+    }
+
+    #[test]
+    fn test_report() {
+        let report = report();
+
+        assert_eq!(report.chunks[0].offset, 8);
+        assert_eq!(report.chunks[1].offset, 8 + 12 + 13);
+        assert_eq!(report.header.as_ref().unwrap().width, 671);
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.payloads.len(), 1);
+        assert_eq!(report.payloads[0].size, Ok(6));
+    }
+
+    #[test]
+    fn test_markdown() {
+        let markdown = report().to_markdown();
+
+        assert!(markdown.starts_with("# pngme report: <dice>.png\n"));
+        assert!(markdown.contains("| 0 | 8 | IHDR | 13 |"));
+        assert!(markdown.contains("- **private-chunks**: private ancillary chunk ruSt"));
+        assert!(markdown.contains("ruSt chunk #"));
+    }
+
+    #[test]
+    fn test_html() {
+        let html = report().to_html();
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>pngme report: &lt;dice&gt;.png</title>"));
+        assert!(html.contains("<td>IHDR</td>"));
+        assert!(html.trim_end().ends_with("</html>"));
+    }
+}
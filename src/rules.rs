@@ -0,0 +1,200 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::chunk_type::ChunkType;
+use crate::envelope::Location;
+use crate::png::Png;
+use crate::Result;
+
+/// Where a rule looks for its pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// The data of every chunk and the data after `IEND` (`*`)
+    Everywhere,
+    /// The data after `IEND` (`eof`)
+    Trailer,
+    /// The data of the chunks of these types (e.g. `ruSt,tEXt`)
+    Chunks(Vec<ChunkType>),
+}
+
+impl FromStr for Scope {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "*" => Ok(Scope::Everywhere),
+            "eof" => Ok(Scope::Trailer),
+            _ => Ok(Scope::Chunks(s.split(',').map(ChunkType::from_str).collect::<std::result::Result<_, _>>()?)),
+        }
+    }
+}
+
+/// A user-supplied pattern `scan` looks for
+#[derive(Debug, Clone)]
+pub struct Rule {
+    name: String,
+    scope: Scope,
+    pattern: Regex,
+}
+
+/// Where a rule matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleMatch {
+    /// The name of the rule
+    pub name: String,
+    pub location: Location,
+    /// The offset of the match in the data of the chunk or after `IEND`
+    pub offset: usize,
+}
+
+impl Rule {
+    /// The name of this rule
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns where this rule matches in `png`, at most once per chunk
+    pub fn find_in(&self, png: &Png) -> Vec<RuleMatch> {
+        let chunks = png.chunks().iter()
+            .enumerate()
+            .filter(|(_, chunk)| match &self.scope {
+                Scope::Everywhere => true,
+                Scope::Trailer => false,
+                Scope::Chunks(types) => types.contains(chunk.chunk_type()),
+            })
+            .map(|(index, chunk)| (Location::Chunk(index), chunk.data()));
+        let trailer = matches!(self.scope, Scope::Everywhere | Scope::Trailer)
+            .then_some((Location::Trailer, png.trailer()));
+
+        chunks.chain(trailer)
+            .filter_map(|(location, data)| {
+                let found = self.pattern.find(data)?;
+                Some(RuleMatch { name: self.name.clone(), location, offset: found.start() })
+            })
+            .collect()
+    }
+}
+
+/// Parses a rule line: a name, a scope and a pattern separated by whitespace, e.g.
+/// `beacon ruSt,tEXt regex:beacon-[0-9a-f]{8}` or `zip eof hex:50 4b ?? 04`.
+/// Hex patterns may use `??` for any byte; regex patterns match bytes, not UTF-8 text.
+impl FromStr for Rule {
+    type Err = crate::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let fields = line.trim()
+            .split_once(char::is_whitespace)
+            .and_then(|(name, rest)| Some((name, rest.trim_start().split_once(char::is_whitespace)?)));
+        let Some((name, (scope, pattern))) = fields else {
+            return Err(format!("expected a name, a scope and a pattern: '{}'", line).into());
+        };
+
+        let pattern = pattern.trim();
+        let regex = if let Some(hex) = pattern.strip_prefix("hex:") {
+            hex_to_regex(hex)?
+        } else if let Some(regex) = pattern.strip_prefix("regex:") {
+            regex.to_owned()
+        } else {
+            return Err(format!("patterns start with hex: or regex: ('{}')", pattern).into());
+        };
+
+        Ok(Rule {
+            name: name.to_owned(),
+            scope: scope.parse()?,
+            pattern: RegexBuilder::new(&regex).unicode(false).build()?,
+        })
+    }
+}
+
+/// Turns hex bytes (with `??` wildcards) into a byte regex
+fn hex_to_regex(hex: &str) -> Result<String> {
+    let digits: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("invalid hex pattern '{}'", hex).into());
+    }
+
+    digits.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            b"??" => Ok("(?s:.)".to_owned()),
+            _ => {
+                let byte = u8::from_str_radix(std::str::from_utf8(pair)?, 16)
+                    .map_err(|_| format!("invalid hex pattern '{}'", hex))?;
+                Ok(format!("\\x{:02x}", byte))
+            }
+        })
+        .collect()
+}
+
+/// Parses a rules file: one rule per line, empty lines and lines starting with `#` are ignored
+pub fn parse_rules(rules: &str) -> Result<Vec<Rule>> {
+    rules.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(number, line)| line.parse().map_err(|e| format!("line {}: {}", number + 1, e).into()))
+        .collect()
+}
+
+/// Reads a rules file, see `parse_rules`
+pub fn read_rules<P: AsRef<Path>>(path: P) -> Result<Vec<Rule>> {
+    parse_rules(&fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    fn png() -> Png {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.insert_before_end(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"xx beacon-0badc0de".to_vec()));
+        png.set_trailer(vec![0x50, 0x4b, 0x03, 0x04, 0x14]);
+        png
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse_rules("
+            # indicators
+            beacon ruSt,tEXt regex:beacon-[0-9a-f]{8}
+
+            zip    eof       hex:50 4b ?? 04
+        ").unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].name(), "beacon");
+        assert_eq!(rules[1].scope, Scope::Trailer);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(parse_rules("beacon ruSt").is_err());
+        assert!(parse_rules("beacon ruSt text:beacon").is_err());
+        assert!(parse_rules("beacon ruSt hex:5").is_err());
+        assert!(parse_rules("beacon ruSt hex:zz").is_err());
+        assert!(parse_rules("beacon r2St regex:beacon").is_err());
+        assert!(parse_rules("beacon * regex:(").unwrap_err().to_string().starts_with("line 1"));
+    }
+
+    #[test]
+    fn test_find_in() {
+        let png = png();
+        let rules = parse_rules("beacon ruSt regex:beacon-[0-9a-f]{8}\nzip * hex:50 4b ?? 04\nnone tEXt regex:beacon").unwrap();
+
+        let beacon = rules[0].find_in(&png);
+        assert_eq!(beacon.len(), 1);
+        assert_eq!(beacon[0].location, Location::Chunk(png.chunks().len() - 2));
+        assert_eq!(beacon[0].offset, 3);
+
+        let zip = rules[1].find_in(&png);
+        assert_eq!(zip, [RuleMatch { name: "zip".to_owned(), location: Location::Trailer, offset: 0 }]);
+
+        assert!(rules[2].find_in(&png).is_empty());
+    }
+}
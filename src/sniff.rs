@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+
+/// Detects the file type of a payload from its content, e.g. `image/jpeg` for a JPEG image
+pub fn detect(payload: &[u8]) -> Option<infer::Type> {
+    infer::get(payload)
+}
+
+/// Appends the extension of the detected file type to `path` if it doesn't have an extension yet
+pub fn with_extension<P: AsRef<Path>>(path: P, payload: &[u8]) -> PathBuf {
+    let path = path.as_ref();
+    match detect(payload) {
+        Some(kind) if path.extension().is_none() => path.with_extension(kind.extension()),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Detects executable code from its magic number: `PE` and `MZ` (Windows and DOS), `ELF`,
+/// `Mach-O` and `script` (a `#!` interpreter line)
+pub fn executable(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [b'M', b'Z', ..] => {
+            // The PE header offset is stored at 0x3c in the DOS header
+            let pe_offset = data.get(0x3c..0x40).map(|offset| u32::from_le_bytes(offset.try_into().unwrap()) as usize);
+            let is_pe = pe_offset.and_then(|offset| data.get(offset..offset.checked_add(4)?)) == Some(b"PE\0\0");
+            Some(if is_pe { "PE" } else { "MZ" })
+        }
+        [0x7f, b'E', b'L', b'F', ..] => Some("ELF"),
+        [0xfe, 0xed, 0xfa, 0xce | 0xcf, ..] | [0xce | 0xcf, 0xfa, 0xed, 0xfe, ..] | [0xca, 0xfe, 0xba, 0xbe, ..] => Some("Mach-O"),
+        [b'#', b'!', b'/', ..] | [b'#', b'!', b' ', b'/', ..] => Some("script"),
+        _ => None,
+    }
+}
+
+/// Detects data another file format's reader would accept, which makes a PNG file holding it
+/// a polyglot: `ZIP` (an end of central directory record), `JAR` (a ZIP archive with a
+/// manifest) or `PDF` (a `%PDF-` header followed by `%%EOF`)
+pub fn polyglot(data: &[u8]) -> Option<&'static str> {
+    if find(data, b"PK\x05\x06").is_some() {
+        return Some(if find(data, b"META-INF/MANIFEST.MF").is_some() { "JAR" } else { "ZIP" });
+    }
+    let pdf = find(data, b"%PDF-")?;
+    find(&data[pdf..], b"%%EOF").map(|_| "PDF")
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(DICE).unwrap().mime_type(), "image/png");
+        assert!(detect(b"plain text").is_none());
+    }
+
+    #[test]
+    fn test_with_extension() {
+        assert_eq!(with_extension("payload", DICE), PathBuf::from("payload.png"));
+        assert_eq!(with_extension("payload.bin", DICE), PathBuf::from("payload.bin"));
+        assert_eq!(with_extension("payload", b"plain text"), PathBuf::from("payload"));
+    }
+
+    #[test]
+    fn test_executable() {
+        let mut pe = vec![0; 0x80];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c] = 0x40;
+        pe[0x40..0x44].copy_from_slice(b"PE\0\0");
+
+        assert_eq!(executable(&pe), Some("PE"));
+        assert_eq!(executable(b"MZ\x90\x00"), Some("MZ"));
+        assert_eq!(executable(b"\x7fELF\x02\x01"), Some("ELF"));
+        assert_eq!(executable(&[0xcf, 0xfa, 0xed, 0xfe, 7]), Some("Mach-O"));
+        assert_eq!(executable(b"#!/bin/sh\necho hi"), Some("script"));
+        assert_eq!(executable(b"#!not a script"), None);
+        assert_eq!(executable(DICE), None);
+    }
+
+    #[test]
+    fn test_polyglot() {
+        assert_eq!(polyglot(b"PK\x03\x04 ... PK\x05\x06\0\0"), Some("ZIP"));
+        assert_eq!(polyglot(b"PK\x03\x04META-INF/MANIFEST.MF PK\x05\x06"), Some("JAR"));
+        assert_eq!(polyglot(b"%PDF-1.7\n1 0 obj\n%%EOF\n"), Some("PDF"));
+        assert_eq!(polyglot(b"%%EOF %PDF-1.7"), None);
+        assert_eq!(polyglot(DICE), None);
+    }
+}
@@ -0,0 +1,1018 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::{Envelope, Manifest, Replica};
+use crate::image::RawImage;
+use crate::png::Png;
+use crate::Result;
+
+/// A technique for hiding a payload inside a `Png`
+pub trait StegoBackend {
+    /// The maximum payload size in bytes that can be hidden in `png`
+    fn capacity(&self, png: &Png) -> Result<usize>;
+
+    /// Hides `payload` inside `png`
+    fn embed(&self, png: &mut Png, payload: &[u8]) -> Result<()>;
+
+    /// Recovers a payload previously hidden by `embed`
+    fn extract(&self, png: &Png) -> Result<Vec<u8>>;
+
+    /// Removes a payload hidden by `embed`. Modes hiding it in the pixels can't restore the
+    /// original samples, they overwrite the payload bits with zeros.
+    fn remove(&self, png: &mut Png) -> Result<()>;
+
+    /// Replaces the payload hidden by `embed` with `payload`. The old payload is removed and
+    /// the new one embedded, unless the backend can rewrite it where it is stored.
+    fn update(&self, png: &mut Png, payload: &[u8]) -> Result<()> {
+        self.remove(png)?;
+        self.embed(png, payload)
+    }
+
+    /// True if `embed` changes the image data (the content of the `IDAT` chunks)
+    fn modifies_image_data(&self) -> bool {
+        false
+    }
+}
+
+/// The available steganography backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A dedicated chunk of the given type
+    Chunk,
+    /// The least significant bits of every pixel sample
+    Lsb,
+    /// The least significant bits of the alpha samples only
+    Alpha,
+    /// The bytes after the `IEND` chunk
+    Eof,
+    /// Several small chunks of the given type placed around the `IDAT` chunks
+    Interleave,
+}
+
+impl Mode {
+    /// Creates the backend for this mode. `chunk_type` is only used by the chunk based modes.
+    pub fn backend(&self, chunk_type: &str) -> Result<Box<dyn StegoBackend>> {
+        Ok(match self {
+            Mode::Chunk => Box::new(ChunkBackend::new(ChunkType::from_str(chunk_type)?)),
+            Mode::Lsb => Box::new(LsbBackend::default()),
+            Mode::Alpha => Box::new(AlphaBackend::default()),
+            Mode::Eof => Box::new(EofBackend),
+            Mode::Interleave => Box::new(InterleavedBackend::new(ChunkType::from_str(chunk_type)?)),
+        })
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "chunk" => Ok(Mode::Chunk),
+            "lsb" => Ok(Mode::Lsb),
+            "alpha" => Ok(Mode::Alpha),
+            "eof" => Ok(Mode::Eof),
+            "interleave" => Ok(Mode::Interleave),
+            _ => Err(format!("unknown mode '{}' (expected chunk, lsb, alpha, eof or interleave)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Mode::Chunk => "chunk",
+            Mode::Lsb => "lsb",
+            Mode::Alpha => "alpha",
+            Mode::Eof => "eof",
+            Mode::Interleave => "interleave",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Stores the payload as the data of a new chunk appended to the `Png`.
+/// With more than one copy, every copy is stored in a chunk of a different type derived
+/// from `chunk_type` (see [`ChunkBackend::copy_type`]), and extracting falls back to the
+/// next copy when one is missing or corrupted. The copies after the first are wrapped in a
+/// [`Replica`] envelope, so chunks of those types that aren't copies of the payload are
+/// left alone.
+///
+/// A large payload can also be split into several chunks of the same type, behind a
+/// [`Manifest`] describing them (see [`ChunkBackend::with_pieces`]). Extracting reassembles
+/// it transparently.
+#[derive(Debug, Clone)]
+pub struct ChunkBackend {
+    chunk_type: ChunkType,
+    copies: usize,
+    pieces: usize,
+}
+
+/// Where a copy of a `ChunkBackend` payload is stored
+struct StoredCopy {
+    /// The index of the first chunk of the copy, the manifest if it is split
+    position: usize,
+    /// The number of copies recorded in a replica
+    count: Option<usize>,
+    /// The data of the first chunk, unwrapped from its replica envelope
+    data: Vec<u8>,
+}
+
+impl ChunkBackend {
+    /// The maximum number of copies, one per letter of the last character of the chunk type
+    pub const MAX_COPIES: usize = 26;
+
+    pub fn new(chunk_type: ChunkType) -> ChunkBackend {
+        ChunkBackend { chunk_type, copies: 1, pieces: 1 }
+    }
+
+    /// Creates a backend storing `copies` identical copies of the payload
+    pub fn with_copies(chunk_type: ChunkType, copies: usize) -> Result<ChunkBackend> {
+        if copies == 0 || copies > Self::MAX_COPIES {
+            return Err(format!("the number of copies must be between 1 and {}", Self::MAX_COPIES).into());
+        }
+        Ok(ChunkBackend { chunk_type, copies, pieces: 1 })
+    }
+
+    /// Splits every copy of the payload into `pieces` chunks following a manifest chunk
+    pub fn with_pieces(self, pieces: usize) -> Result<ChunkBackend> {
+        if pieces == 0 || pieces > Manifest::MAX_PIECES {
+            return Err(format!("the number of chunks must be between 1 and {}", Manifest::MAX_PIECES).into());
+        }
+        Ok(ChunkBackend { pieces, ..self })
+    }
+
+    /// The chunk type of the copy at `index`: the last letter of the chunk type is advanced
+    /// by `index` (wrapping from z to a), keeping its case so the chunk properties don't change.
+    /// The first copy uses the chunk type itself.
+    pub fn copy_type(&self, index: usize) -> Result<ChunkType> {
+        let mut bytes = self.chunk_type.bytes();
+        let base = if bytes[3].is_ascii_uppercase() { b'A' } else { b'a' };
+        let offset = (bytes[3] - base) as usize + index;
+        bytes[3] = base + (offset % Self::MAX_COPIES) as u8;
+        Ok(ChunkType::try_from(bytes)?)
+    }
+
+    /// Finds the copy at `index`: the first chunk of the chunk type for the first copy, the
+    /// first chunk of its copy type holding the replica with this index for the others
+    fn find_copy(&self, png: &Png, index: usize) -> Result<Option<StoredCopy>> {
+        let chunk_type = self.copy_type(index)?;
+        let mut chunks = png.chunks().iter()
+            .enumerate()
+            .filter(|(_, chunk)| chunk.chunk_type() == &chunk_type);
+        if index == 0 {
+            return Ok(chunks.next().map(|(position, chunk)| StoredCopy { position, count: None, data: chunk.data().to_vec() }));
+        }
+
+        Ok(chunks.find_map(|(position, chunk)| {
+            let replica = Envelope::from_bytes(chunk.data())
+                .and_then(|envelope| Replica::from_envelope(&envelope))
+                .ok()
+                .filter(|replica| replica.index() == index)?;
+            Some(StoredCopy { position, count: Some(replica.count()), data: replica.into_data() })
+        }))
+    }
+
+    /// Returns the payload of a copy found by `find_copy`, reassembled from the chunks of the
+    /// same type following it if it is split
+    fn read_copy(&self, png: &Png, index: usize, copy: &StoredCopy) -> Result<Vec<u8>> {
+        let chunk_type = self.copy_type(index)?;
+        let rest = png.chunks()[copy.position + 1..].iter()
+            .filter(|chunk| chunk.chunk_type() == &chunk_type)
+            .map(Chunk::data);
+        reassemble(&copy.data, rest)
+    }
+
+    /// The positions, in ascending order, of the chunks storing the payload: the chunk of each
+    /// copy and, if it is split, the piece chunks following its manifest. Other chunks of the
+    /// copy types aren't included.
+    pub fn stored_positions(&self, png: &Png) -> Result<Vec<usize>> {
+        let mut positions = Vec::new();
+        for index in 0..Self::MAX_COPIES {
+            let Some(copy) = self.find_copy(png, index)? else { continue };
+            positions.push(copy.position);
+
+            let pieces = match Envelope::from_bytes(&copy.data) {
+                Ok(envelope) if envelope.is_manifest() => Manifest::from_envelope(&envelope)?.count() as usize,
+                _ => 0,
+            };
+            let chunk_type = self.copy_type(index)?;
+            positions.extend(png.chunks().iter()
+                .enumerate()
+                .skip(copy.position + 1)
+                .filter(|(_, chunk)| chunk.chunk_type() == &chunk_type)
+                .map(|(position, _)| position)
+                .take(pieces));
+        }
+
+        positions.sort_unstable();
+        positions.dedup();
+        Ok(positions)
+    }
+}
+
+impl StegoBackend for ChunkBackend {
+    fn capacity(&self, _png: &Png) -> Result<usize> {
+        Ok(i32::MAX as usize)
+    }
+
+    fn embed(&self, png: &mut Png, payload: &[u8]) -> Result<()> {
+        for index in 0..self.copies {
+            let chunk_type = self.copy_type(index)?;
+            let (first, pieces) = if self.pieces == 1 {
+                (payload.to_vec(), Vec::new())
+            } else {
+                let (manifest, pieces) = Manifest::split(payload, self.pieces)?;
+                (manifest.to_envelope().as_bytes(), pieces)
+            };
+            let first = match index {
+                0 => first,
+                _ => Replica::new(index, self.copies, first)?.to_envelope().as_bytes(),
+            };
+
+            png.append_chunk(Chunk::new(chunk_type, first));
+            for piece in pieces {
+                png.append_chunk(Chunk::new(chunk_type, piece));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the first copy holding an intact payload envelope. Files written with any
+    /// number of copies can be read: the replicas record the number of copies, and only the
+    /// chunks holding a replica of the expected index are read. If no copy is an envelope,
+    /// the data of the first copy found is returned as is.
+    fn extract(&self, png: &Png) -> Result<Vec<u8>> {
+        let mut found = Vec::new();
+        let mut count = Self::MAX_COPIES;
+        for index in 0..Self::MAX_COPIES {
+            if index >= count {
+                break;
+            }
+            let Some(copy) = self.find_copy(png, index)? else { continue };
+            count = copy.count.unwrap_or(count);
+            let data = self.read_copy(png, index, &copy);
+            if data.as_ref().is_ok_and(|data| Envelope::from_bytes(data).is_ok()) {
+                return data;
+            }
+            found.push(data);
+        }
+
+        found.into_iter()
+            .next()
+            .unwrap_or_else(|| Err(png.chunk_not_found(&self.chunk_type.to_string())))
+    }
+
+    /// Removes every copy of the payload, see `stored_positions`
+    fn remove(&self, png: &mut Png) -> Result<()> {
+        for position in self.stored_positions(png)?.into_iter().rev() {
+            png.chunks_mut().remove(position);
+        }
+        Ok(())
+    }
+
+    /// Rewrites the data of the chunk storing the payload in place, keeping its position, when
+    /// it is stored in a single chunk and written the same way
+    fn update(&self, png: &mut Png, payload: &[u8]) -> Result<()> {
+        let positions = self.stored_positions(png)?;
+        if let ([position], 1, 1) = (&positions[..], self.copies, self.pieces) {
+            png.chunks_mut()[*position] = Chunk::new(self.chunk_type, payload.to_vec());
+            return Ok(());
+        }
+        self.remove(png)?;
+        self.embed(png, payload)
+    }
+}
+
+/// Returns the payload stored in the first chunk of a copy, reassembling it from the chunks
+/// that follow when the first one holds a [`Manifest`]
+fn reassemble<'a>(first: &[u8], rest: impl Iterator<Item = &'a [u8]>) -> Result<Vec<u8>> {
+    match Envelope::from_bytes(first) {
+        Ok(envelope) if envelope.is_manifest() => Manifest::from_envelope(&envelope)?.combine(rest),
+        _ => Ok(first.to_vec()),
+    }
+}
+
+/// Slices the payload into several small chunks placed around the `IDAT` chunks, half of them
+/// right before the first `IDAT` and the rest right after the last one, so the file doesn't end
+/// with one big private chunk. The `IDAT` chunks stay consecutive, as the PNG spec requires.
+#[derive(Debug, Clone)]
+pub struct InterleavedBackend {
+    chunk_type: ChunkType,
+}
+
+impl InterleavedBackend {
+    /// The number of chunks the payload is sliced into
+    const PIECES: usize = 4;
+
+    pub fn new(chunk_type: ChunkType) -> InterleavedBackend {
+        InterleavedBackend { chunk_type }
+    }
+}
+
+impl StegoBackend for InterleavedBackend {
+    fn capacity(&self, _png: &Png) -> Result<usize> {
+        Ok(i32::MAX as usize)
+    }
+
+    fn embed(&self, png: &mut Png, payload: &[u8]) -> Result<()> {
+        let idat_type = ChunkType::IDAT;
+        let is_idat = |chunk: &Chunk| chunk.chunk_type() == &idat_type;
+        let first_idat = png.chunks().iter().position(is_idat).ok_or("image has no IDAT chunk")?;
+        let last_idat = png.chunks().iter().rposition(is_idat).unwrap();
+
+        let size = payload.len().div_ceil(Self::PIECES).max(1);
+        let mut pieces: Vec<Chunk> = payload.chunks(size)
+            .map(|piece| Chunk::new(self.chunk_type, piece.to_vec()))
+            .collect();
+        let after = pieces.split_off(pieces.len() / 2);
+
+        let chunks = png.chunks_mut();
+        chunks.splice(last_idat + 1..last_idat + 1, after);
+        chunks.splice(first_idat..first_idat, pieces);
+        Ok(())
+    }
+
+    fn extract(&self, png: &Png) -> Result<Vec<u8>> {
+        let pieces: Vec<&Chunk> = png.chunks_by_type(&self.chunk_type).collect();
+
+        if pieces.is_empty() {
+            return Err(png.chunk_not_found(&self.chunk_type.to_string()));
+        }
+        Ok(pieces.into_iter().flat_map(|chunk| chunk.data().iter().copied()).collect())
+    }
+
+    fn remove(&self, png: &mut Png) -> Result<()> {
+        png.chunks_mut().retain(|chunk| chunk.chunk_type() != &self.chunk_type);
+        Ok(())
+    }
+}
+
+/// Stores the payload in the lowest bits of every pixel sample: one by default, up to
+/// `MAX_BITS` to fit more data at the cost of more visible changes. Adaptive backends only use
+/// the samples of textured regions (see `textured_positions`). A payload must be extracted with
+/// the settings it was embedded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LsbBackend {
+    bits: u8,
+    adaptive: bool,
+}
+
+impl LsbBackend {
+    pub const MAX_BITS: u8 = 4;
+
+    /// Uses the lowest `bits` bits of every sample, from 1 to `MAX_BITS`
+    pub fn with_bits(bits: u8) -> Result<LsbBackend> {
+        if !(1..=Self::MAX_BITS).contains(&bits) {
+            return Err(format!("can't use {} bits per sample, only 1 to {}", bits, Self::MAX_BITS).into());
+        }
+        Ok(LsbBackend { bits, adaptive: false })
+    }
+
+    /// Skips the flat regions of the image and fills the most textured ones first
+    pub fn adaptive(self, adaptive: bool) -> LsbBackend {
+        LsbBackend { adaptive, ..self }
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    /// Estimates how much hiding `payload_len` bytes in `png` changes the image
+    pub fn estimate(&self, png: &Png, payload_len: usize) -> Result<LsbEstimate> {
+        let image = RawImage::from_png(png)?;
+        let samples = sample_positions(&image, false)?.len();
+        let carriers = carrier_positions(&image, false, self.bits, self.adaptive)?.len();
+        let used = ((LENGTH_PREFIX + payload_len) * 8).div_ceil(self.bits as usize).min(carriers);
+
+        // The low bits of the original samples and of the payload are assumed to be random, so
+        // a changed sample differs by X - Y for X and Y uniform in 0..2^bits
+        let levels = 1u32 << self.bits;
+        let mean_squared_error = (levels * levels - 1) as f64 / 6.0 * used as f64 / samples.max(1) as f64;
+        let peak = ((1u64 << image.header().bit_depth) - 1) as f64;
+
+        Ok(LsbEstimate {
+            bits: self.bits,
+            capacity: bits_capacity(carriers * self.bits as usize),
+            payload_len,
+            max_change: levels - 1,
+            psnr: 10.0 * (peak * peak / mean_squared_error).log10(),
+        })
+    }
+}
+
+impl Default for LsbBackend {
+    fn default() -> Self {
+        LsbBackend { bits: 1, adaptive: false }
+    }
+}
+
+impl StegoBackend for LsbBackend {
+    fn capacity(&self, png: &Png) -> Result<usize> {
+        let image = RawImage::from_png(png)?;
+        Ok(bits_capacity(carrier_positions(&image, false, self.bits, self.adaptive)?.len() * self.bits as usize))
+    }
+
+    fn embed(&self, png: &mut Png, payload: &[u8]) -> Result<()> {
+        embed_bits(png, payload, false, self.bits, self.adaptive)
+    }
+
+    fn extract(&self, png: &Png) -> Result<Vec<u8>> {
+        extract_bits(png, false, self.bits, self.adaptive)
+    }
+
+    fn remove(&self, png: &mut Png) -> Result<()> {
+        clear_bits(png, false, self.bits, self.adaptive)
+    }
+
+    fn modifies_image_data(&self) -> bool {
+        true
+    }
+}
+
+/// Stores the payload in the least significant bit of the alpha samples, leaving colors
+/// untouched. Like `LsbBackend`, it can be made adaptive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlphaBackend {
+    adaptive: bool,
+}
+
+impl AlphaBackend {
+    /// Skips the flat regions of the alpha channel and fills the most textured ones first
+    pub fn adaptive(self, adaptive: bool) -> AlphaBackend {
+        AlphaBackend { adaptive }
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.adaptive
+    }
+}
+
+impl StegoBackend for AlphaBackend {
+    fn capacity(&self, png: &Png) -> Result<usize> {
+        let image = RawImage::from_png(png)?;
+        Ok(bits_capacity(carrier_positions(&image, true, 1, self.adaptive)?.len()))
+    }
+
+    fn embed(&self, png: &mut Png, payload: &[u8]) -> Result<()> {
+        embed_bits(png, payload, true, 1, self.adaptive)
+    }
+
+    fn extract(&self, png: &Png) -> Result<Vec<u8>> {
+        extract_bits(png, true, 1, self.adaptive)
+    }
+
+    fn remove(&self, png: &mut Png) -> Result<()> {
+        clear_bits(png, true, 1, self.adaptive)
+    }
+
+    fn modifies_image_data(&self) -> bool {
+        true
+    }
+}
+
+/// How much an `LsbBackend` payload changes an image, see `LsbBackend::estimate`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LsbEstimate {
+    pub bits: u8,
+    /// The largest payload that fits, in bytes
+    pub capacity: usize,
+    pub payload_len: usize,
+    /// The largest change of a sample value
+    pub max_change: u32,
+    /// The expected peak signal-to-noise ratio against the original image, in dB. Above
+    /// about 40 dB changes are invisible to the eye.
+    pub psnr: f64,
+}
+
+impl fmt::Display for LsbEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bit(s) per sample: {} of {} bytes used ({:.1}%), samples change by up to {}, expected PSNR {:.1} dB",
+            self.bits,
+            self.payload_len,
+            self.capacity,
+            self.payload_len as f64 * 100.0 / self.capacity.max(1) as f64,
+            self.max_change,
+            self.psnr,
+        )
+    }
+}
+
+/// Stores the payload after the `IEND` chunk, where PNG decoders never look
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EofBackend;
+
+impl StegoBackend for EofBackend {
+    fn capacity(&self, _png: &Png) -> Result<usize> {
+        Ok(usize::MAX)
+    }
+
+    fn embed(&self, png: &mut Png, payload: &[u8]) -> Result<()> {
+        png.set_trailer(payload.to_vec());
+        Ok(())
+    }
+
+    fn extract(&self, png: &Png) -> Result<Vec<u8>> {
+        if png.trailer().is_empty() {
+            return Err("no data after IEND".into());
+        }
+        Ok(png.trailer().to_vec())
+    }
+
+    fn remove(&self, png: &mut Png) -> Result<()> {
+        png.set_trailer(Vec::new());
+        Ok(())
+    }
+}
+
+/// The payload is prefixed with its length as a 4 byte big-endian integer
+const LENGTH_PREFIX: usize = 4;
+
+/// The number of payload bytes that fit into `samples` bits, after the length prefix
+fn bits_capacity(samples: usize) -> usize {
+    (samples / 8).saturating_sub(LENGTH_PREFIX)
+}
+
+/// The indices of the bytes in the image data whose lowest bits can carry the payload.
+/// For 16-bit images this is the low byte of every sample.
+fn sample_positions(image: &RawImage, alpha_only: bool) -> Result<Vec<usize>> {
+    let header = image.header();
+    let bytes_per_sample = match header.bit_depth {
+        8 => 1,
+        16 => 2,
+        _ => return Err("only 8 and 16 bit images are supported".into()),
+    };
+    if header.color_type == crate::image::ImageHeader::PALETTE {
+        return Err("palette images are not supported".into());
+    }
+    if alpha_only && !header.has_alpha() {
+        return Err("image has no alpha channel".into());
+    }
+
+    let channels = header.channels();
+    let positions = (0..image.data().len() / bytes_per_sample)
+        .filter(|sample| !alpha_only || sample % channels == channels - 1)
+        .map(|sample| sample * bytes_per_sample + bytes_per_sample - 1)
+        .collect();
+    Ok(positions)
+}
+
+/// The positions the payload bits go to, in order: every sample position, or only the textured
+/// ones when `adaptive`
+fn carrier_positions(image: &RawImage, alpha_only: bool, bits: u8, adaptive: bool) -> Result<Vec<usize>> {
+    let positions = sample_positions(image, alpha_only)?;
+    Ok(if adaptive { textured_positions(image, positions, bits) } else { positions })
+}
+
+/// Keeps the sample positions whose local variance is at least the square of the largest change
+/// embedding `bits` bits makes, most textured first. Changing the low bits of flat regions is
+/// easy to detect statistically, while textured regions already look noisy.
+///
+/// The local variance is taken over the same channel of the 3x3 pixels around a sample, with
+/// the lowest `bits` bits of every sample ignored, so embedding and removing a payload don't
+/// change it and the positions can be found again when extracting.
+fn textured_positions(image: &RawImage, positions: Vec<usize>, bits: u8) -> Vec<usize> {
+    let header = image.header();
+    let (width, height) = (header.width as usize, header.height as usize);
+    let channels = header.channels();
+    let bytes_per_sample = header.bit_depth as usize / 8;
+    let data = image.data();
+    let mask = !((1u8 << bits) - 1);
+    let value = |sample: usize| match bytes_per_sample {
+        1 => (data[sample] & mask) as u64,
+        _ => u16::from_be_bytes([data[sample * 2], data[sample * 2 + 1] & mask]) as u64,
+    };
+    let threshold = ((1u64 << bits) - 1).pow(2) as f64;
+
+    let mut textured: Vec<(usize, f64)> = positions.into_iter()
+        .filter_map(|position| {
+            let sample = position / bytes_per_sample;
+            let (pixel, channel) = (sample / channels, sample % channels);
+            let (x, y) = (pixel % width, pixel / width);
+            let (mut n, mut sum, mut squares) = (0u64, 0u64, 0u64);
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let v = value((ny * width + nx) * channels + channel);
+                    n += 1;
+                    sum += v;
+                    squares += v * v;
+                }
+            }
+            let variance = (n * squares - sum * sum) as f64 / (n * n) as f64;
+            (variance >= threshold).then_some((position, variance))
+        })
+        .collect();
+    textured.sort_by(|a, b| b.1.total_cmp(&a.1));
+    textured.into_iter().map(|(position, _)| position).collect()
+}
+
+/// Writes the length prefix and the payload, most significant bit first, into the lowest
+/// `bits` bits of the samples
+fn embed_bits(png: &mut Png, payload: &[u8], alpha_only: bool, bits: u8, adaptive: bool) -> Result<()> {
+    let mut image = RawImage::from_png(png)?;
+    let positions = carrier_positions(&image, alpha_only, bits, adaptive)?;
+    let capacity = bits_capacity(positions.len() * bits as usize);
+    if payload.len() > capacity {
+        return Err(format!(
+            "the payload ({} bytes) is larger than the image capacity ({} bytes with {} bit(s) per sample)",
+            payload.len(),
+            capacity,
+            bits,
+        ).into());
+    }
+
+    let length = (payload.len() as u32).to_be_bytes();
+    let mut stream = length.iter()
+        .chain(payload)
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+
+    let data = image.data_mut();
+    for position in positions {
+        // The last sample may only get some of its bits, the others are left as they are
+        let (value, taken) = stream.by_ref().take(bits as usize).fold((0u8, 0), |(value, taken), bit| ((value << 1) | bit, taken + 1));
+        if taken == 0 {
+            break;
+        }
+        let shift = bits - taken;
+        let mask = ((1u8 << taken) - 1) << shift;
+        data[position] = (data[position] & !mask) | (value << shift);
+    }
+
+    image.write_to_png(png)
+}
+
+fn extract_bits(png: &Png, alpha_only: bool, bits: u8, adaptive: bool) -> Result<Vec<u8>> {
+    let image = RawImage::from_png(png)?;
+    let positions = carrier_positions(&image, alpha_only, bits, adaptive)?;
+    let data = image.data();
+
+    let mut stream = positions.iter()
+        .flat_map(|&position| (0..bits).rev().map(move |i| (data[position] >> i) & 1));
+    let mut bytes = std::iter::from_fn(|| {
+        let byte: Vec<u8> = stream.by_ref().take(8).collect();
+        (byte.len() == 8).then(|| byte.iter().fold(0u8, |acc, bit| (acc << 1) | bit))
+    });
+
+    let length: Vec<u8> = bytes.by_ref().take(LENGTH_PREFIX).collect();
+    let length = u32::from_be_bytes(length.try_into().map_err(|_| "image is too small")?) as usize;
+    if length > bits_capacity(positions.len() * bits as usize) {
+        return Err("no LSB payload found".into());
+    }
+
+    Ok(bytes.take(length).collect())
+}
+
+/// Zeroes the bits holding the length prefix and the payload
+fn clear_bits(png: &mut Png, alpha_only: bool, bits: u8, adaptive: bool) -> Result<()> {
+    let length = extract_bits(png, alpha_only, bits, adaptive)?.len();
+    let mut image = RawImage::from_png(png)?;
+    let positions = carrier_positions(&image, alpha_only, bits, adaptive)?;
+
+    let data = image.data_mut();
+    let mask = (1u8 << bits) - 1;
+    for position in positions.into_iter().take(((LENGTH_PREFIX + length) * 8).div_ceil(bits as usize)) {
+        data[position] &= !mask;
+    }
+
+    image.write_to_png(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ImageHeader;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    fn rgba_png() -> Png {
+        let header = ImageHeader::new(16, 16, 8, ImageHeader::RGBA);
+        let data = (0..16 * 16 * 4).map(|i| (i % 251) as u8).collect();
+        RawImage::new(header, data).unwrap().to_png().unwrap()
+    }
+
+    fn roundtrip(backend: &dyn StegoBackend, mut png: Png) -> Vec<u8> {
+        backend.embed(&mut png, b"hidden message").unwrap();
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        backend.extract(&reparsed).unwrap()
+    }
+
+    #[test]
+    fn test_chunk_backend() {
+        let backend = Mode::Chunk.backend("ruSt").unwrap();
+        let png = Png::try_from(DICE).unwrap();
+        assert_eq!(roundtrip(backend.as_ref(), png), b"hidden message");
+    }
+
+    #[test]
+    fn test_chunk_backend_copies() {
+        let backend = ChunkBackend::with_copies(ChunkType::from_str("ruSy").unwrap(), 3).unwrap();
+        let mut png = Png::try_from(DICE).unwrap();
+        let envelope = Envelope::new(b"hidden message".to_vec()).as_bytes();
+        backend.embed(&mut png, &envelope).unwrap();
+
+        let types: Vec<String> = (0..3).map(|i| backend.copy_type(i).unwrap().to_string()).collect();
+        assert_eq!(types, ["ruSy", "ruSz", "ruSa"]);
+
+        // A stripped first copy and a corrupted second copy leave the third one
+        png.remove_chunk("ruSy").unwrap();
+        let mut corrupted = envelope.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        png.remove_chunk("ruSz").unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSz").unwrap(), corrupted));
+
+        let decoder = ChunkBackend::new(ChunkType::from_str("ruSy").unwrap());
+        assert_eq!(decoder.extract(&png).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_chunk_backend_ignores_unrelated_siblings() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let unrelated = Envelope::new(b"someone else's".to_vec()).as_bytes();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSu").unwrap(), unrelated.clone()));
+        let decoder = ChunkBackend::new(ChunkType::from_str("ruSt").unwrap());
+        assert!(decoder.extract(&png).is_err());
+
+        // The third copy of a payload written from `ruSs` isn't a copy of `ruSt` either
+        let replica = Replica::new(2, 3, unrelated).unwrap().to_envelope().as_bytes();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSu").unwrap(), replica));
+        assert!(decoder.extract(&png).is_err());
+
+        let envelope = Envelope::new(b"hidden message".to_vec()).as_bytes();
+        let backend = ChunkBackend::with_copies(ChunkType::from_str("ruSt").unwrap(), 2).unwrap();
+        backend.embed(&mut png, &envelope).unwrap();
+        png.remove_chunk("ruSt").unwrap();
+        assert_eq!(decoder.extract(&png).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_chunk_backend_remove_keeps_unrelated_chunks() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let original = png.chunks().len();
+        let unrelated = [("ruSa", b"user data".to_vec()), ("ruSu", Envelope::new(b"other".to_vec()).as_bytes())];
+        for (chunk_type, data) in &unrelated {
+            png.append_chunk(Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.clone()));
+        }
+
+        let backend = ChunkBackend::with_copies(ChunkType::from_str("ruSt").unwrap(), 3).unwrap().with_pieces(2).unwrap();
+        backend.embed(&mut png, &Envelope::new(vec![7; 100]).as_bytes()).unwrap();
+        assert_eq!(png.chunks().len(), original + 2 + 3 * 3);
+        ChunkBackend::new(ChunkType::from_str("ruSt").unwrap()).remove(&mut png).unwrap();
+
+        assert_eq!(png.chunks().len(), original + 2);
+        for (chunk_type, data) in &unrelated {
+            assert_eq!(png.chunk_by_type(*chunk_type).unwrap().data(), data);
+        }
+    }
+
+    #[test]
+    fn test_chunk_backend_stored_positions() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let original = png.chunks().len();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSu").unwrap(), b"user data".to_vec()));
+
+        let backend = ChunkBackend::with_copies(ChunkType::from_str("ruSt").unwrap(), 2).unwrap().with_pieces(2).unwrap();
+        backend.embed(&mut png, &Envelope::new(vec![7; 100]).as_bytes()).unwrap();
+        let positions = ChunkBackend::new(ChunkType::from_str("ruSt").unwrap()).stored_positions(&png).unwrap();
+
+        assert_eq!(positions.len(), 2 * 3);
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(positions.iter().all(|&position| position >= original));
+        assert!(positions.iter().all(|&position| png.chunks()[position].data() != b"user data"));
+    }
+
+    #[test]
+    fn test_chunk_backend_update_in_place() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let backend = ChunkBackend::new(chunk_type);
+        let mut png = Png::try_from(DICE).unwrap();
+        png.insert_chunk_at(1, Chunk::new(chunk_type, b"old".to_vec())).unwrap();
+        let count = png.chunks().len();
+
+        backend.update(&mut png, b"old and new").unwrap();
+
+        assert_eq!(png.chunks().len(), count);
+        assert_eq!(png.chunks()[1].chunk_type(), &chunk_type);
+        assert_eq!(png.chunks()[1].data(), b"old and new");
+        assert!(Png::try_from(&png.as_bytes()[..]).is_ok());
+    }
+
+    #[test]
+    fn test_chunk_backend_pieces() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let backend = ChunkBackend::with_copies(chunk_type, 2).unwrap().with_pieces(4).unwrap();
+        let mut png = Png::try_from(DICE).unwrap();
+        let envelope = Envelope::new(vec![42; 1000]).as_bytes();
+        backend.embed(&mut png, &envelope).unwrap();
+        assert_eq!(png.chunks().iter().filter(|chunk| chunk.chunk_type() == &chunk_type).count(), 5);
+
+        // Extracting with the default backend reassembles the pieces, falling back to the
+        // second copy when a piece of the first one is missing
+        let decoder = ChunkBackend::new(chunk_type);
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoder.extract(&reparsed).unwrap(), envelope);
+        let index = png.chunks().iter().rposition(|chunk| chunk.chunk_type() == &chunk_type).unwrap();
+        png.chunks_mut().remove(index);
+        assert_eq!(decoder.extract(&png).unwrap(), envelope);
+
+        decoder.remove(&mut png).unwrap();
+        assert!(decoder.extract(&png).is_err());
+        assert!(ChunkBackend::new(chunk_type).with_pieces(0).is_err());
+    }
+
+    #[test]
+    fn test_too_many_copies() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        assert!(ChunkBackend::with_copies(chunk_type, 0).is_err());
+        assert!(ChunkBackend::with_copies(chunk_type, 27).is_err());
+    }
+
+    #[test]
+    fn test_lsb_backend() {
+        let png = Png::try_from(DICE).unwrap();
+        assert_eq!(LsbBackend::default().capacity(&png).unwrap(), 671 * 448 * 3 / 8 - 4);
+        assert_eq!(roundtrip(&LsbBackend::default(), png), b"hidden message");
+    }
+
+    #[test]
+    fn test_lsb_keeps_pixels_close() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let original = RawImage::from_png(&png).unwrap();
+        LsbBackend::default().embed(&mut png, b"hidden message").unwrap();
+        let modified = RawImage::from_png(&png).unwrap();
+
+        assert!(original.data().iter()
+            .zip(modified.data())
+            .all(|(a, b)| (*a as i16 - *b as i16).abs() <= 1));
+    }
+
+    #[test]
+    fn test_lsb_bits() {
+        let png = rgba_png();
+        let payload: Vec<u8> = (0..=255).collect();
+        for bits in 1..=LsbBackend::MAX_BITS {
+            let backend = LsbBackend::with_bits(bits).unwrap();
+            assert_eq!(backend.capacity(&png).unwrap(), 16 * 16 * 4 * bits as usize / 8 - 4);
+
+            let mut modified = rgba_png();
+            let fits = &payload[..backend.capacity(&png).unwrap().min(payload.len())];
+            backend.embed(&mut modified, fits).unwrap();
+            assert_eq!(backend.extract(&modified).unwrap(), fits);
+            let (original, changed) = (RawImage::from_png(&png).unwrap(), RawImage::from_png(&modified).unwrap());
+            assert!(original.data().iter()
+                .zip(changed.data())
+                .all(|(a, b)| (*a as i16 - *b as i16).unsigned_abs() < 1 << bits));
+
+            backend.remove(&mut modified).unwrap();
+            assert!(backend.extract(&modified).unwrap().is_empty());
+        }
+        assert!(LsbBackend::with_bits(2).unwrap().embed(&mut rgba_png(), &[0; 253]).is_err());
+        assert!(LsbBackend::with_bits(0).is_err());
+        assert!(LsbBackend::with_bits(5).is_err());
+    }
+
+    #[test]
+    fn test_lsb_estimate() {
+        let png = Png::try_from(DICE).unwrap();
+        let one = LsbBackend::default().estimate(&png, 1000).unwrap();
+        let four = LsbBackend::with_bits(4).unwrap().estimate(&png, 1000).unwrap();
+        assert_eq!(one.capacity, LsbBackend::default().capacity(&png).unwrap());
+        assert_eq!((one.max_change, four.max_change), (1, 15));
+        assert!(one.psnr > four.psnr && four.psnr > 40.0);
+        assert!(one.to_string().starts_with("1 bit(s) per sample: 1000 of 112724 bytes used"));
+    }
+
+    #[test]
+    fn test_adaptive() {
+        // Flat on the left half, noisy on the right half
+        let header = ImageHeader::new(32, 16, 8, ImageHeader::RGBA);
+        let data = (0..32 * 16 * 4)
+            .map(|i: usize| if (i / 4) % 32 < 16 { 200 } else { (i.wrapping_mul(2654435761) >> 7) as u8 })
+            .collect();
+        let png = RawImage::new(header, data).unwrap().to_png().unwrap();
+
+        for backend in [&LsbBackend::with_bits(2).unwrap().adaptive(true) as &dyn StegoBackend, &AlphaBackend::default().adaptive(true)] {
+            let mut modified = RawImage::from_png(&png).unwrap().to_png().unwrap();
+            backend.embed(&mut modified, b"hidden in the noise").unwrap();
+            assert_eq!(backend.extract(&modified).unwrap(), b"hidden in the noise");
+
+            // Only the samples next to the noisy half may change
+            let (original, changed) = (RawImage::from_png(&png).unwrap(), RawImage::from_png(&modified).unwrap());
+            assert!(original.data().iter()
+                .zip(changed.data())
+                .enumerate()
+                .all(|(i, (a, b))| (i / 4) % 32 >= 15 || a == b));
+
+            backend.remove(&mut modified).unwrap();
+            assert!(backend.extract(&modified).unwrap().is_empty());
+        }
+
+        let adaptive = LsbBackend::default().adaptive(true).capacity(&png).unwrap();
+        assert!(adaptive > 0 && adaptive < LsbBackend::default().capacity(&png).unwrap() * 6 / 10);
+    }
+
+    #[test]
+    fn test_alpha_backend() {
+        let png = rgba_png();
+        assert_eq!(AlphaBackend::default().capacity(&png).unwrap(), 16 * 16 / 8 - 4);
+        assert_eq!(roundtrip(&AlphaBackend::default(), png), b"hidden message");
+
+        let rgb = Png::try_from(DICE).unwrap();
+        assert!(AlphaBackend::default().capacity(&rgb).is_err());
+    }
+
+    #[test]
+    fn test_eof_backend() {
+        let png = Png::try_from(DICE).unwrap();
+        assert!(EofBackend.extract(&png).is_err());
+        assert_eq!(roundtrip(&EofBackend, png), b"hidden message");
+    }
+
+    #[test]
+    fn test_eof_matches_plain_concatenation() {
+        // Files produced by `cat image.png payload > out.png`, as most EOF tools do
+        let appended: Vec<u8> = DICE.iter().chain(b"PK\x03\x04 other tool").copied().collect();
+        let png = Png::try_from(&appended[..]).unwrap();
+        assert_eq!(EofBackend.extract(&png).unwrap(), b"PK\x03\x04 other tool");
+
+        let mut png = Png::try_from(DICE).unwrap();
+        EofBackend.embed(&mut png, b"PK\x03\x04 other tool").unwrap();
+        assert_eq!(png.as_bytes(), appended);
+    }
+
+    #[test]
+    fn test_remove() {
+        for mode in [Mode::Chunk, Mode::Lsb, Mode::Alpha, Mode::Eof, Mode::Interleave] {
+            let backend = mode.backend("ruSt").unwrap();
+            let mut png = rgba_png();
+            backend.embed(&mut png, b"hidden").unwrap();
+            backend.remove(&mut png).unwrap();
+            assert!(backend.extract(&png).map_or(true, |data| data.is_empty()), "{}", mode);
+        }
+
+        let mut png = Png::try_from(DICE).unwrap();
+        let original = png.as_bytes();
+        Mode::Interleave.backend("ruSt").unwrap().embed(&mut png, b"hidden").unwrap();
+        Mode::Interleave.backend("ruSt").unwrap().remove(&mut png).unwrap();
+        assert_eq!(png.as_bytes(), original);
+    }
+
+    #[test]
+    fn test_interleaved_backend() {
+        let backend = Mode::Interleave.backend("ruSt").unwrap();
+        let mut png = Png::try_from(DICE).unwrap();
+        let original = RawImage::from_png(&png).unwrap();
+
+        backend.embed(&mut png, b"hidden message").unwrap();
+
+        let types: Vec<String> = png.chunks().iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .filter(|chunk_type| chunk_type == "IDAT" || chunk_type == "ruSt")
+            .collect();
+        assert_eq!(types, ["ruSt", "ruSt", "IDAT", "IDAT", "IDAT", "ruSt", "ruSt"]);
+        assert_eq!(RawImage::from_png(&png).unwrap(), original);
+        assert_eq!(backend.extract(&png).unwrap(), b"hidden message");
+        assert!(png.validate().is_empty());
+    }
+
+    #[test]
+    fn test_interleave_keeps_idat_consecutive() {
+        let backend = InterleavedBackend::new(ChunkType::from_str("ruSt").unwrap());
+        let mut png = rgba_png();
+        let stream = png.chunk_by_type("IDAT").unwrap().data().to_vec();
+        let idats = stream.chunks(stream.len().div_ceil(3))
+            .map(|piece| Chunk::new(ChunkType::from_str("IDAT").unwrap(), piece.to_vec()))
+            .collect();
+        crate::image::replace_idat(&mut png, idats);
+
+        backend.embed(&mut png, b"x").unwrap();
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        let types: Vec<String> = reparsed.chunks().iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .filter(|chunk_type| chunk_type == "IDAT" || chunk_type == "ruSt")
+            .collect();
+        assert_eq!(types, ["IDAT", "IDAT", "IDAT", "ruSt"]);
+        assert!(reparsed.validate().is_empty());
+        assert_eq!(backend.extract(&reparsed).unwrap(), b"x");
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let mut png = rgba_png();
+        assert!(AlphaBackend::default().embed(&mut png, &[0; 29]).is_err());
+    }
+
+    #[test]
+    fn test_mode_from_str() {
+        assert_eq!(Mode::from_str("lsb").unwrap(), Mode::Lsb);
+        assert_eq!(Mode::from_str("eof").unwrap().to_string(), "eof");
+        assert!(Mode::from_str("dct").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::env;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::Result;
+
+/// The values of the template variables, besides `{env:VAR}` which reads the environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Context {
+    /// `{hostname}`
+    pub hostname: String,
+    /// `{date}`, as YYYY-MM-DD in UTC
+    pub date: String,
+    /// `{datetime}`, as an RFC 3339 timestamp in UTC
+    pub datetime: String,
+    /// `{file}`, the name of the PNG file the message is encoded into
+    pub file: String,
+}
+
+impl Context {
+    /// The context of a message encoded into `file_path` on this machine, now
+    pub fn current<P: AsRef<Path>>(file_path: P) -> Result<Context> {
+        let datetime = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+        Ok(Context {
+            hostname: whoami::fallible::hostname()?,
+            date: datetime[..10].to_owned(),
+            datetime,
+            file: file_path.as_ref()
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+        })
+    }
+}
+
+/// Expands the placeholders of a message: `{hostname}`, `{date}`, `{datetime}`, `{file}` and
+/// `{env:VAR}`. `{{` and `}}` stand for literal braces. Unknown placeholders and unset
+/// environment variables are errors, so typos don't end up in the payload.
+pub fn expand(template: &str, context: &Context) -> Result<String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..start]);
+        let brace = &rest[start..];
+        if brace.starts_with("{{") || brace.starts_with("}}") {
+            expanded.push_str(&brace[..1]);
+            rest = &brace[2..];
+            continue;
+        }
+        if brace.starts_with('}') {
+            return Err("unmatched '}' in the message, write '}}' for a literal brace".into());
+        }
+
+        let end = brace.find('}').ok_or("unclosed '{' in the message, write '{{' for a literal brace")?;
+        let name = &brace[1..end];
+        match name {
+            "hostname" => expanded.push_str(&context.hostname),
+            "date" => expanded.push_str(&context.date),
+            "datetime" => expanded.push_str(&context.datetime),
+            "file" => expanded.push_str(&context.file),
+            _ => match name.strip_prefix("env:") {
+                Some(var) => expanded.push_str(&env::var(var)
+                    .map_err(|_| format!("environment variable {} is not set", var))?),
+                None => return Err(format!("unknown placeholder {{{}}}", name).into()),
+            },
+        }
+        rest = &brace[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> Context {
+        Context {
+            hostname: "build-7".to_owned(),
+            date: "2024-05-01".to_owned(),
+            datetime: "2024-05-01T12:30:00Z".to_owned(),
+            file: "dice.png".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        env::set_var("PNGME_TEMPLATE_TEST", "ci");
+        let message = expand("{file} stamped by {env:PNGME_TEMPLATE_TEST}@{hostname} on {date}", &context()).unwrap();
+        assert_eq!(message, "dice.png stamped by ci@build-7 on 2024-05-01");
+        assert_eq!(expand("{{\"at\": \"{datetime}\"}}", &context()).unwrap(), "{\"at\": \"2024-05-01T12:30:00Z\"}");
+        assert_eq!(expand("no placeholders", &context()).unwrap(), "no placeholders");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!(expand("{host}", &context()).is_err());
+        assert!(expand("{date", &context()).is_err());
+        assert!(expand("date}", &context()).is_err());
+        assert!(expand("{env:PNGME_TEMPLATE_UNSET}", &context()).is_err());
+    }
+
+    #[test]
+    fn test_current_context() {
+        let context = Context::current("images/dice.png").unwrap();
+        assert_eq!(context.file, "dice.png");
+        assert!(context.datetime.starts_with(&context.date));
+        assert_eq!(context.date.len(), 10);
+    }
+}
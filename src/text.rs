@@ -0,0 +1,375 @@
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::image::{deflate, inflate};
+use crate::png::Png;
+use crate::Result;
+
+/// The chunk types that carry textual metadata
+pub const TEXT_CHUNK_TYPES: [&str; 3] = ["tEXt", "zTXt", "iTXt"];
+
+/// A keyword/text pair stored in a `tEXt`, `zTXt` or `iTXt` chunk. See the PNG spec for more details.
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Anc-text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    keyword: String,
+    text: String,
+    compressed: bool,
+    translation: Option<Translation>,
+}
+
+/// The extra fields of an international (`iTXt`) text entry
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Translation {
+    /// RFC 1766 language tag of the text, e.g. `en-US` (may be empty)
+    pub language_tag: String,
+    /// The keyword translated into the language of the text (may be empty)
+    pub translated_keyword: String,
+}
+
+impl TextChunk {
+    /// Creates an uncompressed (`tEXt`) text entry
+    pub fn new(keyword: &str, text: &str) -> Result<TextChunk> {
+        validate_keyword(keyword)?;
+        latin1(text)?;
+
+        Ok(TextChunk {
+            keyword: keyword.to_owned(),
+            text: text.to_owned(),
+            compressed: false,
+            translation: None,
+        })
+    }
+
+    /// Creates a `tEXt` entry, or an `iTXt` entry if the text isn't representable in Latin-1
+    pub fn any_text(keyword: &str, text: &str) -> Result<TextChunk> {
+        if latin1(text).is_ok() {
+            TextChunk::new(keyword, text)
+        } else {
+            TextChunk::international(keyword, text, Translation::default(), false)
+        }
+    }
+
+    /// Creates a zlib compressed (`zTXt`) text entry
+    pub fn compressed(keyword: &str, text: &str) -> Result<TextChunk> {
+        Ok(TextChunk { compressed: true, ..TextChunk::new(keyword, text)? })
+    }
+
+    /// Creates an international (`iTXt`) text entry. Unlike the other text chunks, the text
+    /// and the translated keyword may contain any UTF-8 characters.
+    pub fn international(
+        keyword: &str,
+        text: &str,
+        translation: Translation,
+        compressed: bool,
+    ) -> Result<TextChunk> {
+        validate_keyword(keyword)?;
+
+        let tag = &translation.language_tag;
+        let valid_tag = tag.split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+        if !tag.is_empty() && !valid_tag {
+            return Err("invalid language tag".into());
+        }
+        if translation.translated_keyword.contains('\0') || text.contains('\0') {
+            return Err("iTXt fields must not contain null characters".into());
+        }
+
+        Ok(TextChunk {
+            keyword: keyword.to_owned(),
+            text: text.to_owned(),
+            compressed,
+            translation: Some(translation),
+        })
+    }
+
+    /// Parses a `tEXt`, `zTXt` or `iTXt` chunk, see `to_chunk` for the layouts
+    pub fn from_chunk(chunk: &Chunk) -> Result<TextChunk> {
+        let data = chunk.data();
+        let invalid = || format!("invalid {} chunk", chunk.chunk_type());
+        let (keyword, rest) = split_at_null(data).ok_or_else(invalid)?;
+        let keyword: String = keyword.iter().map(|&byte| byte as char).collect();
+
+        match chunk.chunk_type().as_str() {
+            "tEXt" => Ok(TextChunk {
+                keyword,
+                text: rest.iter().map(|&byte| byte as char).collect(),
+                compressed: false,
+                translation: None,
+            }),
+            "zTXt" => {
+                let (&method, text) = rest.split_first().ok_or_else(invalid)?;
+                if method != 0 {
+                    return Err("unknown zTXt compression method".into());
+                }
+                Ok(TextChunk {
+                    keyword,
+                    text: inflate(text)?.iter().map(|&byte| byte as char).collect(),
+                    compressed: true,
+                    translation: None,
+                })
+            }
+            "iTXt" => {
+                // The compression method (0) follows the compression flag
+                let (&[flag, _], rest) = rest.split_first_chunk().ok_or_else(invalid)?;
+                let compressed = flag == 1;
+                let (language_tag, rest) = split_at_null(rest).ok_or_else(invalid)?;
+                let (translated_keyword, text) = split_at_null(rest).ok_or_else(invalid)?;
+                let text = if compressed { inflate(text)? } else { text.to_vec() };
+                Ok(TextChunk {
+                    keyword,
+                    text: String::from_utf8(text)?,
+                    compressed,
+                    translation: Some(Translation {
+                        language_tag: String::from_utf8(language_tag.to_vec())?,
+                        translated_keyword: String::from_utf8(translated_keyword.to_vec())?,
+                    }),
+                })
+            }
+            _ => Err(format!("{} is not a text chunk", chunk.chunk_type()).into()),
+        }
+    }
+
+    /// The keyword of this entry
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    /// The text of this entry
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns true if the text of this entry is zlib compressed
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// The language tag and translated keyword of an international entry
+    pub fn translation(&self) -> Option<&Translation> {
+        self.translation.as_ref()
+    }
+
+    /// Builds the chunk storing this entry:
+    /// * `tEXt`: keyword, null separator, Latin-1 text
+    /// * `zTXt`: keyword, null separator, compression method (0), zlib compressed Latin-1 text
+    /// * `iTXt`: keyword, null separator, compression flag, compression method (0),
+    ///   language tag, null separator, translated keyword, null separator, UTF-8 text
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let mut data = latin1(&self.keyword)?;
+        data.push(0);
+
+        if let Some(translation) = &self.translation {
+            data.extend([self.compressed as u8, 0]);
+            data.extend(translation.language_tag.as_bytes());
+            data.push(0);
+            data.extend(translation.translated_keyword.as_bytes());
+            data.push(0);
+            if self.compressed {
+                data.extend(deflate(self.text.as_bytes())?);
+            } else {
+                data.extend(self.text.as_bytes());
+            }
+            return Ok(Chunk::new(ChunkType::ITXT, data));
+        }
+
+        let text = latin1(&self.text)?;
+        let chunk_type = if self.compressed {
+            data.push(0);
+            data.extend(deflate(&text)?);
+            "zTXt"
+        } else {
+            data.extend(text);
+            "tEXt"
+        };
+
+        Ok(Chunk::new(ChunkType::from_str(chunk_type)?, data))
+    }
+}
+
+/// Returns the keyword of a textual chunk, or `None` for any other chunk
+pub fn keyword_of(chunk: &Chunk) -> Option<String> {
+    if !TEXT_CHUNK_TYPES.contains(&chunk.chunk_type().as_str()) {
+        return None;
+    }
+
+    let end = chunk.data().iter().position(|&byte| byte == 0)?;
+    Some(chunk.data()[..end].iter().map(|&byte| byte as char).collect())
+}
+
+/// Returns the first text entry with `keyword` in `png`
+pub fn get_text(png: &Png, keyword: &str) -> Result<Option<TextChunk>> {
+    png.chunks().iter()
+        .find(|chunk| keyword_of(chunk).as_deref() == Some(keyword))
+        .map(TextChunk::from_chunk)
+        .transpose()
+}
+
+/// Returns every text entry of `png`, in file order
+pub fn entries(png: &Png) -> Result<Vec<TextChunk>> {
+    png.chunks().iter()
+        .filter(|chunk| keyword_of(chunk).is_some())
+        .map(TextChunk::from_chunk)
+        .collect()
+}
+
+/// Stores `entry` in `png`, replacing every existing textual chunk with the same keyword.
+/// The new chunk is placed right before `IEND`.
+pub fn set_text(png: &mut Png, entry: &TextChunk) -> Result<()> {
+    let chunk = entry.to_chunk()?;
+    png.chunks_mut().retain(|chunk| keyword_of(chunk).as_deref() != Some(entry.keyword()));
+    png.insert_before_end(chunk);
+    Ok(())
+}
+
+fn split_at_null(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = data.iter().position(|&byte| byte == 0)?;
+    Some((&data[..end], &data[end + 1..]))
+}
+
+/// Keywords are 1-79 printable Latin-1 characters without leading, trailing or consecutive spaces
+pub(crate) fn validate_keyword(keyword: &str) -> Result<()> {
+    let printable = keyword.chars().all(|c| matches!(c as u32, 32..=126 | 161..=255));
+
+    if keyword.is_empty() || keyword.chars().count() > 79 || !printable {
+        return Err("keyword must be 1-79 printable Latin-1 characters".into());
+    }
+    if keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ") {
+        return Err("keyword must not have leading, trailing or consecutive spaces".into());
+    }
+    Ok(())
+}
+
+/// Encodes `text` as Latin-1, which is the character set of `tEXt` and `zTXt` chunks
+pub(crate) fn latin1(text: &str) -> Result<Vec<u8>> {
+    text.chars()
+        .map(|c| u8::try_from(c as u32).map_err(|_| "text is not representable in Latin-1".into()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::inflate;
+    use std::convert::TryFrom;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[test]
+    fn test_text_chunk() {
+        let chunk = TextChunk::new("Title", "Dice").unwrap().to_chunk().unwrap();
+
+        assert_eq!(chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Title\0Dice");
+        assert_eq!(keyword_of(&chunk).unwrap(), "Title");
+    }
+
+    #[test]
+    fn test_compressed_text_chunk() {
+        let text = "A rather long description ".repeat(20);
+        let chunk = TextChunk::compressed("Description", &text).unwrap().to_chunk().unwrap();
+
+        assert_eq!(chunk.chunk_type().to_string(), "zTXt");
+        assert!(chunk.data().starts_with(b"Description\0\0"));
+        assert_eq!(inflate(&chunk.data()[13..]).unwrap(), text.as_bytes());
+        assert!(chunk.data().len() < text.len());
+    }
+
+    #[test]
+    fn test_international_text_chunk() {
+        let translation = Translation {
+            language_tag: "de-DE".to_owned(),
+            translated_keyword: "Titel".to_owned(),
+        };
+        let entry = TextChunk::international("Title", "Würfel 日本", translation, false).unwrap();
+        let chunk = entry.to_chunk().unwrap();
+
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        assert_eq!(chunk.data(), "Title\0\0\0de-DE\0Titel\0Würfel 日本".as_bytes());
+        assert_eq!(keyword_of(&chunk).unwrap(), "Title");
+    }
+
+    #[test]
+    fn test_compressed_international_text_chunk() {
+        let entry = TextChunk::international("Title", "Würfel", Translation::default(), true).unwrap();
+        let chunk = entry.to_chunk().unwrap();
+
+        assert!(chunk.data().starts_with(b"Title\0\x01\0\0\0"));
+        assert_eq!(inflate(&chunk.data()[10..]).unwrap(), "Würfel".as_bytes());
+    }
+
+    #[test]
+    fn test_text_chunk_round_trip() {
+        let translation = Translation { language_tag: "ja".to_owned(), translated_keyword: "題".to_owned() };
+        let entries = [
+            TextChunk::new("Author", "Jürgen").unwrap(),
+            TextChunk::compressed("Comment", "squeezed").unwrap(),
+            TextChunk::international("Title", "サイコロ", translation, true).unwrap(),
+        ];
+
+        for entry in entries {
+            assert_eq!(TextChunk::from_chunk(&entry.to_chunk().unwrap()).unwrap(), entry);
+        }
+        assert!(TextChunk::from_chunk(&Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"no null".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_invalid_language_tag() {
+        let translation = Translation { language_tag: "en_US".to_owned(), ..Translation::default() };
+        assert!(TextChunk::international("Title", "text", translation, false).is_err());
+    }
+
+    #[test]
+    fn test_latin1_text() {
+        let chunk = TextChunk::new("Author", "Jürgen").unwrap().to_chunk().unwrap();
+        assert_eq!(chunk.data(), b"Author\0J\xfcrgen");
+        assert!(TextChunk::new("Author", "日本").is_err());
+    }
+
+    #[test]
+    fn test_any_text() {
+        assert!(TextChunk::any_text("Author", "Jürgen").unwrap().translation().is_none());
+        assert!(TextChunk::any_text("Author", "山田").unwrap().translation().is_some());
+    }
+
+    #[test]
+    fn test_invalid_keywords() {
+        assert!(TextChunk::new("", "text").is_err());
+        assert!(TextChunk::new(" Title", "text").is_err());
+        assert!(TextChunk::new("Two  spaces", "text").is_err());
+        assert!(TextChunk::new(&"k".repeat(80), "text").is_err());
+        assert!(TextChunk::new("Tab\there", "text").is_err());
+    }
+
+    #[test]
+    fn test_set_text_replaces_keyword() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let before = png.chunks().len();
+
+        set_text(&mut png, &TextChunk::new("Comment", "pngme").unwrap()).unwrap();
+        set_text(&mut png, &TextChunk::compressed("Comment", "pngme v2").unwrap()).unwrap();
+
+        let chunks = png.chunks();
+        assert_eq!(chunks.len(), before + 1);
+        assert_eq!(chunks[chunks.len() - 2].chunk_type().to_string(), "zTXt");
+        assert_eq!(chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let before = entries(&png).unwrap().len();
+        let title = TextChunk::international("Title", "サイコロ", Translation::default(), true).unwrap();
+        set_text(&mut png, &TextChunk::compressed("Comment", "pngme").unwrap()).unwrap();
+        set_text(&mut png, &title).unwrap();
+
+        let entries = entries(&png).unwrap();
+        assert_eq!(entries.len(), before + 2);
+        assert_eq!(entries[before].keyword(), "Comment");
+        assert_eq!(entries[before].text(), "pngme");
+        assert_eq!(entries[before + 1], title);
+        assert_eq!(get_text(&png, "Title").unwrap(), Some(title));
+        assert_eq!(get_text(&png, "Author").unwrap(), None);
+    }
+}
@@ -0,0 +1,309 @@
+use std::io::{self, Read};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngMeError;
+use crate::png::{ChunkParseError, InvalidLength, LimitExceeded, ParseLimits, Png};
+use crate::Result;
+
+/// What `Png::parse_with` does after a visitor callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    /// Goes on and keeps the chunk (or trailer) in the returned `Png`
+    Keep,
+    /// Goes on without keeping it. Skipped in `visit_header`, the data isn't even read into
+    /// memory (nor its CRC checked).
+    Skip,
+    /// Stops parsing, returning what was kept so far
+    Abort,
+}
+
+/// The part of a chunk read before its data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkHeader {
+    /// The offset of the chunk (its length field) in the file
+    pub offset: u64,
+    /// The length of the data
+    pub length: u32,
+    pub chunk_type: ChunkType,
+}
+
+/// Callbacks receiving the chunks of a file as `Png::parse_with` reads them. Every callback
+/// keeps everything by default.
+pub trait ChunkVisitor {
+    /// Called when the length and type of a chunk have been read
+    fn visit_header(&mut self, _header: &ChunkHeader) -> Visit {
+        Visit::Keep
+    }
+
+    /// Called with the chunk once its data has been read and its CRC checked
+    fn visit_chunk(&mut self, _header: &ChunkHeader, _chunk: &Chunk) -> Visit {
+        Visit::Keep
+    }
+
+    /// Called with the data after `IEND`, if there is any
+    fn visit_trailer(&mut self, _offset: u64, _data: &[u8]) -> Visit {
+        Visit::Keep
+    }
+}
+
+/// Reads a PNG file from `reader`, passing every chunk to `visitor` as it is read.
+/// Only the chunks the visitor keeps are held in memory. Whole chunks after `IEND` are
+/// visited like the others, the data after them is the trailer.
+pub(crate) fn parse_with<R: Read, V: ChunkVisitor + ?Sized>(
+    mut reader: R,
+    visitor: &mut V,
+    limits: &ParseLimits,
+) -> Result<Png> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    if header != Png::STANDARD_HEADER {
+        return Err(PngMeError::InvalidSignature.into());
+    }
+
+    let mut kept = Vec::new();
+    let mut offset = header.len() as u64;
+    let mut count = 0;
+    let mut previous = None;
+    loop {
+        let mut fields = [0; 8];
+        let in_context = |index, source| ChunkParseError { index, offset, previous, source };
+        if !read_fields(&mut reader, &mut fields).map_err(|e| in_context(count, e.into()))? {
+            break;
+        }
+
+        count += 1;
+        if count > limits.max_chunks {
+            return Err(LimitExceeded::Chunks(limits.max_chunks).into());
+        }
+        let length = u32::from_be_bytes(fields[..4].try_into()?);
+        if length > limits.max_chunk_len {
+            return Err(LimitExceeded::ChunkLength(limits.max_chunk_len).into());
+        }
+        if offset + 12 + length as u64 > limits.max_total_size as u64 {
+            return Err(LimitExceeded::TotalSize(limits.max_total_size).into());
+        }
+        let in_context = |source| in_context(count - 1, source);
+        let chunk_type = read_type(&fields, length, limits).map_err(in_context)?;
+        let is_end = &chunk_type == "IEND";
+        let header = ChunkHeader { offset, length, chunk_type };
+
+        match visitor.visit_header(&header) {
+            Visit::Abort => return Ok(Png::from_chunks(kept)),
+            Visit::Skip => {
+                let skipped = io::copy(&mut (&mut reader).take(length as u64 + 4), &mut io::sink())?;
+                if skipped != length as u64 + 4 {
+                    return Err(in_context(io::Error::from(io::ErrorKind::UnexpectedEof).into()).into());
+                }
+            }
+            Visit::Keep => {
+                let chunk = Chunk::read_body(&mut reader, header.chunk_type, length).map_err(|e| in_context(e.into()))?;
+                match visitor.visit_chunk(&header, &chunk) {
+                    Visit::Abort => return Ok(Png::from_chunks(kept)),
+                    Visit::Skip => {}
+                    Visit::Keep => kept.push(chunk),
+                }
+            }
+        }
+        offset += 12 + length as u64;
+        previous = Some(header.chunk_type);
+        if is_end {
+            break;
+        }
+    }
+
+    // Chunks appended after `IEND` (as older versions of pngme did) are chunks, like in
+    // `Png::parse`: anything else stored there, from the first bytes that aren't a whole valid
+    // chunk, is the trailer
+    let mut trailer = Vec::new();
+    loop {
+        let allowed = (limits.max_total_size as u64).saturating_sub(offset);
+        (&mut reader).take(8.min(allowed)).read_to_end(&mut trailer)?;
+        let Ok(fields) = <[u8; 8]>::try_from(&trailer[..]) else {
+            break;
+        };
+        let length = u32::from_be_bytes(fields[..4].try_into()?);
+        if count == limits.max_chunks || length > limits.max_chunk_len || 12 + length as u64 > allowed {
+            break;
+        }
+        (&mut reader).take(length as u64 + 4).read_to_end(&mut trailer)?;
+        let Ok(chunk) = read_type(&fields, length, limits)
+            .and_then(|_| Ok(Chunk::try_from(&trailer[..])?)) else {
+            break;
+        };
+        trailer.clear();
+
+        count += 1;
+        let header = ChunkHeader { offset, length, chunk_type: *chunk.chunk_type() };
+        offset += 12 + length as u64;
+        match visitor.visit_header(&header) {
+            Visit::Abort => return Ok(Png::from_chunks(kept)),
+            Visit::Skip => {}
+            Visit::Keep => match visitor.visit_chunk(&header, &chunk) {
+                Visit::Abort => return Ok(Png::from_chunks(kept)),
+                Visit::Skip => {}
+                Visit::Keep => kept.push(chunk),
+            },
+        }
+    }
+
+    let mut png = Png::from_chunks(kept);
+    // One byte more than the limit allows tells a trailer that exceeds it
+    let allowed = (limits.max_total_size as u64).saturating_sub(offset);
+    reader.take(allowed.saturating_add(1).saturating_sub(trailer.len() as u64)).read_to_end(&mut trailer)?;
+    if trailer.len() as u64 > allowed {
+        return Err(LimitExceeded::TotalSize(limits.max_total_size).into());
+    }
+    if !trailer.is_empty() && visitor.visit_trailer(offset, &trailer) == Visit::Keep {
+        png.set_trailer(trailer);
+    }
+    Ok(png)
+}
+
+/// Reads the length and type fields of a chunk. Returns false at the end of the input, and an
+/// `UnexpectedEof` error if it ends in the middle of the fields.
+pub(crate) fn read_fields<R: Read>(reader: &mut R, fields: &mut [u8; 8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < fields.len() {
+        match reader.read(&mut fields[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Parses the type of a chunk from its length and type fields, checking its length if asked to
+pub(crate) fn read_type(fields: &[u8; 8], length: u32, limits: &ParseLimits) -> Result<ChunkType> {
+    let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&fields[4..])?)?;
+    if limits.check_lengths {
+        InvalidLength::check(&chunk_type, length)?;
+    }
+    Ok(chunk_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    /// Keeps the critical chunks, skipping the data of the others
+    struct Critical {
+        skipped: Vec<String>,
+    }
+
+    impl ChunkVisitor for Critical {
+        fn visit_header(&mut self, header: &ChunkHeader) -> Visit {
+            if header.chunk_type.is_critical() {
+                Visit::Keep
+            } else {
+                self.skipped.push(header.chunk_type.to_string());
+                Visit::Skip
+            }
+        }
+    }
+
+    #[test]
+    fn test_skip() {
+        let mut visitor = Critical { skipped: Vec::new() };
+        let png = Png::parse_with(DICE, &mut visitor).unwrap();
+
+        assert_eq!(visitor.skipped, ["gAMA", "tEXt", "tIME"]);
+        let types: Vec<String> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "IDAT", "IDAT", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_keep_everything() {
+        let mut bytes = DICE.to_vec();
+        bytes.extend(b"appended");
+
+        struct Everything;
+        impl ChunkVisitor for Everything {}
+
+        let png = Png::parse_with(&bytes[..], &mut Everything).unwrap();
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_chunks_after_end() {
+        struct Everything;
+        impl ChunkVisitor for Everything {}
+
+        // Two chunks appended after IEND by older versions of pngme, then a chunk with a damaged
+        // CRC and other data, which start the trailer
+        let mut bytes = DICE.to_vec();
+        bytes.extend(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"first".to_vec()).as_bytes());
+        bytes.extend(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"second".to_vec()).as_bytes());
+        let mut damaged = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"third".to_vec()).as_bytes();
+        *damaged.last_mut().unwrap() ^= 1;
+        bytes.extend(&damaged);
+        bytes.extend(b"appended");
+
+        let streamed = Png::parse_with(&bytes[..], &mut Everything).unwrap();
+        let parsed = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(streamed.as_bytes(), parsed.as_bytes());
+        assert_eq!(streamed.chunks().len(), parsed.chunks().len());
+        assert_eq!(streamed.chunks().len(), 10);
+        assert_eq!(streamed.trailer(), parsed.trailer());
+        assert_eq!(streamed.trailer(), [&damaged[..], b"appended"].concat());
+    }
+
+    #[test]
+    fn test_abort() {
+        struct FirstIdat(Option<u64>);
+        impl ChunkVisitor for FirstIdat {
+            fn visit_header(&mut self, header: &ChunkHeader) -> Visit {
+                if header.chunk_type == ChunkType::IDAT {
+                    self.0 = Some(header.offset);
+                    return Visit::Abort;
+                }
+                Visit::Skip
+            }
+        }
+
+        let mut visitor = FirstIdat(None);
+        let png = Png::parse_with(DICE, &mut visitor).unwrap();
+        assert!(png.chunks().is_empty());
+        assert_eq!(visitor.0, Some(8 + 25 + 16 + 58));
+    }
+
+    #[test]
+    fn test_damaged_input() {
+        struct Everything;
+        impl ChunkVisitor for Everything {}
+
+        let err = Png::parse_with(&DICE[..100], &mut Everything).unwrap_err();
+        assert_eq!(err.to_string(), "chunk #2 (after type gAMA at offset 49): unexpected end of file");
+        assert!(Png::parse_with(&b"not a png"[..], &mut Everything).is_err());
+
+        // The input ends in the middle of the length and type fields of the next chunk
+        let err = Png::parse_with(&DICE[..8 + 25 + 3], &mut Everything).unwrap_err();
+        assert_eq!(err.to_string(), "chunk #1 (after type IHDR at offset 33): unexpected end of file");
+    }
+
+    #[test]
+    fn test_max_total_size() {
+        struct Nothing;
+        impl ChunkVisitor for Nothing {
+            fn visit_header(&mut self, _header: &ChunkHeader) -> Visit {
+                Visit::Skip
+            }
+        }
+
+        let mut bytes = DICE.to_vec();
+        bytes.extend(b"appended");
+        let limits = |max_total_size| ParseLimits { max_total_size, ..ParseLimits::default() };
+
+        assert!(Png::parse_with_limits(&bytes[..], &mut Nothing, &limits(bytes.len())).is_ok());
+        let err = Png::parse_with_limits(&bytes[..], &mut Nothing, &limits(bytes.len() - 1)).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&LimitExceeded::TotalSize(bytes.len() - 1)));
+        let err = Png::parse_with_limits(DICE, &mut Nothing, &limits(100)).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&LimitExceeded::TotalSize(100)));
+    }
+}
@@ -0,0 +1,129 @@
+//! JavaScript bindings, built with wasm-bindgen when the `wasm` feature is enabled. They are
+//! deliberately low level (indices and byte arrays); the npm package in `npm/` wraps them in a
+//! typed API.
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use wasm_bindgen::prelude::*;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::{EmbeddedFile, Envelope};
+use crate::png::Png;
+use crate::stego::Mode;
+use crate::Result;
+
+/// A PNG file held in memory
+#[wasm_bindgen(js_name = Png)]
+pub struct WasmPng {
+    png: Png,
+}
+
+#[wasm_bindgen(js_class = Png)]
+impl WasmPng {
+    /// Parses a PNG file
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<WasmPng, JsError> {
+        Png::try_from(bytes).map(|png| WasmPng { png }).map_err(js_error)
+    }
+
+    /// The whole file
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.png.as_bytes()
+    }
+
+    #[wasm_bindgen(js_name = chunkCount)]
+    pub fn chunk_count(&self) -> usize {
+        self.png.chunks().len()
+    }
+
+    /// The type of the chunk at `index`
+    #[wasm_bindgen(js_name = chunkType)]
+    pub fn chunk_type(&self, index: usize) -> Option<String> {
+        self.png.chunks().get(index).map(|chunk| chunk.chunk_type().to_string())
+    }
+
+    /// The data of the chunk at `index`
+    #[wasm_bindgen(js_name = chunkData)]
+    pub fn chunk_data(&self, index: usize) -> Option<Vec<u8>> {
+        self.png.chunks().get(index).map(|chunk| chunk.data().to_vec())
+    }
+
+    /// The CRC of the chunk at `index`
+    #[wasm_bindgen(js_name = chunkCrc)]
+    pub fn chunk_crc(&self, index: usize) -> Option<u32> {
+        self.png.chunks().get(index).map(Chunk::crc)
+    }
+
+    /// Adds a chunk before `IEND`
+    #[wasm_bindgen(js_name = insertChunk)]
+    pub fn insert_chunk(&mut self, chunk_type: &str, data: Vec<u8>) -> std::result::Result<(), JsError> {
+        let chunk_type = ChunkType::from_str(chunk_type).map_err(js_error)?;
+        self.png.insert_before_end(Chunk::new(chunk_type, data));
+        Ok(())
+    }
+
+    /// Removes the first chunk of the given type, returning its data
+    #[wasm_bindgen(js_name = removeChunk)]
+    pub fn remove_chunk(&mut self, chunk_type: &str) -> std::result::Result<Vec<u8>, JsError> {
+        self.png.remove_chunk(chunk_type).map(|chunk| chunk.data().to_vec()).map_err(js_error)
+    }
+
+    /// The data after `IEND`
+    pub fn trailer(&self) -> Vec<u8> {
+        self.png.trailer().to_vec()
+    }
+
+    /// Hides a payload like `pngme encode --mode <mode>`
+    pub fn encode(&mut self, mode: &str, chunk_type: &str, payload: &[u8]) -> std::result::Result<(), JsError> {
+        encode(&mut self.png, mode, chunk_type, payload).map_err(js_error)
+    }
+
+    /// Reads a payload hidden like `pngme decode --mode <mode>`
+    pub fn decode(&self, mode: &str, chunk_type: &str) -> std::result::Result<Vec<u8>, JsError> {
+        decode(&self.png, mode, chunk_type).map_err(js_error)
+    }
+}
+
+fn js_error<E: ToString>(err: E) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+fn encode(png: &mut Png, mode: &str, chunk_type: &str, payload: &[u8]) -> Result<()> {
+    let backend = Mode::from_str(mode)?.backend(chunk_type)?;
+    backend.embed(png, &Envelope::new(payload.to_vec()).as_bytes())
+}
+
+/// Encrypted and split payloads need keys or other files, which the bindings don't handle
+fn decode(png: &Png, mode: &str, chunk_type: &str) -> Result<Vec<u8>> {
+    let backend = Mode::from_str(mode)?.backend(chunk_type)?;
+    let envelope = Envelope::open(backend.extract(png)?)?;
+    if envelope.is_encrypted() || envelope.is_part() {
+        return Err("encrypted and split payloads can only be decoded with the pngme CLI".into());
+    }
+    if envelope.is_file() {
+        return Ok(EmbeddedFile::from_bytes(envelope.payload())?.into_data());
+    }
+    Ok(envelope.into_payload())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DICE: &[u8] = include_bytes!("../dice.png");
+
+    #[test]
+    fn test_round_trip() {
+        for mode in ["chunk", "lsb", "eof", "interleave"] {
+            let mut png = Png::try_from(DICE).unwrap();
+            encode(&mut png, mode, "ruSt", b"from the browser").unwrap();
+            let png = Png::try_from(&png.as_bytes()[..]).unwrap();
+            assert_eq!(decode(&png, mode, "ruSt").unwrap(), b"from the browser");
+        }
+        assert!(decode(&Png::try_from(DICE).unwrap(), "chunk", "ruSt").is_err());
+        assert!(encode(&mut Png::try_from(DICE).unwrap(), "pixels", "ruSt", b"").is_err());
+    }
+}